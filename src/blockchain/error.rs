@@ -1,5 +1,7 @@
+use crate::consensus::upgrades::Upgrade;
 use crate::db::KvStoreError;
 use crate::zk::{StateManagerError, ZkError};
+use serde_json::json;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -28,8 +30,8 @@ pub enum BlockchainError {
     InvalidParentHash,
     #[error("merkle root invalid")]
     InvalidMerkleRoot,
-    #[error("transaction nonce invalid")]
-    InvalidTransactionNonce,
+    #[error("transaction nonce invalid, expected {expected} but got {got}")]
+    InvalidTransactionNonce { expected: u32, got: u32 },
     #[error("block timestamp is in past")]
     InvalidTimestamp,
     #[error("unmet difficulty target")]
@@ -86,8 +88,6 @@ pub enum BlockchainError {
         "deposit/withdraw transaction was not intended to be passed to this contract/function"
     )]
     DepositWithdrawPassedToWrongFunction,
-    #[error("block not on the testnet forced fork")]
-    TestnetForcedFork,
     #[error("token already exists")]
     TokenAlreadyExists,
     #[error("token not found")]
@@ -100,10 +100,174 @@ pub enum BlockchainError {
     TokenSupplyInsufficient,
     #[error("token supply overflows when issued")]
     TokenSupplyOverflow,
+    #[error("sum of the fees overflows")]
+    FeesOverflow,
     #[error("token has an invalid name/symbol")]
     TokenBadNameSymbol,
-    #[error("only ziesha fees are accepted!")]
-    OnlyZieshaFeesAccepted,
     #[error("transaction memo is too long")]
     MemoTooLong,
+    #[error("contract update emits too many events")]
+    TooManyContractEvents,
+    #[error("block conflicts with a checkpoint")]
+    CheckpointMismatch,
+    #[error("cannot reorg/rollback beyond the finality depth")]
+    BeyondFinality,
+    #[error("transaction is not valid at this height yet")]
+    TransactionNotYetValid,
+    #[error("transaction has expired")]
+    TransactionExpired,
+    #[error("validity windows cannot be nested")]
+    NestedValidityWindow,
+    #[error("transaction is scheduled too far in the future")]
+    TransactionScheduledTooFar,
+    #[error("daily spending limit of the account exceeded")]
+    SpendingLimitExceeded,
+    #[error("account policy is invalid")]
+    InvalidAccountPolicy,
+    #[error("account cannot be recovered by this key")]
+    AccountNotRecoverable,
+    #[error("account has no pending recovery")]
+    NoPendingRecovery,
+    #[error("contract has no admin to upgrade it")]
+    ContractNotUpgradable,
+    #[error("contract is being upgraded by a wrong account")]
+    ContractUpgradePermissionDenied,
+    #[error("contract upgrade has an invalid function index")]
+    InvalidContractUpgrade,
+    #[error("contract admin policy is invalid")]
+    InvalidContractAdminPolicy,
+    #[error("all of the candidate slots of the mpn account are taken")]
+    MpnAccountSlotsFull,
+    #[error("verifier key is not supported by the plonk setup of the network")]
+    UnsupportedPlonkKey,
+    #[error("upgrade {0} is not active yet")]
+    UpgradeNotActive(Upgrade),
+}
+
+/// Maps the variants to their stable error codes. Variants without any payload
+/// can also be rebuilt from their codes.
+macro_rules! error_codes {
+    (
+        $($variant:ident => $code:literal,)*
+        ;
+        $($other:pat => $other_code:literal,)*
+    ) => {
+        impl BlockchainError {
+            /// Stable identifier of the error, reported to the API clients
+            pub fn code(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $code,)*
+                    $($other => $other_code,)*
+                }
+            }
+            fn from_unit_code(code: &str) -> Option<Self> {
+                match code {
+                    $($code => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    DifferentGenesis => "different_genesis",
+    SignatureError => "signature_error",
+    BalanceInsufficient => "balance_insufficient",
+    ContractBalanceInsufficient => "contract_balance_insufficient",
+    Inconsistency => "inconsistency",
+    BlockNotFound => "block_not_found",
+    ExtendFromGenesis => "extend_from_genesis",
+    ExtendFromFuture => "extend_from_future",
+    InvalidBlockNumber => "invalid_block_number",
+    InvalidParentHash => "invalid_parent_hash",
+    InvalidMerkleRoot => "invalid_merkle_root",
+    InvalidTimestamp => "invalid_timestamp",
+    DifficultyTargetUnmet => "difficulty_target_unmet",
+    DifficultyTargetWrong => "difficulty_target_wrong",
+    MinerRewardNotFound => "miner_reward_not_found",
+    IllegalTreasuryAccess => "illegal_treasury_access",
+    InvalidMinerReward => "invalid_miner_reward",
+    ContractNotFound => "contract_not_found",
+    ContractFunctionNotFound => "contract_function_not_found",
+    IncorrectZkProof => "incorrect_zk_proof",
+    FullStateNotFound => "full_state_not_found",
+    FullStateNotValid => "full_state_not_valid",
+    StatesOutdated => "states_outdated",
+    StatesUnavailable => "states_unavailable",
+    BlockTooBig => "block_too_big",
+    StateDeltaTooBig => "state_delta_too_big",
+    CompressedStateNotFound => "compressed_state_not_found",
+    NoBlocksToRollback => "no_blocks_to_rollback",
+    InvalidContractPaymentSignature => "invalid_contract_payment_signature",
+    InsufficientMpnUpdates => "insufficient_mpn_updates",
+    InvalidMpnTransaction => "invalid_mpn_transaction",
+    CannotExecuteOwnPayments => "cannot_execute_own_payments",
+    InvalidStateModel => "invalid_state_model",
+    TestnetHeightLimitReached => "testnet_height_limit_reached",
+    AddressNotAllowedToMine => "address_not_allowed_to_mine",
+    DepositWithdrawPassedToWrongFunction => "deposit_withdraw_passed_to_wrong_function",
+    TokenAlreadyExists => "token_already_exists",
+    TokenNotFound => "token_not_found",
+    TokenNotUpdatable => "token_not_updatable",
+    TokenUpdatePermissionDenied => "token_update_permission_denied",
+    TokenSupplyInsufficient => "token_supply_insufficient",
+    TokenSupplyOverflow => "token_supply_overflow",
+    FeesOverflow => "fees_overflow",
+    TokenBadNameSymbol => "token_bad_name_symbol",
+    MemoTooLong => "memo_too_long",
+    TooManyContractEvents => "too_many_contract_events",
+    CheckpointMismatch => "checkpoint_mismatch",
+    BeyondFinality => "beyond_finality",
+    TransactionNotYetValid => "transaction_not_yet_valid",
+    TransactionExpired => "transaction_expired",
+    NestedValidityWindow => "nested_validity_window",
+    TransactionScheduledTooFar => "transaction_scheduled_too_far",
+    SpendingLimitExceeded => "spending_limit_exceeded",
+    InvalidAccountPolicy => "invalid_account_policy",
+    AccountNotRecoverable => "account_not_recoverable",
+    NoPendingRecovery => "no_pending_recovery",
+    ContractNotUpgradable => "contract_not_upgradable",
+    ContractUpgradePermissionDenied => "contract_upgrade_permission_denied",
+    InvalidContractUpgrade => "invalid_contract_upgrade",
+    InvalidContractAdminPolicy => "invalid_contract_admin_policy",
+    MpnAccountSlotsFull => "mpn_account_slots_full",
+    UnsupportedPlonkKey => "unsupported_plonk_key",
+    ;
+    Self::KvStoreError(_) => "kvstore_error",
+    Self::InvalidTransactionNonce { .. } => "invalid_transaction_nonce",
+    Self::UpgradeNotActive(_) => "upgrade_not_active",
+    Self::ZkError(_) => "zk_error",
+    Self::StateManagerError(_) => "state_manager_error",
+}
+
+impl BlockchainError {
+    /// Machine-readable payload of the error, if it has any
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::InvalidTransactionNonce { expected, got } => {
+                Some(json!({ "expected": expected, "got": got }))
+            }
+            Self::UpgradeNotActive(upgrade) => Some(json!({ "upgrade": upgrade.name() })),
+            _ => None,
+        }
+    }
+
+    /// Rebuilds an error reported by a remote node. Errors wrapping other
+    /// local error types cannot be rebuilt.
+    pub fn from_code(code: &str, details: Option<&serde_json::Value>) -> Option<Self> {
+        match code {
+            "invalid_transaction_nonce" => {
+                let details = details?;
+                Some(Self::InvalidTransactionNonce {
+                    expected: details.get("expected")?.as_u64()? as u32,
+                    got: details.get("got")?.as_u64()? as u32,
+                })
+            }
+            "upgrade_not_active" => Some(Self::UpgradeNotActive(
+                details?.get("upgrade")?.as_str()?.parse().ok()?,
+            )),
+            code => Self::from_unit_code(code),
+        }
+    }
 }
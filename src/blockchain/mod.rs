@@ -1,16 +1,23 @@
 mod error;
 pub use error::*;
 
+use crate::consensus::difficulty::{
+    BlockInfo, DifficultyAlgorithm, RetargetAlgorithm, RetargetParams,
+};
 use crate::consensus::pow::Difficulty;
+use crate::consensus::upgrades::{Upgrade, Upgrades};
 use crate::core::{
-    hash::Hash, Account, Address, Amount, Block, ChainSourcedTx, ContractAccount, ContractDeposit,
-    ContractId, ContractUpdate, ContractWithdraw, Hasher, Header, Money, MpnDeposit, MpnSourcedTx,
-    MpnWithdraw, ProofOfWork, RegularSendEntry, Signature, Token, TokenId, TokenUpdate,
-    Transaction, TransactionAndDelta, TransactionData, ZkHasher as CoreZkHasher,
+    hash::Hash, Account, AccountControl, Address, Amount, Block, ChainSourcedTx, ContractAccount,
+    ContractAdmin, ContractAdminPolicy, ContractControl, ContractDeposit, ContractEvent,
+    ContractId, ContractUpdate, ContractUpgrade, ContractWithdraw, DailySpending, Hasher, Header,
+    Money, MpnAddress, MpnDeposit, MpnSourcedTx, MpnWithdraw, ProofOfWork, RegularSendEntry,
+    Signature, Token, TokenId, TokenUpdate, Transaction, TransactionAndDelta, TransactionData,
+    ZkHasher as CoreZkHasher,
 };
 use crate::crypto::ZkSignatureScheme;
 use crate::db::{keys, KvStore, RamMirrorKvStore, WriteOp};
 use crate::utils;
+use crate::utils::metrics::METRICS;
 use crate::wallet::TxBuilder;
 use crate::zk;
 use crate::zk::ZkHasher;
@@ -28,9 +35,7 @@ pub struct BlockchainConfig {
     pub max_block_size: usize,
     pub max_delta_count: usize,
     pub block_time: u32,
-    pub difficulty_window: u64,
-    pub difficulty_lag: u64,
-    pub difficulty_cut: u64,
+    pub difficulty_algorithm: DifficultyAlgorithm,
     pub pow_base_key: &'static [u8],
     pub pow_key_change_delay: u64,
     pub pow_key_change_interval: u64,
@@ -41,10 +46,117 @@ pub struct BlockchainConfig {
     pub mpn_num_contract_deposits: usize,
     pub mpn_num_contract_withdraws: usize,
     pub mpn_log4_account_capacity: u8,
+    /// Number of the candidate slots an MPN account may take
+    pub mpn_account_slot_choices: u8,
     pub mpn_proving_time: u32,
     pub minimum_pow_difficulty: Difficulty,
     pub testnet_height_limit: Option<u64>,
     pub max_memo_length: usize,
+    pub max_contract_events: usize,
+    /// Universal PLONK setup of the network, PLONK keys are rejected without it
+    pub plonk_srs: Option<zk::plonk::PlonkVerifierSrs>,
+    /// Admin of the MPN contract, in effect once contract upgrades are active
+    pub mpn_admin: Option<ContractAdminPolicy>,
+    /// Verifier keys the MPN contract switches to as the upgrades changing the
+    /// MPN circuits take effect. Such upgrades are inert without their keys.
+    pub mpn_circuit_upgrades: Vec<(Upgrade, ContractUpgrade)>,
+    /// Maximum delay of account and contract admin policies, in blocks
+    pub max_policy_delay: u64,
+    /// Blocks ahead of the tip a transaction may become valid to be kept in mempool
+    pub max_tx_schedule: u64,
+    pub checkpoints: HashMap<u64, <Hasher as Hash>::Output>,
+    pub finality_depth: Option<u64>,
+    pub upgrades: Upgrades,
+}
+
+impl BlockchainConfig {
+    /// Minimum number of MPN function calls a block at the given height should have
+    pub fn mpn_num_function_calls_at(&self, height: u64) -> usize {
+        if self
+            .upgrades
+            .is_active(Upgrade::MoreMpnFunctionCalls, height)
+        {
+            10
+        } else {
+            self.mpn_num_function_calls
+        }
+    }
+
+    /// Does the transaction become valid too far after the given height to be
+    /// kept in the mempool?
+    pub fn is_scheduled_too_far(&self, tx: &Transaction, height: u64) -> bool {
+        tx.valid_after_height
+            .map(|h| h > height.saturating_add(self.max_tx_schedule))
+            .unwrap_or(false)
+    }
+
+    /// PLONK keys should be prepared with the setup of the network
+    pub fn check_verifier_keys<'a>(
+        &self,
+        vks: impl IntoIterator<Item = &'a zk::ZkVerifierKey>,
+        height: u64,
+    ) -> Result<(), BlockchainError> {
+        for vk in vks {
+            if let zk::ZkVerifierKey::Plonk(vk) = vk {
+                if !self.upgrades.is_active(Upgrade::PlonkProofs, height) {
+                    return Err(BlockchainError::UpgradeNotActive(Upgrade::PlonkProofs));
+                }
+                if !self
+                    .plonk_srs
+                    .as_ref()
+                    .map(|srs| vk.fits(srs))
+                    .unwrap_or(false)
+                {
+                    return Err(BlockchainError::UnsupportedPlonkKey);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Is the upgrade of the MPN circuits in effect at the given height? The
+    /// proofs of the old circuits are rejected by the new rules (And the other
+    /// way around), so it takes effect together with its verifier keys.
+    pub fn is_mpn_circuit_upgrade_active(&self, upgrade: Upgrade, height: u64) -> bool {
+        self.upgrades.is_active(upgrade, height)
+            && self.mpn_circuit_upgrades.iter().any(|(u, _)| *u == upgrade)
+    }
+
+    fn mpn_circuit_features(&self, is_active: impl Fn(Upgrade) -> bool) -> MpnCircuitFeatures {
+        MpnCircuitFeatures {
+            account_slot_choices: if is_active(Upgrade::MpnAccountSlots) {
+                self.mpn_account_slot_choices
+            } else {
+                1
+            },
+            reuse_empty_token_slots: is_active(Upgrade::MpnTokenSlotReuse),
+        }
+    }
+
+    /// Features of the MPN circuits in effect at the given height
+    pub fn mpn_circuit_features_at(&self, height: u64) -> MpnCircuitFeatures {
+        self.mpn_circuit_features(|upgrade| self.is_mpn_circuit_upgrade_active(upgrade, height))
+    }
+
+    /// Features of the MPN circuits once the upgrades scheduled until the given
+    /// height take effect, which the keys of `mpn_circuit_upgrades` are built for
+    pub fn scheduled_mpn_circuit_features(&self, height: u64) -> MpnCircuitFeatures {
+        self.mpn_circuit_features(|upgrade| self.upgrades.is_active(upgrade, height))
+    }
+
+    /// Candidate slots of an MPN account at the given height
+    pub fn mpn_account_slot_choices_at(&self, height: u64) -> u8 {
+        self.mpn_circuit_features_at(height).account_slot_choices
+    }
+}
+
+/// Rules of the MPN contract which are baked into its circuits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MpnCircuitFeatures {
+    /// Number of the candidate slots an MPN account may take
+    pub account_slot_choices: u8,
+    /// Whether emptied token slots of MPN accounts take new tokens
+    pub reuse_empty_token_slots: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -74,6 +186,77 @@ pub struct ZkCompressedStateChange {
     prev_height: u64,
 }
 
+/// Exchange rates (In Ziesha) of the tokens a node accepts as fee. Ziesha is
+/// always accepted, tokens without a rate are not.
+#[derive(Debug, Clone)]
+pub struct FeeRates {
+    rates: HashMap<TokenId, f64>,
+}
+
+impl Default for FeeRates {
+    fn default() -> Self {
+        Self {
+            rates: [(TokenId::Ziesha, 1.0)].into_iter().collect(),
+        }
+    }
+}
+
+impl FeeRates {
+    pub fn with_rate(mut self, token_id: TokenId, rate: f64) -> Self {
+        if token_id != TokenId::Ziesha {
+            self.rates.insert(token_id, rate);
+        }
+        self
+    }
+    pub fn rate(&self, token_id: TokenId) -> Option<f64> {
+        self.rates.get(&token_id).cloned()
+    }
+    /// Value of the given money in Ziesha
+    pub fn value(&self, money: Money) -> Option<f64> {
+        self.rate(money.token_id)
+            .map(|rate| Into::<u64>::into(money.amount) as f64 * rate)
+    }
+}
+
+/// Entries of the miner reward: the block reward plus the Ziesha fees come
+/// first, followed by the fees paid in other tokens.
+pub fn miner_reward_entries<I: IntoIterator<Item = Money>>(
+    dst: Address,
+    reward: Amount,
+    fees: I,
+) -> Result<Vec<RegularSendEntry>, BlockchainError> {
+    let mut ziesha = reward;
+    let mut others: HashMap<TokenId, Amount> = HashMap::new();
+    for fee in fees {
+        let sum = if fee.token_id == TokenId::Ziesha {
+            &mut ziesha
+        } else if fee.amount != Amount(0) {
+            others.entry(fee.token_id).or_default()
+        } else {
+            continue;
+        };
+        *sum = sum
+            .checked_add(fee.amount)
+            .ok_or(BlockchainError::FeesOverflow)?;
+    }
+    let mut others = others.into_iter().collect::<Vec<_>>();
+    others.sort_unstable_by_key(|(token_id, _)| token_id.to_string());
+    Ok(std::iter::once(Money {
+        token_id: TokenId::Ziesha,
+        amount: ziesha,
+    })
+    .chain(
+        others
+            .into_iter()
+            .map(|(token_id, amount)| Money { token_id, amount }),
+    )
+    .map(|amount| RegularSendEntry {
+        dst: dst.clone(),
+        amount,
+    })
+    .collect())
+}
+
 #[derive(Clone)]
 pub struct BlockAndPatch {
     pub block: Block,
@@ -101,9 +284,11 @@ pub trait Blockchain {
         &self,
         mempool: &mut HashMap<MpnSourcedTx, TransactionStats>,
         capacity: usize,
+        fee_rates: &FeeRates,
     ) -> Result<(), BlockchainError>;
 
     fn db_checksum(&self) -> Result<String, BlockchainError>;
+    fn db_size(&self) -> Result<u64, BlockchainError>;
 
     fn get_token(&self, token_id: TokenId) -> Result<Option<Token>, BlockchainError>;
 
@@ -114,7 +299,17 @@ pub trait Blockchain {
         token_id: TokenId,
     ) -> Result<Amount, BlockchainError>;
     fn get_account(&self, addr: Address) -> Result<Account, BlockchainError>;
+    fn get_account_control(&self, addr: Address) -> Result<AccountControl, BlockchainError>;
     fn get_mpn_account(&self, index: u64) -> Result<zk::MpnAccount, BlockchainError>;
+    /// Index of the slot the MPN account of the given address takes (Or would take)
+    fn get_mpn_account_index(&self, address: &MpnAddress) -> Result<u64, BlockchainError>;
+    /// Recent states of an MPN account, newest first, along with the MPN contract
+    /// heights they belong to
+    fn get_mpn_account_history(
+        &self,
+        index: u64,
+        count: u64,
+    ) -> Result<Vec<(u64, zk::MpnAccount)>, BlockchainError>;
     fn get_mpn_accounts(
         &self,
         page: usize,
@@ -125,6 +320,17 @@ pub trait Blockchain {
         &self,
         contract_id: ContractId,
     ) -> Result<ContractAccount, BlockchainError>;
+    /// Admin policy and scheduled upgrade of a contract
+    fn get_contract_control(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<ContractControl, BlockchainError>;
+    /// Events emitted by the updates of a contract in the block at `height`
+    fn get_contract_events(
+        &self,
+        contract_id: ContractId,
+        height: u64,
+    ) -> Result<Vec<ContractEvent>, BlockchainError>;
     fn read_state(
         &self,
         contract_id: ContractId,
@@ -139,11 +345,14 @@ pub trait Blockchain {
     ) -> Result<bool, BlockchainError>;
     fn extend(&mut self, from: u64, blocks: &[Block]) -> Result<(), BlockchainError>;
     fn rollback(&mut self) -> Result<(), BlockchainError>;
+    /// Blocks below this height can't be rolled back anymore
+    fn get_finalized_height(&self) -> Result<u64, BlockchainError>;
     fn draft_block(
         &self,
         timestamp: u32,
         mempool: &[TransactionAndDelta],
         wallet: &TxBuilder,
+        fee_rates: &FeeRates,
         check: bool,
     ) -> Result<Option<BlockAndPatch>, BlockchainError>;
     fn get_height(&self) -> Result<u64, BlockchainError>;
@@ -155,6 +364,7 @@ pub trait Blockchain {
     fn get_power(&self) -> Result<u128, BlockchainError>;
     fn pow_key(&self, index: u64) -> Result<Vec<u8>, BlockchainError>;
 
+    /// Contract with its scheduled upgrade applied, if already in effect
     fn get_contract(&self, contract_id: ContractId) -> Result<zk::ZkContract, BlockchainError>;
 
     fn get_outdated_contracts(&self) -> Result<Vec<ContractId>, BlockchainError>;
@@ -208,43 +418,34 @@ impl<K: KvStore> KvStoreChain<K> {
         Ok((mirror.database.to_ops(), result))
     }
 
-    fn next_difficulty(&self) -> Result<Difficulty, BlockchainError> {
-        let to = self
-            .get_height()?
-            .saturating_sub(self.config.difficulty_lag);
-        let from = to.saturating_sub(self.config.difficulty_window);
-        let headers = (from..to)
-            .map(|i| self.get_header(i))
-            .collect::<Result<Vec<_>, _>>()?;
-        let mut timestamps = headers
-            .iter()
-            .map(|h| h.proof_of_work.timestamp)
-            .collect::<Vec<_>>();
-        if timestamps.len() < 2 {
-            return Ok(self.config.minimum_pow_difficulty);
-        }
-        timestamps.sort_unstable();
-        let final_size = self.config.difficulty_window - 2 * self.config.difficulty_cut;
-        let (begin, end) = if timestamps.len() as u64 > final_size {
-            let begin = (timestamps.len() as u64 - final_size + 1) / 2;
-            let end = begin + final_size - 1;
-            (begin as usize, end as usize)
-        } else {
-            (0, timestamps.len() - 1)
-        };
-        let time_delta = (timestamps[end] - timestamps[begin])
-            .saturating_sub((end - begin) as u32 * self.config.mpn_proving_time as u32);
-        if time_delta == 0 {
-            return Ok(self.config.minimum_pow_difficulty);
+    fn retarget_params(&self) -> RetargetParams {
+        RetargetParams {
+            block_time: self.config.block_time,
+            proving_time: self.config.mpn_proving_time,
+            minimum: self.config.minimum_pow_difficulty,
         }
-        let power_delta =
-            self.get_power_at(from + end as u64)? - self.get_power_at(from + begin as u64)?;
-        Ok(std::cmp::max(
-            Difficulty::from_power(
-                power_delta * (self.config.block_time as u128) / (time_delta as u128),
-            ),
-            self.config.minimum_pow_difficulty,
-        ))
+    }
+
+    /// Blocks needed by the retarget algorithm for the block at `height`
+    fn retarget_history(&self, height: u64) -> Result<Vec<BlockInfo>, BlockchainError> {
+        (height.saturating_sub(self.config.difficulty_algorithm.window())..height)
+            .map(|i| {
+                let header = self.get_header(i)?;
+                Ok(BlockInfo {
+                    timestamp: header.proof_of_work.timestamp,
+                    target: header.proof_of_work.target,
+                    power: self.get_power_at(i)?,
+                })
+            })
+            .collect()
+    }
+
+    fn next_difficulty(&self) -> Result<Difficulty, BlockchainError> {
+        let history = self.retarget_history(self.get_height()?)?;
+        Ok(self
+            .config
+            .difficulty_algorithm
+            .next_difficulty(&history, &self.retarget_params()))
     }
 
     fn get_compressed_state_at(
@@ -272,15 +473,170 @@ impl<K: KvStore> KvStoreChain<K> {
         )
     }
 
+    fn get_account_control_at(
+        &self,
+        addr: &Address,
+        height: u64,
+    ) -> Result<AccountControl, BlockchainError> {
+        Ok(match self.database.get(keys::account_control(addr))? {
+            Some(b) => {
+                let control: AccountControl = b.try_into()?;
+                control.at(height)
+            }
+            None => Default::default(),
+        })
+    }
+
+    /// Index of the MPN account of the key, for the transactions applied at `height`
+    fn locate_mpn_account(
+        &self,
+        pub_key: &<crate::core::ZkSigner as ZkSignatureScheme>::Pub,
+        height: u64,
+    ) -> Result<u64, BlockchainError> {
+        zk::locate_mpn_account(
+            pub_key,
+            self.config.mpn_log4_account_capacity,
+            self.config.mpn_account_slot_choices_at(height),
+            |index| self.get_mpn_account(index),
+        )?
+        .map(|(_, index)| index)
+        .ok_or(BlockchainError::MpnAccountSlotsFull)
+    }
+
+    /// Key which should sign the transactions of the account
+    fn get_signing_key(&self, addr: &Address, height: u64) -> Result<Address, BlockchainError> {
+        Ok(self
+            .get_account_control_at(addr, height)?
+            .key
+            .unwrap_or_else(|| addr.clone()))
+    }
+
+    fn verify_tx_signature(&self, tx: &Transaction, height: u64) -> Result<bool, BlockchainError> {
+        Ok(match &tx.src {
+            Some(src) => tx.verify_signature_with(Some(&self.get_signing_key(src, height)?)),
+            None => true,
+        })
+    }
+
+    /// Records an outflow from an account, checking its daily spending limit
+    fn spend(&mut self, addr: &Address, money: Money, height: u64) -> Result<(), BlockchainError> {
+        let control = self.get_account_control_at(addr, height)?;
+        if let Some(limit) = control
+            .policy
+            .and_then(|policy| policy.daily_limit(money.token_id))
+        {
+            let blocks_per_day =
+                (24 * 60 * 60 / (self.config.block_time + self.config.mpn_proving_time)) as u64;
+            let day = height / blocks_per_day.max(1);
+            let mut spending: DailySpending = match self
+                .database
+                .get(keys::account_spending(addr, money.token_id))?
+            {
+                Some(b) => b.try_into()?,
+                None => Default::default(),
+            };
+            if spending.day != day {
+                spending = DailySpending {
+                    day,
+                    amount: Amount(0),
+                };
+            }
+            let total = spending
+                .amount
+                .0
+                .checked_add(money.amount.0)
+                .filter(|total| *total <= limit.0)
+                .ok_or(BlockchainError::SpendingLimitExceeded)?;
+            spending.amount = Amount(total);
+            self.database.update(&[WriteOp::Put(
+                keys::account_spending(addr, money.token_id),
+                (&spending).into(),
+            )])?;
+        }
+        Ok(())
+    }
+
+    /// Creates the contract, along with its admin policy if it's upgradable
+    fn create_contract(
+        &mut self,
+        tx: &Transaction,
+        contract: &zk::ZkContract,
+        admin: Option<&ContractAdminPolicy>,
+        height: u64,
+    ) -> Result<TxSideEffect, BlockchainError> {
+        if !contract.state_model.is_valid::<CoreZkHasher>() {
+            return Err(BlockchainError::InvalidStateModel);
+        }
+        let governance_vk = match admin.map(|policy| &policy.admin) {
+            Some(ContractAdmin::Governance(func)) => Some(&func.verifier_key),
+            _ => None,
+        };
+        self.config
+            .check_verifier_keys(contract.verifier_keys().chain(governance_vk), height)?;
+        let contract_id = ContractId::new(tx);
+        if let Some(policy) = admin {
+            if !self
+                .config
+                .upgrades
+                .is_active(Upgrade::ContractUpgrades, height)
+            {
+                return Err(BlockchainError::UpgradeNotActive(Upgrade::ContractUpgrades));
+            }
+            if policy.delay == 0 || policy.delay > self.config.max_policy_delay {
+                return Err(BlockchainError::InvalidContractAdminPolicy);
+            }
+            self.database.update(&[WriteOp::Put(
+                keys::contract_control(&contract_id),
+                (&ContractControl {
+                    policy: Some(policy.clone()),
+                    pending_upgrade: None,
+                })
+                    .into(),
+            )])?;
+        }
+        self.database.update(&[WriteOp::Put(
+            keys::contract(&contract_id),
+            contract.clone().into(),
+        )])?;
+        let compressed_empty =
+            zk::ZkCompressedState::empty::<CoreZkHasher>(contract.state_model.clone());
+        self.database.update(&[WriteOp::Put(
+            keys::contract_account(&contract_id),
+            ContractAccount {
+                compressed_state: contract.initial_state,
+                height: 1,
+            }
+            .into(),
+        )])?;
+        self.database.update(&[WriteOp::Put(
+            keys::compressed_state_at(&contract_id, 1),
+            contract.initial_state.into(),
+        )])?;
+        Ok(TxSideEffect::StateChange {
+            contract_id,
+            state_change: ZkCompressedStateChange {
+                prev_height: 0,
+                prev_state: compressed_empty,
+                state: contract.initial_state,
+            },
+        })
+    }
+
     fn apply_deposit(&mut self, deposit: &ContractDeposit) -> Result<(), BlockchainError> {
         let (ops, _) = self.isolated(|chain| {
-            if !deposit.verify_signature() {
+            let height = chain.get_height()?;
+            if !deposit.verify_signature_with(&chain.get_signing_key(&deposit.src, height)?) {
                 return Err(BlockchainError::InvalidContractPaymentSignature);
             }
+            chain.spend(&deposit.src, deposit.amount, height)?;
+            chain.spend(&deposit.src, deposit.fee, height)?;
 
             let mut addr_account = chain.get_account(deposit.src.clone())?;
             if deposit.nonce != addr_account.nonce + 1 {
-                return Err(BlockchainError::InvalidTransactionNonce);
+                return Err(BlockchainError::InvalidTransactionNonce {
+                    expected: addr_account.nonce + 1,
+                    got: deposit.nonce,
+                });
             }
             addr_account.nonce += 1;
             chain.database.update(&[WriteOp::Put(
@@ -340,8 +696,8 @@ impl<K: KvStore> KvStoreChain<K> {
     fn apply_mpn_deposit(&mut self, deposit: &MpnDeposit) -> Result<(), BlockchainError> {
         let (ops, _) = self.isolated(|chain| {
             chain.apply_deposit(&deposit.payment)?;
-            let dst = chain
-                .get_mpn_account(deposit.zk_address_index(self.config.mpn_log4_account_capacity))?;
+            let dst_index = chain.locate_mpn_account(&deposit.zk_address, chain.get_height()?)?;
+            let dst = chain.get_mpn_account(dst_index)?;
 
             let calldata = CoreZkHasher::hash(&[
                 deposit.zk_address.decompress().0,
@@ -349,16 +705,22 @@ impl<K: KvStore> KvStoreChain<K> {
             ]);
             if deposit.payment.calldata != calldata
                 || deposit.zk_token_index >= 64
-                || deposit.zk_address_index(self.config.mpn_log4_account_capacity) > 0x3FFFFFFF
+                || dst_index > 0x3FFFFFFF
                 || !deposit.zk_address.is_on_curve()
                 || (dst.address.is_on_curve() && dst.address != deposit.zk_address.decompress())
             {
                 return Err(BlockchainError::InvalidMpnTransaction);
             }
-            if let Some(money) = dst.tokens.get(&deposit.zk_token_index) {
-                if money.token_id != deposit.payment.amount.token_id {
-                    return Err(BlockchainError::InvalidMpnTransaction);
-                }
+            let reuse_empty = chain
+                .config
+                .mpn_circuit_features_at(chain.get_height()?)
+                .reuse_empty_token_slots;
+            if !dst.token_slot_accepts(
+                deposit.zk_token_index,
+                deposit.payment.amount.token_id,
+                reuse_empty,
+            ) {
+                return Err(BlockchainError::InvalidMpnTransaction);
             }
             // TODO: Check overflow
             let mut size_diff = 0;
@@ -367,16 +729,20 @@ impl<K: KvStore> KvStoreChain<K> {
                 nonce: dst.nonce,
                 tokens: dst.tokens,
             };
-            new_acc
+            let money = new_acc
                 .tokens
                 .entry(deposit.zk_token_index)
-                .or_insert_with(|| Money::new(deposit.payment.amount.token_id, 0))
-                .amount += deposit.payment.amount.amount;
+                .or_insert_with(|| Money::new(deposit.payment.amount.token_id, 0));
+            if money.token_id != deposit.payment.amount.token_id {
+                // Reusing a slot with no balance left
+                *money = Money::new(deposit.payment.amount.token_id, 0);
+            }
+            money.amount += deposit.payment.amount.amount;
 
             zk::KvStoreStateManager::<CoreZkHasher>::set_mpn_account(
                 &mut chain.database,
                 chain.config.mpn_contract_id,
-                deposit.zk_address_index(self.config.mpn_log4_account_capacity),
+                dst_index,
                 new_acc,
                 &mut size_diff,
             )?;
@@ -389,9 +755,8 @@ impl<K: KvStore> KvStoreChain<K> {
     fn apply_mpn_withdraw(&mut self, withdraw: &MpnWithdraw) -> Result<(), BlockchainError> {
         let (ops, _) = self.isolated(|chain| {
             chain.apply_withdraw(&withdraw.payment)?;
-            let src = chain.get_mpn_account(
-                withdraw.zk_address_index(self.config.mpn_log4_account_capacity),
-            )?;
+            let src_index = chain.locate_mpn_account(&withdraw.zk_address, chain.get_height()?)?;
+            let src = chain.get_mpn_account(src_index)?;
             let calldata = CoreZkHasher::hash(&[
                 withdraw.zk_address.decompress().0,
                 withdraw.zk_address.decompress().1,
@@ -404,7 +769,7 @@ impl<K: KvStore> KvStoreChain<K> {
                 withdraw.payment.fingerprint(),
                 zk::ZkScalar::from(withdraw.zk_nonce as u64),
             ]);
-            if withdraw.zk_address_index(self.config.mpn_log4_account_capacity) > 0x3FFFFFFF
+            if src_index > 0x3FFFFFFF
                 || withdraw.zk_token_index >= 64
                 || withdraw.zk_nonce != src.nonce
                 || withdraw.payment.calldata != calldata
@@ -445,7 +810,7 @@ impl<K: KvStore> KvStoreChain<K> {
             zk::KvStoreStateManager::<CoreZkHasher>::set_mpn_account(
                 &mut chain.database,
                 chain.config.mpn_contract_id,
-                withdraw.zk_address_index(self.config.mpn_log4_account_capacity),
+                src_index,
                 new_acc,
                 &mut size_diff,
             )?;
@@ -510,12 +875,18 @@ impl<K: KvStore> KvStoreChain<K> {
     // WARN: Will not check sig!
     fn apply_zero_tx(&mut self, tx: &zk::MpnTransaction) -> Result<(), BlockchainError> {
         let (ops, _) = self.isolated(|chain| {
-            let src = chain.get_mpn_account(tx.src_index(self.config.mpn_log4_account_capacity))?;
-            let dst = chain.get_mpn_account(tx.dst_index(self.config.mpn_log4_account_capacity))?;
-            if tx.src_index(self.config.mpn_log4_account_capacity) > 0x3FFFFFFF
-                || tx.dst_index(self.config.mpn_log4_account_capacity) > 0x3FFFFFFF
-                || tx.src_index(self.config.mpn_log4_account_capacity)
-                    == tx.dst_index(self.config.mpn_log4_account_capacity)
+            let height = chain.get_height()?;
+            let reuse_empty = chain
+                .config
+                .mpn_circuit_features_at(height)
+                .reuse_empty_token_slots;
+            let src_index = chain.locate_mpn_account(&tx.src_pub_key, height)?;
+            let dst_index = chain.locate_mpn_account(&tx.dst_pub_key, height)?;
+            let src = chain.get_mpn_account(src_index)?;
+            let dst = chain.get_mpn_account(dst_index)?;
+            if src_index > 0x3FFFFFFF
+                || dst_index > 0x3FFFFFFF
+                || src_index == dst_index
                 || !tx.src_pub_key.is_on_curve()
                 || !tx.dst_pub_key.is_on_curve()
                 || src.address != tx.src_pub_key.decompress()
@@ -542,6 +913,12 @@ impl<K: KvStore> KvStoreChain<K> {
                     .tokens
                     .entry(tx.dst_token_index)
                     .or_insert_with(|| Money::new(src_money.token_id, 0));
+                if dst_money.token_id != src_money.token_id
+                    && dst.token_slot_accepts(tx.dst_token_index, src_money.token_id, reuse_empty)
+                {
+                    // Reusing a slot with no balance left
+                    *dst_money = Money::new(src_money.token_id, 0);
+                }
                 if src_money.token_id == dst_money.token_id
                     && src_money.token_id == tx.amount.token_id
                 {
@@ -570,14 +947,14 @@ impl<K: KvStore> KvStoreChain<K> {
             zk::KvStoreStateManager::<CoreZkHasher>::set_mpn_account(
                 &mut chain.database,
                 chain.config.mpn_contract_id,
-                tx.src_index(self.config.mpn_log4_account_capacity),
+                src_index,
                 new_src_acc,
                 &mut size_diff,
             )?;
             zk::KvStoreStateManager::<CoreZkHasher>::set_mpn_account(
                 &mut chain.database,
                 chain.config.mpn_contract_id,
-                tx.dst_index(self.config.mpn_log4_account_capacity),
+                dst_index,
                 new_dst_acc,
                 &mut size_diff,
             )?;
@@ -591,6 +968,17 @@ impl<K: KvStore> KvStoreChain<K> {
         &mut self,
         tx: &Transaction,
         allow_treasury: bool,
+    ) -> Result<TxSideEffect, BlockchainError> {
+        self.apply_tx_batched(tx, allow_treasury, None)
+    }
+
+    /// Applies the transaction, queueing its contract update proofs in `proofs`
+    /// instead of verifying them right away, if given
+    fn apply_tx_batched(
+        &mut self,
+        tx: &Transaction,
+        allow_treasury: bool,
+        mut proofs: Option<&mut zk::ZkProofBatch>,
     ) -> Result<TxSideEffect, BlockchainError> {
         let (ops, side_effect) = self.isolated(|chain| {
             let mut side_effect = TxSideEffect::Nothing;
@@ -599,21 +987,46 @@ impl<K: KvStore> KvStoreChain<K> {
                 return Err(BlockchainError::IllegalTreasuryAccess);
             }
 
-            if tx.fee.token_id != TokenId::Ziesha {
-                return Err(BlockchainError::OnlyZieshaFeesAccepted);
-            }
-
             if tx.memo.len() > self.config.max_memo_length {
                 return Err(BlockchainError::MemoTooLong);
             }
 
+            let height = chain.get_height()?;
+
+            if tx.fee.token_id != TokenId::Ziesha
+                && !self
+                    .config
+                    .upgrades
+                    .is_active(Upgrade::NonZieshaFees, height)
+            {
+                return Err(BlockchainError::UpgradeNotActive(Upgrade::NonZieshaFees));
+            }
+
+            if tx.valid_after_height.is_some() || tx.valid_until_height.is_some() {
+                if !self
+                    .config
+                    .upgrades
+                    .is_active(Upgrade::TransactionValidityWindow, height)
+                {
+                    return Err(BlockchainError::UpgradeNotActive(
+                        Upgrade::TransactionValidityWindow,
+                    ));
+                }
+                if tx.is_expired_at(height) {
+                    return Err(BlockchainError::TransactionExpired);
+                }
+            }
+
             let tx_src = tx.src.clone().unwrap_or_default(); // Default is treasury account!
 
             let mut acc_src = chain.get_account(tx_src.clone())?;
             let mut acc_bal = chain.get_balance(tx_src.clone(), tx.fee.token_id)?;
 
             if tx.nonce != acc_src.nonce + 1 {
-                return Err(BlockchainError::InvalidTransactionNonce);
+                return Err(BlockchainError::InvalidTransactionNonce {
+                    expected: acc_src.nonce + 1,
+                    got: tx.nonce,
+                });
             }
 
             if acc_bal < tx.fee.amount {
@@ -622,6 +1035,7 @@ impl<K: KvStore> KvStoreChain<K> {
 
             acc_bal -= tx.fee.amount;
             acc_src.nonce += 1;
+            chain.spend(&tx_src, tx.fee, height)?;
 
             chain
                 .database
@@ -632,6 +1046,81 @@ impl<K: KvStore> KvStoreChain<K> {
             )])?;
 
             match &tx.data {
+                TransactionData::UpgradeContract {
+                    contract_id,
+                    upgrade,
+                    proof,
+                } => {
+                    if !self
+                        .config
+                        .upgrades
+                        .is_active(Upgrade::ContractUpgrades, height)
+                    {
+                        return Err(BlockchainError::UpgradeNotActive(Upgrade::ContractUpgrades));
+                    }
+                    self.config
+                        .check_verifier_keys(upgrade.verifier_keys(), height)?;
+                    let mut contract = chain.get_contract(*contract_id)?;
+                    let mut control = chain.get_contract_control(*contract_id)?;
+                    let policy = control
+                        .policy
+                        .clone()
+                        .ok_or(BlockchainError::ContractNotUpgradable)?;
+                    match &policy.admin {
+                        ContractAdmin::Key(key) => {
+                            if *key != tx_src {
+                                return Err(BlockchainError::ContractUpgradePermissionDenied);
+                            }
+                        }
+                        ContractAdmin::Governance(func) => {
+                            // The circuit decides on the upgrade without changing the state
+                            let account = chain.get_contract_account(*contract_id)?;
+                            let commitment =
+                                zk::ZkCompressedState::new(upgrade.commitment::<Hasher>(), 0);
+                            let proof = proof
+                                .as_ref()
+                                .ok_or(BlockchainError::ContractUpgradePermissionDenied)?;
+                            if !zk::check_proof(
+                                self.config.plonk_srs.as_ref(),
+                                &func.verifier_key,
+                                account.height,
+                                &account.compressed_state,
+                                &commitment,
+                                &account.compressed_state,
+                                proof,
+                            ) {
+                                return Err(BlockchainError::IncorrectZkProof);
+                            }
+                        }
+                    }
+
+                    // An upgrade already in effect is persisted, a scheduled one is replaced
+                    if control.take_active_upgrade(height).is_some() {
+                        chain.database.update(&[WriteOp::Put(
+                            keys::contract(contract_id),
+                            contract.clone().into(),
+                        )])?;
+                    }
+                    // Upgrades changing nothing (E.g. a replayed governance upgrade
+                    // which is already in effect) are rejected
+                    let prev_contract = contract.clone();
+                    if !upgrade.apply(&mut contract) || contract == prev_contract {
+                        return Err(BlockchainError::InvalidContractUpgrade);
+                    }
+                    // Governance proofs aren't bound to their sender, resubmitting
+                    // the scheduled upgrade shouldn't postpone it
+                    let activation = match &control.pending_upgrade {
+                        Some((activation, pending)) if pending == upgrade => *activation,
+                        _ => height
+                            .checked_add(policy.delay)
+                            .ok_or(BlockchainError::InvalidContractAdminPolicy)?,
+                    };
+                    control.pending_upgrade = Some((activation, upgrade.clone()));
+                    chain.database.update(&[WriteOp::Put(
+                        keys::contract_control(contract_id),
+                        (&control).into(),
+                    )])?;
+                }
                 TransactionData::CreateToken { token } => {
                     let token_id = {
                         let tid = TokenId::new(tx);
@@ -644,7 +1133,7 @@ impl<K: KvStore> KvStoreChain<K> {
                     if chain.get_token(token_id)?.is_some() {
                         return Err(BlockchainError::TokenAlreadyExists);
                     } else {
-                        if !token.validate() {
+                        if !token.validate(&self.config.upgrades, height) {
                             return Err(BlockchainError::TokenBadNameSymbol);
                         }
                         chain.database.update(&[WriteOp::Put(
@@ -701,6 +1190,7 @@ impl<K: KvStore> KvStoreChain<K> {
                 TransactionData::RegularSend { entries } => {
                     for entry in entries {
                         if entry.dst != tx_src {
+                            chain.spend(&tx_src, entry.amount, height)?;
                             let mut src_bal =
                                 chain.get_balance(tx_src.clone(), entry.amount.token_id)?;
 
@@ -724,37 +1214,99 @@ impl<K: KvStore> KvStoreChain<K> {
                         }
                     }
                 }
-                TransactionData::CreateContract { contract } => {
-                    if !contract.state_model.is_valid::<CoreZkHasher>() {
-                        return Err(BlockchainError::InvalidStateModel);
+                TransactionData::SetAccountPolicy { policy } => {
+                    if !self
+                        .config
+                        .upgrades
+                        .is_active(Upgrade::AccountPolicies, height)
+                    {
+                        return Err(BlockchainError::UpgradeNotActive(Upgrade::AccountPolicies));
+                    }
+                    // Key rotations should never take effect in the block
+                    // they are sent in
+                    if policy
+                        .as_ref()
+                        .map(|p| p.delay == 0 || p.delay > self.config.max_policy_delay)
+                        .unwrap_or(false)
+                    {
+                        return Err(BlockchainError::InvalidAccountPolicy);
+                    }
+                    let mut control = chain.get_account_control_at(&tx_src, height)?;
+                    if let Some(curr) = &control.policy {
+                        // Give the recovery key some time to react
+                        let activation = height
+                            .checked_add(curr.delay)
+                            .ok_or(BlockchainError::InvalidAccountPolicy)?;
+                        control.pending_policy = Some((activation, policy.clone()));
+                    } else {
+                        control.policy = policy.clone();
                     }
-                    let contract_id = ContractId::new(tx);
                     chain.database.update(&[WriteOp::Put(
-                        keys::contract(&contract_id),
-                        contract.clone().into(),
+                        keys::account_control(&tx_src),
+                        (&control).into(),
                     )])?;
-                    let compressed_empty =
-                        zk::ZkCompressedState::empty::<CoreZkHasher>(contract.state_model.clone());
-                    chain.database.update(&[WriteOp::Put(
-                        keys::contract_account(&contract_id),
-                        ContractAccount {
-                            compressed_state: contract.initial_state,
-                            height: 1,
+                }
+                TransactionData::RecoverAccount { account, key } => {
+                    if !self
+                        .config
+                        .upgrades
+                        .is_active(Upgrade::AccountPolicies, height)
+                    {
+                        return Err(BlockchainError::UpgradeNotActive(Upgrade::AccountPolicies));
+                    }
+                    let mut control = chain.get_account_control_at(account, height)?;
+                    let delay = match &control.policy {
+                        Some(policy) if policy.recovery_key.as_ref() == Some(&tx_src) => {
+                            policy.delay
+                        }
+                        _ => {
+                            return Err(BlockchainError::AccountNotRecoverable);
                         }
-                        .into(),
+                    };
+                    let activation = height
+                        .checked_add(delay)
+                        .ok_or(BlockchainError::InvalidAccountPolicy)?;
+                    // Policy changes made by the old key are cancelled
+                    control.pending_policy = None;
+                    control.pending_key = Some((activation, key.clone()));
+                    chain.database.update(&[WriteOp::Put(
+                        keys::account_control(account),
+                        (&control).into(),
                     )])?;
+                }
+                TransactionData::CancelRecovery { account } => {
+                    if !self
+                        .config
+                        .upgrades
+                        .is_active(Upgrade::AccountPolicies, height)
+                    {
+                        return Err(BlockchainError::UpgradeNotActive(Upgrade::AccountPolicies));
+                    }
+                    let mut control = chain.get_account_control_at(account, height)?;
+                    if !control
+                        .policy
+                        .as_ref()
+                        .map(|policy| policy.recovery_key.as_ref() == Some(&tx_src))
+                        .unwrap_or(false)
+                    {
+                        return Err(BlockchainError::AccountNotRecoverable);
+                    }
+                    if control.pending_key.take().is_none() {
+                        return Err(BlockchainError::NoPendingRecovery);
+                    }
                     chain.database.update(&[WriteOp::Put(
-                        keys::compressed_state_at(&contract_id, 1),
-                        contract.initial_state.into(),
+                        keys::account_control(account),
+                        (&control).into(),
                     )])?;
-                    side_effect = TxSideEffect::StateChange {
-                        contract_id,
-                        state_change: ZkCompressedStateChange {
-                            prev_height: 0,
-                            prev_state: compressed_empty,
-                            state: contract.initial_state,
-                        },
-                    };
+                }
+                TransactionData::ValidityWindow { .. } => {
+                    return Err(BlockchainError::NestedValidityWindow);
+                }
+                TransactionData::CreateContract { contract } => {
+                    side_effect = chain.create_contract(tx, contract, None, height)?;
+                }
+                TransactionData::CreateUpgradableContract { contract, admin } => {
+                    side_effect = chain.create_contract(tx, contract, Some(admin), height)?;
                 }
                 TransactionData::UpdateContract {
                     contract_id,
@@ -773,33 +1325,41 @@ impl<K: KvStore> KvStoreChain<K> {
                         cont_account.into(),
                     )])?;
 
-                    for update in updates {
+                    let mut events = Vec::new();
+                    for (update_index, update) in updates.iter().enumerate() {
+                        if !update.events().is_empty() {
+                            if !self
+                                .config
+                                .upgrades
+                                .is_active(Upgrade::ContractEvents, height)
+                            {
+                                return Err(BlockchainError::UpgradeNotActive(
+                                    Upgrade::ContractEvents,
+                                ));
+                            }
+                            if update.events().len() > self.config.max_contract_events {
+                                return Err(BlockchainError::TooManyContractEvents);
+                            }
+                            events.push(ContractEvent {
+                                tx_hash: tx.hash(),
+                                update_index: update_index as u32,
+                                data: update.events().to_vec(),
+                            });
+                        }
                         let (circuit, aux_data, next_state, proof) = match update {
                             ContractUpdate::Deposit {
                                 deposit_circuit_id,
                                 deposits,
                                 next_state,
                                 proof,
+                                ..
                             } => {
                                 let deposit_func = contract
                                     .deposit_functions
                                     .get(*deposit_circuit_id as usize)
                                     .ok_or(BlockchainError::ContractFunctionNotFound)?;
                                 let circuit = &deposit_func.verifier_key;
-                                let state_model = zk::ZkStateModel::List {
-                                    item_type: Box::new(zk::ZkStateModel::Struct {
-                                        field_types: vec![
-                                            zk::ZkStateModel::Scalar, // Enabled
-                                            zk::ZkStateModel::Scalar, // Token-id
-                                            zk::ZkStateModel::Scalar, // Amount
-                                            zk::ZkStateModel::Scalar, // Calldata
-                                        ],
-                                    }),
-                                    log4_size: deposit_func.log4_payment_capacity,
-                                };
-                                let mut state_builder =
-                                    zk::ZkStateBuilder::<CoreZkHasher>::new(state_model);
-                                for (i, deposit) in deposits.iter().enumerate() {
+                                for deposit in deposits.iter() {
                                     if deposit.contract_id != *contract_id
                                         || deposit.deposit_circuit_id != *deposit_circuit_id
                                     {
@@ -811,31 +1371,12 @@ impl<K: KvStore> KvStoreChain<K> {
                                         return Err(BlockchainError::CannotExecuteOwnPayments);
                                     }
                                     executor_fees.push(deposit.fee);
-                                    state_builder.batch_set(&zk::ZkDeltaPairs(
-                                        [
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 0]),
-                                                Some(zk::ZkScalar::from(1)),
-                                            ),
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 1]),
-                                                Some(deposit.amount.token_id.into()),
-                                            ),
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 2]),
-                                                Some(zk::ZkScalar::from(deposit.amount.amount)),
-                                            ),
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 3]),
-                                                Some(deposit.calldata),
-                                            ),
-                                        ]
-                                        .into(),
-                                    ))?;
-
                                     chain.apply_deposit(deposit)?;
                                 }
-                                let aux_data = state_builder.compress()?;
+                                let aux_data = zk::deposits_aux_data(
+                                    deposit_func.log4_payment_capacity,
+                                    deposits,
+                                )?;
                                 (circuit, aux_data, next_state, proof)
                             }
                             ContractUpdate::Withdraw {
@@ -843,29 +1384,14 @@ impl<K: KvStore> KvStoreChain<K> {
                                 withdraws,
                                 next_state,
                                 proof,
+                                ..
                             } => {
                                 let withdraw_func = contract
                                     .withdraw_functions
                                     .get(*withdraw_circuit_id as usize)
                                     .ok_or(BlockchainError::ContractFunctionNotFound)?;
                                 let circuit = &withdraw_func.verifier_key;
-                                let state_model = zk::ZkStateModel::List {
-                                    item_type: Box::new(zk::ZkStateModel::Struct {
-                                        field_types: vec![
-                                            zk::ZkStateModel::Scalar, // Enabled
-                                            zk::ZkStateModel::Scalar, // Amount token-id
-                                            zk::ZkStateModel::Scalar, // Amount
-                                            zk::ZkStateModel::Scalar, // Fee token-id
-                                            zk::ZkStateModel::Scalar, // Fee
-                                            zk::ZkStateModel::Scalar, // Fingerprint
-                                            zk::ZkStateModel::Scalar, // Calldata
-                                        ],
-                                    }),
-                                    log4_size: withdraw_func.log4_payment_capacity,
-                                };
-                                let mut state_builder =
-                                    zk::ZkStateBuilder::<CoreZkHasher>::new(state_model);
-                                for (i, withdraw) in withdraws.iter().enumerate() {
+                                for withdraw in withdraws.iter() {
                                     if withdraw.contract_id != *contract_id
                                         || withdraw.withdraw_circuit_id != *withdraw_circuit_id
                                     {
@@ -873,48 +1399,16 @@ impl<K: KvStore> KvStoreChain<K> {
                                             BlockchainError::DepositWithdrawPassedToWrongFunction,
                                         );
                                     }
-                                    let fingerprint = withdraw.fingerprint();
                                     if withdraw.dst.clone() == tx_src {
                                         return Err(BlockchainError::CannotExecuteOwnPayments);
                                     }
                                     executor_fees.push(withdraw.fee);
-                                    state_builder.batch_set(&zk::ZkDeltaPairs(
-                                        [
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 0]),
-                                                Some(zk::ZkScalar::from(1)),
-                                            ),
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 1]),
-                                                Some(withdraw.amount.token_id.into()),
-                                            ),
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 2]),
-                                                Some(zk::ZkScalar::from(withdraw.amount.amount)),
-                                            ),
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 3]),
-                                                Some(withdraw.fee.token_id.into()),
-                                            ),
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 4]),
-                                                Some(zk::ZkScalar::from(withdraw.fee.amount)),
-                                            ),
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 5]),
-                                                Some(fingerprint),
-                                            ),
-                                            (
-                                                zk::ZkDataLocator(vec![i as u64, 6]),
-                                                Some(withdraw.calldata),
-                                            ),
-                                        ]
-                                        .into(),
-                                    ))?;
-
                                     chain.apply_withdraw(withdraw)?;
                                 }
-                                let aux_data = state_builder.compress()?;
+                                let aux_data = zk::withdraws_aux_data(
+                                    withdraw_func.log4_payment_capacity,
+                                    withdraws,
+                                )?;
                                 (circuit, aux_data, next_state, proof)
                             }
                             ContractUpdate::FunctionCall {
@@ -922,6 +1416,7 @@ impl<K: KvStore> KvStoreChain<K> {
                                 next_state,
                                 proof,
                                 fee,
+                                ..
                             } => {
                                 executor_fees.push(*fee);
 
@@ -941,31 +1436,24 @@ impl<K: KvStore> KvStoreChain<K> {
                                     .get(*function_id as usize)
                                     .ok_or(BlockchainError::ContractFunctionNotFound)?;
                                 let circuit = &func.verifier_key;
-                                let mut state_builder = zk::ZkStateBuilder::<CoreZkHasher>::new(
-                                    zk::ZkStateModel::Struct {
-                                        field_types: vec![
-                                            zk::ZkStateModel::Scalar, // Token-id
-                                            zk::ZkStateModel::Scalar, // Total fee
-                                        ],
-                                    },
-                                );
-                                state_builder.batch_set(&zk::ZkDeltaPairs(
-                                    [
-                                        (zk::ZkDataLocator(vec![0]), Some(fee.token_id.into())),
-                                        (
-                                            zk::ZkDataLocator(vec![1]),
-                                            Some(zk::ZkScalar::from(fee.amount)),
-                                        ),
-                                    ]
-                                    .into(),
-                                ))?;
-                                let aux_data = state_builder.compress()?;
+                                let aux_data = zk::function_call_aux_data(*fee)?;
                                 (circuit, aux_data, next_state, proof)
                             }
                         };
 
+                        let aux_data = aux_data.with_events::<CoreZkHasher>(update.events());
                         let mut cont_account = chain.get_contract_account(*contract_id)?;
-                        if !zk::check_proof(
+                        if let Some(proofs) = proofs.as_mut() {
+                            proofs.queue(
+                                circuit,
+                                prev_account.height,
+                                &cont_account.compressed_state,
+                                &aux_data,
+                                next_state,
+                                proof,
+                            );
+                        } else if !zk::check_proof(
+                            self.config.plonk_srs.as_ref(),
                             circuit,
                             prev_account.height,
                             &cont_account.compressed_state,
@@ -991,6 +1479,16 @@ impl<K: KvStore> KvStoreChain<K> {
                         )])?;
                     }
 
+                    if !events.is_empty() {
+                        // Stored in the block being applied
+                        let mut block_events = chain.get_contract_events(*contract_id, height)?;
+                        block_events.extend(events);
+                        chain.database.update(&[WriteOp::Put(
+                            keys::contract_events(contract_id, height),
+                            (&block_events).into(),
+                        )])?;
+                    }
+
                     let cont_account = chain.get_contract_account(*contract_id)?;
 
                     chain.database.update(&[WriteOp::Put(
@@ -1019,6 +1517,12 @@ impl<K: KvStore> KvStoreChain<K> {
                 )])?;
             }
 
+            // Checked once everything else is, so that only the scheduled
+            // transactions which would otherwise apply are kept in the mempool
+            if tx.is_scheduled_at(height) {
+                return Err(BlockchainError::TransactionNotYetValid);
+            }
+
             Ok(side_effect)
         })?;
 
@@ -1039,27 +1543,29 @@ impl<K: KvStore> KvStoreChain<K> {
     fn select_transactions(
         &self,
         txs: &[TransactionAndDelta],
+        fee_rates: &FeeRates,
         check: bool,
     ) -> Result<Vec<TransactionAndDelta>, BlockchainError> {
         let mut sorted = txs
             .iter()
-            .filter(|t| t.tx.fee.token_id == TokenId::Ziesha)
-            .cloned()
+            .filter_map(|t| {
+                let value = fee_rates.value(t.tx.fee)?;
+                let is_mpn = if let TransactionData::UpdateContract { contract_id, .. } = &t.tx.data
+                {
+                    *contract_id == self.config.mpn_contract_id
+                } else {
+                    false
+                };
+                Some((is_mpn, value / t.tx.size() as f64, t.clone()))
+            })
             .collect::<Vec<_>>();
-        // WARN: Sort will be invalid if not all fees are specified in Ziesha
-        sorted.sort_unstable_by_key(|tx| {
-            let cost = tx.tx.size();
-            let is_mpn = if let TransactionData::UpdateContract { contract_id, .. } = &tx.tx.data {
-                *contract_id == self.config.mpn_contract_id
-            } else {
-                false
-            };
-            (
-                is_mpn,
-                Into::<u64>::into(tx.tx.fee.amount) / cost as u64,
-                -(tx.tx.nonce as i32),
-            )
+        sorted.sort_unstable_by(|(a_mpn, a_value, a), (b_mpn, b_value, b)| {
+            a_mpn
+                .cmp(b_mpn)
+                .then(a_value.total_cmp(b_value))
+                .then(b.tx.nonce.cmp(&a.tx.nonce))
         });
+        let sorted = sorted.into_iter().map(|(_, _, tx)| tx).collect::<Vec<_>>();
         if !check {
             return Ok(sorted);
         }
@@ -1077,7 +1583,7 @@ impl<K: KvStore> KvStoreChain<K> {
                     let block_diff = tx.tx.size();
                     if delta_cnt + delta_diff <= chain.config.max_delta_count as isize
                         && block_sz + block_diff <= chain.config.max_block_size
-                        && tx.tx.verify_signature()
+                        && chain.verify_tx_signature(&tx.tx, chain.get_height()?)?
                     {
                         delta_cnt += delta_diff;
                         block_sz += block_diff;
@@ -1091,6 +1597,97 @@ impl<K: KvStore> KvStoreChain<K> {
         Ok(result)
     }
 
+    fn rollback_block(&mut self) -> Result<(), BlockchainError> {
+        let (ops, _) = self.isolated(|chain| {
+            let height = chain.get_height()?;
+
+            if height == 0 {
+                return Err(BlockchainError::NoBlocksToRollback);
+            }
+
+            if height <= chain.get_finalized_height()? {
+                return Err(BlockchainError::BeyondFinality);
+            }
+
+            let rollback: Vec<WriteOp> = match chain.database.get(keys::rollback(height - 1))? {
+                Some(b) => b.try_into()?,
+                None => {
+                    return Err(BlockchainError::Inconsistency);
+                }
+            };
+
+            let mut outdated = chain.get_outdated_contracts()?;
+            let changed_states = chain.get_changed_states()?;
+
+            for (cid, comp) in changed_states {
+                if comp.prev_height == 0 {
+                    zk::KvStoreStateManager::<CoreZkHasher>::delete_contract(
+                        &mut chain.database,
+                        cid,
+                    )?;
+                    outdated.retain(|&x| x != cid);
+                    continue;
+                }
+
+                if !outdated.contains(&cid) {
+                    let (ops, result) = chain.isolated(|fork| {
+                        Ok(zk::KvStoreStateManager::<CoreZkHasher>::rollback_contract(
+                            &mut fork.database,
+                            cid,
+                        )?)
+                    })?;
+
+                    if result != Some(comp.prev_state) {
+                        outdated.push(cid);
+                    } else {
+                        chain.database.update(&ops)?;
+                    }
+                } else {
+                    let local_compressed_state =
+                        zk::KvStoreStateManager::<CoreZkHasher>::root(&chain.database, cid)?;
+                    let local_height =
+                        zk::KvStoreStateManager::<CoreZkHasher>::height_of(&chain.database, cid)?;
+                    if local_compressed_state == comp.prev_state && local_height == comp.prev_height
+                    {
+                        outdated.retain(|&x| x != cid);
+                    }
+                }
+            }
+
+            chain.database.update(&rollback)?;
+            chain.database.update(&[
+                WriteOp::Remove(keys::rollback(height - 1)),
+                if outdated.is_empty() {
+                    WriteOp::Remove(keys::outdated())
+                } else {
+                    WriteOp::Put(keys::outdated(), outdated.clone().into())
+                },
+            ])?;
+
+            Ok(())
+        })?;
+        self.database.update(&ops)?;
+        Ok(())
+    }
+
+    /// Switches the MPN contract to the verifier keys of the upgrades of the MPN
+    /// circuits which take effect at the given height
+    fn rotate_mpn_keys(&mut self, height: u64) -> Result<(), BlockchainError> {
+        let contract_id = self.config.mpn_contract_id;
+        for (upgrade, mpn_keys) in self.config.mpn_circuit_upgrades.iter() {
+            if self.config.upgrades.activation_height(*upgrade) != Some(height) {
+                continue;
+            }
+            let mut contract = self.get_contract(contract_id)?;
+            if !mpn_keys.apply(&mut contract) {
+                return Err(BlockchainError::InvalidContractUpgrade);
+            }
+            self.database
+                .update(&[WriteOp::Put(keys::contract(&contract_id), contract.into())])?;
+        }
+        Ok(())
+    }
+
     fn apply_block(&mut self, block: &Block, check_pow: bool) -> Result<(), BlockchainError> {
         let (ops, _) = self.isolated(|chain| {
             let curr_height = chain.get_height()?;
@@ -1113,15 +1710,7 @@ impl<K: KvStore> KvStoreChain<K> {
             }
 
             // All blocks except genesis block should have a miner reward
-            let txs = if !is_genesis {
-                // WARN: Sum will be invalid if fees are not in Ziesha
-                let fee_sum = Amount(
-                    block.body[1..]
-                        .iter()
-                        .map(|t| -> u64 { t.fee.amount.into() })
-                        .sum(),
-                );
-
+            let (reward_tx, txs) = if !is_genesis {
                 let reward_tx = block
                     .body
                     .first()
@@ -1135,20 +1724,22 @@ impl<K: KvStore> KvStoreChain<K> {
                 }
                 match &reward_tx.data {
                     TransactionData::RegularSend { entries } => {
-                        if entries.len() != 1 {
-                            return Err(BlockchainError::InvalidMinerReward);
-                        }
-                        let reward_entry = &entries[0];
+                        let miner = entries
+                            .first()
+                            .ok_or(BlockchainError::InvalidMinerReward)?
+                            .dst
+                            .clone();
                         if let Some(allowed_miners) = &self.config.limited_miners {
-                            if !allowed_miners.contains(&reward_entry.dst) {
+                            if !allowed_miners.contains(&miner) {
                                 return Err(BlockchainError::AddressNotAllowedToMine);
                             }
                         }
-                        let expected_reward = Money {
-                            amount: next_reward + fee_sum,
-                            token_id: TokenId::Ziesha,
-                        };
-                        if reward_entry.amount != expected_reward {
+                        let expected_entries = miner_reward_entries(
+                            miner,
+                            next_reward,
+                            block.body[1..].iter().map(|t| t.fee),
+                        )?;
+                        if *entries != expected_entries {
                             return Err(BlockchainError::InvalidMinerReward);
                         }
                     }
@@ -1157,22 +1748,58 @@ impl<K: KvStore> KvStoreChain<K> {
                     }
                 }
 
-                // Reward tx allowed to get money from Treasury
-                chain.apply_tx(reward_tx, true)?;
-                &block.body[1..]
+                (Some(reward_tx), &block.body[1..])
             } else {
-                &block.body[..]
+                (None, &block.body[..])
             };
 
+            // The MPN contract is created in the genesis block, so the upgrades
+            // active from the beginning rotate its keys after the body
+            if !is_genesis {
+                chain.rotate_mpn_keys(curr_height)?;
+            }
+
+            // Reward tx allowed to get money from Treasury. Since non-Ziesha fees
+            // are accepted, it is applied after the body, so that the fees of the
+            // block are already in the Treasury.
+            let reward_after_body = self
+                .config
+                .upgrades
+                .is_active(Upgrade::NonZieshaFees, curr_height);
+            if !reward_after_body {
+                if let Some(reward_tx) = reward_tx {
+                    chain.apply_tx(reward_tx, true)?;
+                }
+            }
+
             let mut body_size = 0usize;
             let mut state_size_delta = 0isize;
             let mut state_updates: HashMap<ContractId, ZkCompressedStateChange> = HashMap::new();
             let mut outdated_contracts = self.get_outdated_contracts()?;
 
-            if !txs.par_iter().all(|tx| tx.verify_signature()) {
+            // Keys rotated inside this block are only in effect in the next
+            // blocks, so the signing keys can be resolved beforehand
+            let signing_keys = txs
+                .iter()
+                .map(|tx| {
+                    tx.src
+                        .as_ref()
+                        .map(|src| chain.get_signing_key(src, curr_height))
+                        .transpose()
+                })
+                .collect::<Result<Vec<_>, BlockchainError>>()?;
+            if !txs
+                .par_iter()
+                .zip(signing_keys.par_iter())
+                .all(|(tx, key)| tx.verify_signature_with(key.as_ref()))
+            {
                 return Err(BlockchainError::SignatureError);
             }
 
+            // Proofs sharing a verifying key are verified together, once the
+            // whole body is applied
+            let mut proofs = zk::ZkProofBatch::default();
+
             let mut num_mpn_function_calls = 0;
             let mut num_mpn_contract_deposits = 0;
             let mut num_mpn_contract_withdraws = 0;
@@ -1206,7 +1833,7 @@ impl<K: KvStore> KvStoreChain<K> {
                 if let TxSideEffect::StateChange {
                     contract_id,
                     state_change,
-                } = chain.apply_tx(tx, is_genesis)?
+                } = chain.apply_tx_batched(tx, is_genesis, Some(&mut proofs))?
                 {
                     state_size_delta += state_change.state.size() as isize
                         - state_change.prev_state.size() as isize;
@@ -1217,12 +1844,21 @@ impl<K: KvStore> KvStoreChain<K> {
                 }
             }
 
-            // TODO: Temporary! Just for testnet
-            let func_calls = if curr_height < 3550 {
-                self.config.mpn_num_function_calls
-            } else {
-                10
-            };
+            if !proofs.verify(self.config.plonk_srs.as_ref()) {
+                return Err(BlockchainError::IncorrectZkProof);
+            }
+
+            if is_genesis {
+                chain.rotate_mpn_keys(curr_height)?;
+            }
+
+            if reward_after_body {
+                if let Some(reward_tx) = reward_tx {
+                    chain.apply_tx(reward_tx, true)?;
+                }
+            }
+
+            let func_calls = self.config.mpn_num_function_calls_at(curr_height);
 
             if !is_genesis
                 && (num_mpn_function_calls < func_calls
@@ -1292,6 +1928,9 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
     fn db_checksum(&self) -> Result<String, BlockchainError> {
         Ok(hex::encode(self.database.checksum::<Hasher>()?))
     }
+    fn db_size(&self) -> Result<u64, BlockchainError> {
+        Ok(self.database.size()?)
+    }
     fn get_header(&self, index: u64) -> Result<Header, BlockchainError> {
         if index >= self.get_height()? {
             return Err(BlockchainError::BlockNotFound);
@@ -1317,71 +1956,9 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
     }
 
     fn rollback(&mut self) -> Result<(), BlockchainError> {
-        let (ops, _) = self.isolated(|chain| {
-            let height = chain.get_height()?;
-
-            if height == 0 {
-                return Err(BlockchainError::NoBlocksToRollback);
-            }
-
-            let rollback: Vec<WriteOp> = match chain.database.get(keys::rollback(height - 1))? {
-                Some(b) => b.try_into()?,
-                None => {
-                    return Err(BlockchainError::Inconsistency);
-                }
-            };
-
-            let mut outdated = chain.get_outdated_contracts()?;
-            let changed_states = chain.get_changed_states()?;
-
-            for (cid, comp) in changed_states {
-                if comp.prev_height == 0 {
-                    zk::KvStoreStateManager::<CoreZkHasher>::delete_contract(
-                        &mut chain.database,
-                        cid,
-                    )?;
-                    outdated.retain(|&x| x != cid);
-                    continue;
-                }
-
-                if !outdated.contains(&cid) {
-                    let (ops, result) = chain.isolated(|fork| {
-                        Ok(zk::KvStoreStateManager::<CoreZkHasher>::rollback_contract(
-                            &mut fork.database,
-                            cid,
-                        )?)
-                    })?;
-
-                    if result != Some(comp.prev_state) {
-                        outdated.push(cid);
-                    } else {
-                        chain.database.update(&ops)?;
-                    }
-                } else {
-                    let local_compressed_state =
-                        zk::KvStoreStateManager::<CoreZkHasher>::root(&chain.database, cid)?;
-                    let local_height =
-                        zk::KvStoreStateManager::<CoreZkHasher>::height_of(&chain.database, cid)?;
-                    if local_compressed_state == comp.prev_state && local_height == comp.prev_height
-                    {
-                        outdated.retain(|&x| x != cid);
-                    }
-                }
-            }
-
-            chain.database.update(&rollback)?;
-            chain.database.update(&[
-                WriteOp::Remove(keys::rollback(height - 1)),
-                if outdated.is_empty() {
-                    WriteOp::Remove(keys::outdated())
-                } else {
-                    WriteOp::Put(keys::outdated(), outdated.clone().into())
-                },
-            ])?;
-
-            Ok(())
-        })?;
-        self.database.update(&ops)?;
+        self.rollback_block()?;
+        // Not a reorg by itself, only the switches to other forks are
+        METRICS.rolled_back_blocks.inc();
         Ok(())
     }
 
@@ -1411,11 +1988,42 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
         self.get_header(self.get_height()? - 1)
     }
     fn get_contract(&self, contract_id: ContractId) -> Result<zk::ZkContract, BlockchainError> {
-        Ok(self
+        let mut contract: zk::ZkContract = self
             .database
             .get(keys::contract(&contract_id))?
             .map(|b| b.try_into())
-            .ok_or(BlockchainError::ContractNotFound)??)
+            .ok_or(BlockchainError::ContractNotFound)??;
+        if let Some(upgrade) = self
+            .get_contract_control(contract_id)?
+            .take_active_upgrade(self.get_height()?)
+        {
+            upgrade.apply(&mut contract);
+        }
+        Ok(contract)
+    }
+    fn get_contract_control(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<ContractControl, BlockchainError> {
+        Ok(
+            match self.database.get(keys::contract_control(&contract_id))? {
+                Some(b) => b.try_into()?,
+                // The MPN contract is created without an admin, networks may
+                // assign one to it later
+                None if contract_id == self.config.mpn_contract_id
+                    && self
+                        .config
+                        .upgrades
+                        .is_active(Upgrade::ContractUpgrades, self.get_height()?) =>
+                {
+                    ContractControl {
+                        policy: self.config.mpn_admin.clone(),
+                        pending_upgrade: None,
+                    }
+                }
+                None => Default::default(),
+            },
+        )
     }
     fn get_contract_account(
         &self,
@@ -1451,6 +2059,22 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
         })
     }
 
+    fn get_contract_events(
+        &self,
+        contract_id: ContractId,
+        height: u64,
+    ) -> Result<Vec<ContractEvent>, BlockchainError> {
+        Ok(
+            match self
+                .database
+                .get(keys::contract_events(&contract_id, height))?
+            {
+                Some(b) => b.try_into()?,
+                None => Vec::new(),
+            },
+        )
+    }
+
     fn get_balance(&self, addr: Address, token_id: TokenId) -> Result<Amount, BlockchainError> {
         Ok(
             match self.database.get(keys::account_balance(&addr, token_id))? {
@@ -1467,6 +2091,10 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
         })
     }
 
+    fn get_account_control(&self, addr: Address) -> Result<AccountControl, BlockchainError> {
+        self.get_account_control_at(&addr, self.get_height()?)
+    }
+
     fn get_mpn_account(&self, index: u64) -> Result<zk::MpnAccount, BlockchainError> {
         Ok(zk::KvStoreStateManager::<CoreZkHasher>::get_mpn_account(
             &self.database,
@@ -1475,6 +2103,25 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
         )?)
     }
 
+    fn get_mpn_account_index(&self, address: &MpnAddress) -> Result<u64, BlockchainError> {
+        self.locate_mpn_account(&address.pub_key, self.get_height()?)
+    }
+
+    fn get_mpn_account_history(
+        &self,
+        index: u64,
+        count: u64,
+    ) -> Result<Vec<(u64, zk::MpnAccount)>, BlockchainError> {
+        Ok(
+            zk::KvStoreStateManager::<CoreZkHasher>::get_mpn_account_history(
+                &self.database,
+                self.config.mpn_contract_id,
+                index,
+                count,
+            )?,
+        )
+    }
+
     fn get_mpn_accounts(
         &self,
         page: usize,
@@ -1500,6 +2147,8 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
             return Err(BlockchainError::ExtendFromGenesis);
         } else if from > self.get_height()? {
             return Err(BlockchainError::ExtendFromFuture);
+        } else if from < self.get_finalized_height()? {
+            return Err(BlockchainError::BeyondFinality);
         }
 
         let mut new_power: u128 = self
@@ -1517,54 +2166,15 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
             })
             .collect::<Result<Vec<u32>, BlockchainError>>()?;
 
-        let mut diff_timestamps =
-            (from.saturating_sub(self.config.difficulty_window + self.config.difficulty_lag)..from)
-                .map(|i| self.get_header(i).map(|h| h.proof_of_work.timestamp))
-                .collect::<Result<Vec<u32>, BlockchainError>>()?;
-        let mut diff_powers =
-            (from.saturating_sub(self.config.difficulty_window + self.config.difficulty_lag)..from)
-                .map(|i| self.get_power_at(i))
-                .collect::<Result<Vec<u128>, BlockchainError>>()?;
+        let mut history = self.retarget_history(from)?;
+        let retarget_params = self.retarget_params();
+        let retarget_window = self.config.difficulty_algorithm.window() as usize;
 
         for h in headers.iter() {
-            let expected_target = {
-                if diff_timestamps.len() <= self.config.difficulty_lag as usize {
-                    self.config.minimum_pow_difficulty
-                } else {
-                    let mut timestamps = diff_timestamps
-                        [0..diff_timestamps.len() - self.config.difficulty_lag as usize]
-                        .to_vec();
-                    if timestamps.len() >= 2 {
-                        timestamps.sort_unstable();
-                        let final_size =
-                            self.config.difficulty_window - 2 * self.config.difficulty_cut;
-                        let (begin, end) = if timestamps.len() as u64 > final_size {
-                            let begin = (timestamps.len() as u64 - final_size + 1) / 2;
-                            let end = begin + final_size - 1;
-                            (begin as usize, end as usize)
-                        } else {
-                            (0, timestamps.len() - 1)
-                        };
-                        let time_delta = (timestamps[end] - timestamps[begin]).saturating_sub(
-                            (end - begin) as u32 * self.config.mpn_proving_time as u32,
-                        );
-                        if time_delta > 0 {
-                            let power_delta = diff_powers[end] - diff_powers[begin];
-                            std::cmp::max(
-                                Difficulty::from_power(
-                                    power_delta * (self.config.block_time as u128)
-                                        / (time_delta as u128),
-                                ),
-                                self.config.minimum_pow_difficulty,
-                            )
-                        } else {
-                            self.config.minimum_pow_difficulty
-                        }
-                    } else {
-                        self.config.minimum_pow_difficulty
-                    }
-                }
-            };
+            let expected_target = self
+                .config
+                .difficulty_algorithm
+                .next_difficulty(&history, &retarget_params);
             let pow_key = self.pow_key(h.number)?;
 
             if h.proof_of_work.timestamp < utils::median(&timestamps) {
@@ -1587,18 +2197,24 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
                 return Err(BlockchainError::InvalidParentHash);
             }
 
+            if let Some(checkpoint) = self.config.checkpoints.get(&h.number) {
+                if h.hash() != *checkpoint {
+                    return Err(BlockchainError::CheckpointMismatch);
+                }
+            }
+
             timestamps.push(h.proof_of_work.timestamp);
             while timestamps.len() > self.config.median_timestamp_count as usize {
                 timestamps.remove(0);
             }
 
-            diff_powers.push(new_power + h.power());
-            diff_timestamps.push(h.proof_of_work.timestamp);
-            while diff_timestamps.len()
-                > (self.config.difficulty_window + self.config.difficulty_lag) as usize
-            {
-                diff_powers.remove(0);
-                diff_timestamps.remove(0);
+            history.push(BlockInfo {
+                timestamp: h.proof_of_work.timestamp,
+                target: h.proof_of_work.target,
+                power: new_power + h.power(),
+            });
+            while history.len() > retarget_window {
+                history.remove(0);
             }
 
             last_header = h.clone();
@@ -1608,27 +2224,51 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
         Ok(new_power > current_power)
     }
     fn extend(&mut self, from: u64, blocks: &[Block]) -> Result<(), BlockchainError> {
-        let (ops, _) = self.isolated(|chain| {
+        let (ops, rolled_back) = self.isolated(|chain| {
             let curr_height = chain.get_height()?;
 
             if from == 0 {
                 return Err(BlockchainError::ExtendFromGenesis);
             } else if from > curr_height {
                 return Err(BlockchainError::ExtendFromFuture);
+            } else if from < chain.get_finalized_height()? {
+                return Err(BlockchainError::BeyondFinality);
             }
 
             while chain.get_height()? > from {
-                chain.rollback()?;
+                chain.rollback_block()?;
             }
 
             for block in blocks.iter() {
-                chain.apply_block(block, true)?;
+                if let Some(checkpoint) = chain.config.checkpoints.get(&block.header.number) {
+                    if block.header.hash() != *checkpoint {
+                        return Err(BlockchainError::CheckpointMismatch);
+                    }
+                }
+                // Drafts are also applied through `apply_block`, only time the real ones
+                METRICS
+                    .apply_block
+                    .time(|| chain.apply_block(block, true))?;
             }
 
-            Ok(())
+            // Not part of the rollback data of the blocks, finality can't be undone
+            if let Some(depth) = chain.config.finality_depth {
+                let finalized = chain.get_height()?.saturating_sub(depth);
+                if finalized > chain.get_finalized_height()? {
+                    chain
+                        .database
+                        .update(&[WriteOp::Put(keys::finalized_height(), finalized.into())])?;
+                }
+            }
+
+            Ok(curr_height - from)
         })?;
 
         self.database.update(&ops)?;
+        if rolled_back > 0 {
+            METRICS.reorgs.inc();
+            METRICS.rolled_back_blocks.add(rolled_back);
+        }
         Ok(())
     }
     fn get_height(&self) -> Result<u64, BlockchainError> {
@@ -1637,6 +2277,16 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
             None => 0,
         })
     }
+    fn get_finalized_height(&self) -> Result<u64, BlockchainError> {
+        // Chains without a finality depth ignore the stored finalized height
+        if self.config.finality_depth.is_none() {
+            return Ok(0);
+        }
+        Ok(match self.database.get(keys::finalized_height())? {
+            Some(b) => b.try_into()?,
+            None => 0,
+        })
+    }
     fn get_headers(&self, since: u64, count: u64) -> Result<Vec<Header>, BlockchainError> {
         let mut blks: Vec<Header> = Vec::new();
         let until = std::cmp::min(self.get_height()?, since + count);
@@ -1662,6 +2312,7 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
         timestamp: u32,
         mempool: &[TransactionAndDelta],
         wallet: &TxBuilder,
+        fee_rates: &FeeRates,
         check: bool,
     ) -> Result<Option<BlockAndPatch>, BlockchainError> {
         let height = self.get_height()?;
@@ -1674,36 +2325,32 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
         let last_header = self.get_header(height - 1)?;
         let treasury_nonce = self.get_account(Default::default())?.nonce;
 
-        let tx_and_deltas = self.select_transactions(mempool, check)?;
-        // WARN: Sum will be invalid if tx fees are not in Ziesha!
-        let fee_sum = Amount(
-            tx_and_deltas
-                .iter()
-                .map(|t| -> u64 { t.tx.fee.amount.into() })
-                .sum(),
-        );
+        let tx_and_deltas = self.select_transactions(mempool, fee_rates, check)?;
 
         let mut txs = vec![Transaction {
             memo: String::new(),
             src: None,
             data: TransactionData::RegularSend {
-                entries: vec![RegularSendEntry {
-                    dst: wallet.get_address(),
-                    amount: Money {
-                        amount: self.next_reward()? + fee_sum,
-                        token_id: TokenId::Ziesha,
-                    },
-                }],
+                entries: miner_reward_entries(
+                    wallet.get_address(),
+                    self.next_reward()?,
+                    tx_and_deltas.iter().map(|t| t.tx.fee),
+                )?,
             },
             nonce: treasury_nonce + 1,
             fee: Money::ziesha(0),
+            valid_after_height: None,
+            valid_until_height: None,
             sig: Signature::Unsigned,
         }];
 
         let mut block_delta: HashMap<ContractId, zk::ZkStatePatch> = HashMap::new();
         for tx_delta in tx_and_deltas.iter() {
             if let Some(contract_id) = match &tx_delta.tx.data {
-                TransactionData::CreateContract { .. } => Some(ContractId::new(&tx_delta.tx)),
+                TransactionData::CreateContract { .. }
+                | TransactionData::CreateUpgradableContract { .. } => {
+                    Some(ContractId::new(&tx_delta.tx))
+                }
                 TransactionData::UpdateContract { contract_id, .. } => Some(*contract_id),
                 _ => None,
             } {
@@ -1835,6 +2482,7 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
         &self,
         mempool: &mut HashMap<ChainSourcedTx, TransactionStats>,
     ) -> Result<(), BlockchainError> {
+        let height = self.get_height()?;
         self.isolated(|chain| {
             let mut txs: Vec<ChainSourcedTx> = mempool.clone().into_keys().collect();
             txs.sort_unstable_by_key(|tx| {
@@ -1852,9 +2500,18 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
             for tx in txs {
                 match &tx {
                     ChainSourcedTx::TransactionAndDelta(tx_delta) => {
-                        if let Err(e) = chain.apply_tx(&tx_delta.tx, false) {
-                            log::info!("Rejecting transaction: {}", e);
-                            mempool.remove(&tx);
+                        match chain.apply_tx(&tx_delta.tx, false) {
+                            // Correctly signed scheduled transactions are kept until
+                            // they get valid, unless they are scheduled too far in
+                            // the future
+                            Err(BlockchainError::TransactionNotYetValid)
+                                if !self.config.is_scheduled_too_far(&tx_delta.tx, height)
+                                    && chain.verify_tx_signature(&tx_delta.tx, height)? => {}
+                            Err(e) => {
+                                log::info!("Rejecting transaction: {}", e);
+                                mempool.remove(&tx);
+                            }
+                            Ok(_) => {}
                         }
                     }
                     ChainSourcedTx::MpnDeposit(mpn_deposit) => {
@@ -1874,10 +2531,19 @@ impl<K: KvStore> Blockchain for KvStoreChain<K> {
         &self,
         mempool: &mut HashMap<MpnSourcedTx, TransactionStats>,
         capacity: usize,
+        fee_rates: &FeeRates,
     ) -> Result<(), BlockchainError> {
         self.isolated(|chain| {
-            let mut txs: Vec<MpnSourcedTx> = mempool.clone().into_keys().collect();
-            txs.sort_unstable_by_key(|t| t.nonce());
+            let mut txs: Vec<(f64, MpnSourcedTx)> = mempool
+                .clone()
+                .into_keys()
+                .filter_map(|t| fee_rates.value(t.fee()).map(|v| (v, t)))
+                .collect();
+            // Nonces are kept in order, higher fees come first among the same nonces
+            txs.sort_unstable_by(|(a_value, a), (b_value, b)| {
+                a.nonce().cmp(&b.nonce()).then(b_value.total_cmp(a_value))
+            });
+            let txs = txs.into_iter().map(|(_, t)| t).collect::<Vec<_>>();
             let mut new_mempool = HashMap::new();
             for tx in txs.iter() {
                 if new_mempool.len() >= capacity {
@@ -26,11 +26,14 @@ fn test_contract_create_patch() -> Result<(), BlockchainError> {
             functions: Vec::new(),
         },
         full_state.data.clone(),
+        None,
         Money::ziesha(0),
         1,
     );
 
-    let draft = chain.draft_block(1, &[tx.clone()], &miner, true)?.unwrap();
+    let draft = chain
+        .draft_block(1, &[tx.clone()], &miner, &FeeRates::default(), true)?
+        .unwrap();
     chain.apply_block(&draft.block, true)?;
 
     assert_eq!(chain.get_height()?, 2);
@@ -84,12 +87,15 @@ fn test_contract_update() -> Result<(), BlockchainError> {
         state_delta.clone(),
         state_model.compress::<CoreZkHasher>(&full_state.data)?,
         zk::ZkProof::Dummy(true),
+        vec![],
         Money::ziesha(0),
         Money::ziesha(0),
         1,
     );
 
-    let draft = chain.draft_block(1, &[tx.clone()], &miner, false)?.unwrap();
+    let draft = chain
+        .draft_block(1, &[tx.clone()], &miner, &FeeRates::default(), false)?
+        .unwrap();
 
     chain.apply_block(&draft.block, true)?;
 
@@ -222,6 +228,7 @@ fn test_contract_update() -> Result<(), BlockchainError> {
                     state_delta.clone(),
                     state_model.compress::<CoreZkHasher>(&full_state.data)?,
                     zk::ZkProof::Dummy(true),
+                    vec![],
                     Money::ziesha(0),
                     Money::ziesha(0),
                     1,
@@ -229,7 +236,7 @@ fn test_contract_update() -> Result<(), BlockchainError> {
                 .tx,
             false
         ),
-        Err(BlockchainError::InvalidTransactionNonce)
+        Err(BlockchainError::InvalidTransactionNonce { .. })
     ));
 
     assert!(matches!(
@@ -245,6 +252,7 @@ fn test_contract_update() -> Result<(), BlockchainError> {
                     state_delta.clone(),
                     state_model.compress::<CoreZkHasher>(&full_state.data)?,
                     zk::ZkProof::Dummy(true),
+                    vec![],
                     Money::ziesha(0),
                     Money::ziesha(0),
                     2,
@@ -265,6 +273,7 @@ fn test_contract_update() -> Result<(), BlockchainError> {
                     state_delta.clone(),
                     state_model.compress::<CoreZkHasher>(&full_state.data)?,
                     zk::ZkProof::Dummy(true),
+                    vec![],
                     Money::ziesha(0),
                     Money::ziesha(0),
                     2,
@@ -285,6 +294,7 @@ fn test_contract_update() -> Result<(), BlockchainError> {
                     state_delta,
                     state_model.compress::<CoreZkHasher>(&full_state.data)?,
                     zk::ZkProof::Dummy(false),
+                    vec![],
                     Money::ziesha(0),
                     Money::ziesha(0),
                     2,
@@ -307,3 +317,993 @@ fn test_contract_update() -> Result<(), BlockchainError> {
 
     Ok(())
 }
+
+#[test]
+fn test_contract_groth16_updates() -> Result<(), BlockchainError> {
+    use zk::testing::TestCircuit;
+    use zk::{deposits_aux_data, function_call_aux_data, withdraws_aux_data};
+
+    let miner = TxBuilder::new(&Vec::from("MINER"));
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let executor = TxBuilder::new(&Vec::from("EXECUTOR"));
+    let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
+
+    let deposit_circuit = TestCircuit::setup(1);
+    let withdraw_circuit = TestCircuit::setup(2);
+    let function_circuit = TestCircuit::setup(3);
+
+    let state_model = zk::ZkStateModel::List {
+        item_type: Box::new(zk::ZkStateModel::Scalar),
+        log4_size: 5,
+    };
+    let create_tx = alice.create_contract(
+        "".into(),
+        zk::ZkContract {
+            state_model: state_model.clone(),
+            initial_state: state_model.compress::<CoreZkHasher>(&Default::default())?,
+            deposit_functions: vec![zk::ZkMultiInputVerifierKey {
+                verifier_key: deposit_circuit.verifier_key(),
+                log4_payment_capacity: 1,
+            }],
+            withdraw_functions: vec![zk::ZkMultiInputVerifierKey {
+                verifier_key: withdraw_circuit.verifier_key(),
+                log4_payment_capacity: 1,
+            }],
+            functions: vec![zk::ZkSingleInputVerifierKey {
+                verifier_key: function_circuit.verifier_key(),
+            }],
+        },
+        Default::default(),
+        None,
+        Money::ziesha(0),
+        1,
+    );
+    let cid = ContractId::new(&create_tx.tx);
+    let draft = chain
+        .draft_block(1, &[create_tx], &miner, &FeeRates::default(), false)?
+        .unwrap();
+    chain.apply_block(&draft.block, true)?;
+    chain.update_states(&draft.patch)?;
+
+    let set_slot = |state: &mut zk::ZkState, val: u64| -> zk::ZkDeltaPairs {
+        let delta = zk::ZkDeltaPairs(
+            [(zk::ZkDataLocator(vec![0]), Some(zk::ZkScalar::from(val)))]
+                .into_iter()
+                .collect(),
+        );
+        state.apply_delta(&delta);
+        delta
+    };
+    let update_tx = |updates: Vec<ContractUpdate>, delta: zk::ZkDeltaPairs, nonce: u32| {
+        let mut tx = Transaction {
+            memo: "".into(),
+            src: Some(executor.get_address()),
+            data: TransactionData::UpdateContract {
+                contract_id: cid,
+                updates,
+            },
+            nonce,
+            fee: Money::ziesha(0),
+            valid_after_height: None,
+            valid_until_height: None,
+            sig: Signature::Unsigned,
+        };
+        executor.sign_tx(&mut tx);
+        TransactionAndDelta {
+            tx,
+            state_delta: Some(delta),
+        }
+    };
+    let mut state = zk::ZkState {
+        rollbacks: vec![],
+        data: Default::default(),
+    };
+
+    // Deposit
+    let deposit = alice
+        .deposit_mpn(
+            "".into(),
+            cid,
+            crate::core::MpnAddress {
+                pub_key: alice.get_zk_address(),
+            },
+            0,
+            2,
+            Money::ziesha(100),
+            Money::ziesha(0),
+        )
+        .payment;
+    let account = chain.get_contract_account(cid)?;
+    let delta = set_slot(&mut state, 100);
+    let next_state = state_model.compress::<CoreZkHasher>(&state.data)?;
+    let aux_data = deposits_aux_data(1, std::slice::from_ref(&deposit))?;
+    let deposit_update = |proof| ContractUpdate::Deposit {
+        deposit_circuit_id: 0,
+        deposits: vec![deposit.clone()],
+        next_state,
+        proof,
+        events: vec![],
+    };
+    for wrong_proof in [
+        // Proven with a different circuit
+        withdraw_circuit.prove(
+            account.height,
+            &account.compressed_state,
+            &aux_data,
+            &next_state,
+        ),
+        // Proven for different deposits
+        deposit_circuit.prove(
+            account.height,
+            &account.compressed_state,
+            &deposits_aux_data(1, &[])?,
+            &next_state,
+        ),
+        // Proven for a different height
+        deposit_circuit.prove(
+            account.height + 1,
+            &account.compressed_state,
+            &aux_data,
+            &next_state,
+        ),
+    ] {
+        assert!(matches!(
+            chain.fork_on_ram().apply_tx(
+                &update_tx(vec![deposit_update(wrong_proof)], delta.clone(), 1).tx,
+                false
+            ),
+            Err(BlockchainError::IncorrectZkProof)
+        ));
+    }
+    let proof = deposit_circuit.prove(
+        account.height,
+        &account.compressed_state,
+        &aux_data,
+        &next_state,
+    );
+    let draft = chain
+        .draft_block(
+            2,
+            &[update_tx(vec![deposit_update(proof)], delta, 1)],
+            &miner,
+            &FeeRates::default(),
+            false,
+        )?
+        .unwrap();
+    assert_eq!(draft.block.body.len(), 2);
+    chain.apply_block(&draft.block, true)?;
+    chain.update_states(&draft.patch)?;
+    assert_eq!(
+        chain.get_contract_balance(cid, TokenId::Ziesha)?,
+        Amount(100)
+    );
+
+    // Function call and withdraw, in a single transaction
+    let withdraw = alice
+        .withdraw_mpn(
+            "".into(),
+            cid,
+            0,
+            0,
+            Money::ziesha(40),
+            0,
+            Money::ziesha(0),
+            alice.get_address(),
+        )
+        .payment;
+    let account = chain.get_contract_account(cid)?;
+    let fee = Money::ziesha(10);
+    set_slot(&mut state, 90);
+    let call_state = state_model.compress::<CoreZkHasher>(&state.data)?;
+    let call_proof = function_circuit.prove(
+        account.height,
+        &account.compressed_state,
+        &function_call_aux_data(fee)?,
+        &call_state,
+    );
+    let delta = set_slot(&mut state, 50);
+    let withdraw_state = state_model.compress::<CoreZkHasher>(&state.data)?;
+    let withdraw_proof = withdraw_circuit.prove(
+        account.height,
+        &call_state,
+        &withdraws_aux_data(1, std::slice::from_ref(&withdraw))?,
+        &withdraw_state,
+    );
+    let updates = |call_proof| {
+        vec![
+            ContractUpdate::FunctionCall {
+                function_id: 0,
+                next_state: call_state,
+                proof: call_proof,
+                fee,
+                events: vec![],
+            },
+            ContractUpdate::Withdraw {
+                withdraw_circuit_id: 0,
+                withdraws: vec![withdraw.clone()],
+                next_state: withdraw_state,
+                proof: withdraw_proof.clone(),
+                events: vec![],
+            },
+        ]
+    };
+    let draft = chain
+        .draft_block(
+            3,
+            &[update_tx(updates(call_proof), delta.clone(), 2)],
+            &miner,
+            &FeeRates::default(),
+            false,
+        )?
+        .unwrap();
+    assert_eq!(draft.block.body.len(), 2);
+
+    // Proofs of a block are verified as a batch, which should still fail if
+    // any of them is wrong
+    let mut wrong_block = draft.block.clone();
+    wrong_block.body[1] = update_tx(
+        updates(function_circuit.prove(
+            account.height,
+            &account.compressed_state,
+            &function_call_aux_data(Money::ziesha(11))?,
+            &call_state,
+        )),
+        delta,
+        2,
+    )
+    .tx;
+    wrong_block.header.block_root = wrong_block.merkle_tree().root();
+    assert!(matches!(
+        chain.fork_on_ram().apply_block(&wrong_block, false),
+        Err(BlockchainError::IncorrectZkProof)
+    ));
+    chain.apply_block(&draft.block, true)?;
+    chain.update_states(&draft.patch)?;
+    assert_eq!(
+        chain.get_contract_balance(cid, TokenId::Ziesha)?,
+        Amount(50)
+    );
+    assert_eq!(
+        chain.get_balance(alice.get_address(), TokenId::Ziesha)?,
+        Amount(10000 - 100 + 40)
+    );
+    assert_eq!(
+        chain.get_balance(executor.get_address(), TokenId::Ziesha)?,
+        Amount(10)
+    );
+
+    rollback_till_empty(&mut chain)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_contract_plonk_updates() -> Result<(), BlockchainError> {
+    use rand::SeedableRng;
+    use zk::function_call_aux_data;
+    use zk::plonk::{PlonkCircuit, PlonkInputs, PlonkProvingKey, PlonkSrs};
+
+    let miner = TxBuilder::new(&Vec::from("MINER"));
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let executor = TxBuilder::new(&Vec::from("EXECUTOR"));
+    let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
+
+    // A circuit of only the public inputs, accepting any transition
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+    let srs = PlonkSrs::generate(4, &mut rng);
+    chain.config.plonk_srs = Some(srs.verifier_srs());
+    let pk = PlonkProvingKey::new(&srs, &PlonkCircuit::new(Default::default())).unwrap();
+    let mut prove = |prev_height: u64,
+                     prev_state: &zk::ZkCompressedState,
+                     aux_data: &zk::ZkCompressedState,
+                     next_state: &zk::ZkCompressedState| {
+        let circuit = PlonkCircuit::new(PlonkInputs {
+            prev_height,
+            prev_state: prev_state.state_hash,
+            aux_data: aux_data.state_hash,
+            next_state: next_state.state_hash,
+        });
+        zk::ZkProof::Plonk(Box::new(pk.prove(&circuit, &mut rng).unwrap()))
+    };
+
+    let state_model = zk::ZkStateModel::List {
+        item_type: Box::new(zk::ZkStateModel::Scalar),
+        log4_size: 5,
+    };
+    let create_tx = alice.create_contract(
+        "".into(),
+        zk::ZkContract {
+            state_model: state_model.clone(),
+            initial_state: state_model.compress::<CoreZkHasher>(&Default::default())?,
+            deposit_functions: vec![],
+            withdraw_functions: vec![],
+            functions: vec![zk::ZkSingleInputVerifierKey {
+                verifier_key: zk::ZkVerifierKey::Plonk(Box::new(pk.verifier_key())),
+            }],
+        },
+        Default::default(),
+        None,
+        Money::ziesha(0),
+        1,
+    );
+    let cid = ContractId::new(&create_tx.tx);
+
+    // Not accepted before the activation of the upgrade
+    let mut not_upgraded = chain.fork_on_ram();
+    not_upgraded.config.upgrades = Default::default();
+    assert!(matches!(
+        not_upgraded.apply_tx(&create_tx.tx, false),
+        Err(BlockchainError::UpgradeNotActive(Upgrade::PlonkProofs))
+    ));
+    drop(not_upgraded);
+
+    // Keys should be prepared with the setup of the network
+    let mut small_rng = rand::rngs::StdRng::seed_from_u64(10);
+    for plonk_srs in [
+        None,
+        Some(PlonkSrs::generate(2, &mut small_rng).verifier_srs()),
+    ] {
+        let mut other_setup = chain.fork_on_ram();
+        other_setup.config.plonk_srs = plonk_srs;
+        assert!(matches!(
+            other_setup.apply_tx(&create_tx.tx, false),
+            Err(BlockchainError::UnsupportedPlonkKey)
+        ));
+    }
+
+    let draft = chain
+        .draft_block(1, &[create_tx], &miner, &FeeRates::default(), false)?
+        .unwrap();
+    chain.apply_block(&draft.block, true)?;
+    chain.update_states(&draft.patch)?;
+
+    let delta = zk::ZkDeltaPairs(
+        [(zk::ZkDataLocator(vec![0]), Some(zk::ZkScalar::from(7)))]
+            .into_iter()
+            .collect(),
+    );
+    let mut state = zk::ZkState {
+        rollbacks: vec![],
+        data: Default::default(),
+    };
+    state.apply_delta(&delta);
+    let next_state = state_model.compress::<CoreZkHasher>(&state.data)?;
+    let fee = Money::ziesha(0);
+    let aux_data = function_call_aux_data(fee)?;
+    let account = chain.get_contract_account(cid)?;
+    let call_tx = |proof| {
+        let mut tx = Transaction {
+            memo: "".into(),
+            src: Some(executor.get_address()),
+            data: TransactionData::UpdateContract {
+                contract_id: cid,
+                updates: vec![ContractUpdate::FunctionCall {
+                    function_id: 0,
+                    next_state,
+                    proof,
+                    fee,
+                    events: vec![],
+                }],
+            },
+            nonce: 1,
+            fee: Money::ziesha(0),
+            valid_after_height: None,
+            valid_until_height: None,
+            sig: Signature::Unsigned,
+        };
+        executor.sign_tx(&mut tx);
+        TransactionAndDelta {
+            tx,
+            state_delta: Some(delta.clone()),
+        }
+    };
+
+    // Proven for a different transition
+    let wrong_proof = prove(
+        account.height,
+        &account.compressed_state,
+        &aux_data,
+        &account.compressed_state,
+    );
+    assert!(matches!(
+        chain
+            .fork_on_ram()
+            .apply_tx(&call_tx(wrong_proof).tx, false),
+        Err(BlockchainError::IncorrectZkProof)
+    ));
+
+    let proof = prove(
+        account.height,
+        &account.compressed_state,
+        &aux_data,
+        &next_state,
+    );
+    let draft = chain
+        .draft_block(2, &[call_tx(proof)], &miner, &FeeRates::default(), false)?
+        .unwrap();
+    assert_eq!(draft.block.body.len(), 2);
+    chain.apply_block(&draft.block, true)?;
+    chain.update_states(&draft.patch)?;
+    assert_eq!(
+        chain.get_contract_account(cid)?.compressed_state,
+        next_state
+    );
+
+    rollback_till_empty(&mut chain)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_contract_events() -> Result<(), BlockchainError> {
+    use rand::SeedableRng;
+    use zk::function_call_aux_data;
+    use zk::plonk::{PlonkCircuit, PlonkInputs, PlonkProvingKey, PlonkSrs};
+
+    let miner = TxBuilder::new(&Vec::from("MINER"));
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let executor = TxBuilder::new(&Vec::from("EXECUTOR"));
+    let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
+
+    // A circuit of only the public inputs, accepting any transition
+    let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+    let srs = PlonkSrs::generate(4, &mut rng);
+    chain.config.plonk_srs = Some(srs.verifier_srs());
+    let pk = PlonkProvingKey::new(&srs, &PlonkCircuit::new(Default::default())).unwrap();
+    let mut prove = |prev_height: u64,
+                     prev_state: &zk::ZkCompressedState,
+                     aux_data: &zk::ZkCompressedState,
+                     next_state: &zk::ZkCompressedState| {
+        let circuit = PlonkCircuit::new(PlonkInputs {
+            prev_height,
+            prev_state: prev_state.state_hash,
+            aux_data: aux_data.state_hash,
+            next_state: next_state.state_hash,
+        });
+        zk::ZkProof::Plonk(Box::new(pk.prove(&circuit, &mut rng).unwrap()))
+    };
+
+    let state_model = zk::ZkStateModel::List {
+        item_type: Box::new(zk::ZkStateModel::Scalar),
+        log4_size: 5,
+    };
+    let create_tx = alice.create_contract(
+        "".into(),
+        zk::ZkContract {
+            state_model: state_model.clone(),
+            initial_state: state_model.compress::<CoreZkHasher>(&Default::default())?,
+            deposit_functions: vec![],
+            withdraw_functions: vec![],
+            functions: vec![zk::ZkSingleInputVerifierKey {
+                verifier_key: zk::ZkVerifierKey::Plonk(Box::new(pk.verifier_key())),
+            }],
+        },
+        Default::default(),
+        None,
+        Money::ziesha(0),
+        1,
+    );
+    let cid = ContractId::new(&create_tx.tx);
+    let draft = chain
+        .draft_block(1, &[create_tx], &miner, &FeeRates::default(), false)?
+        .unwrap();
+    chain.apply_block(&draft.block, true)?;
+    chain.update_states(&draft.patch)?;
+
+    let delta = zk::ZkDeltaPairs(
+        [(zk::ZkDataLocator(vec![0]), Some(zk::ZkScalar::from(7)))]
+            .into_iter()
+            .collect(),
+    );
+    let mut state = zk::ZkState {
+        rollbacks: vec![],
+        data: Default::default(),
+    };
+    state.apply_delta(&delta);
+    let next_state = state_model.compress::<CoreZkHasher>(&state.data)?;
+    let fee = Money::ziesha(0);
+    let account = chain.get_contract_account(cid)?;
+    let call_tx = |proof, events| {
+        let mut tx = Transaction {
+            memo: "".into(),
+            src: Some(executor.get_address()),
+            data: TransactionData::UpdateContract {
+                contract_id: cid,
+                updates: vec![ContractUpdate::FunctionCall {
+                    function_id: 0,
+                    next_state,
+                    proof,
+                    fee,
+                    events,
+                }],
+            },
+            nonce: 1,
+            fee: Money::ziesha(0),
+            valid_after_height: None,
+            valid_until_height: None,
+            sig: Signature::Unsigned,
+        };
+        executor.sign_tx(&mut tx);
+        TransactionAndDelta {
+            tx,
+            state_delta: Some(delta.clone()),
+        }
+    };
+
+    let events = vec![zk::ZkScalar::from(123), zk::ZkScalar::from(456)];
+    let aux_data = function_call_aux_data(fee)?.with_events::<CoreZkHasher>(&events);
+    let proof = prove(
+        account.height,
+        &account.compressed_state,
+        &aux_data,
+        &next_state,
+    );
+
+    // The proof commits to the events
+    let plain_proof = prove(
+        account.height,
+        &account.compressed_state,
+        &function_call_aux_data(fee)?,
+        &next_state,
+    );
+    assert!(matches!(
+        chain
+            .fork_on_ram()
+            .apply_tx(&call_tx(plain_proof, events.clone()).tx, false),
+        Err(BlockchainError::IncorrectZkProof)
+    ));
+    assert!(matches!(
+        chain
+            .fork_on_ram()
+            .apply_tx(&call_tx(proof.clone(), events[..1].to_vec()).tx, false),
+        Err(BlockchainError::IncorrectZkProof)
+    ));
+
+    // Updates without events keep their original encoding
+    let update = |events| ContractUpdate::FunctionCall {
+        function_id: 0,
+        next_state,
+        proof: proof.clone(),
+        fee,
+        events,
+    };
+    assert_eq!(
+        bincode::serialize(&update(vec![])).unwrap(),
+        bincode::serialize(&(2u32, 0u32, next_state, proof.clone(), fee)).unwrap()
+    );
+    // Events variants without any events are not canonical
+    let empty_events = (
+        5u32,
+        0u32,
+        next_state,
+        proof.clone(),
+        fee,
+        Vec::<zk::ZkScalar>::new(),
+    );
+    assert!(
+        bincode::deserialize::<ContractUpdate>(&bincode::serialize(&empty_events).unwrap())
+            .is_err()
+    );
+    let with_events = update(events.clone());
+    assert_eq!(
+        bincode::deserialize::<ContractUpdate>(&bincode::serialize(&with_events).unwrap()).unwrap(),
+        with_events
+    );
+
+    let too_many = vec![zk::ZkScalar::from(1); chain.config.max_contract_events + 1];
+    assert!(matches!(
+        chain
+            .fork_on_ram()
+            .apply_tx(&call_tx(proof.clone(), too_many).tx, false),
+        Err(BlockchainError::TooManyContractEvents)
+    ));
+
+    let mut not_upgraded = chain.fork_on_ram();
+    not_upgraded.config.upgrades = Default::default();
+    assert!(matches!(
+        not_upgraded.apply_tx(&call_tx(proof.clone(), events.clone()).tx, false),
+        Err(BlockchainError::UpgradeNotActive(Upgrade::ContractEvents))
+    ));
+    drop(not_upgraded);
+
+    let tx = call_tx(proof, events.clone());
+    let draft = chain
+        .draft_block(
+            2,
+            std::slice::from_ref(&tx),
+            &miner,
+            &FeeRates::default(),
+            false,
+        )?
+        .unwrap();
+    assert_eq!(draft.block.body.len(), 2);
+    chain.apply_block(&draft.block, true)?;
+    chain.update_states(&draft.patch)?;
+    assert_eq!(
+        chain.get_contract_events(cid, 2)?,
+        vec![ContractEvent {
+            tx_hash: tx.tx.hash(),
+            update_index: 0,
+            data: events,
+        }]
+    );
+    assert!(chain.get_contract_events(cid, 1)?.is_empty());
+
+    chain.rollback()?;
+    assert!(chain.get_contract_events(cid, 2)?.is_empty());
+
+    rollback_till_empty(&mut chain)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_contract_upgrades() -> Result<(), BlockchainError> {
+    use crate::core::{ContractAdminPolicy, ContractUpgrade};
+    use rand::SeedableRng;
+    use zk::plonk::{PlonkCircuit, PlonkInputs, PlonkProvingKey, PlonkSrs};
+
+    let miner = TxBuilder::new(&Vec::from("MINER"));
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let bob = TxBuilder::new(&Vec::from("CBA"));
+    let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
+
+    // A circuit of only the public inputs, accepting any transition
+    let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+    let srs = PlonkSrs::generate(4, &mut rng);
+    chain.config.plonk_srs = Some(srs.verifier_srs());
+    let pk = PlonkProvingKey::new(&srs, &PlonkCircuit::new(Default::default())).unwrap();
+    let plonk_vk = zk::ZkSingleInputVerifierKey {
+        verifier_key: zk::ZkVerifierKey::Plonk(Box::new(pk.verifier_key())),
+    };
+
+    let state_model = zk::ZkStateModel::List {
+        item_type: Box::new(zk::ZkStateModel::Scalar),
+        log4_size: 5,
+    };
+    let contract = zk::ZkContract {
+        state_model: state_model.clone(),
+        initial_state: state_model.compress::<CoreZkHasher>(&Default::default())?,
+        deposit_functions: vec![],
+        withdraw_functions: vec![],
+        functions: vec![zk::ZkSingleInputVerifierKey {
+            verifier_key: zk::ZkVerifierKey::Dummy,
+        }],
+    };
+    let create = |admin: ContractAdmin, delay: u64, nonce: u32| {
+        alice.create_contract(
+            "".into(),
+            contract.clone(),
+            Default::default(),
+            Some(ContractAdminPolicy { admin, delay }),
+            Money::ziesha(0),
+            nonce,
+        )
+    };
+    let upgrade_tx = |builder: &TxBuilder, cid, upgrade, proof, nonce| {
+        builder.upgrade_contract("".into(), cid, upgrade, proof, Money::ziesha(0), nonce)
+    };
+
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(
+            &create(ContractAdmin::Key(alice.get_address()), 0, 1).tx,
+            false
+        ),
+        Err(BlockchainError::InvalidContractAdminPolicy)
+    ));
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(
+            &create(
+                ContractAdmin::Key(alice.get_address()),
+                chain.config.max_policy_delay + 1,
+                1
+            )
+            .tx,
+            false
+        ),
+        Err(BlockchainError::InvalidContractAdminPolicy)
+    ));
+    let mut not_upgraded = chain.fork_on_ram();
+    not_upgraded.config.upgrades = Default::default();
+    assert!(matches!(
+        not_upgraded.apply_tx(
+            &create(ContractAdmin::Key(alice.get_address()), 2, 1).tx,
+            false
+        ),
+        Err(BlockchainError::UpgradeNotActive(Upgrade::ContractUpgrades))
+    ));
+    drop(not_upgraded);
+
+    let keyed_tx = create(ContractAdmin::Key(alice.get_address()), 2, 1);
+    let keyed = ContractId::new(&keyed_tx.tx);
+    let governed_tx = create(ContractAdmin::Governance(plonk_vk.clone()), 1, 2);
+    let governed = ContractId::new(&governed_tx.tx);
+    let fixed_tx = alice.create_contract(
+        "".into(),
+        contract.clone(),
+        Default::default(),
+        None,
+        Money::ziesha(0),
+        3,
+    );
+    let fixed = ContractId::new(&fixed_tx.tx);
+    let draft = chain
+        .draft_block(
+            1,
+            &[keyed_tx, governed_tx, fixed_tx],
+            &miner,
+            &FeeRates::default(),
+            true,
+        )?
+        .unwrap();
+    assert_eq!(draft.block.body.len(), 4);
+    chain.apply_block(&draft.block, true)?;
+    chain.update_states(&draft.patch)?;
+
+    let upgrade = ContractUpgrade {
+        functions: vec![(0, plonk_vk.clone())],
+        ..Default::default()
+    };
+    let call_tx = |chain: &KvStoreChain<db::RamKvStore>, cid| {
+        let account = chain.get_contract_account(cid).unwrap();
+        let mut tx = Transaction {
+            memo: "".into(),
+            src: Some(bob.get_address()),
+            data: TransactionData::UpdateContract {
+                contract_id: cid,
+                updates: vec![ContractUpdate::FunctionCall {
+                    function_id: 0,
+                    next_state: account.compressed_state,
+                    proof: zk::ZkProof::Dummy(true),
+                    fee: Money::ziesha(0),
+                    events: vec![],
+                }],
+            },
+            nonce: 2,
+            fee: Money::ziesha(0),
+            valid_after_height: None,
+            valid_until_height: None,
+            sig: Signature::Unsigned,
+        };
+        bob.sign_tx(&mut tx);
+        tx
+    };
+
+    assert!(matches!(
+        chain
+            .fork_on_ram()
+            .apply_tx(&upgrade_tx(&bob, keyed, upgrade.clone(), None, 1).tx, false),
+        Err(BlockchainError::ContractUpgradePermissionDenied)
+    ));
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(
+            &upgrade_tx(&alice, fixed, upgrade.clone(), None, 4).tx,
+            false
+        ),
+        Err(BlockchainError::ContractNotUpgradable)
+    ));
+    let out_of_range = ContractUpgrade {
+        functions: vec![(2, plonk_vk.clone())],
+        ..Default::default()
+    };
+    assert!(matches!(
+        chain
+            .fork_on_ram()
+            .apply_tx(&upgrade_tx(&alice, keyed, out_of_range, None, 4).tx, false),
+        Err(BlockchainError::InvalidContractUpgrade)
+    ));
+
+    // The MPN contract is upgradable only if the network assigns an admin to it
+    let mpn = chain.config.mpn_contract_id;
+    let mpn_upgrade = upgrade_tx(&alice, mpn, upgrade.clone(), None, 4);
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(&mpn_upgrade.tx, false),
+        Err(BlockchainError::ContractNotUpgradable)
+    ));
+    let mut with_mpn_admin = chain.fork_on_ram();
+    with_mpn_admin.config.mpn_admin = Some(ContractAdminPolicy {
+        admin: ContractAdmin::Key(alice.get_address()),
+        delay: 2,
+    });
+    with_mpn_admin.apply_tx(&mpn_upgrade.tx, false)?;
+    assert_eq!(
+        with_mpn_admin.get_contract_control(mpn)?.pending_upgrade,
+        Some((4, upgrade.clone()))
+    );
+    drop(with_mpn_admin);
+
+    // Governance proofs are bound to the upgrade being made
+    let account = chain.get_contract_account(governed)?;
+    let mut prove = |upgrade: &ContractUpgrade| {
+        let circuit = PlonkCircuit::new(PlonkInputs {
+            prev_height: account.height,
+            prev_state: account.compressed_state.state_hash,
+            aux_data: upgrade.commitment::<Hasher>(),
+            next_state: account.compressed_state.state_hash,
+        });
+        zk::ZkProof::Plonk(Box::new(pk.prove(&circuit, &mut rng).unwrap()))
+    };
+    let appending = ContractUpgrade {
+        functions: vec![(1, plonk_vk.clone())],
+        ..Default::default()
+    };
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(
+            &upgrade_tx(&bob, governed, appending.clone(), Some(prove(&upgrade)), 1).tx,
+            false
+        ),
+        Err(BlockchainError::IncorrectZkProof)
+    ));
+    assert!(matches!(
+        chain
+            .fork_on_ram()
+            .apply_tx(&upgrade_tx(&bob, governed, appending, None, 1).tx, false),
+        Err(BlockchainError::ContractUpgradePermissionDenied)
+    ));
+    let governed_proof = prove(&upgrade);
+    let governed_upgrade = upgrade_tx(
+        &bob,
+        governed,
+        upgrade.clone(),
+        Some(governed_proof.clone()),
+        1,
+    );
+
+    let draft = chain
+        .draft_block(
+            2,
+            &[
+                upgrade_tx(&alice, keyed, upgrade.clone(), None, 4),
+                governed_upgrade,
+            ],
+            &miner,
+            &FeeRates::default(),
+            true,
+        )?
+        .unwrap();
+    assert_eq!(draft.block.body.len(), 3);
+    chain.apply_block(&draft.block, true)?;
+    chain.update_states(&draft.patch)?;
+    assert_eq!(
+        chain.get_contract_control(keyed)?.pending_upgrade,
+        Some((4, upgrade.clone()))
+    );
+
+    // Anyone may replay a governance upgrade, but resubmitting an upgrade
+    // doesn't postpone it, and it can't be applied again once in effect
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(
+            &upgrade_tx(&alice, governed, upgrade.clone(), Some(governed_proof), 5).tx,
+            false
+        ),
+        Err(BlockchainError::InvalidContractUpgrade)
+    ));
+    let mut resubmitted = chain.fork_on_ram();
+    resubmitted.apply_tx(
+        &upgrade_tx(&alice, keyed, upgrade.clone(), None, 5).tx,
+        false,
+    )?;
+    assert_eq!(
+        resubmitted.get_contract_control(keyed)?.pending_upgrade,
+        Some((4, upgrade.clone()))
+    );
+    drop(resubmitted);
+
+    // The old verifier key is in effect until the delay passes
+    assert_eq!(chain.get_contract(keyed)?, contract);
+    assert_eq!(
+        chain.get_contract(governed)?.functions,
+        vec![plonk_vk.clone()]
+    );
+    chain
+        .fork_on_ram()
+        .apply_tx(&call_tx(&chain, keyed), false)?;
+    assert!(matches!(
+        chain
+            .fork_on_ram()
+            .apply_tx(&call_tx(&chain, governed), false),
+        Err(BlockchainError::IncorrectZkProof)
+    ));
+
+    let draft = chain
+        .draft_block(3, &[], &miner, &FeeRates::default(), false)?
+        .unwrap();
+    chain.apply_block(&draft.block, true)?;
+    chain.update_states(&draft.patch)?;
+    assert_eq!(chain.get_contract(keyed)?.functions, vec![plonk_vk.clone()]);
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(&call_tx(&chain, keyed), false),
+        Err(BlockchainError::IncorrectZkProof)
+    ));
+
+    chain.rollback()?;
+    assert_eq!(chain.get_contract(keyed)?, contract);
+    chain.rollback()?;
+    assert_eq!(chain.get_contract_control(keyed)?.pending_upgrade, None);
+    assert_eq!(chain.get_contract(governed)?, contract);
+
+    rollback_till_empty(&mut chain)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_mpn_circuit_upgrades_rotate_keys() -> Result<(), BlockchainError> {
+    use crate::core::ContractUpgrade;
+
+    let miner = TxBuilder::new(&Vec::from("MINER"));
+    let update_vk = zk::ZkVerifierKey::Groth16(Box::new(blockchain::MPN_UPDATE_VK.clone()));
+    let mut conf = easy_config();
+    conf.upgrades = Upgrades::new(
+        Upgrade::ALL
+            .iter()
+            .filter(|u| **u != Upgrade::MoreMpnFunctionCalls)
+            .map(|u| match u {
+                Upgrade::MpnAccountSlots | Upgrade::MpnTokenSlotReuse => (*u, 2),
+                _ => (*u, 0),
+            }),
+    );
+    // No keys for the token slot reuse, it never takes effect
+    conf.mpn_circuit_upgrades = vec![(
+        Upgrade::MpnAccountSlots,
+        ContractUpgrade {
+            functions: vec![(
+                0,
+                zk::ZkSingleInputVerifierKey {
+                    verifier_key: update_vk.clone(),
+                },
+            )],
+            ..Default::default()
+        },
+    )];
+    let mut chain = KvStoreChain::new(db::RamKvStore::new(), conf)?;
+    let mpn = chain.config.mpn_contract_id;
+
+    for height in 1..3 {
+        assert_eq!(
+            chain.get_contract(mpn)?.functions[0].verifier_key,
+            zk::ZkVerifierKey::Dummy
+        );
+        assert_eq!(
+            chain.config.mpn_circuit_features_at(height - 1),
+            MpnCircuitFeatures {
+                account_slot_choices: 1,
+                reuse_empty_token_slots: false
+            }
+        );
+        let draft = chain
+            .draft_block(height as u32, &[], &miner, &FeeRates::default(), true)?
+            .unwrap();
+        chain.apply_block(&draft.block, true)?;
+        chain.update_states(&draft.patch)?;
+    }
+
+    // The keys are rotated as the upgrade takes effect
+    assert_eq!(
+        chain.get_contract(mpn)?.functions[0].verifier_key,
+        update_vk
+    );
+    assert_eq!(
+        chain.config.mpn_circuit_features_at(2),
+        MpnCircuitFeatures {
+            account_slot_choices: chain.config.mpn_account_slot_choices,
+            reuse_empty_token_slots: false
+        }
+    );
+    assert!(
+        chain
+            .config
+            .scheduled_mpn_circuit_features(2)
+            .reuse_empty_token_slots
+    );
+
+    chain.rollback()?;
+    assert_eq!(
+        chain.get_contract(mpn)?.functions[0].verifier_key,
+        zk::ZkVerifierKey::Dummy
+    );
+
+    rollback_till_empty(&mut chain)?;
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use super::*;
 use crate::config::blockchain;
-use crate::core::{Hasher, Signature, Signer, TransactionData};
+use crate::consensus::difficulty::TrimmedMean;
+use crate::core::{AccountPolicy, Hasher, RegularSendEntry, Signature, Signer, TransactionData};
 use crate::crypto::SignatureScheme;
 use crate::db;
 
@@ -51,7 +52,10 @@ fn test_get_header_and_get_block() -> Result<(), BlockchainError> {
     let miner = TxBuilder::new(&Vec::from("MINER"));
     let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
 
-    let new_block = chain.draft_block(60, &[], &miner, true)?.unwrap().block;
+    let new_block = chain
+        .draft_block(60, &[], &miner, &FeeRates::default(), true)?
+        .unwrap()
+        .block;
     chain.extend(1, &[new_block.clone()])?;
 
     assert_eq!(chain.get_block(1)?, new_block);
@@ -96,11 +100,16 @@ fn test_correct_target_calculation() -> Result<(), BlockchainError> {
     let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
 
     chain.apply_block(
-        &chain.draft_block(60, &[], &miner, true)?.unwrap().block,
+        &chain
+            .draft_block(60, &[], &miner, &FeeRates::default(), true)?
+            .unwrap()
+            .block,
         true,
     )?;
 
-    let mut wrong_pow = chain.draft_block(120, &[], &miner, true)?.unwrap();
+    let mut wrong_pow = chain
+        .draft_block(120, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     wrong_pow.block.header.proof_of_work.target = Difficulty(0x01ffffff);
     assert!(matches!(
         chain.apply_block(&wrong_pow.block, true),
@@ -118,67 +127,93 @@ fn test_difficulty_target_recalculation() -> Result<(), BlockchainError> {
     let mut conf = easy_config();
     conf.minimum_pow_difficulty = Difficulty::from_power(20);
     conf.block_time = 60;
-    conf.difficulty_window = 2;
-    conf.difficulty_cut = 0;
-    conf.difficulty_lag = 0;
+    conf.difficulty_algorithm = DifficultyAlgorithm::TrimmedMean(TrimmedMean {
+        window: 2,
+        lag: 0,
+        cut: 0,
+    });
     let mut chain = KvStoreChain::new(db::RamKvStore::new(), conf.clone())?;
 
-    let mut draft = chain.draft_block(30, &[], &miner, true)?.unwrap();
+    let mut draft = chain
+        .draft_block(30, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 20);
     mine_block(&chain, &mut draft)?;
     chain.extend(1, &[draft.block])?;
 
-    draft = chain.draft_block(60, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(60, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 40);
     mine_block(&chain, &mut draft)?;
     chain.extend(2, &[draft.block])?;
 
-    draft = chain.draft_block(120, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(120, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 80);
     mine_block(&chain, &mut draft)?;
     chain.extend(3, &[draft.block])?;
 
-    draft = chain.draft_block(480, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(480, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     mine_block(&chain, &mut draft)?;
     assert_eq!(draft.block.header.proof_of_work.target.power(), 80);
     chain.extend(4, &[draft.block])?;
 
-    draft = chain.draft_block(540, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(540, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 20);
     mine_block(&chain, &mut draft)?;
     chain.extend(5, &[draft.block])?;
 
-    draft = chain.draft_block(590, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(590, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 20);
     mine_block(&chain, &mut draft)?;
     chain.extend(6, &[draft.block])?;
 
-    draft = chain.draft_block(610, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(610, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 24);
     mine_block(&chain, &mut draft)?;
     chain.extend(7, &[draft.block])?;
 
-    draft = chain.draft_block(650, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(650, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 72);
     mine_block(&chain, &mut draft)?;
     chain.extend(8, &[draft.block])?;
 
-    draft = chain.draft_block(900, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(900, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 108);
     mine_block(&chain, &mut draft)?;
     chain.extend(9, &[draft.block])?;
 
-    draft = chain.draft_block(1000, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(1000, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 25);
     mine_block(&chain, &mut draft)?;
     chain.extend(10, &[draft.block])?;
 
-    draft = chain.draft_block(2000, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(2000, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 20);
     mine_block(&chain, &mut draft)?;
     chain.extend(11, &[draft.block])?;
 
-    draft = chain.draft_block(3000, &[], &miner, true)?.unwrap();
+    draft = chain
+        .draft_block(3000, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     assert_eq!(draft.block.header.proof_of_work.target.power(), 20);
     mine_block(&chain, &mut draft)?;
     chain.extend(12, &[draft.block])?;
@@ -212,7 +247,9 @@ fn test_pow_key_correctness() -> Result<(), BlockchainError> {
     let mut chain = KvStoreChain::new(db::RamKvStore::new(), conf)?;
 
     for i in 0..25 {
-        let mut draft = chain.draft_block(i * 60, &[], &miner, true)?.unwrap();
+        let mut draft = chain
+            .draft_block(i * 60, &[], &miner, &FeeRates::default(), true)?
+            .unwrap();
         mine_block(&chain, &mut draft)?;
         chain.apply_block(&draft.block, true)?;
         chain.update_states(&draft.patch)?;
@@ -246,7 +283,10 @@ fn test_median_timestamp_correctness_check() -> Result<(), BlockchainError> {
 
     let mut fork1 = chain.fork_on_ram();
     fork1.apply_block(
-        &fork1.draft_block(10, &[], &miner, true)?.unwrap().block,
+        &fork1
+            .draft_block(10, &[], &miner, &FeeRates::default(), true)?
+            .unwrap()
+            .block,
         false,
     )?;
     assert!(matches!(
@@ -254,6 +294,7 @@ fn test_median_timestamp_correctness_check() -> Result<(), BlockchainError> {
             5, // 5 < 10
             &[],
             &miner,
+            &FeeRates::default(),
             true,
         ),
         Err(BlockchainError::InvalidTimestamp)
@@ -264,6 +305,7 @@ fn test_median_timestamp_correctness_check() -> Result<(), BlockchainError> {
                 10, // 10, again, should be fine
                 &[],
                 &miner,
+                &FeeRates::default(),
                 true,
             )?
             .unwrap()
@@ -273,7 +315,10 @@ fn test_median_timestamp_correctness_check() -> Result<(), BlockchainError> {
 
     for i in 11..30 {
         fork1.apply_block(
-            &fork1.draft_block(i, &[], &miner, true)?.unwrap().block,
+            &fork1
+                .draft_block(i, &[], &miner, &FeeRates::default(), true)?
+                .unwrap()
+                .block,
             false,
         )?;
     }
@@ -286,12 +331,16 @@ fn test_median_timestamp_correctness_check() -> Result<(), BlockchainError> {
             24, // 24 < 25
             &[],
             &miner,
+            &FeeRates::default(),
             true,
         ),
         Err(BlockchainError::InvalidTimestamp)
     ));
     fork1.apply_block(
-        &fork1.draft_block(25, &[], &miner, true)?.unwrap().block,
+        &fork1
+            .draft_block(25, &[], &miner, &FeeRates::default(), true)?
+            .unwrap()
+            .block,
         false,
     )?;
 
@@ -307,9 +356,13 @@ fn test_block_number_correctness_check() -> Result<(), BlockchainError> {
     let miner = TxBuilder::new(&Vec::from("MINER"));
     let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
     let mut fork1 = chain.fork_on_ram();
-    let blk1 = fork1.draft_block(0, &[], &miner, true)?.unwrap();
+    let blk1 = fork1
+        .draft_block(0, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     fork1.extend(1, &[blk1.block.clone()])?;
-    let blk2 = fork1.draft_block(1, &[], &miner, true)?.unwrap();
+    let blk2 = fork1
+        .draft_block(1, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     fork1.extend(2, &[blk2.block.clone()])?;
     assert_eq!(fork1.get_height()?, 3);
 
@@ -343,14 +396,375 @@ fn test_block_number_correctness_check() -> Result<(), BlockchainError> {
     Ok(())
 }
 
+#[test]
+fn test_checkpoints_and_finality() -> Result<(), BlockchainError> {
+    let miner = TxBuilder::new(&Vec::from("MINER"));
+    let other_miner = TxBuilder::new(&Vec::from("OTHER MINER"));
+    let chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
+    let blk1 = chain
+        .draft_block(0, &[], &miner, &FeeRates::default(), true)?
+        .unwrap()
+        .block;
+    let blk1_alt = chain
+        .draft_block(0, &[], &other_miner, &FeeRates::default(), true)?
+        .unwrap()
+        .block;
+
+    let mut conf = easy_config();
+    conf.checkpoints.insert(1, blk1.header.hash());
+    let mut checkpointed = KvStoreChain::new(db::RamKvStore::new(), conf)?;
+    assert!(matches!(
+        checkpointed.will_extend(1, std::slice::from_ref(&blk1_alt.header), false),
+        Err(BlockchainError::CheckpointMismatch)
+    ));
+    assert!(matches!(
+        checkpointed.extend(1, std::slice::from_ref(&blk1_alt)),
+        Err(BlockchainError::CheckpointMismatch)
+    ));
+    checkpointed.extend(1, std::slice::from_ref(&blk1))?;
+    assert_eq!(checkpointed.get_height()?, 2);
+
+    let mut conf = easy_config();
+    conf.finality_depth = Some(1);
+    let mut chain = KvStoreChain::new(db::RamKvStore::new(), conf)?;
+    chain.extend(1, &[blk1])?;
+    let blk2 = chain
+        .draft_block(1, &[], &miner, &FeeRates::default(), true)?
+        .unwrap()
+        .block;
+    chain.extend(2, &[blk2])?;
+    assert_eq!(chain.get_finalized_height()?, 2);
+
+    // Block #1 is final, only block #2 can be replaced
+    assert!(matches!(
+        chain.will_extend(1, std::slice::from_ref(&blk1_alt.header), false),
+        Err(BlockchainError::BeyondFinality)
+    ));
+    assert!(matches!(
+        chain.extend(1, &[blk1_alt]),
+        Err(BlockchainError::BeyondFinality)
+    ));
+    chain.rollback()?;
+    assert!(matches!(
+        chain.rollback(),
+        Err(BlockchainError::BeyondFinality)
+    ));
+    assert_eq!(chain.get_height()?, 2);
+
+    // The stored finalized height is ignored by a chain without finality depth
+    let mut copy = db::RamKvStore::new();
+    copy.update(
+        &chain
+            .db()
+            .pairs("".into())?
+            .into_iter()
+            .map(|(k, v)| db::WriteOp::Put(k, v))
+            .collect::<Vec<_>>(),
+    )?;
+    let mut unfinalized = KvStoreChain::new(copy, easy_config())?;
+    unfinalized.rollback()?;
+    assert_eq!(unfinalized.get_height()?, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_transaction_validity_window() -> Result<(), BlockchainError> {
+    let miner = TxBuilder::new(&Vec::from("MINER"));
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let bob = TxBuilder::new(&Vec::from("CBA"));
+    let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
+
+    let window_tx = |after: Option<u64>, until: Option<u64>| {
+        let mut tx_delta = alice.create_transaction(
+            "".into(),
+            bob.get_address(),
+            Money::ziesha(10),
+            Money::ziesha(0),
+            1,
+        );
+        tx_delta.tx.valid_after_height = after;
+        tx_delta.tx.valid_until_height = until;
+        tx_delta.tx.sig = Signature::Unsigned;
+        alice.sign_tx(&mut tx_delta.tx);
+        tx_delta
+    };
+
+    // Next block is #1
+    let scheduled = window_tx(Some(1), None);
+    let expired = window_tx(None, Some(0));
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(&scheduled.tx, false),
+        Err(BlockchainError::TransactionNotYetValid)
+    ));
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(&expired.tx, false),
+        Err(BlockchainError::TransactionExpired)
+    ));
+    // The window is carried in the data, and survives serialization
+    let decoded: Transaction =
+        bincode::deserialize(&bincode::serialize(&scheduled.tx).unwrap()).unwrap();
+    assert_eq!(decoded, scheduled.tx);
+    assert!(decoded.verify_signature());
+    // A window without bounds is not a canonical encoding
+    let mut empty_window = window_tx(None, None).tx;
+    empty_window.data = TransactionData::ValidityWindow {
+        valid_after_height: None,
+        valid_until_height: None,
+        data: Box::new(empty_window.data),
+    };
+    assert!(
+        bincode::deserialize::<Transaction>(&bincode::serialize(&empty_window).unwrap()).is_err()
+    );
+
+    // Only the scheduled transaction is kept in the mempool, as long as it
+    // doesn't get valid too far in the future and would otherwise apply
+    let too_far = window_tx(
+        Some(chain.get_height()? + chain.config.max_tx_schedule + 1),
+        None,
+    );
+    let mut bad_nonce = window_tx(Some(1), None);
+    bad_nonce.tx.nonce = 2;
+    let mut bad_sig = window_tx(Some(1), None);
+    bad_sig.tx.sig = Signature::Unsigned;
+    bob.sign_tx(&mut bad_sig.tx);
+    let no_balance = {
+        let mut tx_delta = window_tx(Some(1), None);
+        tx_delta.tx.data = TransactionData::RegularSend {
+            entries: vec![RegularSendEntry {
+                dst: bob.get_address(),
+                amount: Money::ziesha(u64::MAX),
+            }],
+        };
+        tx_delta.tx.sig = Signature::Unsigned;
+        alice.sign_tx(&mut tx_delta.tx);
+        tx_delta
+    };
+    for (tx, kept) in [
+        (&scheduled, true),
+        (&expired, false),
+        (&too_far, false),
+        (&bad_nonce, false),
+        (&bad_sig, false),
+        (&no_balance, false),
+    ] {
+        let draft = chain
+            .draft_block(
+                1,
+                std::slice::from_ref(tx),
+                &miner,
+                &FeeRates::default(),
+                true,
+            )?
+            .unwrap();
+        assert_eq!(draft.block.body.len(), 1);
+        let mut mempool = [(
+            ChainSourcedTx::TransactionAndDelta(tx.clone()),
+            TransactionStats::new(0),
+        )]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        chain.cleanup_chain_mempool(&mut mempool)?;
+        assert_eq!(mempool.is_empty(), !kept);
+    }
+
+    // Not accepted before the activation of the upgrade
+    let mut not_upgraded = chain.fork_on_ram();
+    not_upgraded.config.upgrades = Default::default();
+    assert!(matches!(
+        not_upgraded.apply_tx(&window_tx(None, Some(5)).tx, false),
+        Err(BlockchainError::UpgradeNotActive(
+            Upgrade::TransactionValidityWindow
+        ))
+    ));
+    drop(not_upgraded);
+
+    let blk = chain
+        .draft_block(1, &[], &miner, &FeeRates::default(), true)?
+        .unwrap()
+        .block;
+    chain.apply_block(&blk, true)?;
+
+    let draft = chain
+        .draft_block(2, &[scheduled], &miner, &FeeRates::default(), true)?
+        .unwrap();
+    assert_eq!(draft.block.body.len(), 2);
+    chain.apply_block(&draft.block, true)?;
+    assert_eq!(
+        chain.get_balance(bob.get_address(), TokenId::Ziesha)?,
+        Amount(10)
+    );
+
+    rollback_till_empty(&mut chain)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_account_policy() -> Result<(), BlockchainError> {
+    let miner = TxBuilder::new(&Vec::from("MINER"));
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let bob = TxBuilder::new(&Vec::from("CBA"));
+    let rescuer = TxBuilder::new(&Vec::from("RESCUER"));
+    let new_key = TxBuilder::new(&Vec::from("NEW KEY"));
+    let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
+
+    let mine = |chain: &mut KvStoreChain<db::RamKvStore>,
+                txs: &[TransactionAndDelta]|
+     -> Result<usize, BlockchainError> {
+        let blk = chain
+            .draft_block(1, txs, &miner, &FeeRates::default(), true)?
+            .unwrap()
+            .block;
+        chain.apply_block(&blk, true)?;
+        Ok(blk.body.len() - 1)
+    };
+    let send = |amount: u64, nonce: u32| {
+        alice.create_transaction(
+            "".into(),
+            bob.get_address(),
+            Money::ziesha(amount),
+            Money::ziesha(0),
+            nonce,
+        )
+    };
+
+    let policy = AccountPolicy {
+        daily_limits: vec![Money::ziesha(100)],
+        recovery_key: Some(rescuer.get_address()),
+        delay: 2,
+    };
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(
+            &alice
+                .set_account_policy(
+                    "".into(),
+                    Some(AccountPolicy {
+                        delay: 0,
+                        ..policy.clone()
+                    }),
+                    Money::ziesha(0),
+                    1
+                )
+                .tx,
+            false
+        ),
+        Err(BlockchainError::InvalidAccountPolicy)
+    ));
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(
+            &alice
+                .set_account_policy(
+                    "".into(),
+                    Some(AccountPolicy {
+                        delay: chain.config.max_policy_delay + 1,
+                        ..policy.clone()
+                    }),
+                    Money::ziesha(0),
+                    1
+                )
+                .tx,
+            false
+        ),
+        Err(BlockchainError::InvalidAccountPolicy)
+    ));
+    let set_policy = alice.set_account_policy("".into(), Some(policy), Money::ziesha(0), 1);
+    assert_eq!(mine(&mut chain, &[set_policy, send(60, 2)])?, 2);
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(&send(50, 3).tx, false),
+        Err(BlockchainError::SpendingLimitExceeded)
+    ));
+
+    // Removing the policy is delayed
+    let remove_policy = alice.set_account_policy("".into(), None, Money::ziesha(0), 3);
+    assert_eq!(mine(&mut chain, &[remove_policy])?, 1);
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(&send(50, 4).tx, false),
+        Err(BlockchainError::SpendingLimitExceeded)
+    ));
+
+    // Only the recovery key can rotate the key, cancelling the pending changes
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(
+            &bob.recover_account(
+                "".into(),
+                alice.get_address(),
+                bob.get_address(),
+                Money::ziesha(0),
+                1
+            )
+            .tx,
+            false
+        ),
+        Err(BlockchainError::AccountNotRecoverable)
+    ));
+    let recover = |nonce: u32| {
+        rescuer.recover_account(
+            "".into(),
+            alice.get_address(),
+            new_key.get_address(),
+            Money::ziesha(0),
+            nonce,
+        )
+    };
+    assert_eq!(mine(&mut chain, &[recover(1)])?, 1);
+
+    // Only the recovery key may cancel the recovery before it takes effect,
+    // so that a stolen main key can't keep the account from being recovered
+    let cancel = |builder: &TxBuilder, nonce: u32| {
+        builder.cancel_recovery("".into(), alice.get_address(), Money::ziesha(0), nonce)
+    };
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(&cancel(&alice, 4).tx, false),
+        Err(BlockchainError::AccountNotRecoverable)
+    ));
+    assert_eq!(mine(&mut chain, &[cancel(&rescuer, 2)])?, 1);
+    mine(&mut chain, &[])?;
+    let control = chain.get_account_control(alice.get_address())?;
+    assert!(control.key.is_none() && control.pending_key.is_none());
+    assert!(matches!(
+        chain.fork_on_ram().apply_tx(&cancel(&rescuer, 3).tx, false),
+        Err(BlockchainError::NoPendingRecovery)
+    ));
+
+    assert_eq!(mine(&mut chain, &[recover(3)])?, 1);
+    mine(&mut chain, &[])?;
+
+    let old_key_tx = send(10, 4);
+    let mut new_key_tx = old_key_tx.clone();
+    new_key_tx.tx.sig = Signature::Unsigned;
+    new_key.sign_tx(&mut new_key_tx.tx);
+    let height = chain.get_height()?;
+    assert!(!chain.verify_tx_signature(&old_key_tx.tx, height)?);
+    assert!(chain.verify_tx_signature(&new_key_tx.tx, height)?);
+    assert_eq!(mine(&mut chain, &[old_key_tx])?, 0);
+    assert_eq!(mine(&mut chain, &[new_key_tx])?, 1);
+
+    let control = chain.get_account_control(alice.get_address())?;
+    assert_eq!(control.key, Some(new_key.get_address()));
+    assert!(control.policy.is_some() && control.pending_policy.is_none());
+    assert_eq!(
+        chain.get_balance(bob.get_address(), TokenId::Ziesha)?,
+        Amount(70)
+    );
+
+    rollback_till_empty(&mut chain)?;
+
+    Ok(())
+}
+
 #[test]
 fn test_parent_hash_correctness_check() -> Result<(), BlockchainError> {
     let miner = TxBuilder::new(&Vec::from("MINER"));
     let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
     let mut fork1 = chain.fork_on_ram();
-    let blk1 = fork1.draft_block(0, &[], &miner, true)?.unwrap();
+    let blk1 = fork1
+        .draft_block(0, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     fork1.extend(1, &[blk1.block.clone()])?;
-    let blk2 = fork1.draft_block(1, &[], &miner, true)?.unwrap();
+    let blk2 = fork1
+        .draft_block(1, &[], &miner, &FeeRates::default(), true)?
+        .unwrap();
     fork1.extend(2, &[blk2.block.clone()])?;
     assert_eq!(fork1.get_height()?, 3);
 
@@ -409,6 +823,7 @@ fn test_merkle_root_check() -> Result<(), BlockchainError> {
                 ),
             ],
             &miner,
+            &FeeRates::default(),
             true,
         )?
         .unwrap()
@@ -433,6 +848,7 @@ fn test_merkle_root_check() -> Result<(), BlockchainError> {
                 ),
             ],
             &miner,
+            &FeeRates::default(),
             true,
         )?
         .unwrap()
@@ -497,7 +913,7 @@ fn test_txs_cant_be_duplicated() -> Result<(), BlockchainError> {
     // Alice -> 2700 -> Bob (Fee 300)
     chain.apply_block(
         &chain
-            .draft_block(1, &[tx.clone()], &miner, true)?
+            .draft_block(1, &[tx.clone()], &miner, &FeeRates::default(), true)?
             .unwrap()
             .block,
         true,
@@ -514,7 +930,7 @@ fn test_txs_cant_be_duplicated() -> Result<(), BlockchainError> {
     // Alice -> 2700 -> Bob (Fee 300) (NOT APPLIED: DUPLICATED TRANSACTION!)
     chain.apply_block(
         &chain
-            .draft_block(1, &[tx.clone()], &miner, true)?
+            .draft_block(1, &[tx.clone()], &miner, &FeeRates::default(), true)?
             .unwrap()
             .block,
         true,
@@ -538,7 +954,10 @@ fn test_txs_cant_be_duplicated() -> Result<(), BlockchainError> {
 
     // Alice -> 2700 -> Bob (Fee 300)
     chain.apply_block(
-        &chain.draft_block(1, &[tx2], &miner, true)?.unwrap().block,
+        &chain
+            .draft_block(1, &[tx2], &miner, &FeeRates::default(), true)?
+            .unwrap()
+            .block,
         true,
     )?;
     assert_eq!(
@@ -592,7 +1011,10 @@ fn test_insufficient_balance_is_handled() -> Result<(), BlockchainError> {
 
     // Ensure tx is not included in block and bob has not received funds
     chain.apply_block(
-        &chain.draft_block(1, &[tx], &miner, true)?.unwrap().block,
+        &chain
+            .draft_block(1, &[tx], &miner, &FeeRates::default(), true)?
+            .unwrap()
+            .block,
         true,
     )?;
     assert_eq!(
@@ -625,6 +1047,8 @@ fn test_cant_apply_unsigned_tx() -> Result<(), BlockchainError> {
         },
         nonce: 1,
         fee: Money::ziesha(300),
+        valid_after_height: None,
+        valid_until_height: None,
         sig: Signature::Unsigned,
     };
     let unsigned_tx = TransactionAndDelta {
@@ -633,7 +1057,13 @@ fn test_cant_apply_unsigned_tx() -> Result<(), BlockchainError> {
     };
 
     // Ensure apply_tx will raise
-    match chain.draft_block(1, &[unsigned_tx.clone()], &miner, false) {
+    match chain.draft_block(
+        1,
+        &[unsigned_tx.clone()],
+        &miner,
+        &FeeRates::default(),
+        false,
+    ) {
         Ok(_) => assert!(false, "Unsigned transaction shall not be applied"),
         Err(e) => assert!(matches!(e, BlockchainError::SignatureError)),
     }
@@ -641,7 +1071,7 @@ fn test_cant_apply_unsigned_tx() -> Result<(), BlockchainError> {
     // Ensure tx is not included in block and bob has not received funds
     chain.apply_block(
         &chain
-            .draft_block(1, &[unsigned_tx], &miner, true)?
+            .draft_block(1, &[unsigned_tx], &miner, &FeeRates::default(), true)?
             .unwrap()
             .block,
         true,
@@ -677,6 +1107,8 @@ fn test_cant_apply_invalid_signed_tx() -> Result<(), BlockchainError> {
         },
         nonce: 1,
         fee: Money::ziesha(300),
+        valid_after_height: None,
+        valid_until_height: None,
         sig: Signature::Unsigned,
     };
 
@@ -690,14 +1122,17 @@ fn test_cant_apply_invalid_signed_tx() -> Result<(), BlockchainError> {
     };
 
     // Ensure apply_tx will raise
-    match chain.draft_block(1, &[tx.clone()], &miner, false) {
+    match chain.draft_block(1, &[tx.clone()], &miner, &FeeRates::default(), false) {
         Ok(_) => assert!(false, "Invalid signed transaction shall not be applied"),
         Err(e) => assert!(matches!(e, BlockchainError::SignatureError)),
     }
 
     // Ensure tx is not included in block and bob has not received funds
     chain.apply_block(
-        &chain.draft_block(1, &[tx], &miner, true)?.unwrap().block,
+        &chain
+            .draft_block(1, &[tx], &miner, &FeeRates::default(), true)?
+            .unwrap()
+            .block,
         true,
     )?;
     assert_eq!(
@@ -741,6 +1176,7 @@ fn test_balances_are_correct_after_tx() -> Result<(), BlockchainError> {
                     1,
                 )],
                 &miner,
+                &FeeRates::default(),
                 true,
             )?
             .unwrap()
@@ -769,6 +1205,7 @@ fn test_balances_are_correct_after_tx() -> Result<(), BlockchainError> {
                     1,
                 )],
                 &miner,
+                &FeeRates::default(),
                 true,
             )?
             .unwrap()
@@ -797,6 +1234,7 @@ fn test_balances_are_correct_after_tx() -> Result<(), BlockchainError> {
                     1,
                 )],
                 &miner,
+                &FeeRates::default(),
                 true,
             )?
             .unwrap()
@@ -825,6 +1263,7 @@ fn test_balances_are_correct_after_tx() -> Result<(), BlockchainError> {
                     2,
                 )],
                 &miner,
+                &FeeRates::default(),
                 true,
             )?
             .unwrap()
@@ -853,6 +1292,7 @@ fn test_balances_are_correct_after_tx() -> Result<(), BlockchainError> {
                     3,
                 )],
                 &miner,
+                &FeeRates::default(),
                 true,
             )?
             .unwrap()
@@ -915,6 +1355,8 @@ fn test_chain_should_apply_mined_draft_block() -> Result<(), BlockchainError> {
         },
         nonce: 4,
         fee: Money::ziesha(0),
+        valid_after_height: None,
+        valid_until_height: None,
         sig: Signature::Unsigned,
     });
 
@@ -929,7 +1371,13 @@ fn test_chain_should_apply_mined_draft_block() -> Result<(), BlockchainError> {
     );
     let mempool = vec![t1];
     let mut draft = chain
-        .draft_block(1650000000, &mempool, &wallet_miner, true)?
+        .draft_block(
+            1650000000,
+            &mempool,
+            &wallet_miner,
+            &FeeRates::default(),
+            true,
+        )?
         .unwrap();
 
     assert!(matches!(
@@ -983,6 +1431,8 @@ fn test_chain_should_not_draft_invalid_transactions() -> Result<(), BlockchainEr
         },
         nonce: 4,
         fee: Money::ziesha(0),
+        valid_after_height: None,
+        valid_until_height: None,
         sig: Signature::Unsigned,
     });
 
@@ -1007,6 +1457,8 @@ fn test_chain_should_not_draft_invalid_transactions() -> Result<(), BlockchainEr
             },
             nonce: 1,
             fee: Money::ziesha(0),
+            valid_after_height: None,
+            valid_until_height: None,
             sig: Signature::Unsigned, // invalid transaction
         },
         state_delta: None,
@@ -1023,13 +1475,21 @@ fn test_chain_should_not_draft_invalid_transactions() -> Result<(), BlockchainEr
             },
             nonce: 1,
             fee: Money::ziesha(0),
+            valid_after_height: None,
+            valid_until_height: None,
             sig: Signature::Unsigned, // invalid transaction
         },
         state_delta: None,
     };
     let mempool = vec![t_valid, t_invalid_unsigned, t_invalid_from_treasury];
     let mut draft = chain
-        .draft_block(1650000000, &mempool, &wallet_miner, true)?
+        .draft_block(
+            1650000000,
+            &mempool,
+            &wallet_miner,
+            &FeeRates::default(),
+            true,
+        )?
         .unwrap();
 
     mine_block(&chain, &mut draft)?;
@@ -1060,6 +1520,8 @@ fn test_chain_should_draft_all_valid_transactions() -> Result<(), BlockchainErro
         },
         nonce: 4,
         fee: Money::ziesha(0),
+        valid_after_height: None,
+        valid_until_height: None,
         sig: Signature::Unsigned,
     });
 
@@ -1082,7 +1544,13 @@ fn test_chain_should_draft_all_valid_transactions() -> Result<(), BlockchainErro
 
     let mempool = vec![t1, t2];
     let mut draft = chain
-        .draft_block(1650000000, &mempool, &wallet_miner, true)?
+        .draft_block(
+            1650000000,
+            &mempool,
+            &wallet_miner,
+            &FeeRates::default(),
+            true,
+        )?
         .unwrap();
 
     mine_block(&chain, &mut draft)?;
@@ -1119,6 +1587,8 @@ fn test_chain_should_rollback_applied_block() -> Result<(), BlockchainError> {
         },
         nonce: 4,
         fee: Money::ziesha(0),
+        valid_after_height: None,
+        valid_until_height: None,
         sig: Signature::Unsigned,
     });
 
@@ -1133,7 +1603,13 @@ fn test_chain_should_rollback_applied_block() -> Result<(), BlockchainError> {
     );
     let mut mempool = vec![t1];
     let mut draft = chain
-        .draft_block(1650000000, &mempool, &wallet_miner, true)?
+        .draft_block(
+            1650000000,
+            &mempool,
+            &wallet_miner,
+            &FeeRates::default(),
+            true,
+        )?
         .unwrap();
 
     mine_block(&chain, &mut draft)?;
@@ -1150,7 +1626,13 @@ fn test_chain_should_rollback_applied_block() -> Result<(), BlockchainError> {
     mempool.push(t2);
 
     let mut draft = chain
-        .draft_block(1650000001, &mempool, &wallet_miner, true)?
+        .draft_block(
+            1650000001,
+            &mempool,
+            &wallet_miner,
+            &FeeRates::default(),
+            true,
+        )?
         .unwrap();
 
     mine_block(&chain, &mut draft)?;
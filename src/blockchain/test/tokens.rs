@@ -32,6 +32,7 @@ fn test_token_balances() -> Result<(), BlockchainError> {
                 1
             )],
             &miner,
+            &FeeRates::default(),
             false,
         ),
         Err(BlockchainError::BalanceInsufficient)
@@ -39,7 +40,7 @@ fn test_token_balances() -> Result<(), BlockchainError> {
 
     chain.apply_block(
         &chain
-            .draft_block(1, &[token_create_tx], &miner, false)?
+            .draft_block(1, &[token_create_tx], &miner, &FeeRates::default(), false)?
             .unwrap()
             .block,
         true,
@@ -62,6 +63,7 @@ fn test_token_balances() -> Result<(), BlockchainError> {
                     2,
                 )],
                 &miner,
+                &FeeRates::default(),
                 false,
             )?
             .unwrap()
@@ -87,6 +89,7 @@ fn test_token_balances() -> Result<(), BlockchainError> {
                 3
             )],
             &miner,
+            &FeeRates::default(),
             false,
         ),
         Err(BlockchainError::BalanceInsufficient)
@@ -104,6 +107,7 @@ fn test_token_balances() -> Result<(), BlockchainError> {
                     3,
                 )],
                 &miner,
+                &FeeRates::default(),
                 false,
             )?
             .unwrap()
@@ -117,3 +121,100 @@ fn test_token_balances() -> Result<(), BlockchainError> {
 
     Ok(())
 }
+
+#[test]
+fn test_token_fees() -> Result<(), BlockchainError> {
+    let miner = TxBuilder::new(&Vec::from("MINER"));
+    let alice = TxBuilder::new(&Vec::from("ABCD"));
+    let bob = TxBuilder::new(&Vec::from("DCBA"));
+
+    let mut chain = KvStoreChain::new(db::RamKvStore::new(), easy_config())?;
+
+    let (token_create_tx, token_id) = alice.create_token(
+        "".into(),
+        "My Token".into(),
+        "MYT".into(),
+        Amount(12345),
+        0,
+        Some(alice.get_address()),
+        Money::ziesha(0),
+        1,
+    );
+    chain.apply_block(
+        &chain
+            .draft_block(1, &[token_create_tx], &miner, &FeeRates::default(), false)?
+            .unwrap()
+            .block,
+        true,
+    )?;
+
+    let tx = alice.create_transaction(
+        "".into(),
+        bob.get_address(),
+        Money::new(token_id, 20),
+        Money::new(token_id, 5),
+        2,
+    );
+
+    // Tokens without an exchange rate are not accepted as fee
+    let draft = chain
+        .draft_block(
+            1,
+            std::slice::from_ref(&tx),
+            &miner,
+            &FeeRates::default(),
+            false,
+        )?
+        .unwrap();
+    assert_eq!(draft.block.body.len(), 1);
+
+    let rates = FeeRates::default().with_rate(token_id, 0.5);
+    assert_eq!(rates.value(Money::new(token_id, 5)), Some(2.5));
+
+    // Only accepted after the activation of the upgrade
+    let mut not_upgraded = chain.fork_on_ram();
+    not_upgraded.config.upgrades = Default::default();
+    assert!(matches!(
+        not_upgraded.draft_block(1, std::slice::from_ref(&tx), &miner, &rates, true),
+        Ok(Some(draft)) if draft.block.body.len() == 1
+    ));
+    drop(not_upgraded);
+    let draft = chain.draft_block(1, &[tx], &miner, &rates, false)?.unwrap();
+    assert_eq!(draft.block.body.len(), 2);
+    if let TransactionData::RegularSend { entries } = &draft.block.body[0].data {
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].amount.token_id, TokenId::Ziesha);
+        assert_eq!(entries[1].amount, Money::new(token_id, 5));
+    } else {
+        panic!("Invalid miner reward!");
+    }
+    chain.apply_block(&draft.block, true)?;
+
+    assert_eq!(
+        chain.get_balance(alice.get_address(), token_id)?,
+        Amount(12320)
+    );
+    assert_eq!(chain.get_balance(bob.get_address(), token_id)?, Amount(20));
+    assert_eq!(chain.get_balance(miner.get_address(), token_id)?, Amount(5));
+    assert_eq!(chain.get_balance(Default::default(), token_id)?, Amount(0));
+
+    // Sums of the fees never wrap around
+    assert!(matches!(
+        miner_reward_entries(
+            miner.get_address(),
+            Amount(1),
+            [Money::ziesha(0), Money::new(token_id, 1)]
+        ),
+        Ok(entries) if entries.len() == 2
+    ));
+    for fee in [Money::ziesha(u64::MAX), Money::new(token_id, u64::MAX)] {
+        assert!(matches!(
+            miner_reward_entries(miner.get_address(), Amount(1), [fee, fee]),
+            Err(BlockchainError::FeesOverflow)
+        ));
+    }
+
+    rollback_till_empty(&mut chain)?;
+
+    Ok(())
+}
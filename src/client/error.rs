@@ -1,6 +1,7 @@
-use super::messages::InputError;
+use super::messages::{ErrorResponse, InputError};
 use crate::blockchain::BlockchainError;
 use crate::zk::ZkError;
+use hyper::{Body, Response, StatusCode};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -33,6 +34,10 @@ pub enum NodeError {
     AccountParseAddressError(#[from] crate::core::ParseAddressError),
     #[error("cannot parse account address: {0}")]
     TokenIdParseError(#[from] crate::core::ParseTokenIdError),
+    #[error("cannot parse contract id: {0}")]
+    ContractIdParseError(#[from] crate::core::ParseContractIdError),
+    #[error("cannot parse mpn address: {0}")]
+    MpnAddressParseError(#[from] crate::core::ParseMpnAddressError),
     #[error("timeout reached: {0}")]
     TimeoutError(#[from] tokio::time::error::Elapsed),
     #[error("http body size limit error")]
@@ -55,4 +60,208 @@ pub enum NodeError {
     HandshakeClientMismatch,
     #[error("remote server error: {0}")]
     RemoteServerError(String),
+    #[error("miner puzzle is invalid")]
+    InvalidPuzzle,
+    #[error("node is not running a mining pool")]
+    PoolDisabled,
+    #[error("share signature is invalid")]
+    InvalidShareSignature,
+    #[error("too many subscriptions from this client")]
+    TooManySubscriptions,
+    #[cfg(feature = "mpn-executor")]
+    #[error("mpn executor error: {0}")]
+    MpnError(#[from] crate::mpn::MpnError),
+    #[error("remote error ({code}): {message}")]
+    RemoteError { code: String, message: String },
+}
+
+const BLOCKCHAIN_CODE_PREFIX: &str = "blockchain_error.";
+
+impl NodeError {
+    /// Stable identifier of the error, reported to the API clients
+    pub fn code(&self) -> String {
+        let code = match self {
+            NodeError::BlockchainError(e) => {
+                return format!("{}{}", BLOCKCHAIN_CODE_PREFIX, e.code());
+            }
+            NodeError::RemoteError { code, .. } => return code.clone(),
+            NodeError::NotListeningError => "not_listening",
+            NodeError::NotAnsweringError => "not_answering",
+            NodeError::NodeIsClientOnly => "node_is_client_only",
+            NodeError::ServerError(_) => "server_error",
+            NodeError::ClientError(_) => "client_error",
+            NodeError::InvalidHeaderError(_) => "invalid_header",
+            NodeError::JsonError(_) => "json_error",
+            NodeError::QueryStringError(_) => "query_string_error",
+            NodeError::BincodeError(_) => "bincode_error",
+            NodeError::Utf8Error(_) => "utf8_error",
+            NodeError::AddrParseError(_) => "addr_parse_error",
+            NodeError::AccountParseAddressError(_) => "invalid_address",
+            NodeError::TokenIdParseError(_) => "invalid_token_id",
+            NodeError::ContractIdParseError(_) => "invalid_contract_id",
+            NodeError::MpnAddressParseError(_) => "invalid_mpn_address",
+            NodeError::TimeoutError(_) => "timeout",
+            NodeError::SizeLimitError => "size_limit",
+            NodeError::InputError(_) => "invalid_input",
+            NodeError::InvalidSignatureHeader => "invalid_signature_header",
+            NodeError::InvalidMinerTokenHeader => "invalid_miner_token_header",
+            NodeError::SignatureRequired => "signature_required",
+            NodeError::ZkError(_) => "zk_error",
+            NodeError::WrongNetwork => "wrong_network",
+            NodeError::StatesOutdated => "states_outdated",
+            NodeError::HandshakeClientMismatch => "handshake_client_mismatch",
+            NodeError::RemoteServerError(_) => "remote_server_error",
+            NodeError::InvalidPuzzle => "invalid_puzzle",
+            NodeError::PoolDisabled => "pool_disabled",
+            NodeError::InvalidShareSignature => "invalid_share_signature",
+            NodeError::TooManySubscriptions => "too_many_subscriptions",
+            #[cfg(feature = "mpn-executor")]
+            NodeError::MpnError(_) => "mpn_error",
+        };
+        code.into()
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            NodeError::BlockchainError(e) => match e {
+                BlockchainError::KvStoreError(_)
+                | BlockchainError::StateManagerError(_)
+                | BlockchainError::Inconsistency => StatusCode::INTERNAL_SERVER_ERROR,
+                BlockchainError::BlockNotFound
+                | BlockchainError::ContractNotFound
+                | BlockchainError::TokenNotFound
+                | BlockchainError::CompressedStateNotFound
+                | BlockchainError::StatesUnavailable => StatusCode::NOT_FOUND,
+                _ => StatusCode::BAD_REQUEST,
+            },
+            NodeError::JsonError(_)
+            | NodeError::QueryStringError(_)
+            | NodeError::BincodeError(_)
+            | NodeError::Utf8Error(_)
+            | NodeError::AddrParseError(_)
+            | NodeError::AccountParseAddressError(_)
+            | NodeError::TokenIdParseError(_)
+            | NodeError::ContractIdParseError(_)
+            | NodeError::MpnAddressParseError(_)
+            | NodeError::InputError(_)
+            | NodeError::ZkError(_)
+            | NodeError::WrongNetwork => StatusCode::BAD_REQUEST,
+            NodeError::InvalidSignatureHeader
+            | NodeError::InvalidMinerTokenHeader
+            | NodeError::SignatureRequired => StatusCode::UNAUTHORIZED,
+            NodeError::HandshakeClientMismatch => StatusCode::FORBIDDEN,
+            NodeError::InvalidShareSignature => StatusCode::UNAUTHORIZED,
+            NodeError::PoolDisabled => StatusCode::NOT_FOUND,
+            NodeError::TooManySubscriptions => StatusCode::TOO_MANY_REQUESTS,
+            NodeError::SizeLimitError => StatusCode::PAYLOAD_TOO_LARGE,
+            NodeError::StatesOutdated | NodeError::NodeIsClientOnly => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            NodeError::TimeoutError(_) => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn to_response(&self) -> ErrorResponse {
+        ErrorResponse {
+            code: self.code(),
+            message: self.to_string(),
+            details: match self {
+                NodeError::BlockchainError(e) => e.details(),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn to_http_response(&self) -> Response<Body> {
+        let mut resp = Response::new(Body::from(
+            serde_json::to_vec(&self.to_response()).unwrap_or_default(),
+        ));
+        *resp.status_mut() = self.status_code();
+        resp.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static("application/json"),
+        );
+        resp
+    }
+
+    /// Decodes the body of a failed response back into a typed error
+    pub fn from_response_body(body: &[u8]) -> Self {
+        match serde_json::from_slice::<ErrorResponse>(body) {
+            Ok(resp) => resp.into(),
+            Err(_) => NodeError::RemoteServerError(String::from_utf8_lossy(body).to_string()),
+        }
+    }
+}
+
+impl From<ErrorResponse> for NodeError {
+    fn from(resp: ErrorResponse) -> Self {
+        if let Some(code) = resp.code.strip_prefix(BLOCKCHAIN_CODE_PREFIX) {
+            if let Some(e) = BlockchainError::from_code(code, resp.details.as_ref()) {
+                return NodeError::BlockchainError(e);
+            }
+        }
+        match resp.code.as_str() {
+            "node_is_client_only" => NodeError::NodeIsClientOnly,
+            "size_limit" => NodeError::SizeLimitError,
+            "invalid_input" => NodeError::InputError(InputError::Invalid),
+            "invalid_signature_header" => NodeError::InvalidSignatureHeader,
+            "invalid_miner_token_header" => NodeError::InvalidMinerTokenHeader,
+            "signature_required" => NodeError::SignatureRequired,
+            "wrong_network" => NodeError::WrongNetwork,
+            "states_outdated" => NodeError::StatesOutdated,
+            "handshake_client_mismatch" => NodeError::HandshakeClientMismatch,
+            "pool_disabled" => NodeError::PoolDisabled,
+            "invalid_share_signature" => NodeError::InvalidShareSignature,
+            _ => NodeError::RemoteError {
+                code: resp.code,
+                message: resp.message,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(e: NodeError) -> NodeError {
+        let resp = e.to_http_response();
+        assert_eq!(resp.status(), e.status_code());
+        NodeError::from_response_body(&serde_json::to_vec(&e.to_response()).unwrap())
+    }
+
+    #[test]
+    fn test_error_response_roundtrip() {
+        assert!(matches!(
+            roundtrip(NodeError::BlockchainError(
+                BlockchainError::InvalidTransactionNonce {
+                    expected: 42,
+                    got: 40
+                }
+            )),
+            NodeError::BlockchainError(BlockchainError::InvalidTransactionNonce {
+                expected: 42,
+                got: 40
+            })
+        ));
+        assert!(matches!(
+            roundtrip(NodeError::BlockchainError(BlockchainError::MemoTooLong)),
+            NodeError::BlockchainError(BlockchainError::MemoTooLong)
+        ));
+        assert!(matches!(
+            roundtrip(NodeError::WrongNetwork),
+            NodeError::WrongNetwork
+        ));
+        assert!(matches!(
+            roundtrip(NodeError::BlockchainError(BlockchainError::KvStoreError(
+                crate::db::KvStoreError::Failure
+            ))),
+            NodeError::RemoteError { code, .. } if code == "blockchain_error.kvstore_error"
+        ));
+        assert!(matches!(
+            NodeError::from_response_body(b"Error: something"),
+            NodeError::RemoteServerError(_)
+        ));
+    }
 }
@@ -1,5 +1,6 @@
 use crate::core::{
-    Block, ContractDeposit, ContractUpdate, ContractWithdraw, Header, Money, ProofOfWork, Token,
+    AccountPolicy, Block, ContractAdmin, ContractAdminPolicy, ContractDeposit, ContractEvent,
+    ContractUpdate, ContractUpgrade, ContractWithdraw, Header, Money, ProofOfWork, Token,
     TokenUpdate, Transaction, TransactionData,
 };
 use crate::crypto::jubjub::*;
@@ -46,6 +47,61 @@ impl From<&MpnAccount> for ExplorerMpnAccount {
     }
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExplorerMpnTokenBalance {
+    pub index: u64,
+    pub balance: ExplorerMoney,
+    /// Metadata of the token, when it's a token registered on the chain
+    pub token: Option<ExplorerToken>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExplorerMpnBalanceChange {
+    pub index: u64,
+    pub token_id: String,
+    pub before: u64,
+    pub after: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExplorerMpnAccountChange {
+    /// Height of the MPN contract state after the change
+    pub state_height: u64,
+    /// Block which brought the change, if it is among the blocks looked into
+    pub block_height: Option<u64>,
+    pub nonce: u64,
+    pub balances: Vec<ExplorerMpnBalanceChange>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExplorerMpnDeposit {
+    pub block_height: u64,
+    pub deposit: ExplorerContractDeposit,
+}
+
+/// A withdraw matched to the account by its nonce step and balance decrease
+/// (Withdraw signatures are not kept on the chain)
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExplorerMpnWithdraw {
+    pub block_height: u64,
+    pub nonce: u64,
+    pub withdraw: ExplorerContractWithdraw,
+}
+
+/// MPN transfers of the account in a single contract update. The transfers are
+/// not kept on the chain, only their nonces and what they add up to are known.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExplorerMpnTransfers {
+    /// Height of the MPN contract state after the transfers
+    pub state_height: u64,
+    pub block_height: u64,
+    /// Nonces of the transfers sent
+    pub nonces: Vec<u64>,
+    /// Amounts sent, fees included
+    pub sent: Vec<ExplorerMoney>,
+    pub received: Vec<ExplorerMoney>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ExplorerToken {
     pub name: String,
@@ -65,6 +121,23 @@ impl From<&Token> for ExplorerToken {
     }
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExplorerAccountPolicy {
+    pub daily_limits: Vec<ExplorerMoney>,
+    pub recovery_key: Option<String>,
+    pub delay: u64,
+}
+
+impl From<&AccountPolicy> for ExplorerAccountPolicy {
+    fn from(obj: &AccountPolicy) -> Self {
+        Self {
+            daily_limits: obj.daily_limits.iter().map(|m| (*m).into()).collect(),
+            recovery_key: obj.recovery_key.as_ref().map(|a| a.to_string()),
+            delay: obj.delay,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ExplorerProofOfWork {
     pub timestamp: u32,
@@ -174,6 +247,64 @@ impl From<&ZkContract> for ExplorerContract {
     }
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum ExplorerContractAdmin {
+    Key {
+        key: String,
+    },
+    Governance {
+        circuit: ExplorerSingleInputVerifierKey,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExplorerContractAdminPolicy {
+    pub admin: ExplorerContractAdmin,
+    pub delay: u64,
+}
+
+impl From<&ContractAdminPolicy> for ExplorerContractAdminPolicy {
+    fn from(obj: &ContractAdminPolicy) -> Self {
+        Self {
+            admin: match &obj.admin {
+                ContractAdmin::Key(key) => ExplorerContractAdmin::Key {
+                    key: key.to_string(),
+                },
+                ContractAdmin::Governance(circuit) => ExplorerContractAdmin::Governance {
+                    circuit: circuit.into(),
+                },
+            },
+            delay: obj.delay,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExplorerContractUpgrade {
+    pub deposit_functions: Vec<(u32, ExplorerMultiInputVerifierKey)>,
+    pub withdraw_functions: Vec<(u32, ExplorerMultiInputVerifierKey)>,
+    pub functions: Vec<(u32, ExplorerSingleInputVerifierKey)>,
+}
+
+impl From<&ContractUpgrade> for ExplorerContractUpgrade {
+    fn from(obj: &ContractUpgrade) -> Self {
+        Self {
+            deposit_functions: obj
+                .deposit_functions
+                .iter()
+                .map(|(i, f)| (*i, f.into()))
+                .collect(),
+            withdraw_functions: obj
+                .withdraw_functions
+                .iter()
+                .map(|(i, f)| (*i, f.into()))
+                .collect(),
+            functions: obj.functions.iter().map(|(i, f)| (*i, f.into())).collect(),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ExplorerCompressedState {
     pub state: ZkCompressedState,
@@ -275,18 +406,21 @@ pub enum ExplorerContractUpdate {
         deposits: Vec<ExplorerContractDeposit>,
         next_state: ExplorerCompressedState,
         proof: ExplorerZkProof,
+        events: Vec<String>,
     },
     Withdraw {
         withdraw_circuit_id: u32,
         withdraws: Vec<ExplorerContractWithdraw>,
         next_state: ExplorerCompressedState,
         proof: ExplorerZkProof,
+        events: Vec<String>,
     },
     FunctionCall {
         function_id: u32,
         next_state: ExplorerCompressedState,
         proof: ExplorerZkProof,
         fee: ExplorerMoney,
+        events: Vec<String>,
     },
 }
 
@@ -298,38 +432,63 @@ impl From<&ContractUpdate> for ExplorerContractUpdate {
                 deposits,
                 next_state,
                 proof,
+                events,
             } => Self::Deposit {
                 deposit_circuit_id: *deposit_circuit_id,
                 deposits: deposits.iter().map(|p| p.into()).collect(),
                 next_state: next_state.into(),
                 proof: proof.into(),
+                events: events.iter().map(|e| e.to_string()).collect(),
             },
             ContractUpdate::Withdraw {
                 withdraw_circuit_id,
                 withdraws,
                 next_state,
                 proof,
+                events,
             } => Self::Withdraw {
                 withdraw_circuit_id: *withdraw_circuit_id,
                 withdraws: withdraws.iter().map(|p| p.into()).collect(),
                 next_state: next_state.into(),
                 proof: proof.into(),
+                events: events.iter().map(|e| e.to_string()).collect(),
             },
             ContractUpdate::FunctionCall {
                 function_id,
                 next_state,
                 proof,
                 fee,
+                events,
             } => Self::FunctionCall {
                 function_id: *function_id,
                 fee: (*fee).into(),
                 next_state: next_state.into(),
                 proof: proof.into(),
+                events: events.iter().map(|e| e.to_string()).collect(),
             },
         }
     }
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExplorerContractEvent {
+    pub height: u64,
+    pub tx_hash: String,
+    pub update_index: u32,
+    pub data: Vec<String>,
+}
+
+impl From<(u64, &ContractEvent)> for ExplorerContractEvent {
+    fn from((height, obj): (u64, &ContractEvent)) -> Self {
+        Self {
+            height,
+            tx_hash: hex::encode(obj.tx_hash),
+            update_index: obj.update_index,
+            data: obj.data.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub enum ExplorerTransactionData {
@@ -350,6 +509,30 @@ pub enum ExplorerTransactionData {
         token_id: String,
         update: ExplorerTokenUpdate,
     },
+    SetAccountPolicy {
+        policy: Option<ExplorerAccountPolicy>,
+    },
+    RecoverAccount {
+        account: String,
+        key: String,
+    },
+    UpgradeContract {
+        contract_id: String,
+        upgrade: ExplorerContractUpgrade,
+        proof: Option<ExplorerZkProof>,
+    },
+    CreateUpgradableContract {
+        contract: ExplorerContract,
+        admin: ExplorerContractAdminPolicy,
+    },
+    ValidityWindow {
+        valid_after_height: Option<u64>,
+        valid_until_height: Option<u64>,
+        data: Box<ExplorerTransactionData>,
+    },
+    CancelRecovery {
+        account: String,
+    },
 }
 
 impl From<&TransactionData> for ExplorerTransactionData {
@@ -378,6 +561,40 @@ impl From<&TransactionData> for ExplorerTransactionData {
                 token_id: token_id.to_string(),
                 update: update.into(),
             },
+            TransactionData::SetAccountPolicy { policy } => Self::SetAccountPolicy {
+                policy: policy.as_ref().map(|p| p.into()),
+            },
+            TransactionData::RecoverAccount { account, key } => Self::RecoverAccount {
+                account: account.to_string(),
+                key: key.to_string(),
+            },
+            TransactionData::UpgradeContract {
+                contract_id,
+                upgrade,
+                proof,
+            } => Self::UpgradeContract {
+                contract_id: contract_id.to_string(),
+                upgrade: upgrade.into(),
+                proof: proof.as_ref().map(|p| p.into()),
+            },
+            TransactionData::CreateUpgradableContract { contract, admin } => {
+                Self::CreateUpgradableContract {
+                    contract: contract.into(),
+                    admin: admin.into(),
+                }
+            }
+            TransactionData::ValidityWindow {
+                valid_after_height,
+                valid_until_height,
+                data,
+            } => Self::ValidityWindow {
+                valid_after_height: *valid_after_height,
+                valid_until_height: *valid_until_height,
+                data: Box::new(data.as_ref().into()),
+            },
+            TransactionData::CancelRecovery { account } => Self::CancelRecovery {
+                account: account.to_string(),
+            },
         }
     }
 }
@@ -1,15 +1,18 @@
 use crate::blockchain::ZkBlockchainPatch;
 use crate::consensus::pow::Difficulty;
 use crate::core::{
-    Account, Amount, Block, ChainSourcedTx, ContractId, Header, Money, MpnDeposit, MpnSourcedTx,
-    MpnWithdraw, Token, TransactionAndDelta,
+    Account, Amount, Block, ChainSourcedTx, ContractEvent, ContractId, Header, Money, MpnDeposit,
+    MpnSourcedTx, MpnWithdraw, Token, TransactionAndDelta,
 };
 use crate::zk;
 use std::collections::HashMap;
 use thiserror::Error;
 
 use super::{
-    explorer::{ExplorerBlock, ExplorerMpnAccount},
+    explorer::{
+        ExplorerBlock, ExplorerContractEvent, ExplorerMpnAccount, ExplorerMpnAccountChange,
+        ExplorerMpnDeposit, ExplorerMpnTokenBalance, ExplorerMpnTransfers, ExplorerMpnWithdraw,
+    },
     Peer, PeerAddress,
 };
 use serde::{Deserialize, Serialize};
@@ -33,8 +36,22 @@ pub struct GetStatsResponse {
     pub timestamp: u32,
     pub version: String,
     pub network: String,
+    #[serde(default)]
+    pub upgrades: Vec<UpgradeStatus>,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UpgradeStatus {
+    pub name: String,
+    pub activation_height: Option<u64>,
+    pub active: bool,
+    /// Number of the peers which support the upgrade
+    pub ready_peers: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetMetricsRequest {}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct GetAccountRequest {
     pub address: String,
@@ -55,6 +72,18 @@ pub struct GetMpnAccountResponse {
     pub account: zk::MpnAccount,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetMpnAddressRequest {
+    pub address: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetMpnAddressResponse {
+    /// Slot the account takes, or would take once it receives funds
+    pub index: u64,
+    pub account: zk::MpnAccount,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct GetExplorerMpnAccountsRequest {
     pub page: usize,
@@ -66,9 +95,67 @@ pub struct GetExplorerMpnAccountsResponse {
     pub accounts: HashMap<u64, ExplorerMpnAccount>,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetExplorerMpnAddressRequest {
+    pub address: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetExplorerMpnAddressResponse {
+    pub index: u64,
+    pub account: ExplorerMpnAccount,
+    pub tokens: Vec<ExplorerMpnTokenBalance>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetExplorerMpnAddressHistoryRequest {
+    pub address: String,
+    /// Number of the latest blocks to look into
+    pub count: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetExplorerMpnAddressHistoryResponse {
+    /// Changes of the account, newest first
+    pub changes: Vec<ExplorerMpnAccountChange>,
+    /// Deposits to the account, newest first
+    pub deposits: Vec<ExplorerMpnDeposit>,
+    /// Withdraws from the account, newest first
+    pub withdraws: Vec<ExplorerMpnWithdraw>,
+    /// Transfers sent and received by the account, newest first
+    pub transfers: Vec<ExplorerMpnTransfers>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetContractEventsRequest {
+    pub contract_id: String,
+    pub since: u64,
+    pub count: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetContractEventsResponse {
+    /// Events of the blocks in the range, blocks without events are skipped
+    pub events: HashMap<u64, Vec<ContractEvent>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetExplorerContractEventsRequest {
+    pub contract_id: String,
+    pub since: u64,
+    pub count: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetExplorerContractEventsResponse {
+    pub events: Vec<ExplorerContractEvent>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct PostMinerSolutionRequest {
     pub nonce: String,
+    #[serde(default)]
+    pub template_id: Option<u64>, // None means the latest template
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -90,6 +177,61 @@ pub struct Puzzle {
     pub size: usize,        // How big is the nonce? (Bytes)
     pub target: Difficulty, // Difficulty target
     pub reward: Amount,     // Puzzle reward
+    #[serde(default)]
+    pub template_id: u64, // Template this puzzle belongs to
+    #[serde(default)]
+    pub nonce_start: u64, // First nonce of the range assigned to the miner
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetPoolPuzzleRequest {
+    pub miner: String, // Address of the miner, each miner searches its own nonces
+}
+
+/// Same as the miner puzzle, but targeting the share difficulty of the pool
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetPoolPuzzleResponse {
+    pub puzzle: Option<Puzzle>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PostPoolShareRequest {
+    pub miner: String, // Address of the miner
+    pub nonce: String, // Nonce encoded in hex
+    pub sig: String,   // Signature of the solved blob by the miner, encoded in hex
+    #[serde(default)]
+    pub template_id: Option<u64>, // None means the latest template
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareVerdict {
+    Accepted,
+    BlockFound,
+    Stale,
+    Duplicate,
+    LowDifficulty,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PostPoolShareResponse {
+    pub verdict: ShareVerdict,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetPoolSharesRequest {}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolMinerShares {
+    pub shares: u64,
+    pub work: u128, // Sum of the powers of the accepted shares
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetPoolSharesResponse {
+    pub share_target: Difficulty,
+    pub miners: HashMap<String, PoolMinerShares>, // Shares of the current round
+    #[serde(default)]
+    pub last_round: HashMap<String, PoolMinerShares>, // Shares of the last found block
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -239,6 +381,16 @@ pub struct GetDebugDataRequest {}
 pub struct GetDebugDataResponse {
     pub logs: String,
     pub db_checksum: String,
+    pub firewall: Option<FirewallStats>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct FirewallStats {
+    pub permitted: u64,
+    pub bypassed: u64,
+    pub dropped_denylisted: u64,
+    pub dropped_rate_limited: u64,
+    pub dropped_traffic_limited: u64,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -272,6 +424,15 @@ pub struct JsonMpnTransaction {
     pub sig: String,
 }
 
+/// Body of the responses of failed requests
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ErrorResponse {
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
 #[derive(Error, Debug)]
 pub enum InputError {
     #[error("invalid input")]
@@ -333,6 +494,24 @@ impl TryInto<PostMpnTransactionRequest> for PostJsonMpnTransactionRequest {
     }
 }
 
+impl From<zk::MpnTransaction> for JsonMpnTransaction {
+    fn from(t: zk::MpnTransaction) -> Self {
+        JsonMpnTransaction {
+            nonce: t.nonce,
+            src_pub_key: t.src_pub_key.to_string(),
+            dst_pub_key: t.dst_pub_key.to_string(),
+            src_token_index: t.src_token_index,
+            src_fee_token_index: t.src_fee_token_index,
+            dst_token_index: t.dst_token_index,
+            amount_token_id: t.amount.token_id.to_string(),
+            amount: t.amount.amount,
+            fee_token_id: t.fee.token_id.to_string(),
+            fee: t.fee.amount,
+            sig: hex::encode(bincode::serialize(&t.sig).unwrap()),
+        }
+    }
+}
+
 impl Into<GetJsonMempoolResponse> for GetMempoolResponse {
     fn into(self) -> GetJsonMempoolResponse {
         GetJsonMempoolResponse {
@@ -346,19 +525,7 @@ impl Into<GetJsonMempoolResponse> for GetMempoolResponse {
                         None
                     }
                 })
-                .map(|t| JsonMpnTransaction {
-                    nonce: t.nonce,
-                    src_pub_key: t.src_pub_key.to_string(),
-                    dst_pub_key: t.dst_pub_key.to_string(),
-                    src_token_index: t.src_token_index,
-                    src_fee_token_index: t.src_fee_token_index,
-                    dst_token_index: t.dst_token_index,
-                    amount_token_id: t.amount.token_id.to_string(),
-                    amount: t.amount.amount,
-                    fee_token_id: t.fee.token_id.to_string(),
-                    fee: t.fee.amount,
-                    sig: hex::encode(bincode::serialize(&t.sig).unwrap()),
-                })
+                .map(JsonMpnTransaction::from)
                 .collect(),
         }
     }
@@ -381,3 +548,91 @@ pub struct GetTokenInfoRequest {
 pub struct GetTokenInfoResponse {
     pub token: Token,
 }
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct SubscribeRequest {
+    #[serde(default)]
+    pub headers: bool,
+    #[serde(default)]
+    pub rollbacks: bool,
+    #[serde(default)]
+    pub mpn_mempool: bool,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    #[serde(default)]
+    pub mpn_addresses: Vec<String>,
+    /// Tokens of which the balances are watched on `addresses` (Ziesha if empty)
+    #[serde(default)]
+    pub tokens: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum NodeEvent {
+    NewHeader {
+        header: Header,
+    },
+    /// Blocks from `height` and above have been removed from the chain
+    Rollback {
+        height: u64,
+    },
+    MpnMempoolTransaction {
+        tx: JsonMpnTransaction,
+    },
+    AccountChanged {
+        address: String,
+        nonce: u32,
+        balances: HashMap<String, Amount>,
+    },
+    MpnAccountChanged {
+        address: String,
+        account: zk::MpnAccount,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct JsonRpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+    /// Requests without an id are notifications and get no response
+    pub id: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+    pub id: serde_json::Value,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct JsonRpcMethod {
+    pub name: String,
+    /// Name of the request type taken as params
+    pub params: String,
+    /// Field names of the params object. Only the names are listed, their
+    /// types are those of the request type
+    pub params_fields: Vec<String>,
+    pub result: String,
+    pub local_only: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetJsonRpcMethodsRequest {}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetJsonRpcMethodsResponse {
+    pub methods: Vec<JsonRpcMethod>,
+}
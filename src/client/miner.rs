@@ -0,0 +1,371 @@
+use super::messages::{PostMinerSolutionRequest, Puzzle, ShareVerdict};
+use super::{BazukaClient, NodeError};
+use crate::consensus::pow::Difficulty;
+use rust_randomx::{Context, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Number of hashes a worker computes before checking for a new puzzle
+const HASHES_PER_CHECK: u64 = 32;
+
+#[derive(Debug, Clone)]
+pub struct MinerOptions {
+    pub threads: usize,
+    /// Mine shares of the pool of the node instead of full solutions
+    pub pool: bool,
+    /// Hash with the full RandomX dataset (Needs ~2GB of memory) instead of
+    /// the much slower light mode
+    pub fast: bool,
+    pub refresh_interval: Duration,
+    pub report_interval: Duration,
+}
+
+impl Default for MinerOptions {
+    fn default() -> Self {
+        Self {
+            threads: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            pool: false,
+            fast: true,
+            refresh_interval: Duration::from_secs(1),
+            report_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A decoded and validated puzzle
+#[derive(Debug, Clone, PartialEq)]
+pub struct Work {
+    pub key: Vec<u8>,
+    pub blob: Vec<u8>,
+    pub offset: usize,
+    pub target: Difficulty,
+    pub template_id: u64,
+    pub nonce_start: u64,
+}
+
+impl TryFrom<&Puzzle> for Work {
+    type Error = NodeError;
+    fn try_from(puzzle: &Puzzle) -> Result<Self, Self::Error> {
+        let key = hex::decode(&puzzle.key).map_err(|_| NodeError::InvalidPuzzle)?;
+        let blob = hex::decode(&puzzle.blob).map_err(|_| NodeError::InvalidPuzzle)?;
+        if puzzle.size != 8 || puzzle.offset + puzzle.size > blob.len() {
+            return Err(NodeError::InvalidPuzzle);
+        }
+        Ok(Self {
+            key,
+            blob,
+            offset: puzzle.offset,
+            target: puzzle.target,
+            template_id: puzzle.template_id,
+            nonce_start: puzzle.nonce_start,
+        })
+    }
+}
+
+impl Work {
+    pub fn with_nonce(&self, blob: &mut [u8], nonce: u64) {
+        blob[self.offset..self.offset + 8].copy_from_slice(&nonce.to_le_bytes());
+    }
+}
+
+pub fn solution(template_id: u64, nonce: u64) -> PostMinerSolutionRequest {
+    PostMinerSolutionRequest {
+        nonce: hex::encode(nonce.to_le_bytes()),
+        template_id: Some(template_id),
+    }
+}
+
+/// First nonce of the range assigned to the given worker. Ranges are disjoint.
+pub fn nonce_range_start(worker: usize, workers: usize) -> u64 {
+    (u64::MAX / workers as u64) * worker as u64
+}
+
+struct Job {
+    id: u64,
+    work: Work,
+    context: Arc<Context>,
+}
+
+#[derive(Default)]
+struct Shared {
+    job: RwLock<Option<Arc<Job>>>,
+    job_id: AtomicU64,
+    hashes: AtomicU64,
+}
+
+fn worker(
+    index: usize,
+    workers: usize,
+    shared: Arc<Shared>,
+    solutions: mpsc::UnboundedSender<(u64, u64)>,
+) {
+    let mut hasher: Option<Hasher> = None;
+    loop {
+        if solutions.is_closed() {
+            return;
+        }
+        let job = match shared.job.read().unwrap().clone() {
+            Some(job) => job,
+            None => {
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        // Each worker keeps its own VM, only the context is shared
+        match &mut hasher {
+            Some(h) if h.context().key() == job.context.key() => {}
+            Some(h) => h.update(Arc::clone(&job.context)),
+            None => hasher = Some(Hasher::new(Arc::clone(&job.context))),
+        }
+        let hasher = hasher.as_ref().unwrap();
+        let target = rust_randomx::Difficulty::new(job.work.target.0);
+        let mut blob = job.work.blob.clone();
+        let mut nonce = job
+            .work
+            .nonce_start
+            .wrapping_add(nonce_range_start(index, workers));
+        loop {
+            for _ in 0..HASHES_PER_CHECK {
+                job.work.with_nonce(&mut blob, nonce);
+                if hasher.hash(&blob).meets_difficulty(target) {
+                    // Keep working on the job until it is replaced, there
+                    // might be more shares to find
+                    let _ = solutions.send((job.id, nonce));
+                }
+                nonce = nonce.wrapping_add(1);
+            }
+            shared.hashes.fetch_add(HASHES_PER_CHECK, Ordering::Relaxed);
+            if shared.job_id.load(Ordering::Relaxed) != job.id {
+                break;
+            }
+        }
+    }
+}
+
+/// Multi-threaded RandomX miner which works on the puzzles of a node
+pub struct Miner {
+    opts: MinerOptions,
+    shared: Arc<Shared>,
+    context: Mutex<Option<Arc<Context>>>,
+}
+
+impl Miner {
+    pub fn new(opts: MinerOptions) -> Self {
+        Self {
+            opts,
+            shared: Default::default(),
+            context: Mutex::new(None),
+        }
+    }
+
+    /// The RandomX context is only rebuilt when the key changes. Building it
+    /// (Along with its dataset in fast mode) takes a while, so it's done off
+    /// the async runtime.
+    async fn context(&self, key: &[u8]) -> Arc<Context> {
+        {
+            let context = self.context.lock().unwrap();
+            if let Some(ctx) = context.as_ref().filter(|ctx| ctx.key() == key) {
+                return Arc::clone(ctx);
+            }
+        }
+        log::info!("Initializing RandomX context...");
+        let (key, fast) = (key.to_vec(), self.opts.fast);
+        let ctx = Arc::new(
+            tokio::task::spawn_blocking(move || Context::new(&key, fast))
+                .await
+                .expect("RandomX initialization panicked"),
+        );
+        *self.context.lock().unwrap() = Some(Arc::clone(&ctx));
+        ctx
+    }
+
+    async fn set_work(&self, work: Option<Work>) {
+        let job = match work {
+            Some(work) => {
+                let context = self.context(&work.key).await;
+                Some((work, context))
+            }
+            None => None,
+        };
+        let mut curr = self.shared.job.write().unwrap();
+        let id = self.shared.job_id.fetch_add(1, Ordering::Relaxed) + 1;
+        *curr = job.map(|(work, context)| Arc::new(Job { id, work, context }));
+    }
+
+    async fn fetch_work(&self, client: &BazukaClient) -> Result<Option<Work>, NodeError> {
+        let puzzle = if self.opts.pool {
+            client.get_pool_puzzle().await?.puzzle
+        } else {
+            client.get_miner_puzzle().await?.puzzle
+        };
+        puzzle.as_ref().map(Work::try_from).transpose()
+    }
+
+    /// Submits the solution and returns true if the current job is finished
+    async fn submit(&self, client: &BazukaClient, nonce: u64) -> Result<bool, NodeError> {
+        let work = match self.current_work() {
+            Some(work) => work,
+            None => return Ok(true),
+        };
+        if self.opts.pool {
+            let mut blob = work.blob.clone();
+            work.with_nonce(&mut blob, nonce);
+            let verdict = client
+                .post_pool_share(work.template_id, nonce, &blob)
+                .await?
+                .verdict;
+            log::info!(
+                "Share submitted! (Nonce: {}, Verdict: {:?})",
+                nonce,
+                verdict
+            );
+            Ok(matches!(
+                verdict,
+                ShareVerdict::BlockFound | ShareVerdict::Stale
+            ))
+        } else {
+            log::info!("Solution found! (Nonce: {})", nonce);
+            client
+                .post_miner_solution(solution(work.template_id, nonce))
+                .await?;
+            Ok(true)
+        }
+    }
+
+    fn current_work(&self) -> Option<Work> {
+        self.shared
+            .job
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|j| j.work.clone())
+    }
+
+    pub async fn run(&self, client: &BazukaClient) -> Result<(), NodeError> {
+        let (sol_send, mut sol_recv) = mpsc::unbounded_channel();
+        let handles = (0..self.opts.threads)
+            .map(|i| {
+                let shared = Arc::clone(&self.shared);
+                let sol_send = sol_send.clone();
+                let threads = self.opts.threads;
+                std::thread::spawn(move || worker(i, threads, shared, sol_send))
+            })
+            .collect::<Vec<_>>();
+        drop(sol_send);
+
+        let mut refresh = tokio::time::interval(self.opts.refresh_interval);
+        let mut report = tokio::time::interval(self.opts.report_interval);
+        let mut last_report = (Instant::now(), 0);
+        loop {
+            tokio::select! {
+                _ = refresh.tick() => {
+                    match self.fetch_work(client).await {
+                        Ok(work) => {
+                            if work != self.current_work() {
+                                if let Some(work) = &work {
+                                    log::info!("New puzzle received! (Target: {})", work.target.0);
+                                }
+                                self.set_work(work).await;
+                            }
+                        }
+                        Err(NodeError::InvalidPuzzle) => {
+                            log::error!("Received an invalid puzzle!");
+                            self.set_work(None).await;
+                        }
+                        Err(e) => log::error!("Cannot fetch the puzzle: {}", e),
+                    }
+                }
+                _ = report.tick() => {
+                    let hashes = self.shared.hashes.load(Ordering::Relaxed);
+                    let elapsed = last_report.0.elapsed().as_secs_f64();
+                    if elapsed > 0.0 {
+                        log::info!(
+                            "Hashrate: {:.2} H/s ({} threads)",
+                            (hashes - last_report.1) as f64 / elapsed,
+                            self.opts.threads
+                        );
+                    }
+                    last_report = (Instant::now(), hashes);
+                }
+                sol = sol_recv.recv() => {
+                    let (job_id, nonce) = match sol {
+                        Some(sol) => sol,
+                        None => break,
+                    };
+                    if job_id != self.shared.job_id.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    match self.submit(client, nonce).await {
+                        Ok(false) => {}
+                        Ok(true) => {
+                            // The puzzle is solved, wait for the next one
+                            self.set_work(None).await;
+                            refresh.reset_immediately();
+                        }
+                        Err(e) => log::error!("Cannot submit the solution: {}", e),
+                    }
+                }
+            }
+        }
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Amount;
+
+    #[test]
+    fn test_nonce_ranges_are_disjoint() {
+        let workers = 4;
+        let starts = (0..workers)
+            .map(|i| nonce_range_start(i, workers))
+            .collect::<Vec<_>>();
+        for w in starts.windows(2) {
+            assert_eq!(w[1] - w[0], u64::MAX / workers as u64);
+        }
+    }
+
+    #[test]
+    fn test_invalid_puzzles() {
+        let puzzle = Puzzle {
+            key: "00".into(),
+            blob: "0000".into(),
+            offset: 0,
+            size: 8,
+            target: Difficulty(0),
+            reward: Amount(0),
+            template_id: 0,
+            nonce_start: 0,
+        };
+        assert!(matches!(
+            Work::try_from(&puzzle),
+            Err(NodeError::InvalidPuzzle)
+        ));
+        assert!(matches!(
+            Work::try_from(&Puzzle {
+                blob: "zz".into(),
+                ..puzzle.clone()
+            }),
+            Err(NodeError::InvalidPuzzle)
+        ));
+        let work = Work::try_from(&Puzzle {
+            blob: "00".repeat(10),
+            offset: 2,
+            ..puzzle
+        })
+        .unwrap();
+        let mut blob = work.blob.clone();
+        work.with_nonce(&mut blob, 1);
+        assert_eq!(blob, vec![0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
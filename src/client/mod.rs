@@ -1,4 +1,6 @@
-use crate::core::{Address, MpnDeposit, MpnWithdraw, Signer, TokenId, TransactionAndDelta};
+use crate::core::{
+    Address, ContractId, MpnAddress, MpnDeposit, MpnWithdraw, Signer, TokenId, TransactionAndDelta,
+};
 use crate::crypto::ed25519;
 use crate::crypto::SignatureScheme;
 use crate::zk::MpnTransaction;
@@ -16,6 +18,7 @@ use tokio::time::timeout;
 mod error;
 pub mod explorer;
 pub mod messages;
+pub mod miner;
 pub mod utils;
 pub use error::NodeError;
 use messages::*;
@@ -55,6 +58,9 @@ pub struct Peer {
     pub height: u64,
     pub power: u128,
     pub outdated_states: usize,
+    /// Protocol upgrades supported by the peer
+    #[serde(default)]
+    pub upgrades: Vec<String>,
 }
 
 pub struct NodeRequest {
@@ -89,7 +95,11 @@ impl Limit {
 }
 
 impl OutgoingSender {
-    pub async fn raw(&self, mut body: Request<Body>, limit: Limit) -> Result<Bytes, NodeError> {
+    async fn response(
+        &self,
+        mut body: Request<Body>,
+        limit: &Limit,
+    ) -> Result<Response<Body>, NodeError> {
         let (resp_snd, mut resp_rcv) = mpsc::channel::<Result<Response<Body>, NodeError>>(1);
         body.headers_mut()
             .insert(NETWORK_HEADER, HeaderValue::from_str(&self.network)?);
@@ -110,6 +120,12 @@ impl OutgoingSender {
         }
         .ok_or(NodeError::NotAnsweringError)??;
 
+        Ok(resp)
+    }
+
+    pub async fn raw(&self, body: Request<Body>, limit: Limit) -> Result<Bytes, NodeError> {
+        let resp = self.response(body, &limit).await?;
+
         let status = resp.status();
         let body = resp.into_body();
 
@@ -126,9 +142,7 @@ impl OutgoingSender {
         let body_bytes = hyper::body::to_bytes(body).await?;
 
         if status != StatusCode::OK {
-            return Err(NodeError::RemoteServerError(
-                String::from_utf8_lossy(&body_bytes).to_string(),
-            ));
+            return Err(NodeError::from_response_body(&body_bytes));
         }
 
         Ok(body_bytes)
@@ -164,6 +178,29 @@ impl OutgoingSender {
         Ok(resp)
     }
 
+    /// Sends a json GET request and returns the response body as it arrives
+    pub async fn json_stream<Req: serde::Serialize>(
+        &self,
+        addr: String,
+        req: Req,
+        limit: Limit,
+    ) -> Result<Body, NodeError> {
+        let req = self.sign(
+            Request::builder().method(Method::GET).uri(format!(
+                "{}?{}",
+                addr,
+                serde_qs::to_string(&req)?
+            )),
+            vec![],
+        )?;
+        let resp = self.response(req, &limit).await?;
+        if resp.status() != StatusCode::OK {
+            let body_bytes = hyper::body::to_bytes(resp.into_body()).await?;
+            return Err(NodeError::from_response_body(&body_bytes));
+        }
+        Ok(resp.into_body())
+    }
+
     #[allow(dead_code)]
     pub async fn bincode_post<Req: serde::Serialize, Resp: serde::de::DeserializeOwned>(
         &self,
@@ -309,6 +346,16 @@ impl BazukaClient {
             .await
     }
 
+    pub async fn get_mempool(&self) -> Result<GetMempoolResponse, NodeError> {
+        self.sender
+            .bincode_get::<GetMempoolRequest, GetMempoolResponse>(
+                format!("http://{}/bincode/mempool", self.peer),
+                GetMempoolRequest {},
+                Limit::default(),
+            )
+            .await
+    }
+
     pub async fn get_zero_mempool(&self) -> Result<GetZeroMempoolResponse, NodeError> {
         self.sender
             .bincode_get::<GetZeroMempoolRequest, GetZeroMempoolResponse>(
@@ -355,6 +402,25 @@ impl BazukaClient {
             .await
     }
 
+    pub async fn get_contract_events(
+        &self,
+        contract_id: ContractId,
+        since: u64,
+        count: u64,
+    ) -> Result<GetContractEventsResponse, NodeError> {
+        self.sender
+            .json_get::<GetContractEventsRequest, GetContractEventsResponse>(
+                format!("http://{}/contract/events", self.peer),
+                GetContractEventsRequest {
+                    contract_id: contract_id.to_string(),
+                    since,
+                    count,
+                },
+                Limit::default(),
+            )
+            .await
+    }
+
     pub async fn get_account(&self, address: Address) -> Result<GetAccountResponse, NodeError> {
         self.sender
             .json_get::<GetAccountRequest, GetAccountResponse>(
@@ -394,6 +460,21 @@ impl BazukaClient {
             .await
     }
 
+    pub async fn get_mpn_address(
+        &self,
+        address: MpnAddress,
+    ) -> Result<GetMpnAddressResponse, NodeError> {
+        self.sender
+            .json_get::<GetMpnAddressRequest, GetMpnAddressResponse>(
+                format!("http://{}/mpn/address", self.peer),
+                GetMpnAddressRequest {
+                    address: address.to_string(),
+                },
+                Limit::default(),
+            )
+            .await
+    }
+
     pub async fn transact(
         &self,
         tx_delta: TransactionAndDelta,
@@ -430,34 +511,117 @@ impl BazukaClient {
             .await
     }
 
+    /// Subscribes to the node events, each item of the stream is a single event
+    pub async fn subscribe(
+        &self,
+        req: SubscribeRequest,
+    ) -> Result<impl futures::Stream<Item = Result<NodeEvent, NodeError>>, NodeError> {
+        let body = self
+            .sender
+            .json_stream(
+                format!("http://{}/subscribe", self.peer),
+                req,
+                Limit::default(),
+            )
+            .await?;
+        Ok(futures::stream::unfold(
+            (body, Vec::<u8>::new()),
+            |(mut body, mut buf)| async move {
+                loop {
+                    if let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                        let line = buf.drain(..=pos).collect::<Vec<u8>>();
+                        let event = serde_json::from_slice::<NodeEvent>(&line[..pos])
+                            .map_err(NodeError::from);
+                        return Some((event, (body, buf)));
+                    }
+                    match body.data().await? {
+                        Ok(chunk) => buf.extend_from_slice(&chunk),
+                        Err(e) => return Some((Err(e.into()), (Body::empty(), Vec::new()))),
+                    }
+                }
+            },
+        ))
+    }
+
+    pub async fn post_miner_solution(
+        &self,
+        sol: PostMinerSolutionRequest,
+    ) -> Result<PostMinerSolutionResponse, NodeError> {
+        self.sender
+            .json_post::<PostMinerSolutionRequest, PostMinerSolutionResponse>(
+                format!("http://{}/miner/solution", self.peer),
+                sol,
+                Limit::default(),
+            )
+            .await
+    }
+
+    pub async fn get_pool_puzzle(&self) -> Result<GetPoolPuzzleResponse, NodeError> {
+        self.sender
+            .json_get::<GetPoolPuzzleRequest, GetPoolPuzzleResponse>(
+                format!("http://{}/pool/puzzle", self.peer),
+                GetPoolPuzzleRequest {
+                    miner: ed25519::PublicKey::from(self.sender.priv_key.clone()).to_string(),
+                },
+                Limit::default(),
+            )
+            .await
+    }
+
+    /// Submits a share, signed by the key of the client. `blob` is the puzzle
+    /// blob which has the nonce in it.
+    pub async fn post_pool_share(
+        &self,
+        template_id: u64,
+        nonce: u64,
+        blob: &[u8],
+    ) -> Result<PostPoolShareResponse, NodeError> {
+        let miner = ed25519::PublicKey::from(self.sender.priv_key.clone());
+        let sig = Signer::sign(&self.sender.priv_key, blob);
+        self.sender
+            .json_post::<PostPoolShareRequest, PostPoolShareResponse>(
+                format!("http://{}/pool/share", self.peer),
+                PostPoolShareRequest {
+                    miner: miner.to_string(),
+                    nonce: miner::solution(template_id, nonce).nonce,
+                    sig: hex::encode(bincode::serialize(&sig)?),
+                    template_id: Some(template_id),
+                },
+                Limit::default(),
+            )
+            .await
+    }
+
+    pub async fn get_pool_shares(&self) -> Result<GetPoolSharesResponse, NodeError> {
+        self.sender
+            .json_get::<GetPoolSharesRequest, GetPoolSharesResponse>(
+                format!("http://{}/pool/shares", self.peer),
+                GetPoolSharesRequest {},
+                Limit::default(),
+            )
+            .await
+    }
+
+    /// Solves the current puzzle of the node on the current thread, mainly
+    /// useful for tests. Use `miner::Miner` for real mining.
     pub async fn mine(&self) -> Result<Option<PostMinerSolutionResponse>, NodeError> {
         if let Some(puzzle) = self.get_miner_puzzle().await?.puzzle {
-            let sol = mine_puzzle(&puzzle);
-            Ok(Some(
-                self.sender
-                    .json_post::<PostMinerSolutionRequest, PostMinerSolutionResponse>(
-                        format!("http://{}/miner/solution", self.peer),
-                        sol,
-                        Limit::default(),
-                    )
-                    .await?,
-            ))
+            let sol = mine_puzzle(&puzzle)?;
+            Ok(Some(self.post_miner_solution(sol).await?))
         } else {
             Ok(None)
         }
     }
 }
 
-fn mine_puzzle(puzzle: &Puzzle) -> PostMinerSolutionRequest {
-    let key = hex::decode(&puzzle.key).unwrap();
-    let mut blob = hex::decode(&puzzle.blob).unwrap();
+fn mine_puzzle(puzzle: &Puzzle) -> Result<PostMinerSolutionRequest, NodeError> {
+    let work = miner::Work::try_from(puzzle)?;
+    let mut blob = work.blob.clone();
     let mut nonce = 0u64;
     loop {
-        blob[puzzle.offset..puzzle.offset + puzzle.size].copy_from_slice(&nonce.to_le_bytes());
-        if crate::consensus::pow::meets_difficulty(&key, &blob, puzzle.target) {
-            return PostMinerSolutionRequest {
-                nonce: hex::encode(nonce.to_le_bytes()),
-            };
+        work.with_nonce(&mut blob, nonce);
+        if crate::consensus::pow::meets_difficulty(&work.key, &blob, work.target) {
+            return Ok(miner::solution(work.template_id, nonce));
         }
 
         nonce += 1;
@@ -2,22 +2,36 @@ use super::{UNIT, UNIT_ZEROS};
 
 use crate::blockchain::{BlockAndPatch, BlockchainConfig, ZkBlockchainPatch};
 use crate::common::*;
+use crate::consensus::difficulty::{DifficultyAlgorithm, Lwma, TrimmedMean};
 use crate::consensus::pow::Difficulty;
+use crate::consensus::upgrades::{Upgrade, Upgrades};
 use crate::core::{
-    Amount, Block, ContractId, Header, Money, ProofOfWork, Signature, Token, TokenId, Transaction,
+    hash::Hash, Address, Amount, Block, ContractAdmin, ContractAdminPolicy, ContractId, Hasher,
+    Header, Money, ProofOfWork, RegularSendEntry, Signature, Token, TokenId, Transaction,
     TransactionAndDelta, TransactionData, ZkHasher,
 };
 use crate::zk;
+use std::collections::HashMap;
 
 #[cfg(test)]
 use crate::wallet::TxBuilder;
 
 const MPN_LOG4_ACCOUNT_CAPACITY: u8 = 15;
+const MPN_ACCOUNT_SLOT_CHOICES: u8 = 4;
 const MPN_LOG4_PAYMENT_CAPACITY: u8 = 3;
+// Every MPN account holds at most 4^3 = 64 tokens.
+// TODO: Accounts holding more than 64 tokens are still requested. The capacity
+// is a part of the state model of the MPN contract, which contract upgrades
+// can't change (Only its verifier keys), so it needs a migration of the MPN
+// state whose plan is pending with the owner of the request.
 pub const MPN_LOG4_TOKEN_CAPACITY: u8 = 3;
 
 const TESTNET_HEIGHT_LIMIT: u64 = 12000;
 
+// (Network, Height, Header hash) of the blocks every chain of the network
+// should contain
+const CHECKPOINTS: &[(&str, u64, &str)] = &[];
+
 lazy_static! {
     pub static ref MPN_UPDATE_VK: zk::groth16::Groth16VerifyingKey =
         bincode::deserialize(&hex::decode("b02701884fb4065e5dec5456f29cbbf7b093b5847c56b7f6c1fb103851b674f9122395c01b2ac3015bbffddd0ccce114a8c239c56aa3543ba593e69f94a411230b6138bbfade4ac527e990466b1b625617f415f58d572e2b0f559e590180ee17005001160b651af92d477bc900a6f468abe5a03d8d16667e104721d84053149b8c8e6dbaaa04f767fe3480adf9ec4e2501948c01cd4d17416f97407c9b1b69bd004dbeefb3ab8a56893eb0efd44d13f740d479eb3b43d4b11b0e23f9bed985ac0a0033316f8dbcea7ba33a2e6e3225c09f3db359b808dcd316f27ac309886060cda95c63b1f274d2f15731dd2e54027173182b5f79b1b1875c11669b2a89584308f461ce1becda321c0ede1c8e060e3dea7255d464c93ce846d65d200327888a320043ba1a5d14a41af8c158ed640c8d3ea06a21525671261fd03f8050c6e25c643a6dfb27418d1b36c14c3ce4a035b22a07a70b43b2f39e4cc54ff9bcc27f36508f0a408446d47a5e520c14a809605865a074631777ba098eb61145839216fa571000c0bd67354bcfaff0ac9be6d6e60dd27ba907b73e48cd29c9d04bfb1648047d00e6e8357101d30b79946c6072c6967909b9aae7f069033cafaea578a6b2e0e6b2bfabd528e90c2d3424af26a7d26bf95dd06296c89ddd8a662c52756656304118dce1cb5ca358fa9726344e8c37eede52e11786758be88dab87d896216dc0291c8f250322ca0aff90cea90f5ac30a250a65e187464f11b76f15fe8fd5ae1a71fd02131af2f1585807ba1729693d7481ec47d7731eefba89272466472f6482d109004188bba4fc60efa79ea39994af0bf56accd370b06fdce321aa7c0d00d4bf8cfac3ef3408822145f58963bcddd84f1711752f24db6810bcfc10b9f2d1ee7601703e2da6f8c42ce2e771e85dc81f0f71d3ec1537848e1d29220136e4193ae98a17005732f4779ecd296857e4217453314ebc5b733d289cae7d2b4109ac8df4d7cf4b368c6942006c79503155fde7a4dfbd1840f3f8f8599dcfded2050bfc1c1f41d9a0931b52bd5ea22053e7913104eba04a68e4aa9991c74949ca80871c14744f0c247b6df3c6bd961430f1aa53b855967a91432ad5645876e6b67ccf29f0cb6b2197bdc3fae24a8f5c5215aab931e62b193e64b49c48f4e916a73a2752542b78c53b7b96ab8a819fd45c37c6bc5ef76fe5b7a1d8f74df6a776b413bb7bd38528030005000000000000005d1f971a1c209fdffcfc94c564d972ddcd980b421783d50810724bb935cfda56747e4198e25d4191441a960a57a4e818de1ba9ed81027186437897daf12b241e5d65b9856bcbeb50f03f3de6fe9689976c16d1e8168ec688be1e48770fe2e51400a730c02ba017e1d6e44fafeb802c88c126c4be2e39355de3f432070017b5ca9537d21b94f9179caccc2942506b245f0c10dee8b12d022cdcfd46fd2d0297724262532d4eb846f023115dbeca2fba7b0c0011aee0bb01270ec67c14ce656f2f0200278b42b32043309e1181306c032309d417aef342759dd8c06b5dd5993635d0c8b47633166df858fbf5d7bacedb39af10ccd89be5f30e585d8ff0c8a54c03ff4b69c585eb21c687517dbabc76720b2f32b4f5844e8f2d0baa23e36d90e1c635190003ba146c20bf2d3e8ccd7f9f67b2da3fcdc8248d43b4dc4c7c0832aa69064bafb876ed4f86f7c22de3c92df8788825149ad5e1bf741d303451d49419785f15c866f74e058e0ddb7a00fd9b0c460b3ed6069bb5f4cca4e111ba5acca063bd0e1900d50906b204f773ab4f7cde9983186f6e02666719ec0381c118f06ac589dbcd8ee2fb93a03925091a7a5524fa33b7f60527acfd34bf39e553554d4a1eed1e6be5d97a090bbf78974af8b789c76e0acfa9cf4311a24e71abea818812ee209c001a00").unwrap()).unwrap();
@@ -70,6 +84,8 @@ fn get_mpn_contract() -> TransactionAndDelta {
         },
         nonce: 2, // MPN contract is created after Ziesha token is created
         fee: Money::ziesha(0),
+        valid_after_height: None,
+        valid_until_height: None,
         sig: Signature::Unsigned,
     };
     TransactionAndDelta {
@@ -129,10 +145,76 @@ fn get_ziesha_token_creation_tx() -> Transaction {
         },
         nonce: 1,
         fee: Money::ziesha(0),
+        valid_after_height: None,
+        valid_until_height: None,
         sig: Signature::Unsigned,
     }
 }
 
+pub fn get_checkpoints(network: &str) -> HashMap<u64, <Hasher as Hash>::Output> {
+    CHECKPOINTS
+        .iter()
+        .filter(|(net, _, _)| *net == network)
+        .map(|(_, height, hash)| (*height, hex::decode(hash).unwrap().try_into().unwrap()))
+        .collect()
+}
+
+/// Activation heights of the protocol upgrades on the given network
+pub fn get_upgrades(network: &str) -> Upgrades {
+    match network {
+        // Everything is active from the beginning on a fresh local network
+        "simulator" | "dev" => Upgrades::new(
+            [
+                Upgrade::NonZieshaFees,
+                Upgrade::StrictTokenNames,
+                Upgrade::TransactionValidityWindow,
+                Upgrade::AccountPolicies,
+                Upgrade::PlonkProofs,
+                Upgrade::ContractEvents,
+                Upgrade::ContractUpgrades,
+                Upgrade::MpnAccountSlots,
+                Upgrade::MpnTokenSlotReuse,
+            ]
+            .map(|upgrade| (upgrade, 0)),
+        ),
+        // New upgrades are scheduled at a future height once released, so
+        // that the blocks which are already mined are never re-validated
+        // under new rules
+        _ => Upgrades::new([(Upgrade::MoreMpnFunctionCalls, 3550)]),
+    }
+}
+
+/// Seed of the universal PLONK setup of the local networks. The secret of the
+/// setup is derived from it, so anyone can forge proofs on those networks. The
+/// keys of their PLONK contracts are prepared with
+/// `PlonkSrs::insecure_from_seed(DEV_PLONK_SEED, DEV_PLONK_MAX_ROWS)`.
+pub const DEV_PLONK_SEED: &[u8] = b"BAZUKA DEV PLONK SETUP";
+pub const DEV_PLONK_MAX_ROWS: usize = 1 << 16;
+
+/// Universal PLONK setup of the given network
+pub fn get_plonk_srs(network: &str) -> Option<zk::plonk::PlonkVerifierSrs> {
+    match network {
+        "simulator" | "dev" => Some(zk::plonk::PlonkVerifierSrs::insecure_from_seed(
+            DEV_PLONK_SEED,
+            DEV_PLONK_MAX_ROWS,
+        )),
+        _ => None,
+    }
+}
+
+/// Difficulty retarget algorithm of the given network
+pub fn get_difficulty_algorithm(network: &str) -> DifficultyAlgorithm {
+    match network {
+        // Fresh local networks may use a faster reacting algorithm
+        "simulator" | "dev" => DifficultyAlgorithm::Lwma(Lwma { window: 72 }),
+        _ => DifficultyAlgorithm::TrimmedMean(TrimmedMean {
+            window: 150, // Blocks
+            lag: 10,     // Blocks
+            cut: 15,     // Blocks
+        }),
+    }
+}
+
 pub fn get_blockchain_config() -> BlockchainConfig {
     let mpn_tx_delta = get_mpn_contract();
     let mpn_contract_id = ContractId::new(&mpn_tx_delta.tx);
@@ -173,9 +255,7 @@ pub fn get_blockchain_config() -> BlockchainConfig {
         max_block_size: MB as usize,
         max_delta_count: 1024, // Only allow max of 1024 ZkScalar cells to be added per block
         block_time: 120,       // Seconds
-        difficulty_window: 150, // Blocks
-        difficulty_lag: 10,    // Blocks
-        difficulty_cut: 15,    // Blocks
+        difficulty_algorithm: get_difficulty_algorithm("mainnet"),
 
         // 0 63 -> BAZUKA BASE KEY
         // 64 2111 -> hash(blk#0)
@@ -196,18 +276,86 @@ pub fn get_blockchain_config() -> BlockchainConfig {
         mpn_num_contract_deposits: 1,
         mpn_num_contract_withdraws: 1,
         mpn_log4_account_capacity: MPN_LOG4_ACCOUNT_CAPACITY,
+        mpn_account_slot_choices: MPN_ACCOUNT_SLOT_CHOICES,
         mpn_proving_time: 30, // Seconds
 
         minimum_pow_difficulty: Difficulty::from_power(50000000),
 
         testnet_height_limit: Some(TESTNET_HEIGHT_LIMIT),
         max_memo_length: 64,
+        max_contract_events: 16, // Per contract update
+        // There is no canonical PLONK setup yet, so PLONK contracts are rejected.
+        // The PLONK verifier should be audited before one is set.
+        plonk_srs: get_plonk_srs("mainnet"),
+        // Set per network once the governance of the MPN contract is decided
+        mpn_admin: None,
+        // No keys are generated for the circuits of the MPN upgrades yet (See
+        // `bazuka node mpn-setup`), so they are inert on every network
+        mpn_circuit_upgrades: vec![],
+        max_policy_delay: 21600, // Blocks
+        max_tx_schedule: 720,    // Blocks
+
+        // Checkpoints are set based on the network the node is running on
+        checkpoints: HashMap::new(),
+        finality_depth: Some(720), // Blocks
+
+        upgrades: get_upgrades("mainnet"),
     }
 }
 
+/// Blockchain config of the given network, with its checkpoints and upgrades
+pub fn get_network_blockchain_config(network: &str) -> BlockchainConfig {
+    BlockchainConfig {
+        checkpoints: get_checkpoints(network),
+        upgrades: get_upgrades(network),
+        difficulty_algorithm: get_difficulty_algorithm(network),
+        plonk_srs: get_plonk_srs(network),
+        ..get_blockchain_config()
+    }
+}
+
+/// Single-node chain of `bazuka dev`. Blocks are cheap to mine, the MPN
+/// contract needs no updates and `faucet` is funded in the genesis block and
+/// administers the MPN contract.
+pub fn get_dev_blockchain_config(faucet: Address) -> BlockchainConfig {
+    let mut conf = get_blockchain_config();
+    conf.limited_miners = None;
+    conf.block_time = 1;
+    conf.mpn_num_contract_deposits = 0;
+    conf.mpn_num_contract_withdraws = 0;
+    conf.mpn_num_function_calls = 0;
+    conf.mpn_proving_time = 0;
+    conf.minimum_pow_difficulty = Difficulty(0x007fffff);
+    conf.testnet_height_limit = None;
+    conf.finality_depth = None;
+    conf.upgrades = get_upgrades("dev");
+    conf.difficulty_algorithm = get_difficulty_algorithm("dev");
+    conf.plonk_srs = get_plonk_srs("dev");
+    // The faucet may try out upgrades of the MPN contract
+    conf.mpn_admin = Some(ContractAdminPolicy {
+        admin: ContractAdmin::Key(faucet.clone()),
+        delay: 1,
+    });
+    conf.genesis.block.body.push(Transaction {
+        memo: "Dev faucet".into(),
+        src: None,
+        data: TransactionData::RegularSend {
+            entries: vec![RegularSendEntry {
+                dst: faucet,
+                amount: Money::ziesha(1_000_000 * UNIT),
+            }],
+        },
+        nonce: 3,
+        fee: Money::ziesha(0),
+        valid_after_height: None,
+        valid_until_height: None,
+        sig: Signature::Unsigned,
+    });
+    conf
+}
+
 #[cfg(test)]
 pub fn get_test_blockchain_config() -> BlockchainConfig {
-    use crate::core::RegularSendEntry;
     let mpn_tx_delta = get_test_mpn_contract();
     let mpn_contract_id = ContractId::new(&mpn_tx_delta.tx);
 
@@ -220,6 +368,28 @@ pub fn get_test_blockchain_config() -> BlockchainConfig {
     conf.mpn_contract_id = mpn_contract_id;
     conf.minimum_pow_difficulty = Difficulty(0x007fffff);
     conf.testnet_height_limit = None;
+    conf.finality_depth = None;
+    conf.upgrades = get_upgrades("simulator");
+    conf.plonk_srs = get_plonk_srs("simulator");
+
+    // The dummy keys of the test MPN contract accept the proofs of any circuit
+    conf.mpn_circuit_upgrades = [Upgrade::MpnAccountSlots, Upgrade::MpnTokenSlotReuse]
+        .into_iter()
+        .map(|upgrade| {
+            (
+                upgrade,
+                crate::core::ContractUpgrade {
+                    functions: vec![(
+                        0,
+                        zk::ZkSingleInputVerifierKey {
+                            verifier_key: zk::ZkVerifierKey::Dummy,
+                        },
+                    )],
+                    ..Default::default()
+                },
+            )
+        })
+        .collect();
 
     conf.genesis.block.body[1] = get_test_mpn_contract().tx;
     let abc = TxBuilder::new(&Vec::from("ABC"));
@@ -234,6 +404,8 @@ pub fn get_test_blockchain_config() -> BlockchainConfig {
         },
         nonce: 3,
         fee: Money::ziesha(0),
+        valid_after_height: None,
+        valid_until_height: None,
         sig: Signature::Unsigned,
     });
     conf.genesis.patch = ZkBlockchainPatch {
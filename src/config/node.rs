@@ -1,6 +1,52 @@
-use crate::node::{HeartbeatIntervals, NodeOptions};
+use crate::blockchain::FeeRates;
+use crate::common::*;
+use crate::config::UNIT;
+use crate::core::Amount;
+use crate::node::{FirewallLimits, FirewallOptions, HeartbeatIntervals, IpRange, NodeOptions};
 use std::time::Duration;
 
+pub fn get_firewall_options(
+    allowlist: Vec<IpRange>,
+    denylist: Vec<IpRange>,
+    limits: &FirewallLimits,
+) -> FirewallOptions {
+    FirewallOptions {
+        // Bursts of 360 cheap requests, refilled by 6 per second
+        request_capacity: 360,
+        request_refill_per_second: 6,
+        // Bursts of 4GB, refilled by ~4.5MB per second (4GB per 15min)
+        traffic_capacity: 4 * GB,
+        traffic_refill_per_second: 4 * GB / 900,
+        default_route_cost: 1,
+        route_costs: [
+            ("/bincode/blocks", 20),
+            ("/bincode/headers", 5),
+            ("/bincode/states", 50),
+            ("/bincode/states/outdated", 5),
+            ("/bincode/mempool", 10),
+            ("/mempool", 10),
+            ("/explorer/blocks", 10),
+            ("/explorer/mpn/accounts", 10),
+            ("/explorer/mpn/address/history", 10),
+            ("/explorer/contract/events", 10),
+            ("/contract/events", 10),
+            ("/subscribe", 10),
+            ("/pool/share", 10),
+            ("/bincode/transact", 2),
+            ("/bincode/transact/zero", 2),
+            ("/transact/zero", 2),
+            ("/bincode/transact/deposit", 2),
+            ("/bincode/transact/withdraw", 2),
+        ]
+        .into_iter()
+        .map(|(route, cost)| (route.to_string(), cost))
+        .collect(),
+        allowlist,
+        denylist,
+    }
+    .with_limits(limits)
+}
+
 pub fn get_node_options() -> NodeOptions {
     NodeOptions {
         tx_max_time_alive: Some(600),
@@ -13,6 +59,7 @@ pub fn get_node_options() -> NodeOptions {
             sync_blocks: Duration::from_secs(10),
             sync_mempool: Duration::from_secs(30),
             sync_state: Duration::from_secs(10),
+            notify_subscribers: Duration::from_secs(1),
         },
         num_peers: 8,
         max_blocks_fetch: 16,
@@ -25,6 +72,12 @@ pub fn get_node_options() -> NodeOptions {
         state_unavailable_ban_time: 30,
         candidate_remove_threshold: 3600,
         mpn_mempool_capacity: 1024,
+        pool: None,
+        max_miner_templates: 8,
+        miner_template_fee_threshold: Amount(UNIT / 100),
+        fee_rates: FeeRates::default(),
+        #[cfg(feature = "mpn-executor")]
+        mpn_executor: None,
     }
 }
 
@@ -40,6 +93,7 @@ pub fn get_simulator_options() -> NodeOptions {
             sync_blocks: Duration::from_millis(300),
             sync_mempool: Duration::from_millis(300),
             sync_state: Duration::from_millis(300),
+            notify_subscribers: Duration::from_millis(100),
         },
         num_peers: 8,
         max_blocks_fetch: 16,
@@ -52,5 +106,11 @@ pub fn get_simulator_options() -> NodeOptions {
         state_unavailable_ban_time: 10,
         candidate_remove_threshold: 600,
         mpn_mempool_capacity: 1024,
+        pool: None,
+        max_miner_templates: 8,
+        miner_template_fee_threshold: Amount(UNIT / 100),
+        fee_rates: FeeRates::default(),
+        #[cfg(feature = "mpn-executor")]
+        mpn_executor: None,
     }
 }
@@ -0,0 +1,281 @@
+use super::pow::Difficulty;
+
+/// A previous block, as seen by the retarget algorithms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockInfo {
+    pub timestamp: u32,
+    pub target: Difficulty,
+    pub power: u128, // Total power of the chain, including this block
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetargetParams {
+    pub block_time: u32,
+    /// Part of the block time which is not spent on mining (E.g. proving MPN
+    /// updates), excluded from the measured solve times
+    pub proving_time: u32,
+    pub minimum: Difficulty,
+}
+
+pub trait RetargetAlgorithm {
+    /// Number of the most recent blocks needed for calculating the difficulty
+    fn window(&self) -> u64;
+    /// Difficulty of the next block, given the most recent blocks (Oldest first)
+    fn next_difficulty(&self, blocks: &[BlockInfo], params: &RetargetParams) -> Difficulty;
+}
+
+fn solve_time(prev: &BlockInfo, curr: &BlockInfo, params: &RetargetParams) -> i64 {
+    curr.timestamp as i64 - prev.timestamp as i64 - params.proving_time as i64
+}
+
+fn at_least(power: u128, params: &RetargetParams) -> Difficulty {
+    if power < 1 {
+        return params.minimum;
+    }
+    std::cmp::max(Difficulty::from_power(power), params.minimum)
+}
+
+/// Multiplies `power` by 2^(`exponent` / 2^16), approximating the fractional
+/// part of the exponent with the cubic polynomial of aserti3-2d, which stays
+/// within 0.013% of the exact value. Fixed-point only, so that every node gets
+/// the very same result.
+fn mul_exp2(power: u128, exponent: i64) -> u128 {
+    let shifts = exponent >> 16;
+    let frac = (exponent & 0xffff) as u128;
+    let factor = 65536
+        + ((195766423245049 * frac
+            + 971821376 * frac * frac
+            + 5127 * frac * frac * frac
+            + (1 << 47))
+            >> 48);
+    let power = power.saturating_mul(factor) >> 16;
+    if shifts < 0 {
+        power.checked_shr(shifts.unsigned_abs() as u32).unwrap_or(0)
+    } else if power.leading_zeros() as i64 >= shifts {
+        power << shifts
+    } else {
+        u128::MAX
+    }
+}
+
+/// Sorts the timestamps of a window and cuts the outliers, the difficulty is
+/// then the power added in the window over the time it took. The most recent
+/// `lag` blocks are ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrimmedMean {
+    pub window: u64,
+    pub lag: u64,
+    pub cut: u64,
+}
+
+impl RetargetAlgorithm for TrimmedMean {
+    fn window(&self) -> u64 {
+        self.window + self.lag
+    }
+    fn next_difficulty(&self, blocks: &[BlockInfo], params: &RetargetParams) -> Difficulty {
+        if blocks.len() <= self.lag as usize {
+            return params.minimum;
+        }
+        let blocks = &blocks[blocks.len().saturating_sub(self.window() as usize)..];
+        let blocks = &blocks[..blocks.len() - self.lag as usize];
+        if blocks.len() < 2 {
+            return params.minimum;
+        }
+        let mut timestamps = blocks.iter().map(|b| b.timestamp).collect::<Vec<_>>();
+        timestamps.sort_unstable();
+        let final_size = self.window - 2 * self.cut;
+        let (begin, end) = if timestamps.len() as u64 > final_size {
+            let begin = (timestamps.len() as u64 - final_size).div_ceil(2);
+            let end = begin + final_size - 1;
+            (begin as usize, end as usize)
+        } else {
+            (0, timestamps.len() - 1)
+        };
+        let time_delta = (timestamps[end] - timestamps[begin])
+            .saturating_sub((end - begin) as u32 * params.proving_time);
+        if time_delta == 0 {
+            return params.minimum;
+        }
+        let power_delta = blocks[end].power - blocks[begin].power;
+        std::cmp::max(
+            Difficulty::from_power(
+                power_delta * (params.block_time as u128) / (time_delta as u128),
+            ),
+            params.minimum,
+        )
+    }
+}
+
+/// Linearly weighted moving average of the block difficulties, recent solve
+/// times have higher weights (LWMA-1)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lwma {
+    pub window: u64,
+}
+
+impl RetargetAlgorithm for Lwma {
+    fn window(&self) -> u64 {
+        self.window + 1
+    }
+    fn next_difficulty(&self, blocks: &[BlockInfo], params: &RetargetParams) -> Difficulty {
+        let blocks = &blocks[blocks.len().saturating_sub(self.window() as usize)..];
+        if blocks.len() < 2 {
+            return params.minimum;
+        }
+        let block_time = params.block_time.max(1) as i64;
+        let mut weighted_times = 0u128;
+        let mut sum_power = 0u128;
+        for (i, w) in blocks.windows(2).enumerate() {
+            // Limit the effect of wrong timestamps
+            let st = solve_time(&w[0], &w[1], params).clamp(1, 6 * block_time);
+            weighted_times += (st * (i as i64 + 1)) as u128;
+            sum_power = sum_power.saturating_add(w[1].target.power());
+        }
+        // Average power * block time * k / weighted times, where k = n(n + 1)/2
+        let n = (blocks.len() - 1) as u128;
+        at_least(
+            sum_power.saturating_mul(block_time as u128 * (n + 1)) / (2 * weighted_times),
+            params,
+        )
+    }
+}
+
+/// Exponential adjustment based on how far the last block is from its
+/// schedule: each `half_life` seconds of delay halves the difficulty. This is
+/// the relative (Per-block) form of ASERT.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asert {
+    pub half_life: u32,
+}
+
+impl RetargetAlgorithm for Asert {
+    fn window(&self) -> u64 {
+        2
+    }
+    fn next_difficulty(&self, blocks: &[BlockInfo], params: &RetargetParams) -> Difficulty {
+        if blocks.len() < 2 {
+            return params.minimum;
+        }
+        let (prev, last) = (&blocks[blocks.len() - 2], &blocks[blocks.len() - 1]);
+        let delay = solve_time(prev, last, params) - params.block_time as i64;
+        // Exponent in 16.16 fixed-point, rounded down
+        let exponent = (-delay * 65536).div_euclid(self.half_life.max(1) as i64);
+        at_least(mul_exp2(last.target.power(), exponent), params)
+    }
+}
+
+/// The retarget algorithm of a network
+#[derive(Debug, Clone, PartialEq)]
+pub enum DifficultyAlgorithm {
+    TrimmedMean(TrimmedMean),
+    Lwma(Lwma),
+    Asert(Asert),
+}
+
+impl DifficultyAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TrimmedMean(_) => "trimmed-mean",
+            Self::Lwma(_) => "lwma",
+            Self::Asert(_) => "asert",
+        }
+    }
+}
+
+impl RetargetAlgorithm for DifficultyAlgorithm {
+    fn window(&self) -> u64 {
+        match self {
+            Self::TrimmedMean(algo) => algo.window(),
+            Self::Lwma(algo) => algo.window(),
+            Self::Asert(algo) => algo.window(),
+        }
+    }
+    fn next_difficulty(&self, blocks: &[BlockInfo], params: &RetargetParams) -> Difficulty {
+        match self {
+            Self::TrimmedMean(algo) => algo.next_difficulty(blocks, params),
+            Self::Lwma(algo) => algo.next_difficulty(blocks, params),
+            Self::Asert(algo) => algo.next_difficulty(blocks, params),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(solve_time: u32, power: u128, count: usize) -> Vec<BlockInfo> {
+        (0..count)
+            .map(|i| BlockInfo {
+                timestamp: i as u32 * solve_time,
+                target: Difficulty::from_power(power),
+                power: power * (i as u128 + 1),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_stable_hashrate_keeps_difficulty() {
+        let params = RetargetParams {
+            block_time: 60,
+            proving_time: 0,
+            minimum: Difficulty::from_power(1),
+        };
+        let power = 1_000_000;
+        let history = blocks(60, power, 100);
+        for algo in [
+            DifficultyAlgorithm::TrimmedMean(TrimmedMean {
+                window: 30,
+                lag: 5,
+                cut: 5,
+            }),
+            DifficultyAlgorithm::Lwma(Lwma { window: 30 }),
+            DifficultyAlgorithm::Asert(Asert { half_life: 600 }),
+        ] {
+            let next = algo.next_difficulty(&history, &params).power();
+            assert!(
+                next.abs_diff(power) < power / 100,
+                "{}: {}",
+                algo.name(),
+                next
+            );
+        }
+    }
+
+    #[test]
+    fn test_slow_blocks_decrease_difficulty() {
+        let params = RetargetParams {
+            block_time: 60,
+            proving_time: 0,
+            minimum: Difficulty::from_power(1),
+        };
+        let power = 1_000_000;
+        let history = blocks(120, power, 100);
+        let lwma = Lwma { window: 30 }
+            .next_difficulty(&history, &params)
+            .power();
+        assert!(lwma.abs_diff(power / 2) < power / 100);
+        let asert = Asert { half_life: 60 }
+            .next_difficulty(&history, &params)
+            .power();
+        assert!(asert.abs_diff(power / 2) < power / 100);
+        assert_eq!(
+            Asert { half_life: 60 }.next_difficulty(&history[..1], &params),
+            params.minimum
+        );
+    }
+
+    #[test]
+    fn test_fixed_point_exp2() {
+        let power = 1u128 << 40;
+        assert_eq!(mul_exp2(power, 0), power);
+        assert_eq!(mul_exp2(power, 65536), power * 2);
+        assert_eq!(mul_exp2(power, -65536), power / 2);
+        assert_eq!(mul_exp2(power, -200 * 65536), 0);
+        assert_eq!(mul_exp2(power, 200 * 65536), u128::MAX);
+        for exponent in (-10 * 65536..10 * 65536).step_by(4093) {
+            let exact = power as f64 * (exponent as f64 / 65536.0).exp2();
+            let approx = mul_exp2(power, exponent) as f64;
+            assert!((approx - exact).abs() / exact < 0.0002, "{}", exponent);
+        }
+    }
+}
@@ -1,5 +1,9 @@
+pub mod difficulty;
+pub mod simulator;
+pub mod upgrades;
+
 pub mod pow {
-    use rust_randomx::{Context, Hasher};
+    use rust_randomx::{Context, Hasher, Output};
     use serde::{Deserialize, Serialize};
     use std::cmp::Ordering;
     use std::collections::HashMap;
@@ -18,6 +22,9 @@ pub mod pow {
         pub fn from_power(power: u128) -> Self {
             Self(rust_randomx::Difficulty::from_power(power).to_u32())
         }
+        pub fn is_met_by(&self, output: &Output) -> bool {
+            output.meets_difficulty(rust_randomx::Difficulty::new(self.0))
+        }
     }
 
     impl Ord for Difficulty {
@@ -37,7 +44,9 @@ pub mod pow {
             Arc::new(Mutex::new(HashMap::new()));
     }
 
-    pub fn meets_difficulty(key: &[u8], input: &[u8], diff: Difficulty) -> bool {
+    /// Light-mode RandomX hash of the input, so that a single hash can be
+    /// checked against several targets
+    pub fn hash(key: &[u8], input: &[u8]) -> Output {
         let mut hasher = HASHER.lock().unwrap();
 
         #[cfg(not(test))]
@@ -48,6 +57,9 @@ pub mod pow {
             .entry(key.clone())
             .or_insert_with(|| Hasher::new(Arc::new(Context::new(&key, false))))
             .hash(input)
-            .meets_difficulty(rust_randomx::Difficulty::new(diff.0))
+    }
+
+    pub fn meets_difficulty(key: &[u8], input: &[u8], diff: Difficulty) -> bool {
+        diff.is_met_by(&hash(key, input))
     }
 }
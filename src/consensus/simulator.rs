@@ -0,0 +1,179 @@
+use super::difficulty::{BlockInfo, RetargetAlgorithm, RetargetParams};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Hashrate (Hashes per second) of the network for a number of blocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Phase {
+    pub blocks: u64,
+    pub hashrate: f64,
+}
+
+#[derive(Error, Debug)]
+pub enum PhaseParseError {
+    #[error("phase should be in <blocks>:<hashrate> format")]
+    Invalid,
+}
+
+impl FromStr for Phase {
+    type Err = PhaseParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (blocks, hashrate) = s.split_once(':').ok_or(PhaseParseError::Invalid)?;
+        Ok(Self {
+            blocks: blocks.parse().map_err(|_| PhaseParseError::Invalid)?,
+            hashrate: hashrate.parse().map_err(|_| PhaseParseError::Invalid)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulatedBlock {
+    pub info: BlockInfo,
+    pub block_time: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulationReport {
+    pub blocks: usize,
+    pub mean_block_time: f64,
+    pub stddev_block_time: f64,
+    pub max_block_time: u32,
+    /// Blocks it took to get back to the target block time after a change of
+    /// the hashrate, averaged over the phases
+    pub mean_recovery_blocks: f64,
+}
+
+/// Mines the phases with the given algorithm, solve times are sampled from an
+/// exponential distribution, just like the real PoW
+pub fn simulate<A: RetargetAlgorithm>(
+    algo: &A,
+    params: &RetargetParams,
+    genesis_target: super::pow::Difficulty,
+    phases: &[Phase],
+    seed: u64,
+) -> Vec<SimulatedBlock> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut history = vec![BlockInfo {
+        timestamp: 0,
+        target: genesis_target,
+        power: genesis_target.power(),
+    }];
+    let mut result = Vec::new();
+    let mut time = 0f64;
+    for phase in phases {
+        for _ in 0..phase.blocks {
+            let start = history.len().saturating_sub(algo.window() as usize);
+            let target = algo.next_difficulty(&history[start..], params);
+            let expected = target.powerf() / phase.hashrate.max(f64::MIN_POSITIVE);
+            let sample: f64 = rng.gen_range(f64::EPSILON..1.0);
+            time += -sample.ln() * expected + params.proving_time as f64;
+            let last = history.last().unwrap();
+            let info = BlockInfo {
+                timestamp: time as u32,
+                target,
+                power: last.power + target.power(),
+            };
+            result.push(SimulatedBlock {
+                info,
+                block_time: info.timestamp - last.timestamp,
+            });
+            history.push(info);
+            if history.len() > algo.window() as usize {
+                history.remove(0);
+            }
+        }
+    }
+    result
+}
+
+pub fn report(
+    blocks: &[SimulatedBlock],
+    phases: &[Phase],
+    params: &RetargetParams,
+) -> SimulationReport {
+    let times = blocks
+        .iter()
+        .map(|b| b.block_time as f64)
+        .collect::<Vec<_>>();
+    let n = times.len().max(1) as f64;
+    let mean = times.iter().sum::<f64>() / n;
+    let variance = times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / n;
+
+    // A phase is recovered when the average of the last 10 block times is
+    // within 20% of the target
+    let target = (params.block_time + params.proving_time) as f64;
+    let mut recoveries = Vec::new();
+    let mut offset = 0;
+    for phase in phases {
+        let phase_blocks = &times[offset..offset + phase.blocks as usize];
+        offset += phase.blocks as usize;
+        let recovered = (10..=phase_blocks.len()).find(|&i| {
+            let avg = phase_blocks[i - 10..i].iter().sum::<f64>() / 10.0;
+            (avg - target).abs() <= target * 0.2
+        });
+        recoveries.push(recovered.unwrap_or(phase_blocks.len()) as f64);
+    }
+
+    SimulationReport {
+        blocks: blocks.len(),
+        mean_block_time: mean,
+        stddev_block_time: variance.sqrt(),
+        max_block_time: blocks.iter().map(|b| b.block_time).max().unwrap_or(0),
+        mean_recovery_blocks: recoveries.iter().sum::<f64>() / recoveries.len().max(1) as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::difficulty::{Asert, Lwma};
+    use super::super::pow::Difficulty;
+    use super::*;
+
+    #[test]
+    fn test_simulation_converges() {
+        let params = RetargetParams {
+            block_time: 60,
+            proving_time: 0,
+            minimum: Difficulty::from_power(1000),
+        };
+        let phases = [
+            Phase {
+                blocks: 500,
+                hashrate: 1000.0,
+            },
+            Phase {
+                blocks: 500,
+                hashrate: 10000.0,
+            },
+        ];
+        let lwma = simulate(&Lwma { window: 45 }, &params, params.minimum, &phases, 0);
+        assert_eq!(lwma.len(), 1000);
+        let rep = report(&lwma, &phases, &params);
+        assert!((rep.mean_block_time - 60.0).abs() < 15.0);
+
+        let asert = simulate(
+            &Asert { half_life: 600 },
+            &params,
+            params.minimum,
+            &phases,
+            0,
+        );
+        let rep = report(&asert, &phases, &params);
+        assert!((rep.mean_block_time - 60.0).abs() < 15.0);
+    }
+
+    #[test]
+    fn test_parse_phase() {
+        assert_eq!(
+            "100:2.5".parse::<Phase>().unwrap(),
+            Phase {
+                blocks: 100,
+                hashrate: 2.5
+            }
+        );
+        assert!("100".parse::<Phase>().is_err());
+        assert!("x:1".parse::<Phase>().is_err());
+    }
+}
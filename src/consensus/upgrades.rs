@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Protocol changes which get activated at a certain height of the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Upgrade {
+    /// Blocks need at least 10 MPN function calls
+    MoreMpnFunctionCalls,
+    /// Transaction fees can be paid in any token
+    NonZieshaFees,
+    /// The whole token name/symbol should match the allowed patterns
+    StrictTokenNames,
+    /// Transactions may have a validity window of block heights
+    TransactionValidityWindow,
+    /// Accounts may have spending limits and recovery keys
+    AccountPolicies,
+    /// Contracts may use PLONK verifier keys
+    PlonkProofs,
+    /// Contract updates may emit events
+    ContractEvents,
+    /// Contracts may have an admin which is able to replace their verifier keys
+    ContractUpgrades,
+    /// MPN accounts may take one of several slots when their primary slot is taken.
+    /// Takes effect together with its keys in `BlockchainConfig::mpn_circuit_upgrades`.
+    MpnAccountSlots,
+    /// MPN token slots with no balance left may receive other tokens. Takes
+    /// effect together with its keys in `BlockchainConfig::mpn_circuit_upgrades`.
+    MpnTokenSlotReuse,
+}
+
+impl Upgrade {
+    /// Upgrades supported by this version of the software
+    pub const ALL: &'static [Upgrade] = &[
+        Upgrade::MoreMpnFunctionCalls,
+        Upgrade::NonZieshaFees,
+        Upgrade::StrictTokenNames,
+        Upgrade::TransactionValidityWindow,
+        Upgrade::AccountPolicies,
+        Upgrade::PlonkProofs,
+        Upgrade::ContractEvents,
+        Upgrade::ContractUpgrades,
+        Upgrade::MpnAccountSlots,
+        Upgrade::MpnTokenSlotReuse,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Upgrade::MoreMpnFunctionCalls => "more_mpn_function_calls",
+            Upgrade::NonZieshaFees => "non_ziesha_fees",
+            Upgrade::StrictTokenNames => "strict_token_names",
+            Upgrade::TransactionValidityWindow => "transaction_validity_window",
+            Upgrade::AccountPolicies => "account_policies",
+            Upgrade::PlonkProofs => "plonk_proofs",
+            Upgrade::ContractEvents => "contract_events",
+            Upgrade::ContractUpgrades => "contract_upgrades",
+            Upgrade::MpnAccountSlots => "mpn_account_slots",
+            Upgrade::MpnTokenSlotReuse => "mpn_token_slot_reuse",
+        }
+    }
+}
+
+impl std::fmt::Display for Upgrade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum UpgradeParseError {
+    #[error("unknown upgrade")]
+    Unknown,
+}
+
+impl FromStr for Upgrade {
+    type Err = UpgradeParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|u| u.name() == s)
+            .cloned()
+            .ok_or(UpgradeParseError::Unknown)
+    }
+}
+
+/// Activation heights of the upgrades, upgrades without a height are never
+/// activated
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Upgrades {
+    activations: HashMap<Upgrade, u64>,
+}
+
+impl Upgrades {
+    pub fn new<I: IntoIterator<Item = (Upgrade, u64)>>(activations: I) -> Self {
+        Self {
+            activations: activations.into_iter().collect(),
+        }
+    }
+    pub fn activation_height(&self, upgrade: Upgrade) -> Option<u64> {
+        self.activations.get(&upgrade).cloned()
+    }
+    pub fn is_active(&self, upgrade: Upgrade, height: u64) -> bool {
+        self.activation_height(upgrade)
+            .map(|h| height >= h)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_activation() {
+        let upgrades = Upgrades::new([(Upgrade::NonZieshaFees, 10)]);
+        assert!(!upgrades.is_active(Upgrade::NonZieshaFees, 9));
+        assert!(upgrades.is_active(Upgrade::NonZieshaFees, 10));
+        assert!(!upgrades.is_active(Upgrade::StrictTokenNames, 100));
+        for upgrade in Upgrade::ALL {
+            assert_eq!(upgrade.to_string().parse::<Upgrade>().unwrap(), *upgrade);
+        }
+        assert!("unknown".parse::<Upgrade>().is_err());
+    }
+}
@@ -14,6 +14,7 @@ impl<ZS: ZkSignatureScheme> MpnAddress<ZS>
 where
     ZS::Pub: DeriveMpnAccountIndex,
 {
+    /// The primary slot of the account, colliding keys may live in another one
     pub fn account_index(&self, mpn_log4_account_capacity: u8) -> u64 {
         self.pub_key.mpn_account_index(mpn_log4_account_capacity)
     }
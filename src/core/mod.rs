@@ -29,8 +29,13 @@ pub type TransactionData = transaction::TransactionData<Hasher, Signer>;
 pub type RegularSendEntry = transaction::RegularSendEntry<Signer>;
 pub type ContractAccount = transaction::ContractAccount;
 pub type ContractUpdate = transaction::ContractUpdate<Hasher, Signer>;
+pub type ContractEvent = transaction::ContractEvent<Hasher>;
 pub type ContractDeposit = transaction::ContractDeposit<Hasher, Signer>;
 pub type ContractWithdraw = transaction::ContractWithdraw<Hasher, Signer>;
+pub type ContractAdmin = transaction::ContractAdmin<Signer>;
+pub type ContractAdminPolicy = transaction::ContractAdminPolicy<Signer>;
+pub type ContractUpgrade = transaction::ContractUpgrade;
+pub type ContractControl = transaction::ContractControl<Signer>;
 pub type MpnAddress = address::MpnAddress<ZkSigner>;
 pub type ParseMpnAddressError = address::ParseMpnAddressError;
 pub type MpnDeposit = transaction::MpnDeposit<Hasher, Signer, ZkSigner>;
@@ -40,8 +45,12 @@ pub type Header = header::Header<Hasher>;
 pub type Block = blocks::Block<Hasher, Signer>;
 pub type TokenId = transaction::TokenId;
 pub type ParseTokenIdError = transaction::ParseTokenIdError;
+pub type ParseContractIdError = transaction::ParseContractIdError;
 pub type TokenUpdate = transaction::TokenUpdate<Signer>;
 pub type Token = transaction::Token<Signer>;
+pub type AccountPolicy = transaction::AccountPolicy<Signer>;
+pub type AccountControl = transaction::AccountControl<Signer>;
+pub type DailySpending = transaction::DailySpending;
 
 pub type ProofOfWork = header::ProofOfWork;
 pub type ContractId = transaction::ContractId<Hasher>;
@@ -84,6 +93,7 @@ impl FromStr for ZieshaAddress {
 
 // Transactions initiated from chain accounts
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum ChainSourcedTx {
     TransactionAndDelta(TransactionAndDelta),
     MpnDeposit(MpnDeposit),
@@ -131,6 +141,12 @@ impl MpnSourcedTx {
             MpnSourcedTx::MpnWithdraw(mpn_withdraw) => mpn_withdraw.zk_nonce,
         }
     }
+    pub fn fee(&self) -> Money {
+        match self {
+            MpnSourcedTx::MpnTransaction(mpn_tx) => mpn_tx.fee,
+            MpnSourcedTx::MpnWithdraw(mpn_withdraw) => mpn_withdraw.payment.fee,
+        }
+    }
 }
 
 impl PartialEq<ChainSourcedTx> for ChainSourcedTx {
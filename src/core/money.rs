@@ -82,6 +82,12 @@ impl From<u64> for Amount {
     }
 }
 
+impl Amount {
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+}
+
 impl AddAssign for Amount {
     fn add_assign(&mut self, other: Self) {
         self.0 += other.0;
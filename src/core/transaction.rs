@@ -1,9 +1,12 @@
 use super::address::Signature;
 use super::hash::Hash;
 use super::Amount;
-use crate::crypto::DeriveMpnAccountIndex;
+use crate::consensus::upgrades::{Upgrade, Upgrades};
 use crate::crypto::{SignatureScheme, ZkSignatureScheme};
-use crate::zk::{ZkCompressedState, ZkContract, ZkDeltaPairs, ZkProof, ZkScalar};
+use crate::zk::{
+    ZkCompressedState, ZkContract, ZkDeltaPairs, ZkMultiInputVerifierKey, ZkProof, ZkScalar,
+    ZkSingleInputVerifierKey, ZkVerifierKey,
+};
 use ff::Field;
 use std::str::FromStr;
 use thiserror::Error;
@@ -25,6 +28,15 @@ pub enum ParseTokenIdError {
     Invalid,
 }
 
+/// Encodings which would decode into values that serialize differently
+#[derive(Error, Debug)]
+pub enum NonCanonicalError {
+    #[error("validity window without any bounds")]
+    EmptyValidityWindow,
+    #[error("contract update with events but without any")]
+    EmptyEvents,
+}
+
 impl<H: Hash> ContractId<H> {
     pub fn new<S: SignatureScheme>(tx: &Transaction<H, S>) -> Self {
         Self(tx.hash())
@@ -165,32 +177,17 @@ pub struct MpnWithdraw<H: Hash, S: SignatureScheme, ZS: ZkSignatureScheme> {
     pub payment: ContractWithdraw<H, S>,
 }
 
-impl<H: Hash, S: SignatureScheme, ZS: ZkSignatureScheme> MpnDeposit<H, S, ZS>
-where
-    ZS::Pub: DeriveMpnAccountIndex,
-{
-    pub fn zk_address_index(&self, log4_account_capacity: u8) -> u64 {
-        self.zk_address.mpn_account_index(log4_account_capacity)
-    }
-}
-
-impl<H: Hash, S: SignatureScheme, ZS: ZkSignatureScheme> MpnWithdraw<H, S, ZS>
-where
-    ZS::Pub: DeriveMpnAccountIndex,
-{
-    pub fn zk_address_index(&self, log4_account_capacity: u8) -> u64 {
-        self.zk_address.mpn_account_index(log4_account_capacity)
-    }
-}
-
 impl<H: Hash, S: SignatureScheme> ContractDeposit<H, S> {
     pub fn verify_signature(&self) -> bool {
+        self.verify_signature_with(&self.src)
+    }
+    pub fn verify_signature_with(&self, key: &S::Pub) -> bool {
         let mut unsigned = self.clone();
         unsigned.sig = None;
         let unsigned_bin = bincode::serialize(&unsigned).unwrap();
         self.sig
             .as_ref()
-            .map(|sig| S::verify(&self.src, &unsigned_bin, sig))
+            .map(|sig| S::verify(key, &unsigned_bin, sig))
             .unwrap_or(false)
     }
 }
@@ -210,7 +207,13 @@ pub struct ContractAccount {
     pub compressed_state: ZkCompressedState,
 }
 
+// Every update may emit a few event scalars, which are chained into the aux
+// data its proof is checked against (See `ZkCompressedState::with_events`)
 #[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(
+    into = "ContractUpdateWire<H, S>",
+    try_from = "ContractUpdateWire<H, S>"
+)]
 pub enum ContractUpdate<H: Hash, S: SignatureScheme> {
     // Proof for DepositCircuit[circuit_id](curr_state, next_state, hash(entries))
     Deposit {
@@ -218,6 +221,7 @@ pub enum ContractUpdate<H: Hash, S: SignatureScheme> {
         deposits: Vec<ContractDeposit<H, S>>,
         next_state: ZkCompressedState,
         proof: ZkProof,
+        events: Vec<ZkScalar>,
     },
     // Proof for WithdrawCircuit[circuit_id](curr_state, next_state, hash(entries))
     Withdraw {
@@ -225,6 +229,7 @@ pub enum ContractUpdate<H: Hash, S: SignatureScheme> {
         withdraws: Vec<ContractWithdraw<H, S>>,
         next_state: ZkCompressedState,
         proof: ZkProof,
+        events: Vec<ZkScalar>,
     },
     // Proof for FunctionCallCircuits[function_id](curr_state, next_state)
     FunctionCall {
@@ -232,9 +237,332 @@ pub enum ContractUpdate<H: Hash, S: SignatureScheme> {
         next_state: ZkCompressedState,
         proof: ZkProof,
         fee: Money, // Executor fee
+        events: Vec<ZkScalar>,
+    },
+}
+
+impl<H: Hash, S: SignatureScheme> ContractUpdate<H, S> {
+    pub fn events(&self) -> &[ZkScalar] {
+        match self {
+            Self::Deposit { events, .. }
+            | Self::Withdraw { events, .. }
+            | Self::FunctionCall { events, .. } => events,
+        }
+    }
+}
+
+/// Serialized form of a contract update. Updates without events keep their
+/// original encoding, the ones emitting events are encoded as new variants.
+#[derive(serde::Serialize, serde::Deserialize)]
+enum ContractUpdateWire<H: Hash, S: SignatureScheme> {
+    Deposit {
+        deposit_circuit_id: u32,
+        deposits: Vec<ContractDeposit<H, S>>,
+        next_state: ZkCompressedState,
+        proof: ZkProof,
+    },
+    Withdraw {
+        withdraw_circuit_id: u32,
+        withdraws: Vec<ContractWithdraw<H, S>>,
+        next_state: ZkCompressedState,
+        proof: ZkProof,
+    },
+    FunctionCall {
+        function_id: u32,
+        next_state: ZkCompressedState,
+        proof: ZkProof,
+        fee: Money,
+    },
+    DepositWithEvents {
+        deposit_circuit_id: u32,
+        deposits: Vec<ContractDeposit<H, S>>,
+        next_state: ZkCompressedState,
+        proof: ZkProof,
+        events: Vec<ZkScalar>,
+    },
+    WithdrawWithEvents {
+        withdraw_circuit_id: u32,
+        withdraws: Vec<ContractWithdraw<H, S>>,
+        next_state: ZkCompressedState,
+        proof: ZkProof,
+        events: Vec<ZkScalar>,
+    },
+    FunctionCallWithEvents {
+        function_id: u32,
+        next_state: ZkCompressedState,
+        proof: ZkProof,
+        fee: Money,
+        events: Vec<ZkScalar>,
     },
 }
 
+impl<H: Hash, S: SignatureScheme> From<ContractUpdate<H, S>> for ContractUpdateWire<H, S> {
+    fn from(update: ContractUpdate<H, S>) -> Self {
+        match update {
+            ContractUpdate::Deposit {
+                deposit_circuit_id,
+                deposits,
+                next_state,
+                proof,
+                events,
+            } => {
+                if events.is_empty() {
+                    Self::Deposit {
+                        deposit_circuit_id,
+                        deposits,
+                        next_state,
+                        proof,
+                    }
+                } else {
+                    Self::DepositWithEvents {
+                        deposit_circuit_id,
+                        deposits,
+                        next_state,
+                        proof,
+                        events,
+                    }
+                }
+            }
+            ContractUpdate::Withdraw {
+                withdraw_circuit_id,
+                withdraws,
+                next_state,
+                proof,
+                events,
+            } => {
+                if events.is_empty() {
+                    Self::Withdraw {
+                        withdraw_circuit_id,
+                        withdraws,
+                        next_state,
+                        proof,
+                    }
+                } else {
+                    Self::WithdrawWithEvents {
+                        withdraw_circuit_id,
+                        withdraws,
+                        next_state,
+                        proof,
+                        events,
+                    }
+                }
+            }
+            ContractUpdate::FunctionCall {
+                function_id,
+                next_state,
+                proof,
+                fee,
+                events,
+            } => {
+                if events.is_empty() {
+                    Self::FunctionCall {
+                        function_id,
+                        next_state,
+                        proof,
+                        fee,
+                    }
+                } else {
+                    Self::FunctionCallWithEvents {
+                        function_id,
+                        next_state,
+                        proof,
+                        fee,
+                        events,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<H: Hash, S: SignatureScheme> TryFrom<ContractUpdateWire<H, S>> for ContractUpdate<H, S> {
+    type Error = NonCanonicalError;
+    fn try_from(wire: ContractUpdateWire<H, S>) -> Result<Self, Self::Error> {
+        Ok(match wire {
+            ContractUpdateWire::DepositWithEvents { events, .. }
+            | ContractUpdateWire::WithdrawWithEvents { events, .. }
+            | ContractUpdateWire::FunctionCallWithEvents { events, .. }
+                if events.is_empty() =>
+            {
+                return Err(NonCanonicalError::EmptyEvents);
+            }
+            ContractUpdateWire::Deposit {
+                deposit_circuit_id,
+                deposits,
+                next_state,
+                proof,
+            } => Self::Deposit {
+                deposit_circuit_id,
+                deposits,
+                next_state,
+                proof,
+                events: Vec::new(),
+            },
+            ContractUpdateWire::Withdraw {
+                withdraw_circuit_id,
+                withdraws,
+                next_state,
+                proof,
+            } => Self::Withdraw {
+                withdraw_circuit_id,
+                withdraws,
+                next_state,
+                proof,
+                events: Vec::new(),
+            },
+            ContractUpdateWire::FunctionCall {
+                function_id,
+                next_state,
+                proof,
+                fee,
+            } => Self::FunctionCall {
+                function_id,
+                next_state,
+                proof,
+                fee,
+                events: Vec::new(),
+            },
+            ContractUpdateWire::DepositWithEvents {
+                deposit_circuit_id,
+                deposits,
+                next_state,
+                proof,
+                events,
+            } => Self::Deposit {
+                deposit_circuit_id,
+                deposits,
+                next_state,
+                proof,
+                events,
+            },
+            ContractUpdateWire::WithdrawWithEvents {
+                withdraw_circuit_id,
+                withdraws,
+                next_state,
+                proof,
+                events,
+            } => Self::Withdraw {
+                withdraw_circuit_id,
+                withdraws,
+                next_state,
+                proof,
+                events,
+            },
+            ContractUpdateWire::FunctionCallWithEvents {
+                function_id,
+                next_state,
+                proof,
+                fee,
+                events,
+            } => Self::FunctionCall {
+                function_id,
+                next_state,
+                proof,
+                fee,
+                events,
+            },
+        })
+    }
+}
+
+/// Events emitted by an update of a contract, stored per block
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct ContractEvent<H: Hash> {
+    pub tx_hash: H::Output,
+    /// Index of the update in its transaction
+    pub update_index: u32,
+    pub data: Vec<ZkScalar>,
+}
+
+/// Entity which is allowed to upgrade a contract
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone)]
+pub enum ContractAdmin<S: SignatureScheme> {
+    /// Upgrades are sent by this key
+    Key(S::Pub),
+    /// Upgrades come with a proof of this circuit, checked against the current
+    /// state of the contract and the commitment of the upgrade
+    Governance(ZkSingleInputVerifierKey),
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct ContractAdminPolicy<S: SignatureScheme> {
+    pub admin: ContractAdmin<S>,
+    /// Blocks it takes for an upgrade to take effect
+    pub delay: u64,
+}
+
+/// Verifier keys to be set on a contract. A key with an index equal to the
+/// number of the existing functions is appended as a new function.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct ContractUpgrade {
+    pub deposit_functions: Vec<(u32, ZkMultiInputVerifierKey)>,
+    pub withdraw_functions: Vec<(u32, ZkMultiInputVerifierKey)>,
+    pub functions: Vec<(u32, ZkSingleInputVerifierKey)>,
+}
+
+fn upgrade_functions<T: Clone>(functions: &mut Vec<T>, upgrades: &[(u32, T)]) -> bool {
+    for (index, key) in upgrades {
+        let index = *index as usize;
+        if index < functions.len() {
+            functions[index] = key.clone();
+        } else if index == functions.len() {
+            functions.push(key.clone());
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+impl ContractUpgrade {
+    /// Returns false if an index is out of range, in which case the contract
+    /// might be partially upgraded.
+    pub fn apply(&self, contract: &mut ZkContract) -> bool {
+        upgrade_functions(&mut contract.deposit_functions, &self.deposit_functions)
+            && upgrade_functions(&mut contract.withdraw_functions, &self.withdraw_functions)
+            && upgrade_functions(&mut contract.functions, &self.functions)
+    }
+    pub fn verifier_keys(&self) -> impl Iterator<Item = &ZkVerifierKey> {
+        self.deposit_functions
+            .iter()
+            .chain(self.withdraw_functions.iter())
+            .map(|(_, f)| &f.verifier_key)
+            .chain(self.functions.iter().map(|(_, f)| &f.verifier_key))
+    }
+    pub fn commitment<H: Hash>(&self) -> ZkScalar {
+        let bin = bincode::serialize(self).unwrap();
+        ZkScalar::new(H::hash(&bin).as_ref())
+    }
+}
+
+/// Admin policy of a contract and its scheduled upgrade
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct ContractControl<S: SignatureScheme> {
+    pub policy: Option<ContractAdminPolicy<S>>,
+    pub pending_upgrade: Option<(u64, ContractUpgrade)>,
+}
+
+impl<S: SignatureScheme> Default for ContractControl<S> {
+    fn default() -> Self {
+        Self {
+            policy: None,
+            pending_upgrade: None,
+        }
+    }
+}
+
+impl<S: SignatureScheme> ContractControl<S> {
+    /// Takes the scheduled upgrade out if it is in effect at the given height
+    pub fn take_active_upgrade(&mut self, height: u64) -> Option<ContractUpgrade> {
+        match &self.pending_upgrade {
+            Some((activation, _)) if *activation <= height => {
+                self.pending_upgrade.take().map(|(_, u)| u)
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct RegularSendEntry<S: SignatureScheme> {
     pub dst: S::Pub,
@@ -251,7 +579,7 @@ pub struct Token<S: SignatureScheme> {
 }
 
 impl<S: SignatureScheme> Token<S> {
-    pub fn validate(&self) -> bool {
+    pub fn validate(&self, upgrades: &Upgrades, height: u64) -> bool {
         use regex::Regex;
         const MIN_NAME_LEN: usize = 3;
         const MAX_NAME_LEN: usize = 32;
@@ -260,13 +588,21 @@ impl<S: SignatureScheme> Token<S> {
         lazy_static! {
             static ref RE_NAME: Regex = Regex::new(r"(?:[a-zA-Z0-9]+ )*[a-zA-Z0-9]+").unwrap();
             static ref RE_SYMBOL: Regex = Regex::new(r"[A-Z][A-Z0-9]*").unwrap();
+            static ref RE_STRICT_NAME: Regex =
+                Regex::new(r"^(?:[a-zA-Z0-9]+ )*[a-zA-Z0-9]+$").unwrap();
+            static ref RE_STRICT_SYMBOL: Regex = Regex::new(r"^[A-Z][A-Z0-9]*$").unwrap();
         }
+        let (re_name, re_symbol) = if upgrades.is_active(Upgrade::StrictTokenNames, height) {
+            (&*RE_STRICT_NAME, &*RE_STRICT_SYMBOL)
+        } else {
+            (&*RE_NAME, &*RE_SYMBOL)
+        };
         self.name.len() >= MIN_NAME_LEN
             && self.name.len() <= MAX_NAME_LEN
             && self.symbol.len() >= MIN_SYMBOL_LEN
             && self.symbol.len() <= MAX_SYMBOL_LEN
-            && RE_NAME.is_match(&self.name)
-            && RE_SYMBOL.is_match(&self.symbol)
+            && re_name.is_match(&self.name)
+            && re_symbol.is_match(&self.symbol)
     }
 }
 
@@ -300,18 +636,191 @@ pub enum TransactionData<H: Hash, S: SignatureScheme> {
         token_id: TokenId,
         update: TokenUpdate<S>,
     },
+    // Set or remove the policy of the source account. Changes to an existing
+    // policy take effect after its delay.
+    SetAccountPolicy {
+        policy: Option<AccountPolicy<S>>,
+    },
+    // Replace the main key of an account, sent by its recovery key
+    RecoverAccount {
+        account: S::Pub,
+        key: S::Pub,
+    },
+    // Replace or add verifier keys of an upgradable contract. The upgrade takes
+    // effect after the delay of the admin policy of the contract.
+    UpgradeContract {
+        contract_id: ContractId<H>,
+        upgrade: ContractUpgrade,
+        // Proof for GovernanceCircuit(state, state, upgrade.commitment())
+        proof: Option<ZkProof>,
+    },
+    // Create a contract which can be upgraded by its admin
+    CreateUpgradableContract {
+        contract: ZkContract,
+        admin: ContractAdminPolicy<S>,
+    },
+    // Wraps the data of a transaction which is only valid in a range of
+    // heights. Transactions without a window are serialized as before.
+    ValidityWindow {
+        valid_after_height: Option<u64>,
+        valid_until_height: Option<u64>,
+        data: Box<TransactionData<H, S>>,
+    },
+    // Cancel the pending recovery of an account. Only its recovery key can do
+    // it, a stolen main key can't keep the account from being recovered
+    CancelRecovery {
+        account: S::Pub,
+    },
+}
+
+/// Optional on-chain rules protecting an account
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct AccountPolicy<S: SignatureScheme> {
+    /// Maximum amount of each token which can leave the account in a day
+    pub daily_limits: Vec<Money>,
+    /// Key which is able to replace the main key of the account
+    pub recovery_key: Option<S::Pub>,
+    /// Blocks it takes for policy changes and recoveries to take effect
+    pub delay: u64,
+}
+
+impl<S: SignatureScheme> AccountPolicy<S> {
+    pub fn daily_limit(&self, token_id: TokenId) -> Option<Amount> {
+        self.daily_limits
+            .iter()
+            .find(|m| m.token_id == token_id)
+            .map(|m| m.amount)
+    }
+}
+
+/// Policy and key of an account, including the changes not in effect yet
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct AccountControl<S: SignatureScheme> {
+    /// Replaces the address of the account as its signing key
+    pub key: Option<S::Pub>,
+    pub policy: Option<AccountPolicy<S>>,
+    pub pending_policy: Option<(u64, Option<AccountPolicy<S>>)>,
+    pub pending_key: Option<(u64, S::Pub)>,
+}
+
+impl<S: SignatureScheme> Default for AccountControl<S> {
+    fn default() -> Self {
+        Self {
+            key: None,
+            policy: None,
+            pending_policy: None,
+            pending_key: None,
+        }
+    }
+}
+
+impl<S: SignatureScheme> AccountControl<S> {
+    /// Applies the pending changes which are in effect at the given height.
+    /// A key rotation discards the policy changes scheduled after it.
+    pub fn at(mut self, height: u64) -> Self {
+        let key_activation = self.pending_key.as_ref().map(|(h, _)| *h);
+        if let Some((activation, _)) = &self.pending_policy {
+            if *activation <= height && key_activation.map(|h| *activation < h).unwrap_or(true) {
+                self.policy = self.pending_policy.take().unwrap().1;
+            }
+        }
+        if let Some(activation) = key_activation {
+            if activation <= height {
+                self.key = self.pending_key.take().map(|(_, k)| k);
+                self.pending_policy = None;
+            }
+        }
+        self
+    }
+}
+
+/// Outflow of a token from an account in a day, tracked for spending limits
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct DailySpending {
+    pub day: u64,
+    pub amount: Amount,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(into = "TransactionWire<H, S>", try_from = "TransactionWire<H, S>")]
 pub struct Transaction<H: Hash, S: SignatureScheme> {
     pub src: Option<S::Pub>, // None is reward treasury!
     pub nonce: u32,
     pub data: TransactionData<H, S>,
     pub fee: Money,
     pub memo: String,
+    /// Transaction can only be included in blocks after this height
+    pub valid_after_height: Option<u64>,
+    /// Transaction can't be included in blocks after this height
+    pub valid_until_height: Option<u64>,
     pub sig: Signature<S>,
 }
 
+/// Serialized form of a transaction, the validity window is carried inside
+/// the data so that transactions without a window keep their encoding.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TransactionWire<H: Hash, S: SignatureScheme> {
+    src: Option<S::Pub>,
+    nonce: u32,
+    data: TransactionData<H, S>,
+    fee: Money,
+    memo: String,
+    sig: Signature<S>,
+}
+
+impl<H: Hash, S: SignatureScheme> From<Transaction<H, S>> for TransactionWire<H, S> {
+    fn from(tx: Transaction<H, S>) -> Self {
+        let data = if tx.valid_after_height.is_some() || tx.valid_until_height.is_some() {
+            TransactionData::ValidityWindow {
+                valid_after_height: tx.valid_after_height,
+                valid_until_height: tx.valid_until_height,
+                data: Box::new(tx.data),
+            }
+        } else {
+            tx.data
+        };
+        Self {
+            src: tx.src,
+            nonce: tx.nonce,
+            data,
+            fee: tx.fee,
+            memo: tx.memo,
+            sig: tx.sig,
+        }
+    }
+}
+
+impl<H: Hash, S: SignatureScheme> TryFrom<TransactionWire<H, S>> for Transaction<H, S> {
+    type Error = NonCanonicalError;
+    fn try_from(wire: TransactionWire<H, S>) -> Result<Self, Self::Error> {
+        let (valid_after_height, valid_until_height, data) = match wire.data {
+            TransactionData::ValidityWindow {
+                valid_after_height: None,
+                valid_until_height: None,
+                ..
+            } => {
+                return Err(NonCanonicalError::EmptyValidityWindow);
+            }
+            TransactionData::ValidityWindow {
+                valid_after_height,
+                valid_until_height,
+                data,
+            } => (valid_after_height, valid_until_height, *data),
+            data => (None, None, data),
+        };
+        Ok(Self {
+            src: wire.src,
+            nonce: wire.nonce,
+            data,
+            fee: wire.fee,
+            memo: wire.memo,
+            valid_after_height,
+            valid_until_height,
+            sig: wire.sig,
+        })
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct TransactionAndDelta<H: Hash, S: SignatureScheme> {
     pub tx: Transaction<H, S>,
@@ -325,8 +834,26 @@ impl<H: Hash, S: SignatureScheme> Transaction<H, S> {
     pub fn hash(&self) -> H::Output {
         H::hash(&bincode::serialize(self).unwrap())
     }
+    /// Is the transaction allowed to be included in a block with the given height?
+    pub fn is_valid_at(&self, height: u64) -> bool {
+        !self.is_scheduled_at(height) && !self.is_expired_at(height)
+    }
+    /// Is the transaction waiting for its validity window to start?
+    pub fn is_scheduled_at(&self, height: u64) -> bool {
+        self.valid_after_height
+            .map(|h| height <= h)
+            .unwrap_or(false)
+    }
+    pub fn is_expired_at(&self, height: u64) -> bool {
+        self.valid_until_height.map(|h| height > h).unwrap_or(false)
+    }
     pub fn verify_signature(&self) -> bool {
-        match &self.src {
+        self.verify_signature_with(self.src.as_ref())
+    }
+    /// Verifies the signature against the given key instead of the source
+    /// address (E.g. when the key of the account is rotated)
+    pub fn verify_signature_with(&self, key: Option<&S::Pub>) -> bool {
+        match key {
             None => true,
             Some(pk) => match &self.sig {
                 Signature::Unsigned => false,
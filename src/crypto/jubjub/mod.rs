@@ -33,9 +33,19 @@ pub struct PrivateKey {
 pub struct PublicKey(pub PointCompressed);
 
 impl DeriveMpnAccountIndex for PublicKey {
-    fn mpn_account_index(&self, log4_account_capacity: u8) -> u64 {
-        u64::from_le_bytes(self.0 .0.to_repr().as_ref()[0..8].try_into().unwrap())
-            & ((1 << (2 * log4_account_capacity)) - 1)
+    // Slots are disjoint windows of the little-endian bits of the X coordinate
+    fn mpn_account_slot(&self, log4_account_capacity: u8, choice: u8) -> u64 {
+        let repr = self.0 .0.to_repr();
+        let bytes = repr.as_ref();
+        let width = 2 * log4_account_capacity as usize;
+        let mut index = 0;
+        for i in 0..width {
+            let bit = width * choice as usize + i;
+            if bit < bytes.len() * 8 && (bytes[bit / 8] >> (bit % 8)) & 1 == 1 {
+                index |= 1 << i;
+            }
+        }
+        index
     }
 }
 
@@ -31,7 +31,12 @@ pub trait SignatureScheme: Clone + Serialize {
 }
 
 pub trait DeriveMpnAccountIndex {
-    fn mpn_account_index(&self, log4_account_capacity: u8) -> u64;
+    /// Candidate slot number `choice` of the account in the MPN, the first one
+    /// being its primary slot
+    fn mpn_account_slot(&self, log4_account_capacity: u8, choice: u8) -> u64;
+    fn mpn_account_index(&self, log4_account_capacity: u8) -> u64 {
+        self.mpn_account_slot(log4_account_capacity, 0)
+    }
 }
 
 pub trait ZkSignatureScheme: Clone + Serialize {
@@ -50,18 +50,31 @@ impl Drop for ReadOnlyLevelDbKvStore {
     }
 }
 
-pub struct LevelDbKvStore(Database<StringKey>);
+pub struct LevelDbKvStore(Database<StringKey>, PathBuf);
 impl LevelDbKvStore {
     pub fn new(path: &Path, cache_size: usize) -> Result<LevelDbKvStore, KvStoreError> {
         fs::create_dir_all(&path)?;
         let mut options = Options::new();
         options.create_if_missing = true;
         options.cache = Some(Cache::new(cache_size));
-        Ok(LevelDbKvStore(Database::open(path, options)?))
+        Ok(LevelDbKvStore(
+            Database::open(path, options)?,
+            path.to_path_buf(),
+        ))
     }
 }
 
 impl KvStore for LevelDbKvStore {
+    fn size(&self) -> Result<u64, KvStoreError> {
+        let mut size = 0;
+        for entry in fs::read_dir(&self.1)? {
+            let metadata = entry?.metadata()?;
+            if metadata.is_file() {
+                size += metadata.len();
+            }
+        }
+        Ok(size)
+    }
     fn get(&self, k: StringKey) -> Result<Option<Blob>, KvStoreError> {
         let read_opts = ReadOptions::new();
         match self.0.get(read_opts, k) {
@@ -10,6 +10,10 @@ pub fn outdated() -> StringKey {
     "OUT".into()
 }
 
+pub fn finalized_height() -> StringKey {
+    "FIN".into()
+}
+
 pub fn block(index: u64) -> StringKey {
     format!("BLK-{:010}", index).into()
 }
@@ -42,6 +46,14 @@ pub fn account_balance(address: &Address, token_id: TokenId) -> StringKey {
     format!("ACB-{}-{}", address, token_id).into()
 }
 
+pub fn account_control(address: &Address) -> StringKey {
+    format!("ACT-{}", address).into()
+}
+
+pub fn account_spending(address: &Address, token_id: TokenId) -> StringKey {
+    format!("ASP-{}-{}", address, token_id).into()
+}
+
 pub fn contract_account(contract_id: &ContractId) -> StringKey {
     format!("CAC-{}", contract_id).into()
 }
@@ -54,10 +66,18 @@ pub fn contract(contract_id: &ContractId) -> StringKey {
     format!("CON-{}", contract_id).into()
 }
 
+pub fn contract_control(contract_id: &ContractId) -> StringKey {
+    format!("CCT-{}", contract_id).into()
+}
+
 pub fn token(token_id: &TokenId) -> StringKey {
     format!("TKN-{}", token_id).into()
 }
 
+pub fn contract_events(contract_id: &ContractId, height: u64) -> StringKey {
+    format!("CEV-{}-{:010}", contract_id, height).into()
+}
+
 pub fn contract_updates() -> StringKey {
     "CUP".into()
 }
@@ -2,7 +2,8 @@ pub mod keys;
 
 use crate::blockchain::{ZkBlockchainPatch, ZkCompressedStateChange};
 use crate::core::{
-    hash::Hash, Account, Amount, Block, ContractAccount, ContractId, Hasher, Header, Token,
+    hash::Hash, Account, AccountControl, Amount, Block, ContractAccount, ContractControl,
+    ContractEvent, ContractId, DailySpending, Hasher, Header, Token,
 };
 use crate::crypto::merkle::MerkleTree;
 use crate::zk::{
@@ -110,7 +111,11 @@ gen_try_into!(
     ZkDataPairs,
     ZkDeltaPairs,
     Token,
-    Amount
+    Amount,
+    AccountControl,
+    DailySpending,
+    Vec<ContractEvent>,
+    ContractControl
 );
 gen_from!(
     u32,
@@ -135,7 +140,11 @@ gen_from!(
     &ZkDataPairs,
     &ZkDeltaPairs,
     &Token,
-    Amount
+    Amount,
+    &AccountControl,
+    &DailySpending,
+    &Vec<ContractEvent>,
+    &ContractControl
 );
 
 impl Key for StringKey {
@@ -174,6 +183,14 @@ pub trait KvStore {
         kvs.sort_by_key(|(k, _)| k.clone());
         Ok(H::hash(&bincode::serialize(&kvs).unwrap()))
     }
+    /// Approximate number of bytes occupied by the store
+    fn size(&self) -> Result<u64, KvStoreError> {
+        Ok(self
+            .pairs("".into())?
+            .iter()
+            .map(|(k, v)| (k.0.len() + v.0.len()) as u64)
+            .sum())
+    }
     fn mirror(&self) -> RamMirrorKvStore<'_, Self>
     where
         Self: Sized,
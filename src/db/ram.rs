@@ -27,6 +27,13 @@ impl KvStore for RamKvStore {
         }
         Ok(())
     }
+    fn size(&self) -> Result<u64, KvStoreError> {
+        Ok(self
+            .0
+            .iter()
+            .map(|(k, v)| (k.len() + v.0.len()) as u64)
+            .sum())
+    }
     fn pairs(&self, prefix: StringKey) -> Result<HashMap<StringKey, Blob>, KvStoreError> {
         Ok(self
             .0
@@ -9,6 +9,7 @@ pub mod consensus;
 pub mod core;
 pub mod crypto;
 pub mod db;
+pub mod sdk;
 pub mod utils;
 pub mod wallet;
 pub mod zk;
@@ -18,3 +19,6 @@ pub mod node;
 
 #[cfg(feature = "client")]
 pub mod client;
+
+#[cfg(feature = "mpn-executor")]
+pub mod mpn;
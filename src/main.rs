@@ -1,25 +1,36 @@
 #[cfg(feature = "node")]
 use {
-    bazuka::blockchain::{Blockchain, KvStoreChain},
+    bazuka::blockchain::{Blockchain, BlockchainConfig, FeeRates, KvStoreChain},
     bazuka::client::{messages::SocialProfiles, Limit, NodeRequest},
-    bazuka::common::*,
-    bazuka::db::{KvStore, LevelDbKvStore, ReadOnlyLevelDbKvStore},
-    bazuka::node::{node_create, Firewall},
+    bazuka::consensus::difficulty::{Asert, DifficultyAlgorithm, Lwma, RetargetParams},
+    bazuka::consensus::pow::Difficulty,
+    bazuka::consensus::simulator::{self, Phase},
+    bazuka::db::{KvStore, LevelDbKvStore, RamKvStore, ReadOnlyLevelDbKvStore},
+    bazuka::node::{node_create, Firewall, FirewallLimits, IpRange, NodeOptions, PoolOptions},
     hyper::server::conn::AddrStream,
     hyper::service::{make_service_fn, service_fn},
-    hyper::{Body, Client, Request, Response, Server, StatusCode},
+    hyper::{Body, Client, Request, Response, Server},
     std::sync::Arc,
     tokio::sync::mpsc,
 };
 
+#[cfg(feature = "mpn-executor")]
+use {
+    bazuka::mpn::{MpnParams, MpnShape},
+    bazuka::node::MpnExecutorOptions,
+};
+
 #[cfg(feature = "client")]
 use {
-    bazuka::client::{BazukaClient, NodeError, PeerAddress},
+    bazuka::client::{
+        miner::{Miner, MinerOptions},
+        BazukaClient, NodeError, PeerAddress,
+    },
     bazuka::config,
     bazuka::core::{
         Amount, ChainSourcedTx, Money, MpnAddress, MpnSourcedTx, TokenId, ZieshaAddress,
     },
-    bazuka::wallet::{TxBuilder, Wallet},
+    bazuka::wallet::{batch, TxBuilder, Wallet},
     colored::Colorize,
     rand::Rng,
     serde::{Deserialize, Serialize},
@@ -42,6 +53,20 @@ struct BazukaConfig {
     miner_token: String,
     bootstrap: Vec<PeerAddress>,
     db: PathBuf,
+    #[cfg(feature = "node")]
+    #[serde(default)]
+    firewall_allowlist: Vec<IpRange>,
+    #[cfg(feature = "node")]
+    #[serde(default)]
+    firewall_denylist: Vec<IpRange>,
+    /// Request/traffic capacities and per-route costs of the firewall
+    #[cfg(feature = "node")]
+    #[serde(default)]
+    firewall_limits: FirewallLimits,
+    /// Exchange rates (In Ziesha) of the tokens accepted as transaction fee
+    #[cfg(feature = "node")]
+    #[serde(default)]
+    fee_rates: HashMap<String, f64>,
 }
 
 #[cfg(feature = "client")]
@@ -96,6 +121,22 @@ enum WalletOptions {
         #[structopt(long, default_value = "0")]
         fee: Amount,
     },
+    /// Pay a list of addresses, given as <address>,<token>,<amount> CSV rows
+    BatchSend {
+        #[structopt(long)]
+        memo: Option<String>,
+        #[structopt(long)]
+        payouts: PathBuf,
+        /// Where to write the hashes of the submitted transactions
+        #[structopt(long)]
+        report: PathBuf,
+        /// Fee of each transaction
+        #[structopt(long, default_value = "0")]
+        fee: Amount,
+        /// Defaults to half of the block size
+        #[structopt(long)]
+        max_tx_size: Option<usize>,
+    },
     /// Resets wallet nonces
     Reset {},
     /// Get info and balances of the wallet
@@ -114,9 +155,35 @@ enum NodeCliOptions {
         client_only: bool,
         #[structopt(long)]
         discord_handle: Option<String>,
+        /// Run a mining pool, handing out shares of the given power
+        #[structopt(long)]
+        pool_share_power: Option<u128>,
+        /// Prove MPN contract updates with the parameters in the given directory
+        #[cfg(feature = "mpn-executor")]
+        #[structopt(long)]
+        mpn_params: Option<PathBuf>,
     },
     /// Get status of a node
     Status {},
+    /// Generate MPN proving parameters (Insecure, only for test networks)
+    #[cfg(feature = "mpn-executor")]
+    MpnSetup {
+        #[structopt(long)]
+        out: PathBuf,
+        /// Network whose MPN rules the circuits follow
+        #[structopt(long, default_value = "mainnet")]
+        network: String,
+        /// Follow the MPN rules of the given height, instead of the rules once
+        /// every scheduled upgrade is in effect
+        #[structopt(long)]
+        height: Option<u64>,
+        #[structopt(long, default_value = "3")]
+        log4_token_capacity: u8,
+        #[structopt(long, default_value = "3")]
+        log4_payment_capacity: u8,
+        #[structopt(long, default_value = "16")]
+        update_batch_size: usize,
+    },
 }
 
 #[derive(StructOpt)]
@@ -129,6 +196,14 @@ enum ChainCliOptions {
     DbQuery { prefix: String },
     /// Check health of the blockchain
     HealthCheck {},
+    /// Replay a hashrate scenario through the difficulty retarget algorithms
+    SimulateDifficulty {
+        /// Phases of the scenario as <blocks>:<hashrate>
+        #[structopt(long, required = true)]
+        phase: Vec<Phase>,
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+    },
 }
 
 #[derive(StructOpt)]
@@ -159,9 +234,34 @@ enum CliOptions {
     /// Node subcommand
     Node(NodeCliOptions),
 
+    #[cfg(feature = "node")]
+    /// Run a local in-memory chain for developing contracts, funding the
+    /// address of the wallet
+    Dev {
+        #[structopt(long, default_value = "127.0.0.1:8765")]
+        listen: SocketAddr,
+        /// Milliseconds between checks of the mempool for new transactions
+        #[structopt(long, default_value = "500")]
+        interval: u64,
+    },
+
     /// Wallet subcommand
     Wallet(WalletOptions),
 
+    /// Mine blocks of a node using the CPU
+    Miner {
+        #[structopt(long)]
+        threads: Option<usize>,
+        #[structopt(long)]
+        node: Option<PeerAddress>,
+        /// Mine shares of the pool run by the node
+        #[structopt(long)]
+        pool: bool,
+        /// Use the light mode of RandomX, slower but with much less memory
+        #[structopt(long)]
+        light: bool,
+    },
+
     /// Chain subcommand
     Chain(ChainCliOptions),
 }
@@ -215,6 +315,8 @@ async fn run_node(
     wallet: Wallet,
     social_profiles: SocialProfiles,
     client_only: bool,
+    pool: Option<PoolOptions>,
+    #[cfg(feature = "mpn-executor")] mpn_executor: Option<MpnExecutorOptions>,
 ) -> Result<(), NodeError> {
     let address = if client_only {
         None
@@ -253,25 +355,46 @@ async fn run_node(
     );
 
     let (inc_send, inc_recv) = mpsc::unbounded_channel::<NodeRequest>();
-    let (out_send, mut out_recv) = mpsc::unbounded_channel::<NodeRequest>();
+    let (out_send, out_recv) = mpsc::unbounded_channel::<NodeRequest>();
 
     let bootstrap_nodes = bazuka_config.bootstrap.clone();
 
     let bazuka_dir = bazuka_config.db.clone();
 
-    // 60 request per minute / 4GB per 15min
-    let firewall = Firewall::new(360, 4 * GB);
+    let firewall = Firewall::new(config::node::get_firewall_options(
+        bazuka_config.firewall_allowlist.clone(),
+        bazuka_config.firewall_denylist.clone(),
+        &bazuka_config.firewall_limits,
+    ));
+
+    let mut fee_rates = FeeRates::default();
+    for (token_id, rate) in bazuka_config.fee_rates.iter() {
+        fee_rates = fee_rates.with_rate(token_id.parse()?, *rate);
+    }
 
     // Async loop that is responsible for answering external requests and gathering
     // data from external world through a heartbeat loop.
+    if pool.is_some() {
+        println!("{}", "Running a mining pool!".bright_yellow());
+    }
+    #[cfg(feature = "mpn-executor")]
+    if mpn_executor.is_some() {
+        println!("{}", "Running an MPN executor!".bright_yellow());
+    }
     let node = node_create(
-        config::node::get_node_options(),
+        NodeOptions {
+            pool,
+            fee_rates,
+            #[cfg(feature = "mpn-executor")]
+            mpn_executor,
+            ..config::node::get_node_options()
+        },
         &bazuka_config.network,
         address,
         bootstrap_nodes,
         KvStoreChain::new(
             LevelDbKvStore::new(&bazuka_dir, 64).unwrap(),
-            config::blockchain::get_blockchain_config(),
+            config::blockchain::get_network_blockchain_config(&bazuka_config.network),
         )
         .unwrap(),
         0,
@@ -283,72 +406,180 @@ async fn run_node(
         Some(bazuka_config.miner_token.clone()),
     );
 
-    // Async loop that is responsible for getting incoming HTTP requests through a
-    // socket and redirecting it to the node channels.
-    let server_loop = async {
-        let arc_inc_send = Arc::new(inc_send);
-        Server::bind(&bazuka_config.listen)
-            .serve(make_service_fn(|conn: &AddrStream| {
-                let client = conn.remote_addr();
-                let arc_inc_send = Arc::clone(&arc_inc_send);
-                async move {
-                    Ok::<_, NodeError>(service_fn(move |req: Request<Body>| {
-                        let arc_inc_send = Arc::clone(&arc_inc_send);
-                        async move {
-                            let (resp_snd, mut resp_rcv) =
-                                mpsc::channel::<Result<Response<Body>, NodeError>>(1);
-                            let req = NodeRequest {
-                                limit: Limit::default(),
-                                socket_addr: Some(client),
-                                body: req,
-                                resp: resp_snd,
-                            };
-                            arc_inc_send
-                                .send(req)
-                                .map_err(|_| NodeError::NotListeningError)?;
-                            Ok::<Response<Body>, NodeError>(
-                                match resp_rcv.recv().await.ok_or(NodeError::NotAnsweringError)? {
-                                    Ok(resp) => resp,
-                                    Err(e) => {
-                                        let mut resp =
-                                            Response::new(Body::from(format!("Error: {}", e)));
-                                        *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-                                        resp
-                                    }
-                                },
-                            )
-                        }
-                    }))
-                }
-            }))
-            .await?;
-        Ok::<(), NodeError>(())
-    };
+    try_join!(
+        serve_node(bazuka_config.listen, inc_send),
+        forward_node_requests(out_recv),
+        node
+    )
+    .unwrap();
 
-    // Async loop that is responsible for redirecting node requests from its outgoing
-    // channel to the Internet and piping back the responses.
-    let client_loop = async {
-        while let Some(req) = out_recv.recv().await {
-            tokio::spawn(async move {
-                let resp = async {
-                    let client = Client::new();
-                    let resp = if let Some(time_limit) = req.limit.time {
-                        tokio::time::timeout(time_limit, client.request(req.body)).await?
-                    } else {
-                        client.request(req.body).await
-                    }?;
-                    Ok::<_, NodeError>(resp)
-                }
-                .await;
-                if let Err(e) = req.resp.send(resp).await {
-                    log::debug!("Node not listening to its HTTP request answer: {}", e);
-                }
-            });
+    Ok(())
+}
+
+/// Async loop that is responsible for getting incoming HTTP requests through a
+/// socket and redirecting it to the node channels.
+#[cfg(feature = "node")]
+async fn serve_node(
+    listen: SocketAddr,
+    inc_send: mpsc::UnboundedSender<NodeRequest>,
+) -> Result<(), NodeError> {
+    let arc_inc_send = Arc::new(inc_send);
+    Server::bind(&listen)
+        .serve(make_service_fn(|conn: &AddrStream| {
+            let client = conn.remote_addr();
+            let arc_inc_send = Arc::clone(&arc_inc_send);
+            async move {
+                Ok::<_, NodeError>(service_fn(move |req: Request<Body>| {
+                    let arc_inc_send = Arc::clone(&arc_inc_send);
+                    async move {
+                        let (resp_snd, mut resp_rcv) =
+                            mpsc::channel::<Result<Response<Body>, NodeError>>(1);
+                        let req = NodeRequest {
+                            limit: Limit::default(),
+                            socket_addr: Some(client),
+                            body: req,
+                            resp: resp_snd,
+                        };
+                        arc_inc_send
+                            .send(req)
+                            .map_err(|_| NodeError::NotListeningError)?;
+                        Ok::<Response<Body>, NodeError>(
+                            match resp_rcv.recv().await.ok_or(NodeError::NotAnsweringError)? {
+                                Ok(resp) => resp,
+                                Err(e) => e.to_http_response(),
+                            },
+                        )
+                    }
+                }))
+            }
+        }))
+        .await?;
+    Ok(())
+}
+
+/// Async loop that is responsible for redirecting node requests from its outgoing
+/// channel to the Internet and piping back the responses.
+#[cfg(feature = "node")]
+async fn forward_node_requests(
+    mut out_recv: mpsc::UnboundedReceiver<NodeRequest>,
+) -> Result<(), NodeError> {
+    while let Some(req) = out_recv.recv().await {
+        tokio::spawn(async move {
+            let resp = async {
+                let client = Client::new();
+                let resp = if let Some(time_limit) = req.limit.time {
+                    tokio::time::timeout(time_limit, client.request(req.body)).await?
+                } else {
+                    client.request(req.body).await
+                }?;
+                Ok::<_, NodeError>(resp)
+            }
+            .await;
+            if let Err(e) = req.resp.send(resp).await {
+                log::debug!("Node not listening to its HTTP request answer: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Mines a block whenever the devnet node has transactions waiting
+#[cfg(feature = "node")]
+async fn mine_dev_blocks(
+    client: &BazukaClient,
+    interval: std::time::Duration,
+) -> Result<(), NodeError> {
+    loop {
+        tokio::time::sleep(interval).await;
+        // Transactions already in the chain are only dropped from the mempool
+        // when the zero mempool is requested
+        let mempool = match client.get_zero_mempool().await {
+            Ok(_) => client.get_mempool().await,
+            Err(e) => Err(e),
+        };
+        let mempool = match mempool {
+            Ok(mempool) => mempool,
+            Err(e) => {
+                log::debug!("Devnet node not ready: {}", e);
+                continue;
+            }
+        };
+        if mempool.chain_sourced.is_empty() && mempool.mpn_sourced.is_empty() {
+            continue;
         }
-        Ok::<(), NodeError>(())
-    };
+        match client.mine().await {
+            Ok(Some(_)) => {
+                let height = client.stats().await?.height;
+                println!("{} #{}", "Mined block".bright_green(), height - 1);
+            }
+            Ok(None) => {}
+            Err(e) => log::error!("Devnet mining failed: {}", e),
+        }
+    }
+}
+
+/// Runs a single-node chain in memory, a block is mined as soon as there are
+/// transactions in the mempool
+#[cfg(feature = "node")]
+async fn run_dev_node(
+    listen: SocketAddr,
+    wallet: Wallet,
+    interval: std::time::Duration,
+) -> Result<(), NodeError> {
+    let wallet = TxBuilder::new(&wallet.seed());
+    let network = "dev".to_string();
+    let miner_token = generate_miner_token();
+    let conf = config::blockchain::get_dev_blockchain_config(wallet.get_address());
 
-    try_join!(server_loop, client_loop, node).unwrap();
+    println!(
+        "{} v{}",
+        "Bazuka devnet!".bright_green(),
+        env!("CARGO_PKG_VERSION")
+    );
+    println!();
+    println!("{} {}", "Listening:".bright_yellow(), listen);
+    println!(
+        "{} {}",
+        "Funded address:".bright_yellow(),
+        wallet.get_address()
+    );
+    println!(
+        "{} {}",
+        "MPN contract:".bright_yellow(),
+        conf.mpn_contract_id
+    );
+
+    let (inc_send, inc_recv) = mpsc::unbounded_channel::<NodeRequest>();
+    let (out_send, out_recv) = mpsc::unbounded_channel::<NodeRequest>();
+    let (req_loop, client) = BazukaClient::connect(
+        wallet.get_priv_key(),
+        PeerAddress(listen),
+        network.clone(),
+        Some(miner_token.clone()),
+    );
+    let node = node_create(
+        config::node::get_simulator_options(),
+        &network,
+        None,
+        vec![],
+        KvStoreChain::new(RamKvStore::new(), conf).unwrap(),
+        0,
+        wallet,
+        SocialProfiles::default(),
+        inc_recv,
+        out_send,
+        None,
+        Some(miner_token),
+    );
+
+    try_join!(
+        serve_node(listen, inc_send),
+        forward_node_requests(out_recv),
+        node,
+        req_loop,
+        mine_dev_blocks(&client, interval)
+    )
+    .unwrap();
 
     Ok(())
 }
@@ -366,7 +597,49 @@ fn generate_miner_token() -> String {
 #[cfg(not(tarpaulin_include))]
 #[cfg(feature = "client")]
 #[tokio::main]
-async fn main() -> Result<(), NodeError> {
+async fn main() {
+    // Errors are printed in their human-readable form, e.g. errors decoded
+    // from the responses of the node
+    if let Err(e) = run().await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "node")]
+fn simulate_difficulty(phases: &[Phase], seed: u64) {
+    let conf = config::blockchain::get_blockchain_config();
+    let params = RetargetParams {
+        block_time: conf.block_time,
+        proving_time: conf.mpn_proving_time,
+        minimum: conf.minimum_pow_difficulty,
+    };
+    let algorithms = [
+        conf.difficulty_algorithm.clone(),
+        DifficultyAlgorithm::Lwma(Lwma { window: 72 }),
+        DifficultyAlgorithm::Asert(Asert {
+            half_life: 60 * conf.block_time,
+        }),
+    ];
+    println!(
+        "Target block time: {}s",
+        conf.block_time + conf.mpn_proving_time
+    );
+    for algo in algorithms.iter() {
+        let blocks = simulator::simulate(algo, &params, params.minimum, phases, seed);
+        let report = simulator::report(&blocks, phases, &params);
+        println!(
+            "{} Mean: {:.1}s Stddev: {:.1}s Max: {}s Recovery: {:.1} blocks",
+            format!("{:>12}", algo.name()).bright_yellow(),
+            report.mean_block_time,
+            report.stddev_block_time,
+            report.max_block_time,
+            report.mean_recovery_blocks
+        );
+    }
+}
+
+async fn run() -> Result<(), NodeError> {
     env_logger::init();
 
     let opts = CliOptions::from_args();
@@ -387,10 +660,12 @@ async fn main() -> Result<(), NodeError> {
     }
 
     let mpn_contract_id = config::blockchain::get_blockchain_config().mpn_contract_id;
-    let mpn_log4_account_capacity =
-        config::blockchain::get_blockchain_config().mpn_log4_account_capacity;
 
     match opts {
+        #[cfg(feature = "node")]
+        CliOptions::Chain(ChainCliOptions::SimulateDifficulty { phase, seed }) => {
+            simulate_difficulty(&phase, seed);
+        }
         #[cfg(feature = "node")]
         CliOptions::Chain(chain_opts) => {
             let conf = conf.expect("Bazuka is not initialized!");
@@ -398,7 +673,7 @@ async fn main() -> Result<(), NodeError> {
                 ChainCliOptions::Rollback {} => {
                     let mut chain = KvStoreChain::new(
                         LevelDbKvStore::new(&conf.db, 64).unwrap(),
-                        config::blockchain::get_blockchain_config(),
+                        config::blockchain::get_network_blockchain_config(&conf.network),
                     )
                     .unwrap();
                     chain.rollback().unwrap();
@@ -413,13 +688,25 @@ async fn main() -> Result<(), NodeError> {
                 ChainCliOptions::HealthCheck {} => {
                     let rdb = ReadOnlyLevelDbKvStore::read_only(&conf.db, 64).unwrap();
                     let db = rdb.snapshot();
-                    let chain =
-                        KvStoreChain::new(db, config::blockchain::get_blockchain_config()).unwrap();
+                    // Finality is ignored, so that the whole chain can be rolled back
+                    let chain = KvStoreChain::new(
+                        db,
+                        BlockchainConfig {
+                            finality_depth: None,
+                            ..config::blockchain::get_network_blockchain_config(&conf.network)
+                        },
+                    )
+                    .unwrap();
                     let mut fork = chain.fork_on_ram();
                     while fork.get_height().unwrap() != 0 {
                         fork.rollback().unwrap();
                     }
-                    let rollback_validity_check = fork.db().pairs("".into()).unwrap().is_empty();
+                    let rollback_validity_check = fork
+                        .db()
+                        .pairs("".into())
+                        .unwrap()
+                        .keys()
+                        .all(|k| *k == bazuka::db::keys::finalized_height());
                     let mut sum_mpn: Amount = 0.into();
                     for mpn_acc in chain.get_mpn_accounts(0, 10000).unwrap() {
                         for money in mpn_acc.1.tokens.values() {
@@ -459,13 +746,24 @@ async fn main() -> Result<(), NodeError> {
                         }
                     );
                 }
+                ChainCliOptions::SimulateDifficulty { .. } => {
+                    unreachable!("Simulation doesn't need an initialized node")
+                }
             }
         }
         #[cfg(feature = "node")]
+        CliOptions::Dev { listen, interval } => {
+            let wallet = wallet.expect("Wallet is not initialized!");
+            run_dev_node(listen, wallet, std::time::Duration::from_millis(interval)).await?;
+        }
+        #[cfg(feature = "node")]
         CliOptions::Node(node_opts) => match node_opts {
             NodeCliOptions::Start {
                 discord_handle,
                 client_only,
+                pool_share_power,
+                #[cfg(feature = "mpn-executor")]
+                mpn_params,
             } => {
                 let conf = conf.expect("Bazuka is not initialized!");
                 let wallet = wallet.expect("Wallet is not initialized!");
@@ -476,9 +774,52 @@ async fn main() -> Result<(), NodeError> {
                         discord: discord_handle,
                     },
                     client_only,
+                    pool_share_power.map(|power| PoolOptions {
+                        share_target: Difficulty::from_power(power),
+                    }),
+                    #[cfg(feature = "mpn-executor")]
+                    mpn_params.map(|params_dir| MpnExecutorOptions {
+                        params_dir,
+                        interval: std::time::Duration::from_secs(10),
+                    }),
                 )
                 .await?;
             }
+            #[cfg(feature = "mpn-executor")]
+            NodeCliOptions::MpnSetup {
+                out,
+                network,
+                height,
+                log4_token_capacity,
+                log4_payment_capacity,
+                update_batch_size,
+            } => {
+                // The keys of an upgrade of the MPN circuits are generated before
+                // they are configured, so the upgrades are taken as scheduled
+                let conf = config::blockchain::get_network_blockchain_config(&network);
+                let features = conf.scheduled_mpn_circuit_features(height.unwrap_or(u64::MAX));
+                let params = MpnParams::generate(
+                    MpnShape {
+                        log4_account_capacity: conf.mpn_log4_account_capacity,
+                        log4_token_capacity,
+                        log4_payment_capacity,
+                        update_batch_size,
+                        account_slot_choices: features.account_slot_choices,
+                        reuse_empty_token_slots: features.reuse_empty_token_slots,
+                    },
+                    &mut rand::thread_rng(),
+                )?;
+                params.save(&out)?;
+                println!("Update VK: {}", serde_json::to_string(&params.update_vk())?);
+                println!(
+                    "Deposit VK: {}",
+                    serde_json::to_string(&params.deposit_vk())?
+                );
+                println!(
+                    "Withdraw VK: {}",
+                    serde_json::to_string(&params.withdraw_vk())?
+                );
+            }
             NodeCliOptions::Status {} => {
                 let (conf, wallet) = conf.zip(wallet).expect("Bazuka is not initialized!");
                 let wallet = TxBuilder::new(&wallet.seed());
@@ -498,6 +839,32 @@ async fn main() -> Result<(), NodeError> {
                 .unwrap();
             }
         },
+        CliOptions::Miner {
+            threads,
+            node,
+            pool,
+            light,
+        } => {
+            let (conf, wallet) = conf.zip(wallet).expect("Bazuka is not initialized!");
+            let wallet = TxBuilder::new(&wallet.seed());
+            let mut opts = MinerOptions {
+                pool,
+                fast: !light,
+                ..Default::default()
+            };
+            if let Some(threads) = threads {
+                opts.threads = threads.max(1);
+            }
+            let (req_loop, client) = BazukaClient::connect(
+                wallet.get_priv_key(),
+                node.unwrap_or_else(|| conf.random_node()),
+                conf.network,
+                Some(conf.miner_token),
+            );
+            println!("Mining with {} threads...", opts.threads);
+            let miner = Miner::new(opts);
+            try_join!(miner.run(&client), req_loop)?;
+        }
         #[cfg(feature = "client")]
         CliOptions::Init {
             network,
@@ -540,6 +907,14 @@ async fn main() -> Result<(), NodeError> {
                         db: db.unwrap_or_else(|| {
                             home::home_dir().unwrap().join(Path::new(".bazuka"))
                         }),
+                        #[cfg(feature = "node")]
+                        fee_rates: HashMap::new(),
+                        #[cfg(feature = "node")]
+                        firewall_allowlist: Vec::new(),
+                        #[cfg(feature = "node")]
+                        firewall_denylist: Vec::new(),
+                        #[cfg(feature = "node")]
+                        firewall_limits: Default::default(),
                     })
                     .unwrap(),
                 )
@@ -680,24 +1055,17 @@ async fn main() -> Result<(), NodeError> {
                                             .await?
                                             .account
                                             .nonce;
-                                        let dst_acc = client
-                                            .get_mpn_account(
-                                                to.account_index(mpn_log4_account_capacity),
-                                            )
-                                            .await?
-                                            .account;
-                                        let to_token_index = if let Some(ind) = dst_acc
+                                        let dst_acc =
+                                            client.get_mpn_address(to.clone()).await?.account;
+                                        let to_token_index = dst_acc
                                             .find_token_index(
                                                 config::blockchain::MPN_LOG4_TOKEN_CAPACITY,
                                                 tkn,
                                                 true,
-                                            ) {
-                                            ind
-                                        } else {
-                                            panic!(
-                                                "Cannot find empty token slot in your MPN account!"
-                                            );
-                                        };
+                                            )
+                                            .unwrap_or_else(|e| {
+                                                panic!("Cannot deposit to {}: {}", to, e)
+                                            });
                                         let new_nonce =
                                             wallet.new_r_nonce().unwrap_or(curr_nonce + 1);
                                         let pay = tx_builder.deposit_mpn(
@@ -737,31 +1105,22 @@ async fn main() -> Result<(), NodeError> {
                             ZieshaAddress::ChainAddress(to) => {
                                 try_join!(
                                     async move {
-                                        let acc = client
-                                            .get_mpn_account(
-                                                from.account_index(mpn_log4_account_capacity),
+                                        let acc =
+                                            client.get_mpn_address(from.clone()).await?.account;
+                                        let token_index = acc
+                                            .find_token_index(
+                                                config::blockchain::MPN_LOG4_TOKEN_CAPACITY,
+                                                tkn,
+                                                false,
                                             )
-                                            .await?
-                                            .account;
-                                        let token_index = if let Some(ind) = acc.find_token_index(
-                                            config::blockchain::MPN_LOG4_TOKEN_CAPACITY,
-                                            tkn,
-                                            false,
-                                        ) {
-                                            ind
-                                        } else {
-                                            panic!("Token not found in your account!");
-                                        };
-                                        let fee_token_index = if let Some(ind) = acc
+                                            .unwrap_or_else(|e| panic!("{}", e));
+                                        let fee_token_index = acc
                                             .find_token_index(
                                                 config::blockchain::MPN_LOG4_TOKEN_CAPACITY,
                                                 TokenId::Ziesha,
                                                 false,
-                                            ) {
-                                            ind
-                                        } else {
-                                            panic!("Token not found in your account!");
-                                        };
+                                            )
+                                            .unwrap_or_else(|e| panic!("{}", e));
                                         let new_nonce =
                                             wallet.new_z_nonce(&from).unwrap_or(acc.nonce);
                                         let pay = tx_builder.withdraw_mpn(
@@ -800,64 +1159,37 @@ async fn main() -> Result<(), NodeError> {
                                                 "Cannot assign a memo to a MPN-to-MPN transaction!"
                                             );
                                         }
-                                        let acc = client
-                                            .get_mpn_account(
-                                                from.account_index(mpn_log4_account_capacity),
-                                            )
-                                            .await?
-                                            .account;
-                                        let dst_acc = client
-                                            .get_mpn_account(
-                                                to.account_index(mpn_log4_account_capacity),
-                                            )
-                                            .await?
-                                            .account;
-                                        let to_token_index = if let Some(ind) = dst_acc
-                                            .find_token_index(
-                                                config::blockchain::MPN_LOG4_TOKEN_CAPACITY,
-                                                tkn,
-                                                true,
-                                            ) {
-                                            ind
-                                        } else {
-                                            panic!("Token not found in your account!");
-                                        };
-                                        let token_index = if let Some(ind) = acc.find_token_index(
-                                            config::blockchain::MPN_LOG4_TOKEN_CAPACITY,
-                                            tkn,
-                                            false,
-                                        ) {
-                                            ind
-                                        } else {
-                                            panic!("Token not found in your account!");
-                                        };
-                                        let fee_token_index = if let Some(ind) = acc
-                                            .find_token_index(
-                                                config::blockchain::MPN_LOG4_TOKEN_CAPACITY,
-                                                TokenId::Ziesha,
-                                                false,
-                                            ) {
-                                            ind
-                                        } else {
-                                            panic!("Token not found in your account!");
-                                        };
+                                        let acc =
+                                            client.get_mpn_address(from.clone()).await?.account;
+                                        let mut dst_acc =
+                                            client.get_mpn_address(to.clone()).await?.account;
+                                        wallet.reserve_mpn_token_slots(
+                                            &from,
+                                            acc.nonce,
+                                            &to,
+                                            &mut dst_acc,
+                                        );
                                         let new_nonce =
                                             wallet.new_z_nonce(&from).unwrap_or(acc.nonce);
-                                        let tx = tx_builder.create_mpn_transaction(
-                                            token_index,
-                                            to,
-                                            to_token_index,
-                                            Money {
-                                                amount,
-                                                token_id: tkn,
-                                            },
-                                            fee_token_index,
-                                            Money {
-                                                amount: fee,
-                                                token_id: TokenId::Ziesha,
-                                            },
-                                            new_nonce,
-                                        );
+                                        let tx = tx_builder
+                                            .create_mpn_transfer(
+                                                config::blockchain::MPN_LOG4_TOKEN_CAPACITY,
+                                                &acc,
+                                                to.clone(),
+                                                &dst_acc,
+                                                Money {
+                                                    amount,
+                                                    token_id: tkn,
+                                                },
+                                                Money {
+                                                    amount: fee,
+                                                    token_id: TokenId::Ziesha,
+                                                },
+                                                new_nonce,
+                                            )
+                                            .unwrap_or_else(|e| {
+                                                panic!("Cannot create the MPN transaction: {}", e)
+                                            });
                                         wallet.add_zsend(tx.clone());
                                         wallet.save(wallet_path).unwrap();
                                         println!("{:#?}", client.zero_transact(tx).await?);
@@ -871,6 +1203,125 @@ async fn main() -> Result<(), NodeError> {
                     }
                 }
             }
+            WalletOptions::BatchSend {
+                memo,
+                payouts,
+                report,
+                fee,
+                max_tx_size,
+            } => {
+                let (conf, mut wallet) = conf.zip(wallet).expect("Bazuka is not initialized!");
+                let payouts = std::fs::read_to_string(payouts).expect("Cannot read payouts!");
+                let payouts = batch::parse_payouts(&payouts).unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                });
+                let (chain_payouts, mpn_payouts) = batch::split_payouts(payouts);
+                let max_tx_size = max_tx_size
+                    .unwrap_or(config::blockchain::get_blockchain_config().max_block_size / 2);
+                let fee = Money {
+                    amount: fee,
+                    token_id: TokenId::Ziesha,
+                };
+                let tx_builder = TxBuilder::new(&wallet.seed());
+                let (req_loop, client) = BazukaClient::connect(
+                    tx_builder.get_priv_key(),
+                    conf.random_node(),
+                    conf.network,
+                    None,
+                );
+                try_join!(
+                    async move {
+                        use std::io::Write;
+                        // Rows of the report: <kind>,<nonce>,<hash>,<receivers>. Each row
+                        // is flushed before its transaction is submitted, so that the
+                        // report stays complete if the command is interrupted.
+                        let mut report_file =
+                            std::fs::File::create(&report).expect("Cannot write report!");
+                        let mut add_row = |row: String| {
+                            writeln!(report_file, "{}", row)
+                                .and_then(|_| report_file.flush())
+                                .expect("Cannot write report!");
+                        };
+                        add_row("kind,nonce,hash,receivers".into());
+                        let mut submitted = 0;
+                        if !chain_payouts.is_empty() {
+                            let curr_nonce = client
+                                .get_account(tx_builder.get_address())
+                                .await?
+                                .account
+                                .nonce;
+                            let first_nonce = wallet.new_r_nonce().unwrap_or(curr_nonce + 1);
+                            let txs = tx_builder
+                                .create_batch_transactions(
+                                    memo.unwrap_or_default(),
+                                    chain_payouts,
+                                    fee,
+                                    first_nonce,
+                                    max_tx_size,
+                                )
+                                .unwrap_or_else(|e| {
+                                    eprintln!("Error: {}", e);
+                                    std::process::exit(1);
+                                });
+                            for tx in txs {
+                                wallet.add_rsend(tx.clone());
+                                wallet.save(&wallet_path).unwrap();
+                                add_row(format!(
+                                    "chain,{},{},{}",
+                                    tx.tx.nonce,
+                                    hex::encode(tx.tx.hash()),
+                                    batch::receivers(&tx)
+                                        .iter()
+                                        .map(|r| r.to_string())
+                                        .collect::<Vec<_>>()
+                                        .join(" ")
+                                ));
+                                client.transact(tx).await?;
+                                submitted += 1;
+                            }
+                        }
+                        if !mpn_payouts.is_empty() {
+                            let from = MpnAddress {
+                                pub_key: tx_builder.get_zk_address(),
+                            };
+                            let acc = client.get_mpn_address(from.clone()).await?.account;
+                            let first_nonce = wallet.new_z_nonce(&from).unwrap_or(acc.nonce);
+                            for (i, (to, money)) in mpn_payouts.into_iter().enumerate() {
+                                // Earlier payouts of the batch are pending in the wallet
+                                let mut dst_acc = client.get_mpn_address(to.clone()).await?.account;
+                                wallet.reserve_mpn_token_slots(&from, acc.nonce, &to, &mut dst_acc);
+                                let tx = tx_builder
+                                    .create_mpn_transfer(
+                                        config::blockchain::MPN_LOG4_TOKEN_CAPACITY,
+                                        &acc,
+                                        to.clone(),
+                                        &dst_acc,
+                                        money,
+                                        fee,
+                                        first_nonce + i as u64,
+                                    )
+                                    .unwrap_or_else(|e| {
+                                        panic!("Cannot create the MPN transaction: {}", e)
+                                    });
+                                wallet.add_zsend(tx.clone());
+                                wallet.save(&wallet_path).unwrap();
+                                add_row(format!("mpn,{},{},{}", tx.nonce, tx.hash(), to));
+                                client.zero_transact(tx).await?;
+                                submitted += 1;
+                            }
+                        }
+                        println!(
+                            "{} transactions submitted, report written to {}",
+                            submitted,
+                            report.display()
+                        );
+                        Ok::<(), NodeError>(())
+                    },
+                    req_loop
+                )
+                .unwrap();
+            }
             WalletOptions::Reset {} => {
                 let mut wallet = wallet.expect("Bazuka is not initialized!");
                 wallet.reset();
@@ -953,7 +1404,7 @@ async fn main() -> Result<(), NodeError> {
                                 format!("MPN Account #{}\n---------", i).bright_green()
                             );
                             let resp = client
-                                .get_mpn_account(addr.account_index(mpn_log4_account_capacity))
+                                .get_mpn_address(addr.clone())
                                 .await
                                 .map(|resp| resp.account);
                             if let Ok(resp) = resp {
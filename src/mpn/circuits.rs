@@ -0,0 +1,969 @@
+use super::gadgets::*;
+use crate::crypto::jubjub;
+use crate::zk::{MpnTransaction, ZkScalar};
+use bellman::{Circuit, ConstraintSystem, SynthesisError};
+use bls12_381::Scalar as BellmanFr;
+use serde::{Deserialize, Serialize};
+
+/// Sizes which the MPN circuits (And therefore their keys) are built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MpnShape {
+    pub log4_account_capacity: u8,
+    pub log4_token_capacity: u8,
+    pub log4_payment_capacity: u8,
+    pub update_batch_size: usize,
+    /// Candidate slots an account may take (See `zk::locate_mpn_account`)
+    #[serde(default = "default_account_slot_choices")]
+    pub account_slot_choices: u8,
+    /// Token slots with no balance left may receive other tokens
+    #[serde(default)]
+    pub reuse_empty_token_slots: bool,
+}
+
+fn default_account_slot_choices() -> u8 {
+    1
+}
+
+impl MpnShape {
+    pub fn payment_capacity(&self) -> usize {
+        1 << (2 * self.log4_payment_capacity)
+    }
+}
+
+/// An MPN account before being modified, with its proof in the accounts tree
+#[derive(Debug, Clone, Default)]
+pub struct AccountWitness {
+    /// Which of the candidate slots of its owner the account is in
+    pub slot_choice: u8,
+    pub nonce: u64,
+    pub address: jubjub::PointAffine,
+    pub tokens_root: ZkScalar,
+    pub proof: Vec<[ZkScalar; 3]>,
+    /// The candidate slots before `slot_choice`, which are taken by other accounts
+    pub skipped: Vec<SkippedSlotWitness>,
+}
+
+/// An account in a candidate slot which comes before the chosen one, with its
+/// proof in the accounts tree
+#[derive(Debug, Clone, Default)]
+pub struct SkippedSlotWitness {
+    pub nonce: u64,
+    pub address: jubjub::PointAffine,
+    pub tokens_root: ZkScalar,
+    pub proof: Vec<[ZkScalar; 3]>,
+}
+
+/// A token slot before being modified, with its proof in the tokens tree of its account
+#[derive(Debug, Clone, Default)]
+pub struct TokenWitness {
+    pub index: u64,
+    pub token_id: ZkScalar,
+    pub balance: u64,
+    pub proof: Vec<[ZkScalar; 3]>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TransferWitness {
+    pub tx: MpnTransaction,
+    pub src: AccountWitness,
+    pub src_amount: TokenWitness,
+    pub src_fee: TokenWitness, // After the amount is deducted
+    pub dst: AccountWitness,   // After the source is updated
+    pub dst_amount: TokenWitness,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DepositWitness {
+    pub pub_key: jubjub::PointAffine,
+    pub token_id: ZkScalar,
+    pub amount: u64,
+    pub account: AccountWitness,
+    pub slot: TokenWitness,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WithdrawWitness {
+    pub pub_key: jubjub::PointAffine,
+    pub nonce: u64,
+    pub sig: jubjub::Signature,
+    pub token_id: ZkScalar,
+    pub amount: u64,
+    pub fee_token_id: ZkScalar,
+    pub fee: u64,
+    pub fingerprint: ZkScalar,
+    pub account: AccountWitness,
+    pub amount_slot: TokenWitness,
+    pub fee_slot: TokenWitness, // After the amount is deducted
+}
+
+/// Public inputs shared by all of the MPN circuits
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PublicInputs {
+    pub height: u64,
+    pub state: ZkScalar,
+    pub aux_data: ZkScalar,
+    pub next_state: ZkScalar,
+}
+
+/// Applies a batch of MPN transactions, all paying their fees in `fee_token`
+#[derive(Debug, Clone)]
+pub struct UpdateCircuit {
+    pub shape: MpnShape,
+    pub inputs: PublicInputs,
+    pub fee_token: ZkScalar,
+    pub transfers: Vec<TransferWitness>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DepositCircuit {
+    pub shape: MpnShape,
+    pub inputs: PublicInputs,
+    pub deposits: Vec<DepositWitness>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WithdrawCircuit {
+    pub shape: MpnShape,
+    pub inputs: PublicInputs,
+    pub withdraws: Vec<WithdrawWitness>,
+}
+
+fn alloc_scalar<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    val: ZkScalar,
+) -> Result<Num, SynthesisError> {
+    Num::alloc(cs, val.into())
+}
+
+fn alloc_u64<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    val: u64,
+) -> Result<Num, SynthesisError> {
+    let num = alloc_scalar(&mut cs.namespace(|| "num"), ZkScalar::from(val))?;
+    to_bits(&mut cs.namespace(|| "range"), &num, 64)?;
+    Ok(num)
+}
+
+struct AllocatedInputs {
+    state: Num,
+    aux_data: Num,
+    next_state: Num,
+}
+
+fn alloc_inputs<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    inputs: &PublicInputs,
+) -> Result<AllocatedInputs, SynthesisError> {
+    // Order matters, it is the order the verifier passes the inputs in
+    Num::alloc_input(
+        &mut cs.namespace(|| "height"),
+        ZkScalar::from(inputs.height).into(),
+    )?;
+    let state = Num::alloc_input(&mut cs.namespace(|| "state"), inputs.state.into())?;
+    let aux_data = Num::alloc_input(&mut cs.namespace(|| "aux data"), inputs.aux_data.into())?;
+    let next_state =
+        Num::alloc_input(&mut cs.namespace(|| "next state"), inputs.next_state.into())?;
+    Ok(AllocatedInputs {
+        state,
+        aux_data,
+        next_state,
+    })
+}
+
+/// Candidate slots of the account owned by a public-key, each one a window of
+/// the bits of its X coordinate (See `zk::locate_mpn_account`)
+struct AccountSlots {
+    /// Little-endian index bits of each of the candidate slots
+    windows: Vec<Vec<Num>>,
+    /// Whether each of the candidate slots comes before the chosen one
+    skipped: Vec<Num>,
+    /// Little-endian index bits of the slot chosen by `slot_choice`
+    index_bits: Vec<Num>,
+}
+
+fn account_slots<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    pub_key: &Point,
+    slot_choice: u8,
+    shape: &MpnShape,
+) -> Result<AccountSlots, SynthesisError> {
+    let width = 2 * shape.log4_account_capacity as usize;
+    let mut bits = to_bits_strict(&mut cs.namespace(|| "x bits"), &pub_key.x)?;
+    if shape.account_slot_choices <= 1 {
+        bits.truncate(width);
+        return Ok(AccountSlots {
+            windows: vec![bits.clone()],
+            skipped: vec![Num::zero()],
+            index_bits: bits,
+        });
+    }
+    let mut windows = Vec::new();
+    let mut chosen = Vec::new();
+    let mut index = Num::zero();
+    for choice in 0..shape.account_slot_choices {
+        let window = (0..width)
+            .map(|i| {
+                bits.get(width * choice as usize + i)
+                    .cloned()
+                    .unwrap_or_else(Num::zero)
+            })
+            .collect::<Vec<_>>();
+        let is_chosen = Num::alloc_bit(
+            &mut cs.namespace(|| format!("choice {}", choice)),
+            choice == slot_choice,
+        )?;
+        index = index.add(&is_chosen.mul(
+            &mut cs.namespace(|| format!("choice {} window", choice)),
+            &bits_to_num(&window),
+        )?);
+        windows.push(window);
+        chosen.push(is_chosen);
+    }
+    assert_equal(
+        &mut cs.namespace(|| "one choice"),
+        &chosen.iter().fold(Num::zero(), |sum, c| sum.add(c)),
+        &Num::constant(BellmanFr::one()),
+    );
+    // A slot is skipped when one of the slots after it is chosen
+    let skipped = (0..chosen.len())
+        .map(|i| {
+            chosen[i + 1..]
+                .iter()
+                .fold(Num::zero(), |sum, c| sum.add(c))
+        })
+        .collect();
+    Ok(AccountSlots {
+        windows,
+        skipped,
+        index_bits: to_bits(&mut cs.namespace(|| "index bits"), &index, width)?,
+    })
+}
+
+/// An account should be in the first of its candidate slots which is either owned
+/// by it or empty, as `zk::locate_mpn_account` finds it. So the slots it skips
+/// should be taken by other keys in `state`.
+fn assert_skipped_slots_taken_if<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    enabled: &Num,
+    state: &Num,
+    pub_key: &Point,
+    slots: &AccountSlots,
+    acc: &AccountWitness,
+    shape: &MpnShape,
+) -> Result<(), SynthesisError> {
+    // The last slot is never skipped
+    for choice in 0..slots.windows.len() - 1 {
+        let cs = &mut cs.namespace(|| format!("skipped {}", choice));
+        let witness = acc.skipped.get(choice).cloned().unwrap_or_default();
+        let check = enabled.mul(&mut cs.namespace(|| "check"), &slots.skipped[choice])?;
+        let nonce = alloc_scalar(&mut cs.namespace(|| "nonce"), ZkScalar::from(witness.nonce))?;
+        let address = Point::alloc(&mut cs.namespace(|| "address"), &witness.address)?;
+        let tokens_root = alloc_scalar(&mut cs.namespace(|| "tokens root"), witness.tokens_root)?;
+        let proof = alloc_proof(
+            &mut cs.namespace(|| "proof"),
+            &witness.proof,
+            shape.log4_account_capacity,
+        )?;
+        let leaf = account_leaf(&mut cs.namespace(|| "leaf"), &nonce, &address, &tokens_root)?;
+        let root = merkle_root(
+            &mut cs.namespace(|| "root"),
+            &leaf,
+            &slots.windows[choice],
+            &proof,
+        )?;
+        assert_zero_if(&mut cs.namespace(|| "root check"), &check, &root.sub(state));
+        assert_not_both_zero_if(
+            &mut cs.namespace(|| "not empty"),
+            &check,
+            &address.x,
+            &address.y,
+        )?;
+        address.assert_not_equal_if(&mut cs.namespace(|| "not owned"), &check, pub_key)?;
+    }
+    Ok(())
+}
+
+struct AllocatedAccount {
+    nonce: Num,
+    address: Point,
+    tokens_root: Num,
+    proof: Vec<[Num; 3]>,
+}
+
+impl AllocatedAccount {
+    fn alloc<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        acc: &AccountWitness,
+        shape: &MpnShape,
+    ) -> Result<Self, SynthesisError> {
+        let nonce = alloc_scalar(&mut cs.namespace(|| "nonce"), ZkScalar::from(acc.nonce))?;
+        let address = Point::alloc(&mut cs.namespace(|| "address"), &acc.address)?;
+        Self::alloc_owned(cs, acc, shape, &nonce, &address)
+    }
+
+    /// Allocates an account whose nonce and address are already in the circuit
+    /// (E.g. the ones of a signer). Allocating them again would leave unconstrained
+    /// variables, which the Groth16 setup rejects.
+    fn alloc_owned<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        acc: &AccountWitness,
+        shape: &MpnShape,
+        nonce: &Num,
+        address: &Point,
+    ) -> Result<Self, SynthesisError> {
+        let tokens_root = alloc_scalar(&mut cs.namespace(|| "tokens root"), acc.tokens_root)?;
+        let proof = alloc_proof(
+            &mut cs.namespace(|| "proof"),
+            &acc.proof,
+            shape.log4_account_capacity,
+        )?;
+        Ok(Self {
+            nonce: nonce.clone(),
+            address: address.clone(),
+            tokens_root,
+            proof,
+        })
+    }
+
+    /// An account may be written to by `pub_key` if it is empty or already owned by it
+    fn assert_writable_by_if<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        enabled: &Num,
+        pub_key: &Point,
+    ) -> Result<(), SynthesisError> {
+        let is_empty = self.address.x.value() == BellmanFr::zero()
+            && self.address.y.value() == BellmanFr::zero();
+        let occupied = Num::alloc_bit(&mut cs.namespace(|| "occupied"), !is_empty)?;
+        let empty = Num::constant(BellmanFr::one()).sub(&occupied);
+        assert_zero_if(&mut cs.namespace(|| "empty x"), &empty, &self.address.x);
+        assert_zero_if(&mut cs.namespace(|| "empty y"), &empty, &self.address.y);
+        let enabled_occupied = enabled.mul(&mut cs.namespace(|| "enabled occupied"), &occupied)?;
+        self.address
+            .assert_equal_if(&mut cs.namespace(|| "owned"), &enabled_occupied, pub_key);
+        Ok(())
+    }
+}
+
+fn account_leaf<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    nonce: &Num,
+    address: &Point,
+    tokens_root: &Num,
+) -> Result<Num, SynthesisError> {
+    poseidon(
+        cs,
+        &[
+            nonce.clone(),
+            address.x.clone(),
+            address.y.clone(),
+            tokens_root.clone(),
+        ],
+    )
+}
+
+struct AllocatedToken {
+    index_bits: Vec<Num>,
+    token_id: Num,
+    balance: Num,
+    proof: Vec<[Num; 3]>,
+    reuse_empty: bool,
+}
+
+impl AllocatedToken {
+    fn alloc<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        slot: &TokenWitness,
+        shape: &MpnShape,
+    ) -> Result<Self, SynthesisError> {
+        let index = alloc_scalar(&mut cs.namespace(|| "index"), ZkScalar::from(slot.index))?;
+        let index_bits = to_bits(
+            &mut cs.namespace(|| "index bits"),
+            &index,
+            2 * shape.log4_token_capacity as usize,
+        )?;
+        let token_id = alloc_scalar(&mut cs.namespace(|| "token id"), slot.token_id)?;
+        let balance = alloc_scalar(
+            &mut cs.namespace(|| "balance"),
+            ZkScalar::from(slot.balance),
+        )?;
+        let proof = alloc_proof(
+            &mut cs.namespace(|| "proof"),
+            &slot.proof,
+            shape.log4_token_capacity,
+        )?;
+        Ok(Self {
+            index_bits,
+            token_id,
+            balance,
+            proof,
+            reuse_empty: shape.reuse_empty_token_slots,
+        })
+    }
+
+    fn update<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        enabled: &Num,
+        tokens_root: &Num,
+        token_id: &Num,
+        new_balance: &Num,
+    ) -> Result<Num, SynthesisError> {
+        // New balance should still fit in 64 bits (No underflows/overflows)
+        to_bits(&mut cs.namespace(|| "new balance range"), new_balance, 64)?;
+        let old_leaf = poseidon(
+            &mut cs.namespace(|| "old leaf"),
+            &[self.token_id.clone(), self.balance.clone()],
+        )?;
+        let new_leaf = poseidon(
+            &mut cs.namespace(|| "new leaf"),
+            &[token_id.clone(), new_balance.clone()],
+        )?;
+        merkle_update(
+            &mut cs.namespace(|| "update"),
+            enabled,
+            tokens_root,
+            &self.index_bits,
+            &self.proof,
+            &old_leaf,
+            &new_leaf,
+        )
+    }
+
+    /// Deducts `amount` of `token_id` from the slot, returning the new tokens root
+    fn debit<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        enabled: &Num,
+        tokens_root: &Num,
+        token_id: &Num,
+        amount: &Num,
+    ) -> Result<Num, SynthesisError> {
+        assert_zero_if(
+            &mut cs.namespace(|| "same token"),
+            enabled,
+            &self.token_id.sub(token_id),
+        );
+        self.update(
+            cs,
+            enabled,
+            tokens_root,
+            token_id,
+            &self.balance.sub(amount),
+        )
+    }
+
+    /// Adds `amount` of `token_id` to the slot, returning the new tokens root
+    fn credit<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        enabled: &Num,
+        tokens_root: &Num,
+        token_id: &Num,
+        amount: &Num,
+    ) -> Result<Num, SynthesisError> {
+        // The slot should either be empty or hold the same token (Or have no balance
+        // left, when empty slots are reused)
+        let diff = self.token_id.sub(token_id);
+        if !self.reuse_empty {
+            let empty_or_same = self
+                .token_id
+                .mul(&mut cs.namespace(|| "empty or same"), &diff)?;
+            assert_zero_if(
+                &mut cs.namespace(|| "empty or same check"),
+                enabled,
+                &empty_or_same,
+            );
+        }
+        let no_balance_if_different = self
+            .balance
+            .mul(&mut cs.namespace(|| "no balance if different"), &diff)?;
+        assert_zero_if(
+            &mut cs.namespace(|| "no balance if different check"),
+            enabled,
+            &no_balance_if_different,
+        );
+        self.update(
+            cs,
+            enabled,
+            tokens_root,
+            token_id,
+            &self.balance.add(amount),
+        )
+    }
+}
+
+fn synthesize_transfer<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    shape: &MpnShape,
+    enabled: &Num,
+    state: &Num,
+    fee_token: &Num,
+    w: &TransferWitness,
+) -> Result<(Num, Num), SynthesisError> {
+    let src_pub_key = Point::alloc(
+        &mut cs.namespace(|| "src pub-key"),
+        &w.tx.src_pub_key.decompress(),
+    )?;
+    let dst_pub_key = Point::alloc(
+        &mut cs.namespace(|| "dst pub-key"),
+        &w.tx.dst_pub_key.decompress(),
+    )?;
+    let nonce = alloc_scalar(&mut cs.namespace(|| "nonce"), ZkScalar::from(w.tx.nonce))?;
+    let token_id = alloc_scalar(
+        &mut cs.namespace(|| "token id"),
+        w.tx.amount.token_id.into(),
+    )?;
+    let amount = alloc_u64(&mut cs.namespace(|| "amount"), w.tx.amount.amount.into())?;
+    let fee_token_id = alloc_scalar(
+        &mut cs.namespace(|| "fee token id"),
+        w.tx.fee.token_id.into(),
+    )?;
+    let fee = alloc_u64(&mut cs.namespace(|| "fee"), w.tx.fee.amount.into())?;
+    assert_zero_if(
+        &mut cs.namespace(|| "fee token check"),
+        enabled,
+        &fee_token_id.sub(fee_token),
+    );
+
+    // Transaction hash, as in `MpnTransaction::hash`
+    let msg = poseidon(
+        &mut cs.namespace(|| "msg"),
+        &[
+            nonce.clone(),
+            dst_pub_key.x.clone(),
+            dst_pub_key.y.clone(),
+            token_id.clone(),
+            amount.clone(),
+            fee_token_id.clone(),
+            fee.clone(),
+        ],
+    )?;
+    let sig_r = Point::alloc(&mut cs.namespace(|| "sig r"), &w.tx.sig.r)?;
+    let sig_s = alloc_scalar(&mut cs.namespace(|| "sig s"), w.tx.sig.s)?;
+    assert_signature_if(
+        &mut cs.namespace(|| "signature"),
+        enabled,
+        &src_pub_key,
+        &msg,
+        &sig_r,
+        &sig_s,
+    )?;
+    dst_pub_key.assert_on_curve_if(&mut cs.namespace(|| "dst on curve"), enabled)?;
+
+    let src_index_bits = account_slots(
+        &mut cs.namespace(|| "src index"),
+        &src_pub_key,
+        w.src.slot_choice,
+        shape,
+    )?
+    .index_bits;
+    let dst_slots = account_slots(
+        &mut cs.namespace(|| "dst index"),
+        &dst_pub_key,
+        w.dst.slot_choice,
+        shape,
+    )?;
+    let dst_index_bits = dst_slots.index_bits.clone();
+    let same_index = is_equal(
+        &mut cs.namespace(|| "same index"),
+        &bits_to_num(&src_index_bits),
+        &bits_to_num(&dst_index_bits),
+    )?;
+    assert_zero_if(
+        &mut cs.namespace(|| "different index check"),
+        enabled,
+        &same_index,
+    );
+
+    // Source account should be owned by the signer and have the same nonce as the
+    // transaction, both guaranteed by building its leaf out of them
+    let src = AllocatedAccount::alloc_owned(
+        &mut cs.namespace(|| "src"),
+        &w.src,
+        shape,
+        &nonce,
+        &src_pub_key,
+    )?;
+    let src_old_leaf = account_leaf(
+        &mut cs.namespace(|| "src old leaf"),
+        &nonce,
+        &src_pub_key,
+        &src.tokens_root,
+    )?;
+    let src_amount =
+        AllocatedToken::alloc(&mut cs.namespace(|| "src amount"), &w.src_amount, shape)?;
+    let src_tokens_root = src_amount.debit(
+        &mut cs.namespace(|| "src amount debit"),
+        enabled,
+        &src.tokens_root,
+        &token_id,
+        &amount,
+    )?;
+    let src_fee = AllocatedToken::alloc(&mut cs.namespace(|| "src fee"), &w.src_fee, shape)?;
+    let src_tokens_root = src_fee.debit(
+        &mut cs.namespace(|| "src fee debit"),
+        enabled,
+        &src_tokens_root,
+        &fee_token_id,
+        &fee,
+    )?;
+    let src_new_leaf = account_leaf(
+        &mut cs.namespace(|| "src new leaf"),
+        &nonce.add_constant(BellmanFr::one()),
+        &src_pub_key,
+        &src_tokens_root,
+    )?;
+    let state = merkle_update(
+        &mut cs.namespace(|| "src update"),
+        enabled,
+        state,
+        &src_index_bits,
+        &src.proof,
+        &src_old_leaf,
+        &src_new_leaf,
+    )?;
+
+    let dst = AllocatedAccount::alloc(&mut cs.namespace(|| "dst"), &w.dst, shape)?;
+    dst.assert_writable_by_if(&mut cs.namespace(|| "dst writable"), enabled, &dst_pub_key)?;
+    assert_skipped_slots_taken_if(
+        &mut cs.namespace(|| "dst skipped slots"),
+        enabled,
+        &state,
+        &dst_pub_key,
+        &dst_slots,
+        &w.dst,
+        shape,
+    )?;
+    let dst_old_leaf = account_leaf(
+        &mut cs.namespace(|| "dst old leaf"),
+        &dst.nonce,
+        &dst.address,
+        &dst.tokens_root,
+    )?;
+    let dst_amount =
+        AllocatedToken::alloc(&mut cs.namespace(|| "dst amount"), &w.dst_amount, shape)?;
+    let dst_tokens_root = dst_amount.credit(
+        &mut cs.namespace(|| "dst amount credit"),
+        enabled,
+        &dst.tokens_root,
+        &token_id,
+        &amount,
+    )?;
+    let dst_new_leaf = account_leaf(
+        &mut cs.namespace(|| "dst new leaf"),
+        &dst.nonce,
+        &dst_pub_key,
+        &dst_tokens_root,
+    )?;
+    let state = merkle_update(
+        &mut cs.namespace(|| "dst update"),
+        enabled,
+        &state,
+        &dst_index_bits,
+        &dst.proof,
+        &dst_old_leaf,
+        &dst_new_leaf,
+    )?;
+
+    let collected_fee = fee.mul(&mut cs.namespace(|| "collected fee"), enabled)?;
+    Ok((state, collected_fee))
+}
+
+impl Circuit<BellmanFr> for UpdateCircuit {
+    fn synthesize<CS: ConstraintSystem<BellmanFr>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
+        let inputs = alloc_inputs(cs, &self.inputs)?;
+        let fee_token = alloc_scalar(&mut cs.namespace(|| "fee token"), self.fee_token)?;
+        let mut state = inputs.state;
+        let mut total_fee = Num::zero();
+        for i in 0..self.shape.update_batch_size {
+            let cs = &mut cs.namespace(|| format!("transfer {}", i));
+            let witness = self.transfers.get(i).cloned();
+            let enabled = Num::alloc_bit(&mut cs.namespace(|| "enabled"), witness.is_some())?;
+            let (next_state, fee) = synthesize_transfer(
+                cs,
+                &self.shape,
+                &enabled,
+                &state,
+                &fee_token,
+                &witness.unwrap_or_default(),
+            )?;
+            state = next_state;
+            total_fee = total_fee.add(&fee);
+        }
+        let aux_data = poseidon(
+            &mut cs.namespace(|| "compute aux data"),
+            &[fee_token, total_fee],
+        )?;
+        assert_equal(
+            &mut cs.namespace(|| "aux data check"),
+            &aux_data,
+            &inputs.aux_data,
+        );
+        assert_equal(
+            &mut cs.namespace(|| "next state check"),
+            &state,
+            &inputs.next_state,
+        );
+        Ok(())
+    }
+}
+
+fn synthesize_deposit<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    shape: &MpnShape,
+    enabled: &Num,
+    state: &Num,
+    w: &DepositWitness,
+) -> Result<(Num, Num), SynthesisError> {
+    let pub_key = Point::alloc(&mut cs.namespace(|| "pub-key"), &w.pub_key)?;
+    let token_id = alloc_scalar(&mut cs.namespace(|| "token id"), w.token_id)?;
+    let amount = alloc_u64(&mut cs.namespace(|| "amount"), w.amount)?;
+    pub_key.assert_on_curve_if(&mut cs.namespace(|| "on curve"), enabled)?;
+
+    let calldata = poseidon(
+        &mut cs.namespace(|| "calldata"),
+        &[pub_key.x.clone(), pub_key.y.clone()],
+    )?;
+    let calldata = calldata.mul(&mut cs.namespace(|| "enabled calldata"), enabled)?;
+    let payment = poseidon(
+        &mut cs.namespace(|| "payment"),
+        &[enabled.clone(), token_id.clone(), amount.clone(), calldata],
+    )?;
+
+    let slots = account_slots(
+        &mut cs.namespace(|| "index"),
+        &pub_key,
+        w.account.slot_choice,
+        shape,
+    )?;
+    let acc = AllocatedAccount::alloc(&mut cs.namespace(|| "account"), &w.account, shape)?;
+    acc.assert_writable_by_if(&mut cs.namespace(|| "writable"), enabled, &pub_key)?;
+    assert_skipped_slots_taken_if(
+        &mut cs.namespace(|| "skipped slots"),
+        enabled,
+        state,
+        &pub_key,
+        &slots,
+        &w.account,
+        shape,
+    )?;
+    let old_leaf = account_leaf(
+        &mut cs.namespace(|| "old leaf"),
+        &acc.nonce,
+        &acc.address,
+        &acc.tokens_root,
+    )?;
+    let slot = AllocatedToken::alloc(&mut cs.namespace(|| "slot"), &w.slot, shape)?;
+    let tokens_root = slot.credit(
+        &mut cs.namespace(|| "credit"),
+        enabled,
+        &acc.tokens_root,
+        &token_id,
+        &amount,
+    )?;
+    let new_leaf = account_leaf(
+        &mut cs.namespace(|| "new leaf"),
+        &acc.nonce,
+        &pub_key,
+        &tokens_root,
+    )?;
+    let state = merkle_update(
+        &mut cs.namespace(|| "update"),
+        enabled,
+        state,
+        &slots.index_bits,
+        &acc.proof,
+        &old_leaf,
+        &new_leaf,
+    )?;
+    Ok((state, payment))
+}
+
+impl Circuit<BellmanFr> for DepositCircuit {
+    fn synthesize<CS: ConstraintSystem<BellmanFr>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
+        let inputs = alloc_inputs(cs, &self.inputs)?;
+        let mut state = inputs.state;
+        let mut payments = Vec::new();
+        for i in 0..self.shape.payment_capacity() {
+            let cs = &mut cs.namespace(|| format!("deposit {}", i));
+            let witness = self.deposits.get(i).cloned();
+            let enabled = Num::alloc_bit(&mut cs.namespace(|| "enabled"), witness.is_some())?;
+            let (next_state, payment) = synthesize_deposit(
+                cs,
+                &self.shape,
+                &enabled,
+                &state,
+                &witness.unwrap_or_default(),
+            )?;
+            state = next_state;
+            payments.push(payment);
+        }
+        let aux_data = list_root(&mut cs.namespace(|| "compute aux data"), payments)?;
+        assert_equal(
+            &mut cs.namespace(|| "aux data check"),
+            &aux_data,
+            &inputs.aux_data,
+        );
+        assert_equal(
+            &mut cs.namespace(|| "next state check"),
+            &state,
+            &inputs.next_state,
+        );
+        Ok(())
+    }
+}
+
+fn synthesize_withdraw<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    shape: &MpnShape,
+    enabled: &Num,
+    state: &Num,
+    w: &WithdrawWitness,
+) -> Result<(Num, Num), SynthesisError> {
+    let pub_key = Point::alloc(&mut cs.namespace(|| "pub-key"), &w.pub_key)?;
+    let nonce = alloc_scalar(&mut cs.namespace(|| "nonce"), ZkScalar::from(w.nonce))?;
+    let token_id = alloc_scalar(&mut cs.namespace(|| "token id"), w.token_id)?;
+    let amount = alloc_u64(&mut cs.namespace(|| "amount"), w.amount)?;
+    let fee_token_id = alloc_scalar(&mut cs.namespace(|| "fee token id"), w.fee_token_id)?;
+    let fee = alloc_u64(&mut cs.namespace(|| "fee"), w.fee)?;
+    let fingerprint = alloc_scalar(&mut cs.namespace(|| "fingerprint"), w.fingerprint)?;
+
+    let msg = poseidon(
+        &mut cs.namespace(|| "msg"),
+        &[fingerprint.clone(), nonce.clone()],
+    )?;
+    let sig_r = Point::alloc(&mut cs.namespace(|| "sig r"), &w.sig.r)?;
+    let sig_s = alloc_scalar(&mut cs.namespace(|| "sig s"), w.sig.s)?;
+    assert_signature_if(
+        &mut cs.namespace(|| "signature"),
+        enabled,
+        &pub_key,
+        &msg,
+        &sig_r,
+        &sig_s,
+    )?;
+
+    let calldata = poseidon(
+        &mut cs.namespace(|| "calldata"),
+        &[
+            pub_key.x.clone(),
+            pub_key.y.clone(),
+            nonce.clone(),
+            sig_r.x.clone(),
+            sig_r.y.clone(),
+            sig_s.clone(),
+        ],
+    )?;
+    let calldata = calldata.mul(&mut cs.namespace(|| "enabled calldata"), enabled)?;
+    let payment = poseidon(
+        &mut cs.namespace(|| "payment"),
+        &[
+            enabled.clone(),
+            token_id.clone(),
+            amount.clone(),
+            fee_token_id.clone(),
+            fee.clone(),
+            fingerprint,
+            calldata,
+        ],
+    )?;
+
+    // The account should be owned by the signer and have the same nonce
+    let index_bits = account_slots(
+        &mut cs.namespace(|| "index"),
+        &pub_key,
+        w.account.slot_choice,
+        shape,
+    )?
+    .index_bits;
+    let acc = AllocatedAccount::alloc_owned(
+        &mut cs.namespace(|| "account"),
+        &w.account,
+        shape,
+        &nonce,
+        &pub_key,
+    )?;
+    let old_leaf = account_leaf(
+        &mut cs.namespace(|| "old leaf"),
+        &nonce,
+        &pub_key,
+        &acc.tokens_root,
+    )?;
+    let amount_slot =
+        AllocatedToken::alloc(&mut cs.namespace(|| "amount slot"), &w.amount_slot, shape)?;
+    let tokens_root = amount_slot.debit(
+        &mut cs.namespace(|| "amount debit"),
+        enabled,
+        &acc.tokens_root,
+        &token_id,
+        &amount,
+    )?;
+    let fee_slot = AllocatedToken::alloc(&mut cs.namespace(|| "fee slot"), &w.fee_slot, shape)?;
+    let tokens_root = fee_slot.debit(
+        &mut cs.namespace(|| "fee debit"),
+        enabled,
+        &tokens_root,
+        &fee_token_id,
+        &fee,
+    )?;
+    let new_leaf = account_leaf(
+        &mut cs.namespace(|| "new leaf"),
+        &nonce.add_constant(BellmanFr::one()),
+        &pub_key,
+        &tokens_root,
+    )?;
+    let state = merkle_update(
+        &mut cs.namespace(|| "update"),
+        enabled,
+        state,
+        &index_bits,
+        &acc.proof,
+        &old_leaf,
+        &new_leaf,
+    )?;
+    Ok((state, payment))
+}
+
+impl Circuit<BellmanFr> for WithdrawCircuit {
+    fn synthesize<CS: ConstraintSystem<BellmanFr>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
+        let inputs = alloc_inputs(cs, &self.inputs)?;
+        let mut state = inputs.state;
+        let mut payments = Vec::new();
+        for i in 0..self.shape.payment_capacity() {
+            let cs = &mut cs.namespace(|| format!("withdraw {}", i));
+            let witness = self.withdraws.get(i).cloned();
+            let enabled = Num::alloc_bit(&mut cs.namespace(|| "enabled"), witness.is_some())?;
+            let (next_state, payment) = synthesize_withdraw(
+                cs,
+                &self.shape,
+                &enabled,
+                &state,
+                &witness.unwrap_or_default(),
+            )?;
+            state = next_state;
+            payments.push(payment);
+        }
+        let aux_data = list_root(&mut cs.namespace(|| "compute aux data"), payments)?;
+        assert_equal(
+            &mut cs.namespace(|| "aux data check"),
+            &aux_data,
+            &inputs.aux_data,
+        );
+        assert_equal(
+            &mut cs.namespace(|| "next state check"),
+            &state,
+            &inputs.next_state,
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,316 @@
+use super::circuits::{DepositCircuit, MpnShape, PublicInputs, UpdateCircuit, WithdrawCircuit};
+use super::{MpnError, MpnParams, MpnState};
+use crate::blockchain::Blockchain;
+use crate::core::{
+    Address, ContractDeposit, ContractId, ContractUpdate, ContractWithdraw, Money, MpnDeposit,
+    MpnWithdraw, TokenId,
+};
+use crate::zk::{
+    deposits_aux_data, function_call_aux_data, withdraws_aux_data, MpnAccount, MpnTransaction,
+    ZkCompressedState, ZkDeltaPairs,
+};
+use std::collections::HashSet;
+
+const ACCOUNTS_PAGE_SIZE: usize = 1024;
+
+/// Number of contract updates of each kind a block requires
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MpnUpdateCounts {
+    pub function_calls: usize,
+    pub deposits: usize,
+    pub withdraws: usize,
+}
+
+/// A contract update which is ready to be proven
+pub enum MpnWork {
+    Deposit {
+        deposits: Vec<ContractDeposit>,
+        next_state: ZkCompressedState,
+        circuit: DepositCircuit,
+    },
+    Withdraw {
+        withdraws: Vec<ContractWithdraw>,
+        next_state: ZkCompressedState,
+        circuit: WithdrawCircuit,
+    },
+    Update {
+        fee: Money,
+        next_state: ZkCompressedState,
+        circuit: UpdateCircuit,
+    },
+}
+
+impl MpnWork {
+    pub fn prove(self, params: &MpnParams) -> Result<ContractUpdate, MpnError> {
+        Ok(match self {
+            MpnWork::Deposit {
+                deposits,
+                next_state,
+                circuit,
+            } => ContractUpdate::Deposit {
+                deposit_circuit_id: 0,
+                deposits,
+                next_state,
+                proof: params.prove_deposit(circuit)?,
+                events: vec![],
+            },
+            MpnWork::Withdraw {
+                withdraws,
+                next_state,
+                circuit,
+            } => ContractUpdate::Withdraw {
+                withdraw_circuit_id: 0,
+                withdraws,
+                next_state,
+                proof: params.prove_withdraw(circuit)?,
+                events: vec![],
+            },
+            MpnWork::Update {
+                fee,
+                next_state,
+                circuit,
+            } => ContractUpdate::FunctionCall {
+                function_id: 0,
+                next_state,
+                proof: params.prove_update(circuit)?,
+                fee,
+                events: vec![],
+            },
+        })
+    }
+}
+
+/// Plans MPN contract updates, keeping a copy of the MPN state between runs
+pub struct MpnExecutor {
+    shape: MpnShape,
+    state: Option<MpnState>,
+}
+
+impl MpnExecutor {
+    pub fn new(shape: MpnShape) -> Self {
+        Self { shape, state: None }
+    }
+
+    /// Starts from an already built state instead of syncing with a chain
+    pub fn with_state(state: MpnState) -> Self {
+        Self {
+            shape: state.shape(),
+            state: Some(state),
+        }
+    }
+
+    /// Is the local copy of the state at the given state of the MPN contract?
+    pub fn is_synced(&self, target: &ZkCompressedState) -> bool {
+        self.state
+            .as_ref()
+            .map(|state| state.root().ok().as_ref() == Some(target))
+            .unwrap_or(false)
+    }
+
+    /// All of the accounts of the MPN contract, needed for a sync
+    pub fn fetch_accounts<B: Blockchain>(chain: &B) -> Result<Vec<(u64, MpnAccount)>, MpnError> {
+        let mut all = Vec::new();
+        for page in 0.. {
+            let accounts = chain.get_mpn_accounts(page, ACCOUNTS_PAGE_SIZE)?;
+            if accounts.is_empty() {
+                break;
+            }
+            all.extend(accounts);
+        }
+        Ok(all)
+    }
+
+    /// Rebuilds the local copy of the state from the accounts of the MPN
+    /// contract, which should be at the given state
+    pub fn sync(
+        &mut self,
+        target: ZkCompressedState,
+        accounts: Vec<(u64, MpnAccount)>,
+    ) -> Result<(), MpnError> {
+        let mut state = MpnState::new(self.shape);
+        for (index, acc) in accounts {
+            state.load_account(index, acc)?;
+        }
+        if state.root()? != target {
+            self.state = None;
+            return Err(MpnError::InconsistentState);
+        }
+        self.state = Some(state);
+        Ok(())
+    }
+
+    /// Applies as many of the given MPN operations as fit in the required
+    /// contract updates, returning the updates to be proven along with the delta
+    /// of the contract state. The state should have been synced beforehand.
+    #[allow(clippy::too_many_arguments)]
+    pub fn plan(
+        &mut self,
+        contract_id: ContractId,
+        height: u64,
+        counts: MpnUpdateCounts,
+        executor: &Address,
+        deposits: Vec<MpnDeposit>,
+        withdraws: Vec<MpnWithdraw>,
+        updates: Vec<MpnTransaction>,
+    ) -> Result<(Vec<MpnWork>, ZkDeltaPairs), MpnError> {
+        let res = self.try_plan(
+            contract_id,
+            height,
+            counts,
+            executor,
+            deposits,
+            withdraws,
+            updates,
+        );
+        if res.is_err() {
+            // The local state may be partially updated, build it again next time
+            self.state = None;
+        }
+        res
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn try_plan(
+        &mut self,
+        contract_id: ContractId,
+        height: u64,
+        counts: MpnUpdateCounts,
+        executor: &Address,
+        deposits: Vec<MpnDeposit>,
+        mut withdraws: Vec<MpnWithdraw>,
+        mut updates: Vec<MpnTransaction>,
+    ) -> Result<(Vec<MpnWork>, ZkDeltaPairs), MpnError> {
+        let shape = self.shape;
+        let state = self.state.as_mut().ok_or(MpnError::InconsistentState)?;
+        let mut works = Vec::new();
+
+        let mut deposits = deposits
+            .into_iter()
+            .filter(|d| d.payment.contract_id == contract_id && d.payment.src != *executor)
+            .peekable();
+        let mut rejected_srcs = HashSet::new();
+        let num_deposit_updates = counts.deposits.max(usize::from(deposits.peek().is_some()));
+        for _ in 0..num_deposit_updates {
+            let prev_state = state.root()?;
+            let mut applied = Vec::new();
+            let mut witnesses = Vec::new();
+            while applied.len() < shape.payment_capacity() {
+                let deposit = if let Some(deposit) = deposits.next() {
+                    deposit
+                } else {
+                    break;
+                };
+                // Deposits of the same account should be applied in order
+                if rejected_srcs.contains(&deposit.payment.src) {
+                    continue;
+                }
+                match state.apply_deposit(&deposit) {
+                    Ok(witness) => {
+                        witnesses.push(witness);
+                        applied.push(deposit.payment);
+                    }
+                    Err(_) => {
+                        rejected_srcs.insert(deposit.payment.src);
+                    }
+                }
+            }
+            let aux_data = deposits_aux_data(shape.log4_payment_capacity, &applied)?.state_hash;
+            let next_state = state.root()?;
+            works.push(MpnWork::Deposit {
+                deposits: applied,
+                next_state,
+                circuit: DepositCircuit {
+                    shape,
+                    inputs: PublicInputs {
+                        height,
+                        state: prev_state.state_hash,
+                        aux_data,
+                        next_state: next_state.state_hash,
+                    },
+                    deposits: witnesses,
+                },
+            });
+        }
+
+        withdraws.retain(|w| w.payment.contract_id == contract_id && w.payment.dst != *executor);
+        withdraws.sort_unstable_by_key(|w| w.zk_nonce);
+        let mut withdraws = withdraws.into_iter().peekable();
+        let num_withdraw_updates = counts
+            .withdraws
+            .max(usize::from(withdraws.peek().is_some()));
+        for _ in 0..num_withdraw_updates {
+            let prev_state = state.root()?;
+            let mut applied = Vec::new();
+            let mut witnesses = Vec::new();
+            while applied.len() < shape.payment_capacity() {
+                let withdraw = if let Some(withdraw) = withdraws.next() {
+                    withdraw
+                } else {
+                    break;
+                };
+                if let Ok(witness) = state.apply_withdraw(&withdraw) {
+                    witnesses.push(witness);
+                    applied.push(withdraw.payment);
+                }
+            }
+            let aux_data = withdraws_aux_data(shape.log4_payment_capacity, &applied)?.state_hash;
+            let next_state = state.root()?;
+            works.push(MpnWork::Withdraw {
+                withdraws: applied,
+                next_state,
+                circuit: WithdrawCircuit {
+                    shape,
+                    inputs: PublicInputs {
+                        height,
+                        state: prev_state.state_hash,
+                        aux_data,
+                        next_state: next_state.state_hash,
+                    },
+                    withdraws: witnesses,
+                },
+            });
+        }
+
+        // All of the fees in an update should be paid in a single token
+        updates.retain(|tx| tx.fee.token_id == TokenId::Ziesha);
+        updates.sort_unstable_by_key(|tx| tx.nonce);
+        let mut updates = updates.into_iter().peekable();
+        let num_function_calls = counts
+            .function_calls
+            .max(usize::from(updates.peek().is_some()));
+        for _ in 0..num_function_calls {
+            let prev_state = state.root()?;
+            let mut witnesses = Vec::new();
+            let mut total_fee = Money::ziesha(0);
+            while witnesses.len() < shape.update_batch_size {
+                let tx = if let Some(tx) = updates.next() {
+                    tx
+                } else {
+                    break;
+                };
+                if let Ok(witness) = state.apply_transfer(&tx) {
+                    witnesses.push(witness);
+                    total_fee.amount += tx.fee.amount;
+                }
+            }
+            let next_state = state.root()?;
+            works.push(MpnWork::Update {
+                fee: total_fee,
+                next_state,
+                circuit: UpdateCircuit {
+                    shape,
+                    inputs: PublicInputs {
+                        height,
+                        state: prev_state.state_hash,
+                        aux_data: function_call_aux_data(total_fee)?.state_hash,
+                        next_state: next_state.state_hash,
+                    },
+                    fee_token: total_fee.token_id.into(),
+                    transfers: witnesses,
+                },
+            });
+        }
+
+        Ok((works, state.take_delta()))
+    }
+}
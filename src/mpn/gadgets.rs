@@ -0,0 +1,641 @@
+use crate::crypto::jubjub;
+use crate::zk::{poseidon::PoseidonParams, ZkScalar};
+use bellman::gadgets::num::AllocatedNum;
+use bellman::{ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
+use bls12_381::Scalar as BellmanFr;
+use ff::PrimeFieldBits;
+use std::collections::BTreeMap;
+
+fn one() -> Variable {
+    Variable::new_unchecked(Index::Input(0))
+}
+
+fn fr(s: ZkScalar) -> BellmanFr {
+    s.into()
+}
+
+/// A linear combination of circuit variables along with its witness value
+#[derive(Clone)]
+pub struct Num {
+    lc: LinearCombination<BellmanFr>,
+    val: BellmanFr,
+}
+
+impl Num {
+    pub fn zero() -> Self {
+        Self::constant(BellmanFr::zero())
+    }
+    pub fn constant(val: BellmanFr) -> Self {
+        Self {
+            lc: LinearCombination::zero() + (val, one()),
+            val,
+        }
+    }
+    pub fn alloc<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        val: BellmanFr,
+    ) -> Result<Self, SynthesisError> {
+        let var = cs.alloc(|| "num", || Ok(val))?;
+        Ok(Self {
+            lc: LinearCombination::zero() + var,
+            val,
+        })
+    }
+    pub fn alloc_input<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        val: BellmanFr,
+    ) -> Result<Self, SynthesisError> {
+        let var = cs.alloc_input(|| "input", || Ok(val))?;
+        Ok(Self {
+            lc: LinearCombination::zero() + var,
+            val,
+        })
+    }
+    /// Allocates a number which is constrained to be either 0 or 1
+    pub fn alloc_bit<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        val: bool,
+    ) -> Result<Self, SynthesisError> {
+        let bit = Self::alloc(
+            cs,
+            if val {
+                BellmanFr::one()
+            } else {
+                BellmanFr::zero()
+            },
+        )?;
+        cs.enforce(
+            || "bit",
+            |lc| lc + &bit.lc,
+            |lc| lc + one() - &bit.lc,
+            |lc| lc,
+        );
+        Ok(bit)
+    }
+    pub fn value(&self) -> BellmanFr {
+        self.val
+    }
+    pub fn add(&self, other: &Num) -> Num {
+        Num {
+            lc: self.lc.clone() + &other.lc,
+            val: self.val + other.val,
+        }
+    }
+    pub fn sub(&self, other: &Num) -> Num {
+        Num {
+            lc: self.lc.clone() - &other.lc,
+            val: self.val - other.val,
+        }
+    }
+    pub fn scale(&self, coeff: BellmanFr) -> Num {
+        Num {
+            lc: LinearCombination::zero() + (coeff, &self.lc),
+            val: self.val * coeff,
+        }
+    }
+    pub fn add_constant(&self, val: BellmanFr) -> Num {
+        Num {
+            lc: self.lc.clone() + (val, one()),
+            val: self.val + val,
+        }
+    }
+    pub fn mul<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &Num,
+    ) -> Result<Num, SynthesisError> {
+        let res = Num::alloc(cs, self.val * other.val)?;
+        cs.enforce(
+            || "mul",
+            |lc| lc + &self.lc,
+            |lc| lc + &other.lc,
+            |lc| lc + &res.lc,
+        );
+        Ok(res)
+    }
+    /// Merges the repeated variables of the linear combination, keeping it
+    /// small when it goes through many linear layers (E.g. Poseidon rounds)
+    pub fn simplify(&self) -> Num {
+        let mut terms = BTreeMap::<(bool, usize), (Variable, BellmanFr)>::new();
+        for (var, coeff) in self.lc.as_ref() {
+            let key = match var.get_unchecked() {
+                Index::Input(i) => (true, i),
+                Index::Aux(i) => (false, i),
+            };
+            terms
+                .entry(key)
+                .and_modify(|(_, c)| *c += coeff)
+                .or_insert((*var, *coeff));
+        }
+        Num {
+            lc: terms
+                .into_values()
+                .filter(|(_, coeff)| *coeff != BellmanFr::zero())
+                .fold(LinearCombination::zero(), |lc, (var, coeff)| {
+                    lc + (coeff, var)
+                }),
+            val: self.val,
+        }
+    }
+}
+
+pub fn assert_equal<CS: ConstraintSystem<BellmanFr>>(cs: &mut CS, a: &Num, b: &Num) {
+    cs.enforce(
+        || "equal",
+        |lc| lc + &a.lc - &b.lc,
+        |lc| lc + one(),
+        |lc| lc,
+    );
+}
+
+/// Requires `val` to be zero, only when `enabled` is 1
+pub fn assert_zero_if<CS: ConstraintSystem<BellmanFr>>(cs: &mut CS, enabled: &Num, val: &Num) {
+    cs.enforce(
+        || "zero if enabled",
+        |lc| lc + &enabled.lc,
+        |lc| lc + &val.lc,
+        |lc| lc,
+    );
+}
+
+/// Returns `a` if `cond` is 1 and `b` if it is 0
+pub fn select<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    cond: &Num,
+    a: &Num,
+    b: &Num,
+) -> Result<Num, SynthesisError> {
+    let res = Num::alloc(
+        cs,
+        if cond.val == BellmanFr::one() {
+            a.val
+        } else {
+            b.val
+        },
+    )?;
+    cs.enforce(
+        || "select",
+        |lc| lc + &cond.lc,
+        |lc| lc + &a.lc - &b.lc,
+        |lc| lc + &res.lc - &b.lc,
+    );
+    Ok(res)
+}
+
+/// Returns 1 if `a` and `b` are equal, 0 otherwise
+pub fn is_equal<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    a: &Num,
+    b: &Num,
+) -> Result<Num, SynthesisError> {
+    let diff = a.sub(b);
+    let inv = Num::alloc(
+        &mut cs.namespace(|| "inv"),
+        Option::from(diff.val.invert()).unwrap_or_else(BellmanFr::zero),
+    )?;
+    let res = Num::alloc(
+        &mut cs.namespace(|| "res"),
+        if diff.val == BellmanFr::zero() {
+            BellmanFr::one()
+        } else {
+            BellmanFr::zero()
+        },
+    )?;
+    cs.enforce(
+        || "res is one minus diff times inv",
+        |lc| lc + &diff.lc,
+        |lc| lc + &inv.lc,
+        |lc| lc + one() - &res.lc,
+    );
+    cs.enforce(
+        || "res is zero when diff is not",
+        |lc| lc + &diff.lc,
+        |lc| lc + &res.lc,
+        |lc| lc,
+    );
+    Ok(res)
+}
+
+/// Requires `a` and `b` not to be both zero, only when `enabled` is 1. One of
+/// them is proven to be non-zero by multiplying it with its inverse.
+pub fn assert_not_both_zero_if<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    enabled: &Num,
+    a: &Num,
+    b: &Num,
+) -> Result<(), SynthesisError> {
+    let a_nonzero = Num::alloc_bit(
+        &mut cs.namespace(|| "a non-zero"),
+        a.val != BellmanFr::zero(),
+    )?;
+    let on_a = enabled.mul(&mut cs.namespace(|| "on a"), &a_nonzero)?;
+    let on_b = enabled.sub(&on_a);
+    for (name, num, on) in [("a", a, &on_a), ("b", b, &on_b)] {
+        let inv = Num::alloc(
+            &mut cs.namespace(|| format!("{} inv", name)),
+            if on.val == BellmanFr::one() {
+                Option::from(num.val.invert()).unwrap_or_else(BellmanFr::zero)
+            } else {
+                BellmanFr::zero()
+            },
+        )?;
+        cs.enforce(
+            || format!("{} inv check", name),
+            |lc| lc + &num.lc,
+            |lc| lc + &inv.lc,
+            |lc| lc + &on.lc,
+        );
+    }
+    Ok(())
+}
+
+/// Little-endian bits of `num`, which is also proven to fit in `num_bits` bits
+pub fn to_bits<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    num: &Num,
+    num_bits: usize,
+) -> Result<Vec<Num>, SynthesisError> {
+    let bits = ZkScalar::from(num.val).to_le_bits();
+    let mut res = Vec::new();
+    let mut sum = Num::zero();
+    let mut coeff = BellmanFr::one();
+    for i in 0..num_bits {
+        let bit = Num::alloc_bit(&mut cs.namespace(|| format!("bit {}", i)), bits[i])?;
+        sum = sum.add(&bit.scale(coeff));
+        coeff = coeff.double();
+        res.push(bit);
+    }
+    assert_equal(cs, &sum, num);
+    Ok(res)
+}
+
+/// Canonical little-endian bits of `num` (255 bits, no aliasing modulo the field)
+pub fn to_bits_strict<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    num: &Num,
+) -> Result<Vec<Num>, SynthesisError> {
+    let allocated = AllocatedNum::alloc(cs.namespace(|| "num"), || Ok(num.val))?;
+    assert_equal(
+        cs,
+        num,
+        &Num {
+            lc: LinearCombination::zero() + allocated.get_variable(),
+            val: num.val,
+        },
+    );
+    Ok(allocated
+        .to_bits_le_strict(cs.namespace(|| "bits"))?
+        .into_iter()
+        .map(|b| Num {
+            lc: b.lc(one(), BellmanFr::one()),
+            val: if b.get_value().unwrap_or_default() {
+                BellmanFr::one()
+            } else {
+                BellmanFr::zero()
+            },
+        })
+        .collect())
+}
+
+pub fn bits_to_num(bits: &[Num]) -> Num {
+    let mut sum = Num::zero();
+    let mut coeff = BellmanFr::one();
+    for bit in bits {
+        sum = sum.add(&bit.scale(coeff));
+        coeff = coeff.double();
+    }
+    sum
+}
+
+fn quintic_s_box<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    x: &Num,
+) -> Result<Num, SynthesisError> {
+    let x2 = x.mul(&mut cs.namespace(|| "x^2"), x)?;
+    let x4 = x2.mul(&mut cs.namespace(|| "x^4"), &x2)?;
+    x4.mul(&mut cs.namespace(|| "x^5"), x)
+}
+
+/// Circuit version of `zk::poseidon::poseidon`
+pub fn poseidon<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    vals: &[Num],
+) -> Result<Num, SynthesisError> {
+    let params = PoseidonParams::for_width(vals.len() + 1).ok_or(SynthesisError::Unsatisfiable)?;
+    let mut elements = vec![Num::zero()];
+    elements.extend(vals.iter().cloned());
+    let half_full = params.full_rounds / 2;
+    let mut constants_offset = 0;
+    for round in 0..params.full_rounds + params.partial_rounds {
+        let is_full = round < half_full || round >= half_full + params.partial_rounds;
+        for (i, elem) in elements.iter_mut().enumerate() {
+            *elem = elem.add_constant(fr(params.round_constants[constants_offset]));
+            constants_offset += 1;
+            if is_full || i == 0 {
+                *elem = quintic_s_box(
+                    &mut cs.namespace(|| format!("round {} s-box {}", round, i)),
+                    elem,
+                )?;
+            }
+        }
+        elements = params
+            .mds_constants
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .zip(elements.iter())
+                    .fold(Num::zero(), |sum, (coeff, elem)| {
+                        sum.add(&elem.scale(fr(*coeff)))
+                    })
+                    .simplify()
+            })
+            .collect();
+    }
+    Ok(elements[1].clone())
+}
+
+/// Root of a complete quaternary tree with the given leaves
+pub fn list_root<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    mut layer: Vec<Num>,
+) -> Result<Num, SynthesisError> {
+    let mut depth = 0;
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(4)
+            .enumerate()
+            .map(|(i, chunk)| {
+                poseidon(
+                    &mut cs.namespace(|| format!("layer {} node {}", depth, i)),
+                    chunk,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        depth += 1;
+    }
+    layer.pop().ok_or(SynthesisError::Unsatisfiable)
+}
+
+/// Root of a quaternary tree, given a leaf, its index bits (Two bits per layer) and
+/// the proof returned by `ZkStateBuilder::prove`
+pub fn merkle_root<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    leaf: &Num,
+    index_bits: &[Num],
+    proof: &[[Num; 3]],
+) -> Result<Num, SynthesisError> {
+    let mut curr = leaf.clone();
+    for (layer, (bits, siblings)) in index_bits.chunks(2).zip(proof.iter()).enumerate() {
+        let cs = &mut cs.namespace(|| format!("layer {}", layer));
+        let (b0, b1) = (&bits[0], &bits[1]);
+        let both = b0.mul(&mut cs.namespace(|| "both"), b1)?;
+        let is_first = Num::constant(BellmanFr::one()).sub(b0).sub(b1).add(&both);
+        let c0 = select(&mut cs.namespace(|| "c0"), &is_first, &curr, &siblings[0])?;
+        let c1_low = select(&mut cs.namespace(|| "c1 low"), b0, &curr, &siblings[0])?;
+        let c1 = select(&mut cs.namespace(|| "c1"), b1, &siblings[1], &c1_low)?;
+        let c2_high = select(&mut cs.namespace(|| "c2 high"), b0, &siblings[2], &curr)?;
+        let c2 = select(&mut cs.namespace(|| "c2"), b1, &c2_high, &siblings[1])?;
+        let c3 = select(&mut cs.namespace(|| "c3"), &both, &curr, &siblings[2])?;
+        curr = poseidon(&mut cs.namespace(|| "hash"), &[c0, c1, c2, c3])?;
+    }
+    Ok(curr)
+}
+
+/// Replaces `old_leaf` with `new_leaf` in the tree with the given root and returns
+/// the new root. Does nothing (And checks nothing) when `enabled` is 0.
+pub fn merkle_update<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    enabled: &Num,
+    root: &Num,
+    index_bits: &[Num],
+    proof: &[[Num; 3]],
+    old_leaf: &Num,
+    new_leaf: &Num,
+) -> Result<Num, SynthesisError> {
+    let old_root = merkle_root(
+        &mut cs.namespace(|| "old root"),
+        old_leaf,
+        index_bits,
+        proof,
+    )?;
+    assert_zero_if(cs, enabled, &old_root.sub(root));
+    let new_root = merkle_root(
+        &mut cs.namespace(|| "new root"),
+        new_leaf,
+        index_bits,
+        proof,
+    )?;
+    select(&mut cs.namespace(|| "select"), enabled, &new_root, root)
+}
+
+/// Allocates a Merkle proof of the given depth, missing layers are filled with zeros
+pub fn alloc_proof<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    proof: &[[ZkScalar; 3]],
+    depth: u8,
+) -> Result<Vec<[Num; 3]>, SynthesisError> {
+    (0..depth as usize)
+        .map(|i| proof.get(i).copied().unwrap_or_default())
+        .enumerate()
+        .map(|(i, siblings)| {
+            let s0 = Num::alloc(&mut cs.namespace(|| format!("{} 0", i)), fr(siblings[0]))?;
+            let s1 = Num::alloc(&mut cs.namespace(|| format!("{} 1", i)), fr(siblings[1]))?;
+            let s2 = Num::alloc(&mut cs.namespace(|| format!("{} 2", i)), fr(siblings[2]))?;
+            Ok([s0, s1, s2])
+        })
+        .collect()
+}
+
+/// A point on the JubJub curve
+#[derive(Clone)]
+pub struct Point {
+    pub x: Num,
+    pub y: Num,
+}
+
+impl Point {
+    pub fn alloc<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        p: &jubjub::PointAffine,
+    ) -> Result<Self, SynthesisError> {
+        let x = Num::alloc(&mut cs.namespace(|| "x"), fr(p.0))?;
+        let y = Num::alloc(&mut cs.namespace(|| "y"), fr(p.1))?;
+        Ok(Self { x, y })
+    }
+    pub fn constant(p: &jubjub::PointAffine) -> Self {
+        Self {
+            x: Num::constant(fr(p.0)),
+            y: Num::constant(fr(p.1)),
+        }
+    }
+
+    /// Complete twisted Edwards addition (a = -1)
+    pub fn add<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        other: &Point,
+    ) -> Result<Point, SynthesisError> {
+        let d = fr(*jubjub::D);
+        let x1y2 = self.x.mul(&mut cs.namespace(|| "x1y2"), &other.y)?;
+        let y1x2 = self.y.mul(&mut cs.namespace(|| "y1x2"), &other.x)?;
+        let y1y2 = self.y.mul(&mut cs.namespace(|| "y1y2"), &other.y)?;
+        let x1x2 = self.x.mul(&mut cs.namespace(|| "x1x2"), &other.x)?;
+        let t = x1x2.mul(&mut cs.namespace(|| "t"), &y1y2)?;
+        let x_den = t.scale(d).add_constant(BellmanFr::one());
+        let y_den = t.scale(-d).add_constant(BellmanFr::one());
+        let x_num = x1y2.add(&y1x2);
+        let y_num = y1y2.add(&x1x2);
+        let x = Num::alloc(
+            &mut cs.namespace(|| "x"),
+            x_num.val
+                * Option::<BellmanFr>::from(x_den.val.invert())
+                    .ok_or(SynthesisError::DivisionByZero)?,
+        )?;
+        let y = Num::alloc(
+            &mut cs.namespace(|| "y"),
+            y_num.val
+                * Option::<BellmanFr>::from(y_den.val.invert())
+                    .ok_or(SynthesisError::DivisionByZero)?,
+        )?;
+        cs.enforce(
+            || "x3 * (1 + d * t) == x1 * y2 + y1 * x2",
+            |lc| lc + &x.lc,
+            |lc| lc + &x_den.lc,
+            |lc| lc + &x_num.lc,
+        );
+        cs.enforce(
+            || "y3 * (1 - d * t) == y1 * y2 + x1 * x2",
+            |lc| lc + &y.lc,
+            |lc| lc + &y_den.lc,
+            |lc| lc + &y_num.lc,
+        );
+        Ok(Point { x, y })
+    }
+
+    /// Returns the point if `bit` is 1 and the identity point if it is 0
+    pub fn select_or_identity<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        bit: &Num,
+    ) -> Result<Point, SynthesisError> {
+        let x = self.x.mul(&mut cs.namespace(|| "x"), bit)?;
+        let y = self
+            .y
+            .add_constant(-BellmanFr::one())
+            .mul(&mut cs.namespace(|| "y"), bit)?
+            .add_constant(BellmanFr::one());
+        Ok(Point { x, y })
+    }
+
+    /// Multiplies the point by the number with the given little-endian bits
+    pub fn mul<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        bits: &[Num],
+    ) -> Result<Point, SynthesisError> {
+        let mut result = Point::constant(&jubjub::PointAffine::zero());
+        let mut curr = self.clone();
+        for (i, bit) in bits.iter().enumerate() {
+            let cs = &mut cs.namespace(|| format!("bit {}", i));
+            let selected = curr.select_or_identity(&mut cs.namespace(|| "select"), bit)?;
+            result = result.add(&mut cs.namespace(|| "add"), &selected)?;
+            if i + 1 < bits.len() {
+                curr = curr.add(&mut cs.namespace(|| "double"), &curr)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Multiplies a constant point by the number with the given little-endian bits
+    pub fn mul_constant<CS: ConstraintSystem<BellmanFr>>(
+        cs: &mut CS,
+        base: &jubjub::PointAffine,
+        bits: &[Num],
+    ) -> Result<Point, SynthesisError> {
+        let mut result = Point::constant(&jubjub::PointAffine::zero());
+        let mut curr = *base;
+        for (i, bit) in bits.iter().enumerate() {
+            // Selecting between two constant points is linear
+            let selected = Point {
+                x: bit.scale(fr(curr.0)),
+                y: bit
+                    .scale(fr(curr.1) - BellmanFr::one())
+                    .add_constant(BellmanFr::one()),
+            };
+            result = result.add(&mut cs.namespace(|| format!("bit {}", i)), &selected)?;
+            curr = curr.double();
+        }
+        Ok(result)
+    }
+
+    pub fn assert_on_curve_if<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        enabled: &Num,
+    ) -> Result<(), SynthesisError> {
+        let d = fr(*jubjub::D);
+        let x2 = self.x.mul(&mut cs.namespace(|| "x^2"), &self.x)?;
+        let y2 = self.y.mul(&mut cs.namespace(|| "y^2"), &self.y)?;
+        let x2y2 = x2.mul(&mut cs.namespace(|| "x^2y^2"), &y2)?;
+        // y^2 - x^2 = 1 + d * x^2 * y^2
+        let diff = y2
+            .sub(&x2)
+            .sub(&x2y2.scale(d))
+            .add_constant(-BellmanFr::one());
+        assert_zero_if(cs, enabled, &diff);
+        Ok(())
+    }
+
+    pub fn assert_equal_if<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        enabled: &Num,
+        other: &Point,
+    ) {
+        assert_zero_if(&mut cs.namespace(|| "x"), enabled, &self.x.sub(&other.x));
+        assert_zero_if(&mut cs.namespace(|| "y"), enabled, &self.y.sub(&other.y));
+    }
+
+    pub fn assert_not_equal_if<CS: ConstraintSystem<BellmanFr>>(
+        &self,
+        cs: &mut CS,
+        enabled: &Num,
+        other: &Point,
+    ) -> Result<(), SynthesisError> {
+        assert_not_both_zero_if(cs, enabled, &self.x.sub(&other.x), &self.y.sub(&other.y))
+    }
+}
+
+/// Circuit version of `JubJub::verify`, only checked when `enabled` is 1
+pub fn assert_signature_if<CS: ConstraintSystem<BellmanFr>>(
+    cs: &mut CS,
+    enabled: &Num,
+    pub_key: &Point,
+    msg: &Num,
+    sig_r: &Point,
+    sig_s: &Num,
+) -> Result<(), SynthesisError> {
+    pub_key.assert_on_curve_if(&mut cs.namespace(|| "pub-key on curve"), enabled)?;
+    sig_r.assert_on_curve_if(&mut cs.namespace(|| "r on curve"), enabled)?;
+
+    // h=H(R,A,M)
+    let h = poseidon(
+        &mut cs.namespace(|| "h"),
+        &[
+            sig_r.x.clone(),
+            sig_r.y.clone(),
+            pub_key.x.clone(),
+            pub_key.y.clone(),
+            msg.clone(),
+        ],
+    )?;
+    let h_bits = to_bits_strict(&mut cs.namespace(|| "h bits"), &h)?;
+    let s_bits = to_bits_strict(&mut cs.namespace(|| "s bits"), sig_s)?;
+
+    // sB == R + hA
+    let sb = Point::mul_constant(&mut cs.namespace(|| "sB"), &jubjub::BASE, &s_bits)?;
+    let ha = pub_key.mul(&mut cs.namespace(|| "hA"), &h_bits)?;
+    let r_plus_ha = sig_r.add(&mut cs.namespace(|| "R + hA"), &ha)?;
+    sb.assert_equal_if(&mut cs.namespace(|| "sB == R + hA"), enabled, &r_plus_ha);
+    Ok(())
+}
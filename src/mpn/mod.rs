@@ -0,0 +1,66 @@
+//! A built-in executor for the MPN contract. It picks MPN transactions, deposits
+//! and withdrawals from the mempool, proves them with its own circuits and submits
+//! the resulting contract updates.
+//!
+//! The proofs are only accepted by MPN contracts whose verifying keys are
+//! generated from the circuits in this module (See `MpnParams::generate`).
+
+pub mod circuits;
+pub mod executor;
+pub mod gadgets;
+pub mod prover;
+pub mod state;
+
+pub use circuits::MpnShape;
+pub use executor::{MpnExecutor, MpnUpdateCounts, MpnWork};
+pub use prover::MpnParams;
+pub use state::MpnState;
+
+use crate::blockchain::BlockchainError;
+use crate::zk::{StateManagerError, ZkStateModel};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MpnError {
+    #[error("blockchain error: {0}")]
+    BlockchainError(#[from] BlockchainError),
+    #[error("state-manager error: {0}")]
+    StateManagerError(#[from] StateManagerError),
+    #[error("synthesis error: {0}")]
+    SynthesisError(#[from] bellman::SynthesisError),
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("shape file is invalid: {0}")]
+    InvalidShape(#[from] serde_json::Error),
+    #[error("mpn transaction is invalid")]
+    InvalidTransaction,
+    #[error("parameters are not compatible with the mpn contract")]
+    IncompatibleParams,
+    #[error("local state does not match the mpn contract state")]
+    InconsistentState,
+}
+
+pub fn mpn_state_model(log4_account_capacity: u8, log4_token_capacity: u8) -> ZkStateModel {
+    ZkStateModel::List {
+        log4_size: log4_account_capacity,
+        item_type: Box::new(ZkStateModel::Struct {
+            field_types: vec![
+                ZkStateModel::Scalar, // Nonce
+                ZkStateModel::Scalar, // Pub-key X
+                ZkStateModel::Scalar, // Pub-key Y
+                ZkStateModel::List {
+                    log4_size: log4_token_capacity,
+                    item_type: Box::new(ZkStateModel::Struct {
+                        field_types: vec![
+                            ZkStateModel::Scalar, // Token-Id
+                            ZkStateModel::Scalar, // Balance
+                        ],
+                    }),
+                },
+            ],
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,153 @@
+use super::circuits::{DepositCircuit, MpnShape, PublicInputs, UpdateCircuit, WithdrawCircuit};
+use super::MpnError;
+use crate::blockchain::BlockchainConfig;
+use crate::zk::{groth16::Groth16VerifyingKey, ZkContract, ZkProof, ZkVerifierKey};
+use bellman::groth16::{create_random_proof, generate_random_parameters, Parameters};
+use bellman::Circuit;
+use bls12_381::{Bls12, Scalar as BellmanFr};
+use rand::RngCore;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+const SHAPE_FILE: &str = "shape.json";
+const UPDATE_PARAMS_FILE: &str = "update.params";
+const DEPOSIT_PARAMS_FILE: &str = "deposit.params";
+const WITHDRAW_PARAMS_FILE: &str = "withdraw.params";
+
+/// Groth16 proving parameters of the MPN circuits
+pub struct MpnParams {
+    pub shape: MpnShape,
+    update: Parameters<Bls12>,
+    deposit: Parameters<Bls12>,
+    withdraw: Parameters<Bls12>,
+}
+
+fn read_params(path: &Path) -> Result<Parameters<Bls12>, MpnError> {
+    Ok(Parameters::read(BufReader::new(File::open(path)?), false)?)
+}
+
+fn write_params(params: &Parameters<Bls12>, path: &Path) -> Result<(), MpnError> {
+    params.write(BufWriter::new(File::create(path)?))?;
+    Ok(())
+}
+
+fn prove<C: Circuit<BellmanFr>>(
+    params: &Parameters<Bls12>,
+    circuit: C,
+) -> Result<ZkProof, MpnError> {
+    let proof = create_random_proof(circuit, params, &mut rand::thread_rng())?;
+    Ok(ZkProof::Groth16(Box::new(proof.into())))
+}
+
+fn verifier_key(params: &Parameters<Bls12>) -> ZkVerifierKey {
+    ZkVerifierKey::Groth16(Box::new(Groth16VerifyingKey::from(params.vk.clone())))
+}
+
+impl MpnParams {
+    /// Runs a fresh setup for the circuits. The randomness used is not thrown away
+    /// in a verifiable way, so it is only fit for test/development networks.
+    pub fn generate<R: RngCore>(shape: MpnShape, rng: &mut R) -> Result<Self, MpnError> {
+        let inputs = PublicInputs::default();
+        Ok(Self {
+            shape,
+            update: generate_random_parameters(
+                UpdateCircuit {
+                    shape,
+                    inputs,
+                    fee_token: Default::default(),
+                    transfers: vec![],
+                },
+                rng,
+            )?,
+            deposit: generate_random_parameters(
+                DepositCircuit {
+                    shape,
+                    inputs,
+                    deposits: vec![],
+                },
+                rng,
+            )?,
+            withdraw: generate_random_parameters(
+                WithdrawCircuit {
+                    shape,
+                    inputs,
+                    withdraws: vec![],
+                },
+                rng,
+            )?,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, MpnError> {
+        let dir = dir.as_ref();
+        Ok(Self {
+            shape: serde_json::from_reader(BufReader::new(File::open(dir.join(SHAPE_FILE))?))?,
+            update: read_params(&dir.join(UPDATE_PARAMS_FILE))?,
+            deposit: read_params(&dir.join(DEPOSIT_PARAMS_FILE))?,
+            withdraw: read_params(&dir.join(WITHDRAW_PARAMS_FILE))?,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<(), MpnError> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        serde_json::to_writer_pretty(
+            BufWriter::new(File::create(dir.join(SHAPE_FILE))?),
+            &self.shape,
+        )?;
+        write_params(&self.update, &dir.join(UPDATE_PARAMS_FILE))?;
+        write_params(&self.deposit, &dir.join(DEPOSIT_PARAMS_FILE))?;
+        write_params(&self.withdraw, &dir.join(WITHDRAW_PARAMS_FILE))?;
+        Ok(())
+    }
+
+    pub fn update_vk(&self) -> ZkVerifierKey {
+        verifier_key(&self.update)
+    }
+    pub fn deposit_vk(&self) -> ZkVerifierKey {
+        verifier_key(&self.deposit)
+    }
+    pub fn withdraw_vk(&self) -> ZkVerifierKey {
+        verifier_key(&self.withdraw)
+    }
+
+    /// Checks if proofs generated with the parameters are accepted by the contract,
+    /// under the rules of the chain at the given height
+    pub fn is_compatible(
+        &self,
+        contract: &ZkContract,
+        config: &BlockchainConfig,
+        height: u64,
+    ) -> bool {
+        let shape = self.shape;
+        let log4_account_capacity = config.mpn_log4_account_capacity;
+        let features = config.mpn_circuit_features_at(height);
+        shape.log4_account_capacity == log4_account_capacity
+            && shape.account_slot_choices == features.account_slot_choices
+            && shape.reuse_empty_token_slots == features.reuse_empty_token_slots
+            && contract.state_model
+                == super::mpn_state_model(log4_account_capacity, shape.log4_token_capacity)
+            && contract.functions.first().map(|f| &f.verifier_key) == Some(&self.update_vk())
+            && contract
+                .deposit_functions
+                .first()
+                .map(|f| (&f.verifier_key, f.log4_payment_capacity))
+                == Some((&self.deposit_vk(), shape.log4_payment_capacity))
+            && contract
+                .withdraw_functions
+                .first()
+                .map(|f| (&f.verifier_key, f.log4_payment_capacity))
+                == Some((&self.withdraw_vk(), shape.log4_payment_capacity))
+    }
+
+    pub fn prove_update(&self, circuit: UpdateCircuit) -> Result<ZkProof, MpnError> {
+        prove(&self.update, circuit)
+    }
+    pub fn prove_deposit(&self, circuit: DepositCircuit) -> Result<ZkProof, MpnError> {
+        prove(&self.deposit, circuit)
+    }
+    pub fn prove_withdraw(&self, circuit: WithdrawCircuit) -> Result<ZkProof, MpnError> {
+        prove(&self.withdraw, circuit)
+    }
+}
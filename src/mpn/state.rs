@@ -0,0 +1,344 @@
+use super::circuits::{
+    AccountWitness, DepositWitness, MpnShape, SkippedSlotWitness, TokenWitness, TransferWitness,
+    WithdrawWitness,
+};
+use super::{mpn_state_model, MpnError};
+use crate::core::{Money, MpnDeposit, MpnWithdraw, TokenId, ZkHasher};
+use crate::crypto::{jubjub, DeriveMpnAccountIndex, ZkSignatureScheme};
+use crate::zk::{
+    locate_mpn_account, MpnAccount, MpnTransaction, ZkCompressedState, ZkDataLocator, ZkDeltaPairs,
+    ZkHasher as _, ZkScalar, ZkStateBuilder,
+};
+use std::collections::HashMap;
+
+/// A copy of the MPN contract state, which is updated as the executor applies
+/// transactions and gives the witnesses needed for proving them.
+pub struct MpnState {
+    shape: MpnShape,
+    builder: ZkStateBuilder<ZkHasher>,
+    accounts: HashMap<u64, MpnAccount>,
+    delta: ZkDeltaPairs,
+}
+
+impl MpnState {
+    pub fn new(shape: MpnShape) -> Self {
+        Self {
+            shape,
+            builder: ZkStateBuilder::new(mpn_state_model(
+                shape.log4_account_capacity,
+                shape.log4_token_capacity,
+            )),
+            accounts: HashMap::new(),
+            delta: ZkDeltaPairs::default(),
+        }
+    }
+
+    pub fn shape(&self) -> MpnShape {
+        self.shape
+    }
+
+    pub fn root(&self) -> Result<ZkCompressedState, MpnError> {
+        Ok(self.builder.root()?)
+    }
+
+    pub fn account(&self, index: u64) -> MpnAccount {
+        self.accounts.get(&index).cloned().unwrap_or_default()
+    }
+
+    /// Loads an account from the chain, without recording it as a change
+    pub fn load_account(&mut self, index: u64, acc: MpnAccount) -> Result<(), MpnError> {
+        self.set_account(index, acc)?;
+        self.delta = ZkDeltaPairs::default();
+        Ok(())
+    }
+
+    /// Changes applied since the last call, as a delta of the contract state
+    pub fn take_delta(&mut self) -> ZkDeltaPairs {
+        std::mem::take(&mut self.delta)
+    }
+
+    fn set_account(&mut self, index: u64, acc: MpnAccount) -> Result<(), MpnError> {
+        let mut pairs = vec![
+            (ZkDataLocator(vec![index, 0]), ZkScalar::from(acc.nonce)),
+            (ZkDataLocator(vec![index, 1]), acc.address.0),
+            (ZkDataLocator(vec![index, 2]), acc.address.1),
+        ];
+        for (ind, money) in acc.tokens.iter() {
+            pairs.push((
+                ZkDataLocator(vec![index, 3, *ind, 0]),
+                money.token_id.into(),
+            ));
+            pairs.push((
+                ZkDataLocator(vec![index, 3, *ind, 1]),
+                ZkScalar::from(money.amount),
+            ));
+        }
+        let delta = ZkDeltaPairs(pairs.into_iter().map(|(k, v)| (k, Some(v))).collect());
+        self.builder.batch_set(&delta)?;
+        self.delta.0.extend(delta.0);
+        self.accounts.insert(index, acc);
+        Ok(())
+    }
+
+    fn account_witness(
+        &mut self,
+        pub_key: &jubjub::PublicKey,
+        (slot_choice, index): (u8, u64),
+    ) -> Result<AccountWitness, MpnError> {
+        let acc = self.account(index);
+        // The circuits check that the candidate slots before the chosen one are
+        // taken by other accounts
+        let mut skipped = Vec::new();
+        for choice in 0..slot_choice {
+            let index = pub_key.mpn_account_slot(self.shape.log4_account_capacity, choice);
+            let acc = self.account(index);
+            skipped.push(SkippedSlotWitness {
+                nonce: acc.nonce,
+                address: acc.address,
+                tokens_root: self.builder.get(ZkDataLocator(vec![index, 3]))?,
+                proof: self.builder.prove(ZkDataLocator(vec![]), index)?,
+            });
+        }
+        Ok(AccountWitness {
+            slot_choice,
+            nonce: acc.nonce,
+            address: acc.address,
+            tokens_root: self.builder.get(ZkDataLocator(vec![index, 3]))?,
+            proof: self.builder.prove(ZkDataLocator(vec![]), index)?,
+            skipped,
+        })
+    }
+
+    fn token_witness(&mut self, index: u64, token_index: u64) -> Result<TokenWitness, MpnError> {
+        let money = self.account(index).tokens.get(&token_index).cloned();
+        Ok(TokenWitness {
+            index: token_index,
+            token_id: money.map(|m| m.token_id.into()).unwrap_or_default(),
+            balance: money.map(|m| m.amount.into()).unwrap_or_default(),
+            proof: self
+                .builder
+                .prove(ZkDataLocator(vec![index, 3]), token_index)?,
+        })
+    }
+
+    fn set_token(&mut self, index: u64, token_index: u64, money: Money) -> Result<(), MpnError> {
+        let mut acc = self.account(index);
+        acc.tokens.insert(token_index, money);
+        self.set_account(index, acc)
+    }
+
+    fn token_capacity(&self) -> u64 {
+        1 << (2 * self.shape.log4_token_capacity)
+    }
+
+    /// Slot choice and index of the account of the key, located the same way as
+    /// the chain does
+    fn slot_of(&self, pub_key: &jubjub::PublicKey) -> Result<(u8, u64), MpnError> {
+        locate_mpn_account(
+            pub_key,
+            self.shape.log4_account_capacity,
+            self.shape.account_slot_choices,
+            |index| Ok::<_, MpnError>(self.account(index)),
+        )?
+        .ok_or(MpnError::InvalidTransaction)
+    }
+
+    /// Money in the token slot which is about to receive `token_id`
+    fn receiving_slot(
+        &self,
+        acc: &MpnAccount,
+        index: u64,
+        token_id: TokenId,
+    ) -> Result<Money, MpnError> {
+        if !acc.token_slot_accepts(index, token_id, self.shape.reuse_empty_token_slots) {
+            return Err(MpnError::InvalidTransaction);
+        }
+        Ok(acc
+            .tokens
+            .get(&index)
+            .filter(|money| money.token_id == token_id)
+            .cloned()
+            .unwrap_or_else(|| Money::new(token_id, 0)))
+    }
+
+    /// Applies an MPN transaction, returning the witness needed to prove it
+    pub fn apply_transfer(&mut self, tx: &MpnTransaction) -> Result<TransferWitness, MpnError> {
+        let src_slot = self.slot_of(&tx.src_pub_key)?;
+        let dst_slot = self.slot_of(&tx.dst_pub_key)?;
+        let (src_index, dst_index) = (src_slot.1, dst_slot.1);
+        let src = self.account(src_index);
+        let dst = self.account(dst_index);
+        if src_index == dst_index
+            || tx.src_token_index >= self.token_capacity()
+            || tx.src_fee_token_index >= self.token_capacity()
+            || tx.dst_token_index >= self.token_capacity()
+            || !tx.src_pub_key.is_on_curve()
+            || !tx.dst_pub_key.is_on_curve()
+            || src.address != tx.src_pub_key.decompress()
+            || (dst.address.is_on_curve() && dst.address != tx.dst_pub_key.decompress())
+            || tx.nonce != src.nonce
+            || !tx.verify()
+        {
+            return Err(MpnError::InvalidTransaction);
+        }
+
+        let mut src_money = *src
+            .tokens
+            .get(&tx.src_token_index)
+            .ok_or(MpnError::InvalidTransaction)?;
+        if src_money.token_id != tx.amount.token_id || src_money.amount < tx.amount.amount {
+            return Err(MpnError::InvalidTransaction);
+        }
+        src_money.amount -= tx.amount.amount;
+        let mut src_fee_money = if tx.src_fee_token_index == tx.src_token_index {
+            src_money
+        } else {
+            *src.tokens
+                .get(&tx.src_fee_token_index)
+                .ok_or(MpnError::InvalidTransaction)?
+        };
+        if src_fee_money.token_id != tx.fee.token_id || src_fee_money.amount < tx.fee.amount {
+            return Err(MpnError::InvalidTransaction);
+        }
+        src_fee_money.amount -= tx.fee.amount;
+        let mut dst_money = self.receiving_slot(&dst, tx.dst_token_index, tx.amount.token_id)?;
+        dst_money.amount = u64::from(dst_money.amount)
+            .checked_add(tx.amount.amount.into())
+            .ok_or(MpnError::InvalidTransaction)?
+            .into();
+
+        let src_witness = self.account_witness(&tx.src_pub_key, src_slot)?;
+        let src_amount_witness = self.token_witness(src_index, tx.src_token_index)?;
+        self.set_token(src_index, tx.src_token_index, src_money)?;
+        let src_fee_witness = self.token_witness(src_index, tx.src_fee_token_index)?;
+        self.set_token(src_index, tx.src_fee_token_index, src_fee_money)?;
+        let mut new_src = self.account(src_index);
+        new_src.nonce += 1;
+        self.set_account(src_index, new_src)?;
+
+        let dst_witness = self.account_witness(&tx.dst_pub_key, dst_slot)?;
+        let dst_amount_witness = self.token_witness(dst_index, tx.dst_token_index)?;
+        let mut new_dst = self.account(dst_index);
+        new_dst.address = tx.dst_pub_key.decompress();
+        new_dst.tokens.insert(tx.dst_token_index, dst_money);
+        self.set_account(dst_index, new_dst)?;
+
+        Ok(TransferWitness {
+            tx: tx.clone(),
+            src: src_witness,
+            src_amount: src_amount_witness,
+            src_fee: src_fee_witness,
+            dst: dst_witness,
+            dst_amount: dst_amount_witness,
+        })
+    }
+
+    /// Applies a deposit to the MPN, returning the witness needed to prove it
+    pub fn apply_deposit(&mut self, deposit: &MpnDeposit) -> Result<DepositWitness, MpnError> {
+        let slot = self.slot_of(&deposit.zk_address)?;
+        let index = slot.1;
+        let acc = self.account(index);
+        let pub_key = deposit.zk_address.decompress();
+        let amount = deposit.payment.amount;
+        if deposit.payment.calldata != ZkHasher::hash(&[pub_key.0, pub_key.1])
+            || deposit.zk_token_index >= self.token_capacity()
+            || amount.token_id == TokenId::Null
+            || !deposit.zk_address.is_on_curve()
+            || (acc.address.is_on_curve() && acc.address != pub_key)
+        {
+            return Err(MpnError::InvalidTransaction);
+        }
+        let mut money = self.receiving_slot(&acc, deposit.zk_token_index, amount.token_id)?;
+        money.amount = u64::from(money.amount)
+            .checked_add(amount.amount.into())
+            .ok_or(MpnError::InvalidTransaction)?
+            .into();
+
+        let account_witness = self.account_witness(&deposit.zk_address, slot)?;
+        let slot_witness = self.token_witness(index, deposit.zk_token_index)?;
+        let mut new_acc = acc;
+        new_acc.address = pub_key;
+        new_acc.tokens.insert(deposit.zk_token_index, money);
+        self.set_account(index, new_acc)?;
+
+        Ok(DepositWitness {
+            pub_key,
+            token_id: amount.token_id.into(),
+            amount: amount.amount.into(),
+            account: account_witness,
+            slot: slot_witness,
+        })
+    }
+
+    /// Applies a withdrawal from the MPN, returning the witness needed to prove it
+    pub fn apply_withdraw(&mut self, withdraw: &MpnWithdraw) -> Result<WithdrawWitness, MpnError> {
+        let slot = self.slot_of(&withdraw.zk_address)?;
+        let index = slot.1;
+        let acc = self.account(index);
+        let pub_key = withdraw.zk_address.decompress();
+        let payment = &withdraw.payment;
+        let fingerprint = payment.fingerprint();
+        let calldata = ZkHasher::hash(&[
+            pub_key.0,
+            pub_key.1,
+            ZkScalar::from(withdraw.zk_nonce),
+            withdraw.zk_sig.r.0,
+            withdraw.zk_sig.r.1,
+            withdraw.zk_sig.s,
+        ]);
+        let msg = ZkHasher::hash(&[fingerprint, ZkScalar::from(withdraw.zk_nonce)]);
+        // Unlike the chain, the circuit also requires the account to be owned by
+        // the withdrawer
+        if payment.calldata != calldata
+            || withdraw.zk_token_index >= self.token_capacity()
+            || withdraw.zk_fee_token_index >= self.token_capacity()
+            || withdraw.zk_nonce != acc.nonce
+            || acc.address != pub_key
+            || !crate::core::ZkSigner::verify(&withdraw.zk_address, msg, &withdraw.zk_sig)
+        {
+            return Err(MpnError::InvalidTransaction);
+        }
+        let mut money = *acc
+            .tokens
+            .get(&withdraw.zk_token_index)
+            .ok_or(MpnError::InvalidTransaction)?;
+        if money.token_id != payment.amount.token_id || money.amount < payment.amount.amount {
+            return Err(MpnError::InvalidTransaction);
+        }
+        money.amount -= payment.amount.amount;
+        let mut fee_money = if withdraw.zk_fee_token_index == withdraw.zk_token_index {
+            money
+        } else {
+            *acc.tokens
+                .get(&withdraw.zk_fee_token_index)
+                .ok_or(MpnError::InvalidTransaction)?
+        };
+        if fee_money.token_id != payment.fee.token_id || fee_money.amount < payment.fee.amount {
+            return Err(MpnError::InvalidTransaction);
+        }
+        fee_money.amount -= payment.fee.amount;
+
+        let account_witness = self.account_witness(&withdraw.zk_address, slot)?;
+        let amount_witness = self.token_witness(index, withdraw.zk_token_index)?;
+        self.set_token(index, withdraw.zk_token_index, money)?;
+        let fee_witness = self.token_witness(index, withdraw.zk_fee_token_index)?;
+        self.set_token(index, withdraw.zk_fee_token_index, fee_money)?;
+        let mut new_acc = self.account(index);
+        new_acc.nonce += 1;
+        self.set_account(index, new_acc)?;
+
+        Ok(WithdrawWitness {
+            pub_key,
+            nonce: withdraw.zk_nonce,
+            sig: withdraw.zk_sig.clone(),
+            token_id: payment.amount.token_id.into(),
+            amount: payment.amount.amount.into(),
+            fee_token_id: payment.fee.token_id.into(),
+            fee: payment.fee.amount.into(),
+            fingerprint,
+            account: account_witness,
+            amount_slot: amount_witness,
+            fee_slot: fee_witness,
+        })
+    }
+}
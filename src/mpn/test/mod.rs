@@ -0,0 +1,637 @@
+use super::circuits::{DepositCircuit, PublicInputs};
+use super::gadgets::*;
+use super::*;
+use crate::config::blockchain::get_test_blockchain_config;
+use crate::core::{ContractId, ContractUpdate, Money, MpnAddress, TokenId, ZkHasher};
+use crate::crypto::{jubjub, DeriveMpnAccountIndex, ZkSignatureScheme};
+use crate::wallet::TxBuilder;
+use crate::zk::{ZkDataLocator, ZkDeltaPairs, ZkStateBuilder};
+use crate::zk::{ZkHasher as ZkHasherTrait, ZkScalar};
+use bellman::gadgets::test::TestConstraintSystem;
+use bellman::{Circuit, ConstraintSystem};
+use bls12_381::Scalar as BellmanFr;
+
+#[test]
+fn test_poseidon_gadget() {
+    for arity in 1..8 {
+        let vals = (0..arity)
+            .map(|i| ZkScalar::from(i as u64 + 123))
+            .collect::<Vec<_>>();
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let nums = vals
+            .iter()
+            .enumerate()
+            .map(|(i, v)| Num::alloc(&mut cs.namespace(|| format!("val {}", i)), (*v).into()))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let out = poseidon(&mut cs, &nums).unwrap();
+        assert!(cs.is_satisfied());
+        assert_eq!(ZkScalar::from(out.value()), ZkHasher::hash(&vals));
+    }
+}
+
+#[test]
+fn test_signature_gadget() {
+    let (pk, sk) = jubjub::JubJub::<ZkHasher>::generate_keys(b"ABC");
+    let msg = ZkScalar::from(123456);
+    let sig = jubjub::JubJub::<ZkHasher>::sign(&sk, msg);
+    for (msg, expected) in [(msg, true), (ZkScalar::from(123457), false)] {
+        let mut cs = TestConstraintSystem::<BellmanFr>::new();
+        let enabled = Num::alloc_bit(&mut cs.namespace(|| "enabled"), true).unwrap();
+        let pub_key = Point::alloc(&mut cs.namespace(|| "pk"), &pk.decompress()).unwrap();
+        let msg = Num::alloc(&mut cs.namespace(|| "msg"), msg.into()).unwrap();
+        let r = Point::alloc(&mut cs.namespace(|| "r"), &sig.r).unwrap();
+        let s = Num::alloc(&mut cs.namespace(|| "s"), sig.s.into()).unwrap();
+        assert_signature_if(&mut cs, &enabled, &pub_key, &msg, &r, &s).unwrap();
+        assert_eq!(cs.is_satisfied(), expected);
+    }
+}
+
+const TEST_SHAPE: MpnShape = MpnShape {
+    log4_account_capacity: 2,
+    log4_token_capacity: 1,
+    log4_payment_capacity: 1,
+    update_batch_size: 2,
+    account_slot_choices: 1,
+    reuse_empty_token_slots: false,
+};
+
+fn is_satisfied<C: Circuit<BellmanFr>>(circuit: C) -> bool {
+    let mut cs = TestConstraintSystem::<BellmanFr>::new();
+    circuit.synthesize(&mut cs).unwrap();
+    cs.is_satisfied()
+}
+
+fn check_inputs(inputs: &PublicInputs, height: u64, prev: ZkScalar, next: ZkScalar) {
+    assert_eq!(inputs.height, height);
+    assert_eq!(inputs.state, prev);
+    assert_eq!(inputs.next_state, next);
+}
+
+fn mpn_address(builder: &TxBuilder) -> MpnAddress {
+    MpnAddress {
+        pub_key: builder.get_zk_address(),
+    }
+}
+
+#[test]
+fn test_executor_updates_are_provable() {
+    let cid: ContractId = get_test_blockchain_config().mpn_contract_id;
+    let executor_addr = TxBuilder::new(&Vec::from("EXECUTOR")).get_address();
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let bob = TxBuilder::new(&Vec::from("CBA"));
+    assert_ne!(
+        mpn_address(&alice).account_index(TEST_SHAPE.log4_account_capacity),
+        mpn_address(&bob).account_index(TEST_SHAPE.log4_account_capacity)
+    );
+
+    let deposits = vec![
+        alice.deposit_mpn(
+            "".into(),
+            cid,
+            mpn_address(&alice),
+            0,
+            1,
+            Money::ziesha(100),
+            Money::ziesha(0),
+        ),
+        bob.deposit_mpn(
+            "".into(),
+            cid,
+            mpn_address(&bob),
+            0,
+            1,
+            Money::ziesha(50),
+            Money::ziesha(0),
+        ),
+        // Null token can not be deposited
+        bob.deposit_mpn(
+            "".into(),
+            cid,
+            mpn_address(&bob),
+            1,
+            2,
+            Money::new(TokenId::Null, 10),
+            Money::ziesha(0),
+        ),
+    ];
+    let withdraws = vec![bob.withdraw_mpn(
+        "".into(),
+        cid,
+        0,
+        0,
+        Money::ziesha(20),
+        0,
+        Money::ziesha(1),
+        bob.get_address(),
+    )];
+    let updates = vec![
+        alice.create_mpn_transaction(
+            0,
+            mpn_address(&bob),
+            0,
+            Money::ziesha(10),
+            0,
+            Money::ziesha(2),
+            0,
+        ),
+        // Invalid nonce
+        alice.create_mpn_transaction(
+            0,
+            mpn_address(&bob),
+            0,
+            Money::ziesha(10),
+            0,
+            Money::ziesha(2),
+            5,
+        ),
+    ];
+
+    let initial = MpnState::new(TEST_SHAPE).root().unwrap();
+    let mut executor = MpnExecutor::with_state(MpnState::new(TEST_SHAPE));
+    let (works, delta) = executor
+        .plan(
+            cid,
+            7,
+            MpnUpdateCounts {
+                function_calls: 2,
+                deposits: 1,
+                withdraws: 1,
+            },
+            &executor_addr,
+            deposits,
+            withdraws,
+            updates,
+        )
+        .unwrap();
+    assert_eq!(works.len(), 4);
+
+    let mut prev = initial.state_hash;
+    for work in works {
+        match work {
+            MpnWork::Deposit {
+                deposits,
+                next_state,
+                circuit,
+            } => {
+                assert_eq!(deposits.len(), 2);
+                check_inputs(&circuit.inputs, 7, prev, next_state.state_hash);
+                prev = next_state.state_hash;
+                let mut tampered = circuit.clone();
+                tampered.deposits[0].amount += 1;
+                assert!(!is_satisfied(tampered));
+                assert!(is_satisfied(circuit));
+            }
+            MpnWork::Withdraw {
+                withdraws,
+                next_state,
+                circuit,
+            } => {
+                assert_eq!(withdraws.len(), 1);
+                check_inputs(&circuit.inputs, 7, prev, next_state.state_hash);
+                prev = next_state.state_hash;
+                let mut tampered = circuit.clone();
+                tampered.withdraws[0].nonce += 1;
+                assert!(!is_satisfied(tampered));
+                assert!(is_satisfied(circuit));
+            }
+            MpnWork::Update {
+                fee,
+                next_state,
+                circuit,
+            } => {
+                check_inputs(&circuit.inputs, 7, prev, next_state.state_hash);
+                prev = next_state.state_hash;
+                if circuit.transfers.is_empty() {
+                    assert_eq!(fee, Money::ziesha(0));
+                } else {
+                    assert_eq!(fee, Money::ziesha(2));
+                    assert_eq!(
+                        circuit.inputs.aux_data,
+                        ZkHasher::hash(&[TokenId::Ziesha.into(), ZkScalar::from(2)])
+                    );
+                    let mut tampered = circuit.clone();
+                    tampered.inputs.next_state = initial.state_hash;
+                    assert!(!is_satisfied(tampered));
+                }
+                assert!(is_satisfied(circuit));
+            }
+        }
+    }
+
+    // The delta brings the contract state to the final state of the updates
+    let mut builder = ZkStateBuilder::<ZkHasher>::new(mpn_state_model(
+        TEST_SHAPE.log4_account_capacity,
+        TEST_SHAPE.log4_token_capacity,
+    ));
+    builder.batch_set(&delta).unwrap();
+    assert_eq!(builder.root().unwrap().state_hash, prev);
+
+    let bob_index = mpn_address(&bob).account_index(TEST_SHAPE.log4_account_capacity);
+    assert_eq!(
+        builder
+            .get(ZkDataLocator(vec![bob_index, 3, 0, 1]))
+            .unwrap(),
+        ZkScalar::from(50 - 20 - 1 + 10)
+    );
+}
+
+#[test]
+fn test_colliding_accounts_take_secondary_slots() {
+    let shape = MpnShape {
+        log4_account_capacity: 1,
+        log4_token_capacity: 1,
+        log4_payment_capacity: 1,
+        update_batch_size: 1,
+        account_slot_choices: 2,
+        reuse_empty_token_slots: false,
+    };
+    let cid: ContractId = get_test_blockchain_config().mpn_contract_id;
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let slot = |b: &TxBuilder, choice| {
+        b.get_zk_address()
+            .mpn_account_slot(shape.log4_account_capacity, choice)
+    };
+    let keys = (0..64)
+        .map(|i| TxBuilder::new(format!("KEY{}", i).as_bytes()))
+        .collect::<Vec<_>>();
+    let first = &keys[0];
+    let second = keys
+        .iter()
+        .skip(1)
+        .find(|k| slot(k, 0) == slot(first, 0) && slot(k, 1) != slot(first, 0))
+        .unwrap();
+    let third = keys
+        .iter()
+        .skip(1)
+        .filter(|k| k.get_zk_address() != second.get_zk_address())
+        .find(|k| slot(k, 0) == slot(first, 0) && slot(k, 1) == slot(second, 1))
+        .unwrap();
+    let deposits = [first, second, third]
+        .into_iter()
+        .enumerate()
+        .map(|(i, to)| {
+            alice.deposit_mpn(
+                "".into(),
+                cid,
+                mpn_address(to),
+                0,
+                i as u32 + 1,
+                Money::ziesha(10),
+                Money::ziesha(0),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut executor = MpnExecutor::with_state(MpnState::new(shape));
+    let (works, delta) = executor
+        .plan(
+            cid,
+            5,
+            MpnUpdateCounts::default(),
+            &TxBuilder::new(&Vec::from("EXECUTOR")).get_address(),
+            deposits,
+            vec![],
+            vec![],
+        )
+        .unwrap();
+    assert_eq!(works.len(), 1);
+    match &works[0] {
+        MpnWork::Deposit {
+            deposits, circuit, ..
+        } => {
+            // The third key finds both of its slots taken
+            assert_eq!(deposits.len(), 2);
+            assert_eq!(circuit.deposits[1].account.slot_choice, 1);
+            let mut tampered = circuit.clone();
+            tampered.deposits[1].account.slot_choice = 0;
+            assert!(!is_satisfied(tampered));
+            assert!(is_satisfied(circuit.clone()));
+        }
+        _ => panic!(),
+    }
+
+    let mut builder = ZkStateBuilder::<ZkHasher>::new(mpn_state_model(
+        shape.log4_account_capacity,
+        shape.log4_token_capacity,
+    ));
+    builder.batch_set(&delta).unwrap();
+    for (key, index) in [(first, slot(first, 0)), (second, slot(second, 1))] {
+        assert_eq!(
+            builder.get(ZkDataLocator(vec![index, 1])).unwrap(),
+            key.get_zk_address().decompress().0
+        );
+    }
+}
+
+#[test]
+fn test_accounts_cannot_skip_empty_slots() {
+    let shape = MpnShape {
+        log4_account_capacity: 1,
+        log4_token_capacity: 1,
+        log4_payment_capacity: 1,
+        update_batch_size: 1,
+        account_slot_choices: 2,
+        reuse_empty_token_slots: false,
+    };
+    let cid: ContractId = get_test_blockchain_config().mpn_contract_id;
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let key = (0..64)
+        .map(|i| TxBuilder::new(format!("KEY{}", i).as_bytes()))
+        .find(|k| {
+            let pub_key = k.get_zk_address();
+            pub_key.mpn_account_slot(shape.log4_account_capacity, 0)
+                != pub_key.mpn_account_slot(shape.log4_account_capacity, 1)
+        })
+        .unwrap();
+    let pub_key = key.get_zk_address();
+    let deposit = alice.deposit_mpn(
+        "".into(),
+        cid,
+        mpn_address(&key),
+        0,
+        1,
+        Money::ziesha(10),
+        Money::ziesha(0),
+    );
+
+    let mut executor = MpnExecutor::with_state(MpnState::new(shape));
+    let (works, delta) = executor
+        .plan(
+            cid,
+            5,
+            MpnUpdateCounts::default(),
+            &TxBuilder::new(&Vec::from("EXECUTOR")).get_address(),
+            vec![deposit],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+    let circuit = match &works[0] {
+        MpnWork::Deposit { circuit, .. } => circuit.clone(),
+        _ => panic!(),
+    };
+    assert_eq!(circuit.deposits[0].account.slot_choice, 0);
+    assert!(is_satisfied(circuit.clone()));
+
+    // Put the account in its second slot while the first one is empty. All the
+    // slots of an empty tree share the same proof.
+    let (first, second) = (
+        pub_key.mpn_account_slot(shape.log4_account_capacity, 0),
+        pub_key.mpn_account_slot(shape.log4_account_capacity, 1),
+    );
+    let mut builder = ZkStateBuilder::<ZkHasher>::new(mpn_state_model(
+        shape.log4_account_capacity,
+        shape.log4_token_capacity,
+    ));
+    builder
+        .batch_set(&ZkDeltaPairs(
+            delta
+                .0
+                .into_iter()
+                .map(|(mut loc, val)| {
+                    assert_eq!(loc.0[0], first);
+                    loc.0[0] = second;
+                    (loc, val)
+                })
+                .collect(),
+        ))
+        .unwrap();
+    let mut tampered = circuit;
+    tampered.inputs.next_state = builder.root().unwrap().state_hash;
+    tampered.deposits[0].account.slot_choice = 1;
+    assert!(!is_satisfied(tampered));
+}
+
+#[test]
+fn test_empty_token_slots_are_reused() {
+    let cid: ContractId = get_test_blockchain_config().mpn_contract_id;
+    let executor_addr = TxBuilder::new(&Vec::from("EXECUTOR")).get_address();
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let bob = TxBuilder::new(&Vec::from("CBA"));
+    let custom = TokenId::Custom(ZkScalar::from(123));
+    let counts = MpnUpdateCounts {
+        function_calls: 1,
+        deposits: 0,
+        withdraws: 0,
+    };
+    for reuse_empty_token_slots in [false, true] {
+        let shape = MpnShape {
+            reuse_empty_token_slots,
+            ..TEST_SHAPE
+        };
+        let mut executor = MpnExecutor::with_state(MpnState::new(shape));
+        let deposit = |from: &TxBuilder, token_index, nonce, amount| {
+            from.deposit_mpn(
+                "".into(),
+                cid,
+                mpn_address(from),
+                token_index,
+                nonce,
+                amount,
+                Money::ziesha(0),
+            )
+        };
+        // Alice sends all of her custom tokens away, emptying her slot #1
+        let (works, _) = executor
+            .plan(
+                cid,
+                7,
+                counts,
+                &executor_addr,
+                vec![
+                    deposit(&alice, 0, 1, Money::ziesha(100)),
+                    deposit(&alice, 1, 2, Money::new(custom, 10)),
+                    deposit(&bob, 0, 1, Money::ziesha(100)),
+                ],
+                vec![],
+                vec![alice.create_mpn_transaction(
+                    1,
+                    mpn_address(&bob),
+                    1,
+                    Money::new(custom, 10),
+                    0,
+                    Money::ziesha(1),
+                    0,
+                )],
+            )
+            .unwrap();
+        assert_eq!(works.len(), 2);
+
+        let (works, _) = executor
+            .plan(
+                cid,
+                8,
+                counts,
+                &executor_addr,
+                vec![],
+                vec![],
+                vec![bob.create_mpn_transaction(
+                    0,
+                    mpn_address(&alice),
+                    1,
+                    Money::ziesha(5),
+                    0,
+                    Money::ziesha(1),
+                    0,
+                )],
+            )
+            .unwrap();
+        match &works[..] {
+            [MpnWork::Update { circuit, .. }] => {
+                assert_eq!(
+                    circuit.transfers.len(),
+                    usize::from(reuse_empty_token_slots)
+                );
+                assert!(is_satisfied(circuit.clone()));
+            }
+            _ => panic!(),
+        }
+    }
+}
+
+#[test]
+fn test_deposit_proof_is_applied() {
+    use crate::blockchain::{Blockchain, FeeRates, KvStoreChain};
+    use crate::consensus::pow::Difficulty;
+    use crate::core::{Signature, Transaction, TransactionAndDelta, TransactionData};
+    use crate::zk::groth16::Groth16VerifyingKey;
+    use bellman::groth16::{create_random_proof, generate_random_parameters};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let shape = MpnShape {
+        log4_account_capacity: 1,
+        log4_token_capacity: 1,
+        log4_payment_capacity: 0,
+        update_batch_size: 1,
+        account_slot_choices: 1,
+        reuse_empty_token_slots: false,
+    };
+    let mut conf = get_test_blockchain_config();
+    conf.genesis.block.header.proof_of_work.target = Difficulty(0x00ffffff);
+    conf.minimum_pow_difficulty = Difficulty(0x00ffffff);
+    let mut chain = KvStoreChain::new(crate::db::RamKvStore::new(), conf).unwrap();
+    let miner = TxBuilder::new(&Vec::from("MINER"));
+    let alice = TxBuilder::new(&Vec::from("ABC"));
+    let executor = TxBuilder::new(&Vec::from("EXECUTOR"));
+
+    // Only the deposit circuit is set up, the others are way too slow to setup
+    // in a debug build
+    let params = generate_random_parameters::<bls12_381::Bls12, _, _>(
+        DepositCircuit {
+            shape,
+            inputs: Default::default(),
+            deposits: vec![],
+        },
+        &mut StdRng::seed_from_u64(0),
+    )
+    .unwrap();
+    let state = MpnState::new(shape);
+    let create_tx = alice.create_contract(
+        "".into(),
+        crate::zk::ZkContract {
+            state_model: mpn_state_model(shape.log4_account_capacity, shape.log4_token_capacity),
+            initial_state: state.root().unwrap(),
+            deposit_functions: vec![crate::zk::ZkMultiInputVerifierKey {
+                verifier_key: crate::zk::ZkVerifierKey::Groth16(Box::new(
+                    Groth16VerifyingKey::from(params.vk.clone()),
+                )),
+                log4_payment_capacity: shape.log4_payment_capacity,
+            }],
+            withdraw_functions: vec![],
+            functions: vec![],
+        },
+        Default::default(),
+        None,
+        Money::ziesha(0),
+        1,
+    );
+    let cid = ContractId::new(&create_tx.tx);
+    let mine = |chain: &mut KvStoreChain<_>, timestamp, txs: &[TransactionAndDelta]| {
+        let mut draft = chain
+            .draft_block(timestamp, txs, &miner, &FeeRates::default(), false)
+            .unwrap()
+            .unwrap();
+        let pow_key = chain.pow_key(draft.block.header.number).unwrap();
+        while !draft.block.header.meets_target(&pow_key) {
+            draft.block.header.proof_of_work.nonce += 1;
+        }
+        chain
+            .extend(draft.block.header.number, &[draft.block.clone()])
+            .unwrap();
+        chain.update_states(&draft.patch).unwrap();
+        draft.block
+    };
+    mine(&mut chain, 1, &[create_tx]);
+
+    let deposit = alice.deposit_mpn(
+        "".into(),
+        cid,
+        mpn_address(&alice),
+        0,
+        2,
+        Money::ziesha(100),
+        Money::ziesha(0),
+    );
+    let mut mpn = MpnExecutor::with_state(state);
+    let (works, delta) = mpn
+        .plan(
+            cid,
+            chain.get_contract_account(cid).unwrap().height,
+            MpnUpdateCounts::default(),
+            &executor.get_address(),
+            vec![deposit],
+            vec![],
+            vec![],
+        )
+        .unwrap();
+    assert_eq!(works.len(), 1);
+    let (update, next_state) = match works.into_iter().next().unwrap() {
+        MpnWork::Deposit {
+            deposits,
+            next_state,
+            circuit,
+        } => {
+            let proof = create_random_proof(circuit, &params, &mut StdRng::seed_from_u64(1));
+            (
+                ContractUpdate::Deposit {
+                    deposit_circuit_id: 0,
+                    deposits,
+                    next_state,
+                    proof: crate::zk::ZkProof::Groth16(Box::new(proof.unwrap().into())),
+                    events: vec![],
+                },
+                next_state,
+            )
+        }
+        _ => panic!(),
+    };
+    let mut tx = Transaction {
+        memo: String::new(),
+        src: Some(executor.get_address()),
+        data: TransactionData::UpdateContract {
+            contract_id: cid,
+            updates: vec![update],
+        },
+        nonce: 1,
+        fee: Money::ziesha(0),
+        valid_after_height: None,
+        valid_until_height: None,
+        sig: Signature::Unsigned,
+    };
+    executor.sign_tx(&mut tx);
+
+    let block = mine(
+        &mut chain,
+        2,
+        &[TransactionAndDelta {
+            tx: tx.clone(),
+            state_delta: Some(delta),
+        }],
+    );
+    assert!(block.body.contains(&tx));
+    assert_eq!(
+        chain.get_contract_account(cid).unwrap().compressed_state,
+        next_state
+    );
+}
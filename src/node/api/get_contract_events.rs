@@ -0,0 +1,58 @@
+use super::messages::{
+    GetContractEventsRequest, GetContractEventsResponse, GetExplorerContractEventsRequest,
+    GetExplorerContractEventsResponse,
+};
+use super::{NodeContext, NodeError};
+use crate::blockchain::Blockchain;
+use crate::core::{ContractEvent, ContractId};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+fn contract_events<B: Blockchain>(
+    context: &NodeContext<B>,
+    contract_id: &str,
+    since: u64,
+    count: u64,
+) -> Result<Vec<(u64, Vec<ContractEvent>)>, NodeError> {
+    let contract_id: ContractId = contract_id.parse()?;
+    let count = std::cmp::min(context.opts.max_blocks_fetch, count);
+    let until = std::cmp::min(
+        since.saturating_add(count),
+        context.blockchain.get_height()?,
+    );
+    let mut events = Vec::new();
+    for height in since..until {
+        let block_events = context
+            .blockchain
+            .get_contract_events(contract_id, height)?;
+        if !block_events.is_empty() {
+            events.push((height, block_events));
+        }
+    }
+    Ok(events)
+}
+
+pub async fn get_contract_events<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    req: GetContractEventsRequest,
+) -> Result<GetContractEventsResponse, NodeError> {
+    let context = context.read().await;
+    Ok(GetContractEventsResponse {
+        events: contract_events(&context, &req.contract_id, req.since, req.count)?
+            .into_iter()
+            .collect(),
+    })
+}
+
+pub async fn get_explorer_contract_events<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    req: GetExplorerContractEventsRequest,
+) -> Result<GetExplorerContractEventsResponse, NodeError> {
+    let context = context.read().await;
+    Ok(GetExplorerContractEventsResponse {
+        events: contract_events(&context, &req.contract_id, req.since, req.count)?
+            .iter()
+            .flat_map(|(height, events)| events.iter().map(|e| (*height, e).into()))
+            .collect(),
+    })
+}
@@ -12,5 +12,6 @@ pub async fn get_debug_data<B: Blockchain>(
     Ok(GetDebugDataResponse {
         logs: "".into(),
         db_checksum: context.blockchain.db_checksum()?,
+        firewall: context.firewall.as_ref().map(|f| f.stats()),
     })
 }
@@ -0,0 +1,276 @@
+use super::messages::{
+    GetExplorerMpnAddressHistoryRequest, GetExplorerMpnAddressHistoryResponse,
+    GetExplorerMpnAddressRequest, GetExplorerMpnAddressResponse,
+};
+use super::{NodeContext, NodeError};
+use crate::blockchain::Blockchain;
+use crate::client::explorer::{
+    ExplorerMoney, ExplorerMpnAccountChange, ExplorerMpnBalanceChange, ExplorerMpnDeposit,
+    ExplorerMpnTokenBalance, ExplorerMpnTransfers, ExplorerMpnWithdraw,
+};
+use crate::core::{
+    Amount, ContractUpdate, ContractWithdraw, Money, MpnAddress, TokenId, TransactionData, ZkHasher,
+};
+use crate::zk::{MpnAccount, ZkHasher as _, ZkScalar};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+fn balance_changes(prev: &MpnAccount, next: &MpnAccount) -> Vec<ExplorerMpnBalanceChange> {
+    let indices = prev
+        .tokens
+        .keys()
+        .chain(next.tokens.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let mut changes = Vec::new();
+    for index in indices {
+        let before = prev.tokens.get(&index);
+        let after = next.tokens.get(&index);
+        let same_token = before.map(|m| m.token_id) == after.map(|m| m.token_id);
+        if let Some(before) = before.filter(|_| !same_token) {
+            // The slot is now taken by another token
+            changes.push(ExplorerMpnBalanceChange {
+                index,
+                token_id: before.token_id.to_string(),
+                before: before.amount.into(),
+                after: 0,
+            });
+        }
+        if let Some(after) = after {
+            let before = before
+                .filter(|_| same_token)
+                .map(|m| m.amount.into())
+                .unwrap_or_default();
+            if before != u64::from(after.amount) {
+                changes.push(ExplorerMpnBalanceChange {
+                    index,
+                    token_id: after.token_id.to_string(),
+                    before,
+                    after: after.amount.into(),
+                });
+            }
+        }
+    }
+    changes
+}
+
+/// Payments of an account in an MPN update transaction, found by taking the
+/// deposits, the withdraws and the transfers of the update out of its balances
+struct Payments<'a> {
+    withdraws: Vec<(u64, &'a ContractWithdraw)>,
+    sent_nonces: Vec<u64>,
+    sent: Vec<Money>,
+    received: Vec<Money>,
+}
+
+/// The signatures of the withdraws are not kept on the chain, so the withdraws
+/// of an account are the ones of the update its nonce steps and its balance
+/// decreases cover, in the order they are applied. A withdraw of another account
+/// with the very same amounts may be picked instead, while the account sends a
+/// transfer in the same update. The rest of the nonce steps are the transfers
+/// the account has sent.
+fn payments<'a>(
+    prev: &MpnAccount,
+    next: &MpnAccount,
+    calldata: ZkScalar,
+    updates: &'a [ContractUpdate],
+) -> Payments<'a> {
+    let mut deltas = HashMap::<TokenId, i128>::new();
+    for money in next.tokens.values() {
+        *deltas.entry(money.token_id).or_default() += u64::from(money.amount) as i128;
+    }
+    for money in prev.tokens.values() {
+        *deltas.entry(money.token_id).or_default() -= u64::from(money.amount) as i128;
+    }
+    let mut nonce = prev.nonce;
+    let mut withdraws = Vec::new();
+    for update in updates {
+        match update {
+            ContractUpdate::Deposit { deposits, .. } => {
+                for d in deposits.iter().filter(|d| d.calldata == calldata) {
+                    *deltas.entry(d.amount.token_id).or_default() -=
+                        u64::from(d.amount.amount) as i128;
+                }
+            }
+            ContractUpdate::Withdraw { withdraws: ws, .. } => {
+                for w in ws {
+                    if nonce >= next.nonce {
+                        break;
+                    }
+                    let mut spent = HashMap::<TokenId, i128>::new();
+                    *spent.entry(w.amount.token_id).or_default() +=
+                        u64::from(w.amount.amount) as i128;
+                    *spent.entry(w.fee.token_id).or_default() += u64::from(w.fee.amount) as i128;
+                    if spent
+                        .iter()
+                        .all(|(token_id, amount)| -deltas.get(token_id).unwrap_or(&0) >= *amount)
+                    {
+                        for (token_id, amount) in spent {
+                            *deltas.entry(token_id).or_default() += amount;
+                        }
+                        withdraws.push((nonce, w));
+                        nonce += 1;
+                    }
+                }
+            }
+            ContractUpdate::FunctionCall { .. } => {}
+        }
+    }
+    let mut sent = Vec::new();
+    let mut received = Vec::new();
+    for (token_id, delta) in deltas {
+        let money = Money {
+            token_id,
+            amount: Amount(delta.unsigned_abs() as u64),
+        };
+        match delta.signum() {
+            1 => received.push(money),
+            -1 => sent.push(money),
+            _ => {}
+        }
+    }
+    sent.sort_unstable_by_key(|m| m.token_id.to_string());
+    received.sort_unstable_by_key(|m| m.token_id.to_string());
+    Payments {
+        withdraws,
+        sent_nonces: (nonce..next.nonce).collect(),
+        sent,
+        received,
+    }
+}
+
+pub async fn get_explorer_mpn_address<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    req: GetExplorerMpnAddressRequest,
+) -> Result<GetExplorerMpnAddressResponse, NodeError> {
+    let context = context.read().await;
+    let address: MpnAddress = req.address.parse()?;
+    let index = context.blockchain.get_mpn_account_index(&address)?;
+    let account = context.blockchain.get_mpn_account(index)?;
+    let mut tokens = Vec::new();
+    for (index, money) in account.tokens.iter() {
+        tokens.push(ExplorerMpnTokenBalance {
+            index: *index,
+            balance: (*money).into(),
+            token: context
+                .blockchain
+                .get_token(money.token_id)?
+                .map(|t| (&t).into()),
+        });
+    }
+    tokens.sort_unstable_by_key(|t| t.index);
+    Ok(GetExplorerMpnAddressResponse {
+        index,
+        account: (&account).into(),
+        tokens,
+    })
+}
+
+pub async fn get_explorer_mpn_address_history<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    req: GetExplorerMpnAddressHistoryRequest,
+) -> Result<GetExplorerMpnAddressHistoryResponse, NodeError> {
+    let context = context.read().await;
+    let address: MpnAddress = req.address.parse()?;
+    let mpn_contract_id = context.blockchain.config().mpn_contract_id;
+    let count = std::cmp::min(context.opts.max_blocks_fetch, req.count);
+    let height = context.blockchain.get_height()?;
+    let blocks = context
+        .blockchain
+        .get_blocks(height.saturating_sub(count), count)?;
+
+    // Each MPN update transaction moves the MPN contract one height forward
+    let calldata = {
+        let pub_key = address.pub_key.decompress();
+        ZkHasher::hash(&[pub_key.0, pub_key.1])
+    };
+    let mut state_height = context
+        .blockchain
+        .get_contract_account(mpn_contract_id)?
+        .height;
+    let mut txs = HashMap::new();
+    let mut deposits = Vec::new();
+    for block in blocks.iter().rev() {
+        for tx in block.body.iter().rev() {
+            if let TransactionData::UpdateContract {
+                contract_id,
+                updates,
+            } = &tx.data
+            {
+                if *contract_id != mpn_contract_id {
+                    continue;
+                }
+                txs.insert(state_height, (block.header.number, updates));
+                state_height = state_height.saturating_sub(1);
+                for update in updates.iter().rev() {
+                    if let ContractUpdate::Deposit { deposits: ds, .. } = update {
+                        deposits.extend(ds.iter().rev().filter(|d| d.calldata == calldata).map(
+                            |d| ExplorerMpnDeposit {
+                                block_height: block.header.number,
+                                deposit: d.into(),
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    let index = context.blockchain.get_mpn_account_index(&address)?;
+    let history = context.blockchain.get_mpn_account_history(index, count)?;
+    let mut changes = Vec::new();
+    let mut withdraws = Vec::new();
+    let mut transfers = Vec::new();
+    for pair in history.windows(2) {
+        let ((state_height, next), (_, prev)) = (&pair[0], &pair[1]);
+        let balances = balance_changes(prev, next);
+        if next.nonce == prev.nonce && balances.is_empty() {
+            continue;
+        }
+        let tx = txs.get(state_height);
+        changes.push(ExplorerMpnAccountChange {
+            state_height: *state_height,
+            block_height: tx.map(|(block_height, _)| *block_height),
+            nonce: next.nonce,
+            balances,
+        });
+        if let Some((block_height, updates)) = tx {
+            let payments = payments(prev, next, calldata, updates);
+            withdraws.extend(payments.withdraws.into_iter().rev().map(|(nonce, w)| {
+                ExplorerMpnWithdraw {
+                    block_height: *block_height,
+                    nonce,
+                    withdraw: w.into(),
+                }
+            }));
+            let has_transfers = updates
+                .iter()
+                .any(|u| matches!(u, ContractUpdate::FunctionCall { .. }));
+            if has_transfers
+                && !(payments.sent_nonces.is_empty()
+                    && payments.sent.is_empty()
+                    && payments.received.is_empty())
+            {
+                transfers.push(ExplorerMpnTransfers {
+                    state_height: *state_height,
+                    block_height: *block_height,
+                    nonces: payments.sent_nonces,
+                    sent: payments.sent.into_iter().map(ExplorerMoney::from).collect(),
+                    received: payments
+                        .received
+                        .into_iter()
+                        .map(ExplorerMoney::from)
+                        .collect(),
+                });
+            }
+        }
+    }
+
+    Ok(GetExplorerMpnAddressHistoryResponse {
+        changes,
+        deposits,
+        withdraws,
+        transfers,
+    })
+}
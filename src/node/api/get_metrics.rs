@@ -0,0 +1,124 @@
+use super::messages::GetMetricsRequest;
+use super::{NodeContext, NodeError};
+use crate::blockchain::Blockchain;
+use crate::core::{ChainSourcedTx, MpnSourcedTx};
+use crate::utils::metrics::{PrometheusWriter, METRICS};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub async fn get_metrics<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    _req: GetMetricsRequest,
+) -> Result<String, NodeError> {
+    let context = context.read().await;
+    let now = context.local_timestamp();
+    let mut w = PrometheusWriter::default();
+
+    w.gauge(
+        "bazuka_height",
+        "Number of blocks in the local chain",
+        context.blockchain.get_height()?,
+    );
+    w.gauge(
+        "bazuka_power",
+        "Accumulated proof-of-work power of the local chain",
+        context.blockchain.get_power()?,
+    );
+    w.gauge(
+        "bazuka_outdated_states",
+        "Number of contracts with outdated states",
+        context.blockchain.get_outdated_contracts()?.len(),
+    );
+    w.gauge(
+        "bazuka_db_size_bytes",
+        "Approximate size of the blockchain database",
+        context.blockchain.db_size()?,
+    );
+
+    let (mut txs, mut deposits) = (0, 0);
+    for tx in context.mempool.chain_sourced.keys() {
+        match tx {
+            ChainSourcedTx::TransactionAndDelta(_) => txs += 1,
+            ChainSourcedTx::MpnDeposit(_) => deposits += 1,
+        }
+    }
+    let (mut mpn_txs, mut withdraws) = (0, 0);
+    for tx in context.mempool.mpn_sourced.keys() {
+        match tx {
+            MpnSourcedTx::MpnTransaction(_) => mpn_txs += 1,
+            MpnSourcedTx::MpnWithdraw(_) => withdraws += 1,
+        }
+    }
+    w.labeled_gauge(
+        "bazuka_mempool_size",
+        "Number of transactions in the mempool",
+        "type",
+        &[
+            ("transaction", txs),
+            ("mpn_deposit", deposits),
+            ("mpn_transaction", mpn_txs),
+            ("mpn_withdraw", withdraws),
+        ],
+    );
+
+    w.labeled_gauge(
+        "bazuka_peers",
+        "Number of known peers",
+        "state",
+        &[
+            ("peer", context.peer_manager.get_peers().len()),
+            ("node", context.peer_manager.node_count()),
+            ("candidate", context.peer_manager.candidate_count()),
+            ("punished", context.peer_manager.punished_count(now)),
+        ],
+    );
+    w.counter(
+        "bazuka_punishments_total",
+        "Number of punishments given to ips",
+        METRICS.punishments.get(),
+    );
+
+    if let Some(firewall) = &context.firewall {
+        let stats = firewall.stats();
+        w.labeled_counter(
+            "bazuka_firewall_requests_total",
+            "Number of incoming requests checked by the firewall",
+            "verdict",
+            &[
+                ("permitted", stats.permitted),
+                ("bypassed", stats.bypassed),
+                ("denylisted", stats.dropped_denylisted),
+                ("rate_limited", stats.dropped_rate_limited),
+                ("traffic_limited", stats.dropped_traffic_limited),
+            ],
+        );
+    }
+
+    w.timer(
+        "bazuka_sync_blocks_fetch_seconds",
+        "Time spent fetching blocks from peers while syncing",
+        &METRICS.sync_blocks_fetch,
+    );
+    w.timer(
+        "bazuka_apply_block_seconds",
+        "Time spent applying blocks to the chain (Excluding block drafts)",
+        &METRICS.apply_block,
+    );
+    w.timer(
+        "bazuka_zk_verify_seconds",
+        "Time spent verifying zero-knowledge proofs",
+        &METRICS.zk_verify,
+    );
+    w.counter(
+        "bazuka_reorgs_total",
+        "Number of chain extensions that replaced local blocks",
+        METRICS.reorgs.get(),
+    );
+    w.counter(
+        "bazuka_rolled_back_blocks_total",
+        "Number of local blocks rolled back, by reorgs or explicitly",
+        METRICS.rolled_back_blocks.get(),
+    );
+
+    Ok(w.finish())
+}
@@ -9,15 +9,8 @@ pub async fn get_miner_puzzle<B: Blockchain>(
     _req: GetMinerPuzzleRequest,
 ) -> Result<GetMinerPuzzleResponse, NodeError> {
     let mut context = context.write().await;
-    if let Some((_, puzzle)) = context.miner_puzzle.as_ref() {
-        Ok(GetMinerPuzzleResponse {
-            puzzle: Some(puzzle.clone()),
-        })
-    } else {
-        let wallet = context.wallet.clone();
-        context.miner_puzzle = context.get_puzzle(wallet)?;
-        Ok(GetMinerPuzzleResponse {
-            puzzle: context.miner_puzzle.as_ref().map(|(_, pzl)| pzl.clone()),
-        })
-    }
+    let wallet = context.wallet.clone();
+    Ok(GetMinerPuzzleResponse {
+        puzzle: context.get_template(wallet)?.map(|t| t.puzzle),
+    })
 }
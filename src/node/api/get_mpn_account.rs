@@ -1,6 +1,9 @@
-use super::messages::{GetMpnAccountRequest, GetMpnAccountResponse};
+use super::messages::{
+    GetMpnAccountRequest, GetMpnAccountResponse, GetMpnAddressRequest, GetMpnAddressResponse,
+};
 use super::{NodeContext, NodeError};
 use crate::blockchain::Blockchain;
+use crate::core::MpnAddress;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -13,3 +16,16 @@ pub async fn get_mpn_account<B: Blockchain>(
         account: context.blockchain.get_mpn_account(req.index)?,
     })
 }
+
+pub async fn get_mpn_address<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    req: GetMpnAddressRequest,
+) -> Result<GetMpnAddressResponse, NodeError> {
+    let context = context.read().await;
+    let address: MpnAddress = req.address.parse()?;
+    let index = context.blockchain.get_mpn_account_index(&address)?;
+    Ok(GetMpnAddressResponse {
+        index,
+        account: context.blockchain.get_mpn_account(index)?,
+    })
+}
@@ -0,0 +1,29 @@
+use super::messages::{GetMinerPuzzleRequest, GetPoolPuzzleRequest, GetPoolPuzzleResponse};
+use super::{get_miner_puzzle, NodeContext, NodeError};
+use crate::blockchain::Blockchain;
+use crate::core::Address;
+use crate::node::Pool;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub async fn get_pool_puzzle<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    req: GetPoolPuzzleRequest,
+) -> Result<GetPoolPuzzleResponse, NodeError> {
+    let miner: Address = req.miner.parse()?;
+    if context.read().await.pool.is_none() {
+        return Err(NodeError::PoolDisabled);
+    }
+    let puzzle = get_miner_puzzle(Arc::clone(&context), GetMinerPuzzleRequest {})
+        .await?
+        .puzzle;
+    let context = context.read().await;
+    let pool = context.pool.as_ref().ok_or(NodeError::PoolDisabled)?;
+    Ok(GetPoolPuzzleResponse {
+        puzzle: puzzle.map(|mut puzzle| {
+            puzzle.target = pool.share_target(puzzle.target);
+            puzzle.nonce_start = Pool::nonce_start(&miner);
+            puzzle
+        }),
+    })
+}
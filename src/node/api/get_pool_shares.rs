@@ -0,0 +1,30 @@
+use super::messages::{GetPoolSharesRequest, GetPoolSharesResponse};
+use super::{NodeContext, NodeError};
+use crate::blockchain::Blockchain;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub async fn get_pool_shares<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    _req: GetPoolSharesRequest,
+) -> Result<GetPoolSharesResponse, NodeError> {
+    let context = context.read().await;
+    let pool = context.pool.as_ref().ok_or(NodeError::PoolDisabled)?;
+    let block_target = match context.miner_templates.latest() {
+        Some(template) => template.puzzle.target,
+        None => context.blockchain.get_tip()?.proof_of_work.target,
+    };
+    Ok(GetPoolSharesResponse {
+        share_target: pool.share_target(block_target),
+        miners: pool
+            .miners()
+            .iter()
+            .map(|(addr, shares)| (addr.to_string(), shares.clone()))
+            .collect(),
+        last_round: pool
+            .last_round()
+            .iter()
+            .map(|(addr, shares)| (addr.to_string(), shares.clone()))
+            .collect(),
+    })
+}
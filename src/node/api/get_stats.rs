@@ -1,6 +1,7 @@
-use super::messages::{GetStatsRequest, GetStatsResponse};
+use super::messages::{GetStatsRequest, GetStatsResponse, UpgradeStatus};
 use super::{NodeContext, NodeError};
 use crate::blockchain::Blockchain;
+use crate::consensus::upgrades::Upgrade;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -9,15 +10,33 @@ pub async fn get_stats<B: Blockchain>(
     _req: GetStatsRequest,
 ) -> Result<GetStatsResponse, NodeError> {
     let context = context.read().await;
+    let height = context.blockchain.get_height()?;
+    let peers = context.peer_manager.get_peers();
+    let upgrades = Upgrade::ALL
+        .iter()
+        .map(|upgrade| {
+            let upgrades = &context.blockchain.config().upgrades;
+            UpgradeStatus {
+                name: upgrade.to_string(),
+                activation_height: upgrades.activation_height(*upgrade),
+                active: upgrades.is_active(*upgrade, height),
+                ready_peers: peers
+                    .iter()
+                    .filter(|p| p.upgrades.contains(&upgrade.to_string()))
+                    .count(),
+            }
+        })
+        .collect();
     Ok(GetStatsResponse {
         social_profiles: context.social_profiles.clone(),
         address: context.wallet.get_address().to_string(),
-        height: context.blockchain.get_height()?,
+        height,
         nodes: context.peer_manager.node_count(),
         power: context.blockchain.get_power()?,
         next_reward: context.blockchain.next_reward()?,
         timestamp: context.network_timestamp(),
         version: env!("CARGO_PKG_VERSION").into(),
         network: context.network.clone(),
+        upgrades,
     })
 }
@@ -23,8 +23,11 @@ pub async fn get_zero_mempool<B: Blockchain>(
         let mut chain_sourced = ctx.mempool.chain_sourced.clone();
         let mut mpn_sourced = ctx.mempool.mpn_sourced.clone();
         ctx.blockchain.cleanup_chain_mempool(&mut chain_sourced)?;
-        ctx.blockchain
-            .cleanup_mpn_mempool(&mut mpn_sourced, ctx.opts.mpn_mempool_capacity)?;
+        ctx.blockchain.cleanup_mpn_mempool(
+            &mut mpn_sourced,
+            ctx.opts.mpn_mempool_capacity,
+            &ctx.opts.fee_rates,
+        )?;
         drop(ctx);
 
         let mut ctx = context.write().await;
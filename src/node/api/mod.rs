@@ -36,12 +36,20 @@ mod get_miner_puzzle;
 pub use get_miner_puzzle::*;
 mod post_miner_solution;
 pub use post_miner_solution::*;
+mod get_pool_puzzle;
+pub use get_pool_puzzle::*;
+mod post_pool_share;
+pub use post_pool_share::*;
+mod get_pool_shares;
+pub use get_pool_shares::*;
 mod get_account;
 pub use get_account::*;
 mod get_mpn_account;
 pub use get_mpn_account::*;
 mod get_explorer_mpn_accounts;
 pub use get_explorer_mpn_accounts::*;
+mod get_explorer_mpn_address;
+pub use get_explorer_mpn_address::*;
 mod get_mempool;
 pub use get_mempool::*;
 mod get_debug_data;
@@ -50,3 +58,9 @@ mod get_balance;
 pub use get_balance::*;
 mod get_token;
 pub use get_token::*;
+mod get_contract_events;
+pub use get_contract_events::*;
+mod get_metrics;
+pub use get_metrics::*;
+mod subscribe;
+pub use subscribe::*;
@@ -1,5 +1,6 @@
 use super::messages::{
-    PostBlockRequest, PostBlockResponse, PostMinerSolutionRequest, PostMinerSolutionResponse,
+    InputError, PostBlockRequest, PostBlockResponse, PostMinerSolutionRequest,
+    PostMinerSolutionResponse,
 };
 use super::{http, Limit, NodeContext, NodeError};
 use crate::blockchain::Blockchain;
@@ -7,17 +8,25 @@ use crate::common::*;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-pub async fn post_miner_solution<B: Blockchain>(
-    context: Arc<RwLock<NodeContext<B>>>,
-    req: PostMinerSolutionRequest,
-) -> Result<PostMinerSolutionResponse, NodeError> {
-    let mut context = context.write().await;
-    let net = context.outgoing.clone();
+pub fn parse_nonce(nonce: &str) -> Result<u64, NodeError> {
+    let nonce_bytes: [u8; 8] = hex::decode(nonce)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or(InputError::Invalid)?;
+    Ok(u64::from_le_bytes(nonce_bytes))
+}
 
-    let mut nonce_bytes = [0u8; 8];
-    nonce_bytes.copy_from_slice(&hex::decode(req.nonce).unwrap());
-    if let Some((mut draft, _)) = context.miner_puzzle.clone() {
-        draft.block.header.proof_of_work.nonce = u64::from_le_bytes(nonce_bytes);
+/// Tries to extend the chain with the block of the given template. Returns true
+/// if the nonce was a valid solution.
+pub async fn apply_solution<B: Blockchain>(
+    context: &mut NodeContext<B>,
+    template_id: Option<u64>,
+    nonce: u64,
+) -> Result<bool, NodeError> {
+    let net = context.outgoing.clone();
+    if let Some(template) = context.miner_templates.get(template_id).cloned() {
+        let mut draft = template.draft;
+        draft.block.header.proof_of_work.nonce = nonce;
         if context
             .blockchain
             .extend(draft.block.header.number, &[draft.block.clone()])
@@ -39,10 +48,20 @@ pub async fn post_miner_solution<B: Blockchain>(
             })
             .await;
 
-            context.miner_puzzle = None;
+            return Ok(true);
         }
     } else {
-        log::info!("No block is currently being mined!");
+        log::info!("Template of the solution is not available!");
     }
+    Ok(false)
+}
+
+pub async fn post_miner_solution<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    req: PostMinerSolutionRequest,
+) -> Result<PostMinerSolutionResponse, NodeError> {
+    let nonce = parse_nonce(&req.nonce)?;
+    let mut context = context.write().await;
+    apply_solution(&mut context, req.template_id, nonce).await?;
     Ok(PostMinerSolutionResponse {})
 }
@@ -12,10 +12,14 @@ pub async fn post_mpn_transaction<B: Blockchain>(
     let mut context = context.write().await;
     let now = context.local_timestamp();
     if context.mempool.mpn_sourced.len() < context.opts.mpn_mempool_capacity {
-        context.mempool.mpn_sourced.insert(
-            MpnSourcedTx::MpnTransaction(req.tx),
-            TransactionStats::new(now),
-        );
+        let tx = MpnSourcedTx::MpnTransaction(req.tx);
+        if !context.mempool.mpn_sourced.contains_key(&tx) {
+            context.subscriptions.get_mut().on_mpn_mempool_addition(&tx);
+        }
+        context
+            .mempool
+            .mpn_sourced
+            .insert(tx, TransactionStats::new(now));
     }
     Ok(PostMpnTransactionResponse {})
 }
@@ -0,0 +1,72 @@
+use super::messages::{InputError, PostPoolShareRequest, PostPoolShareResponse, ShareVerdict};
+use super::{apply_solution, parse_nonce, NodeContext, NodeError};
+use crate::blockchain::Blockchain;
+use crate::client::miner::Work;
+use crate::consensus::pow;
+use crate::core::{Address, Signer};
+use crate::crypto::SignatureScheme;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub async fn post_pool_share<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    req: PostPoolShareRequest,
+) -> Result<PostPoolShareResponse, NodeError> {
+    let miner: Address = req.miner.parse()?;
+    let nonce = parse_nonce(&req.nonce)?;
+    let sig: <Signer as SignatureScheme>::Sig =
+        bincode::deserialize(&hex::decode(&req.sig).map_err(|_| InputError::Invalid)?)?;
+
+    let (template_id, work, share_target) = {
+        let context = context.read().await;
+        let pool = context.pool.as_ref().ok_or(NodeError::PoolDisabled)?;
+        match context.miner_templates.get(req.template_id) {
+            Some(template) => {
+                let work = Work::try_from(&template.puzzle)?;
+                let share_target = pool.share_target(work.target);
+                (template.puzzle.template_id, work, share_target)
+            }
+            None => {
+                return Ok(PostPoolShareResponse {
+                    verdict: ShareVerdict::Stale,
+                });
+            }
+        }
+    };
+
+    let block_target = work.target;
+    // Signature check and RandomX hash are expensive, keep them out of the
+    // node's lock and off the async runtime
+    let hash = {
+        let miner = miner.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut blob = work.blob.clone();
+            work.with_nonce(&mut blob, nonce);
+            Signer::verify(&miner, &blob, &sig).then(|| pow::hash(&work.key, &blob))
+        })
+    }
+    .await
+    .expect("share verification task panicked")
+    .ok_or(NodeError::InvalidShareSignature)?;
+
+    let mut context = context.write().await;
+    let pool = context.pool.as_mut().ok_or(NodeError::PoolDisabled)?;
+    let verdict = if !pool.register_nonce(template_id, nonce) {
+        ShareVerdict::Duplicate
+    } else if !share_target.is_met_by(&hash) {
+        ShareVerdict::LowDifficulty
+    } else {
+        pool.credit(miner, share_target);
+        if block_target.is_met_by(&hash)
+            && apply_solution(&mut context, req.template_id, nonce).await?
+        {
+            if let Some(pool) = context.pool.as_mut() {
+                pool.finish_round();
+            }
+            ShareVerdict::BlockFound
+        } else {
+            ShareVerdict::Accepted
+        }
+    };
+    Ok(PostPoolShareResponse { verdict })
+}
@@ -0,0 +1,16 @@
+use super::messages::{NodeEvent, SubscribeRequest};
+use super::{NodeContext, NodeError};
+use crate::blockchain::Blockchain;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{mpsc, RwLock};
+
+pub async fn subscribe<B: Blockchain>(
+    client: Option<SocketAddr>,
+    context: Arc<RwLock<NodeContext<B>>>,
+    req: SubscribeRequest,
+) -> Result<mpsc::Receiver<NodeEvent>, NodeError> {
+    let context = context.read().await;
+    let mut subscriptions = context.subscriptions.lock().await;
+    subscriptions.subscribe(&context.blockchain, client.map(|c| c.ip()), req)
+}
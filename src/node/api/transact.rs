@@ -1,6 +1,6 @@
 use super::messages::{TransactRequest, TransactResponse};
 use super::{NodeContext, NodeError};
-use crate::blockchain::{Blockchain, TransactionStats};
+use crate::blockchain::{Blockchain, BlockchainError, TransactionStats};
 use crate::core::ChainSourcedTx;
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -10,10 +10,24 @@ pub async fn transact<B: Blockchain>(
     req: TransactRequest,
 ) -> Result<TransactResponse, NodeError> {
     let mut context = context.write().await;
+    let height = context.blockchain.get_height()?;
+    if req.tx_delta.tx.is_expired_at(height) {
+        return Err(BlockchainError::TransactionExpired.into());
+    }
+    if context
+        .blockchain
+        .config()
+        .is_scheduled_too_far(&req.tx_delta.tx, height)
+    {
+        return Err(BlockchainError::TransactionScheduledTooFar.into());
+    }
     let now = context.local_timestamp();
-    context.mempool.chain_sourced.insert(
-        ChainSourcedTx::TransactionAndDelta(req.tx_delta),
-        TransactionStats::new(now),
-    );
+    // Resubmitting a transaction doesn't reset its age, so that scheduled
+    // transactions are evicted like the others
+    context
+        .mempool
+        .chain_sourced
+        .entry(ChainSourcedTx::TransactionAndDelta(req.tx_delta))
+        .or_insert(TransactionStats::new(now));
     Ok(TransactResponse {})
 }
@@ -1,19 +1,20 @@
 use super::{
     Firewall, Mempool, NodeError, NodeOptions, OutgoingSender, Peer, PeerAddress, PeerManager,
-    Timestamp,
+    Pool, Subscriptions, Timestamp,
 };
-use crate::blockchain::{BlockAndPatch, Blockchain, BlockchainError};
+use super::{MinerTemplate, MinerTemplates};
+use crate::blockchain::{Blockchain, BlockchainError};
 use crate::client::messages::SocialProfiles;
-use crate::core::{ChainSourcedTx, Header, TransactionAndDelta};
+use crate::consensus::upgrades::Upgrade;
+use crate::core::{Amount, ChainSourcedTx, Header, TransactionAndDelta};
 use crate::utils;
 use crate::wallet::TxBuilder;
 use std::collections::HashMap;
 use std::sync::Arc;
+use tokio::sync::Mutex;
 
 use crate::client::messages::Puzzle;
 
-pub type BlockPuzzle = (BlockAndPatch, Puzzle);
-
 pub struct NodeContext<B: Blockchain> {
     pub miner_token: Option<String>,
 
@@ -28,9 +29,14 @@ pub struct NodeContext<B: Blockchain> {
     pub wallet: TxBuilder,
     pub peer_manager: PeerManager,
     pub timestamp_offset: i32,
-    pub miner_puzzle: Option<BlockPuzzle>,
+    pub miner_templates: MinerTemplates,
+    pub pool: Option<Pool>,
+    #[cfg(feature = "mpn-executor")]
+    pub mpn_executor: Option<super::NodeMpnExecutor>,
 
     pub mempool: Mempool,
+    /// Locked separately, so that the events are collected under a read lock
+    pub subscriptions: Mutex<Subscriptions>,
 
     pub outdated_since: Option<Timestamp>,
     pub banned_headers: HashMap<Header, Timestamp>,
@@ -65,6 +71,7 @@ impl<B: Blockchain> NodeContext<B> {
             power,
             pub_key: self.wallet.get_address(),
             outdated_states,
+            upgrades: Upgrade::ALL.iter().map(|u| u.to_string()).collect(),
         }))
     }
 
@@ -83,6 +90,15 @@ impl<B: Blockchain> NodeContext<B> {
         }
 
         if let Some(max) = self.opts.tx_max_time_alive {
+            let height = self.blockchain.get_height()?;
+            for (tx, stats) in self.mempool.chain_sourced.iter_mut() {
+                // Scheduled transactions only start aging once they get valid
+                if let ChainSourcedTx::TransactionAndDelta(tx_delta) = tx {
+                    if tx_delta.tx.is_scheduled_at(height) {
+                        stats.first_seen = local_ts;
+                    }
+                }
+            }
             for (tx, stats) in self.mempool.chain_sourced.clone().into_iter() {
                 if local_ts - stats.first_seen > max {
                     self.mempool.chain_sourced.remove(&tx);
@@ -100,17 +116,18 @@ impl<B: Blockchain> NodeContext<B> {
     /// Is called whenever chain is extended or rolled back
     pub fn on_update(&mut self) -> Result<(), BlockchainError> {
         self.outdated_since = None;
-        self.miner_puzzle = None;
+        self.miner_templates.clear();
+        self.notify_subscribers();
         Ok(())
     }
 
-    pub fn get_puzzle(
-        &mut self,
-        wallet: TxBuilder,
-    ) -> Result<Option<BlockPuzzle>, BlockchainError> {
-        let ts = self.network_timestamp();
-        let raw_txs: Vec<TransactionAndDelta> = self
-            .mempool
+    /// Is called whenever chain or contract states are changed
+    pub fn notify_subscribers(&mut self) {
+        self.subscriptions.get_mut().mark_outdated();
+    }
+
+    fn mempool_txs(&self) -> Vec<TransactionAndDelta> {
+        self.mempool
             .chain_sourced
             .keys()
             .filter_map(|tx| {
@@ -120,8 +137,55 @@ impl<B: Blockchain> NodeContext<B> {
                     None
                 }
             })
-            .collect();
-        match self.blockchain.draft_block(ts, &raw_txs, &wallet, true) {
+            .collect()
+    }
+
+    /// Sum of the fees a miner could earn by including all of the mempool
+    pub fn mempool_fees(&self) -> Result<Amount, BlockchainError> {
+        self.mempool_txs()
+            .iter()
+            .filter_map(|tx| self.opts.fee_rates.value(tx.tx.fee))
+            .try_fold(Amount(0), |sum, value| {
+                // Values which don't fit in an amount aren't clamped silently
+                (value >= 0.0 && value < u64::MAX as f64)
+                    .then_some(Amount(value as u64))
+                    .and_then(|value| sum.checked_add(value))
+                    .ok_or(BlockchainError::FeesOverflow)
+            })
+    }
+
+    /// Returns the latest miner template, a new one is built if there are no
+    /// templates yet or if the mempool is now paying enough more fees
+    pub fn get_template(
+        &mut self,
+        wallet: TxBuilder,
+    ) -> Result<Option<MinerTemplate>, BlockchainError> {
+        let fees = self.mempool_fees()?;
+        if let Some(latest) = self.miner_templates.latest() {
+            let threshold = latest
+                .fees
+                .checked_add(self.opts.miner_template_fee_threshold)
+                .ok_or(BlockchainError::FeesOverflow)?;
+            if fees < threshold {
+                return Ok(Some(latest.clone()));
+            }
+        }
+        Ok(self
+            .draft_template(wallet, fees)?
+            .map(|template| self.miner_templates.push(template).clone()))
+    }
+
+    fn draft_template(
+        &mut self,
+        wallet: TxBuilder,
+        fees: Amount,
+    ) -> Result<Option<MinerTemplate>, BlockchainError> {
+        let ts = self.network_timestamp();
+        let raw_txs = self.mempool_txs();
+        match self
+            .blockchain
+            .draft_block(ts, &raw_txs, &wallet, &self.opts.fee_rates, true)
+        {
             Ok(draft) => {
                 if let Some(draft) = draft {
                     let puzzle = Puzzle {
@@ -131,8 +195,14 @@ impl<B: Blockchain> NodeContext<B> {
                         size: 8,
                         target: draft.block.header.proof_of_work.target,
                         reward: self.blockchain.next_reward()?,
+                        template_id: 0,
+                        nonce_start: 0,
                     };
-                    Ok(Some((draft, puzzle)))
+                    Ok(Some(MinerTemplate {
+                        draft,
+                        puzzle,
+                        fees,
+                    }))
                 } else {
                     Ok(None)
                 }
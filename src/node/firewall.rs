@@ -1,56 +1,437 @@
 use super::*;
+use crate::client::messages::FirewallStats;
+use std::str::FromStr;
+use thiserror::Error;
 
-pub struct Firewall {
-    request_count_limit_per_minute: usize,
-    request_count_last_reset: Timestamp,
-    request_count: HashMap<IpAddr, usize>,
+#[derive(Error, Debug)]
+pub enum ParseIpRangeError {
+    #[error("ip range invalid")]
+    Invalid,
+    #[error("ip range prefix length out of bounds")]
+    PrefixOutOfBounds,
+}
 
-    traffic_limit_per_15m: u64,
-    traffic_last_reset: Timestamp,
-    traffic: HashMap<IpAddr, u64>,
+/// An ip address or a CIDR range, e.g. `10.0.0.1` or `10.0.0.0/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
 }
 
-impl Firewall {
-    pub fn new(request_count_limit_per_minute: usize, traffic_limit_per_15m: u64) -> Self {
+impl IpRange {
+    fn max_prefix_len(addr: &IpAddr) -> u8 {
+        match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for IpRange {
+    fn from(addr: IpAddr) -> Self {
         Self {
-            request_count_limit_per_minute,
-            traffic_limit_per_15m,
-            request_count_last_reset: 0,
-            request_count: HashMap::new(),
-            traffic_last_reset: 0,
-            traffic: HashMap::new(),
+            addr,
+            prefix_len: Self::max_prefix_len(&addr),
         }
     }
-    pub fn refresh(&mut self, now: u32) {
-        if now - self.request_count_last_reset > 60 {
-            self.request_count.clear();
-            self.request_count_last_reset = now;
+}
+
+impl std::fmt::Display for IpRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = ParseIpRangeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| ParseIpRangeError::Invalid)?;
+        let max_prefix_len = Self::max_prefix_len(&addr);
+        let prefix_len = if let Some(prefix_len) = prefix_len {
+            prefix_len
+                .parse::<u8>()
+                .map_err(|_| ParseIpRangeError::Invalid)?
+        } else {
+            max_prefix_len
+        };
+        if prefix_len > max_prefix_len {
+            return Err(ParseIpRangeError::PrefixOutOfBounds);
         }
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl serde::Serialize for IpRange {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for IpRange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FirewallOptions {
+    /// Maximum number of request-tokens an ip can accumulate
+    pub request_capacity: u64,
+    /// Request-tokens given back to an ip every second
+    pub request_refill_per_second: u64,
+    /// Maximum number of bytes an ip can send/receive in a burst
+    pub traffic_capacity: u64,
+    /// Bytes given back to an ip every second
+    pub traffic_refill_per_second: u64,
+    /// Cost of routes which are not listed in `route_costs`
+    pub default_route_cost: u64,
+    pub route_costs: HashMap<String, u64>,
+    /// Ips that are never limited
+    pub allowlist: Vec<IpRange>,
+    /// Ips that are always dropped
+    pub denylist: Vec<IpRange>,
+}
+
+impl FirewallOptions {
+    pub fn route_cost(&self, route: &str) -> u64 {
+        self.route_costs
+            .get(route)
+            .cloned()
+            .unwrap_or(self.default_route_cost)
+    }
+    /// Overrides the limits which are set in `limits`
+    pub fn with_limits(mut self, limits: &FirewallLimits) -> Self {
+        let FirewallLimits {
+            request_capacity,
+            request_refill_per_second,
+            traffic_capacity,
+            traffic_refill_per_second,
+            default_route_cost,
+            route_costs,
+        } = limits;
+        self.request_capacity = request_capacity.unwrap_or(self.request_capacity);
+        self.request_refill_per_second =
+            request_refill_per_second.unwrap_or(self.request_refill_per_second);
+        self.traffic_capacity = traffic_capacity.unwrap_or(self.traffic_capacity);
+        self.traffic_refill_per_second =
+            traffic_refill_per_second.unwrap_or(self.traffic_refill_per_second);
+        self.default_route_cost = default_route_cost.unwrap_or(self.default_route_cost);
+        self.route_costs
+            .extend(route_costs.iter().map(|(r, c)| (r.clone(), *c)));
+        self
+    }
+}
+
+/// Firewall limits given in the node config, unset limits keep their defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct FirewallLimits {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_capacity: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_refill_per_second: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub traffic_capacity: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub traffic_refill_per_second: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_route_cost: Option<u64>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub route_costs: HashMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallVerdict {
+    Permitted,
+    Denylisted,
+    RateLimited,
+    TrafficLimited,
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: u64,
+    last_refill: Timestamp,
+}
 
-        if now - self.traffic_last_reset > 900 {
-            self.traffic.clear();
-            self.traffic_last_reset = now;
+impl TokenBucket {
+    fn full(capacity: u64, now: Timestamp) -> Self {
+        Self {
+            tokens: capacity,
+            last_refill: now,
         }
     }
-    pub fn add_traffic(&mut self, ip: IpAddr, amount: u64) {
-        *self.traffic.entry(ip).or_insert(0) += amount;
+    fn refill(&mut self, now: Timestamp, capacity: u64, refill_per_second: u64) {
+        let elapsed = now.saturating_sub(self.last_refill) as u64;
+        self.tokens = std::cmp::min(
+            capacity,
+            self.tokens
+                .saturating_add(elapsed.saturating_mul(refill_per_second)),
+        );
+        self.last_refill = now;
+    }
+    fn is_full(&self, now: Timestamp, capacity: u64, refill_per_second: u64) -> bool {
+        let elapsed = now.saturating_sub(self.last_refill) as u64;
+        self.tokens
+            .saturating_add(elapsed.saturating_mul(refill_per_second))
+            >= capacity
     }
-    pub fn incoming_permitted(&mut self, client: SocketAddr) -> bool {
+}
+
+pub struct Firewall {
+    opts: FirewallOptions,
+    requests: HashMap<IpAddr, TokenBucket>,
+    traffic: HashMap<IpAddr, TokenBucket>,
+    stats: FirewallStats,
+}
+
+impl Firewall {
+    pub fn new(opts: FirewallOptions) -> Self {
+        Self {
+            opts,
+            requests: HashMap::new(),
+            traffic: HashMap::new(),
+            stats: FirewallStats::default(),
+        }
+    }
+    pub fn stats(&self) -> FirewallStats {
+        self.stats.clone()
+    }
+    fn is_allowlisted(&self, ip: IpAddr) -> bool {
         // Incoming from loopback is always permitted
-        if client.ip().is_loopback() {
-            return true;
+        ip.is_loopback() || self.opts.allowlist.iter().any(|r| r.contains(ip))
+    }
+    fn is_denylisted(&self, ip: IpAddr) -> bool {
+        self.opts.denylist.iter().any(|r| r.contains(ip))
+    }
+
+    /// Forget the buckets that are already refilled, as they are equal to fresh ones
+    pub fn refresh(&mut self, now: Timestamp) {
+        let (cap, refill) = (
+            self.opts.request_capacity,
+            self.opts.request_refill_per_second,
+        );
+        self.requests.retain(|_, b| !b.is_full(now, cap, refill));
+        let (cap, refill) = (
+            self.opts.traffic_capacity,
+            self.opts.traffic_refill_per_second,
+        );
+        self.traffic.retain(|_, b| !b.is_full(now, cap, refill));
+    }
+
+    pub fn add_traffic(&mut self, now: Timestamp, ip: IpAddr, amount: u64) {
+        if self.is_allowlisted(ip) {
+            return;
         }
+        let (cap, refill) = (
+            self.opts.traffic_capacity,
+            self.opts.traffic_refill_per_second,
+        );
+        let bucket = self
+            .traffic
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::full(cap, now));
+        bucket.refill(now, cap, refill);
+        bucket.tokens = bucket.tokens.saturating_sub(amount);
+    }
 
-        if self.traffic.get(&client.ip()).cloned().unwrap_or(0) > self.traffic_limit_per_15m {
-            return false;
+    /// Requests of authenticated miners skip the limits but are still counted
+    pub fn bypass(&mut self) {
+        self.stats.bypassed += 1;
+    }
+
+    pub fn incoming_permitted(
+        &mut self,
+        now: Timestamp,
+        client: SocketAddr,
+        route: &str,
+    ) -> FirewallVerdict {
+        let ip = client.ip();
+        let verdict = if self.is_allowlisted(ip) {
+            FirewallVerdict::Permitted
+        } else if self.is_denylisted(ip) {
+            FirewallVerdict::Denylisted
+        } else if self
+            .traffic
+            .get_mut(&ip)
+            .map(|b| {
+                b.refill(
+                    now,
+                    self.opts.traffic_capacity,
+                    self.opts.traffic_refill_per_second,
+                );
+                b.tokens == 0
+            })
+            .unwrap_or(false)
+        {
+            FirewallVerdict::TrafficLimited
+        } else {
+            let cost = self.opts.route_cost(route);
+            let (cap, refill) = (
+                self.opts.request_capacity,
+                self.opts.request_refill_per_second,
+            );
+            let bucket = self
+                .requests
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::full(cap, now));
+            bucket.refill(now, cap, refill);
+            if bucket.tokens < cost {
+                FirewallVerdict::RateLimited
+            } else {
+                bucket.tokens -= cost;
+                FirewallVerdict::Permitted
+            }
+        };
+        match verdict {
+            FirewallVerdict::Permitted => self.stats.permitted += 1,
+            FirewallVerdict::Denylisted => self.stats.dropped_denylisted += 1,
+            FirewallVerdict::RateLimited => self.stats.dropped_rate_limited += 1,
+            FirewallVerdict::TrafficLimited => self.stats.dropped_traffic_limited += 1,
         }
+        verdict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let cnt = self.request_count.entry(client.ip()).or_insert(0);
-        if *cnt > self.request_count_limit_per_minute {
-            return false;
+    fn opts() -> FirewallOptions {
+        FirewallOptions {
+            request_capacity: 10,
+            request_refill_per_second: 1,
+            traffic_capacity: 1000,
+            traffic_refill_per_second: 100,
+            default_route_cost: 1,
+            route_costs: [("/bincode/blocks".to_string(), 5)].into_iter().collect(),
+            allowlist: vec!["10.0.0.0/8".parse().unwrap()],
+            denylist: vec!["192.168.1.0/24".parse().unwrap()],
         }
+    }
 
-        *cnt += 1;
-        true
+    fn client(ip: &str) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), 8765)
+    }
+
+    #[test]
+    fn test_ip_range() {
+        let range: IpRange = "192.168.0.0/16".parse().unwrap();
+        assert!(range.contains("192.168.12.34".parse().unwrap()));
+        assert!(!range.contains("192.169.0.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+        let single: IpRange = "1.2.3.4".parse().unwrap();
+        assert!(single.contains("1.2.3.4".parse().unwrap()));
+        assert!(!single.contains("1.2.3.5".parse().unwrap()));
+        let all: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("8.8.8.8".parse().unwrap()));
+        let v6: IpRange = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains("2001:db8::1".parse().unwrap()));
+        assert!("1.2.3.4/33".parse::<IpRange>().is_err());
+        assert!("1.2.3/8".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn test_route_costs() {
+        let mut fw = Firewall::new(opts());
+        let c = client("1.1.1.1");
+        assert_eq!(
+            fw.incoming_permitted(0, c, "/bincode/blocks"),
+            FirewallVerdict::Permitted
+        );
+        assert_eq!(
+            fw.incoming_permitted(0, c, "/bincode/blocks"),
+            FirewallVerdict::Permitted
+        );
+        assert_eq!(
+            fw.incoming_permitted(0, c, "/bincode/blocks"),
+            FirewallVerdict::RateLimited
+        );
+        // Cheap routes are also dropped once the bucket is drained
+        assert_eq!(
+            fw.incoming_permitted(0, c, "/stats"),
+            FirewallVerdict::RateLimited
+        );
+        assert_eq!(
+            fw.incoming_permitted(1, c, "/stats"),
+            FirewallVerdict::Permitted
+        );
+        assert_eq!(
+            fw.incoming_permitted(6, c, "/bincode/blocks"),
+            FirewallVerdict::Permitted
+        );
+        let stats = fw.stats();
+        assert_eq!(stats.permitted, 4);
+        assert_eq!(stats.dropped_rate_limited, 2);
+    }
+
+    #[test]
+    fn test_firewall_limits() {
+        let limits: FirewallLimits = serde_yaml::from_str(
+            "request_capacity: 5\nroute_costs:\n  /bincode/blocks: 100\n  /custom: 3",
+        )
+        .unwrap();
+        let opts = opts().with_limits(&limits);
+        assert_eq!(opts.request_capacity, 5);
+        assert_eq!(opts.request_refill_per_second, 1);
+        assert_eq!(opts.route_cost("/bincode/blocks"), 100);
+        assert_eq!(opts.route_cost("/custom"), 3);
+        assert_eq!(opts.route_cost("/stats"), 1);
+    }
+
+    #[test]
+    fn test_lists_and_traffic() {
+        let mut fw = Firewall::new(opts());
+        for _ in 0..100 {
+            assert_eq!(
+                fw.incoming_permitted(0, client("10.1.2.3"), "/bincode/blocks"),
+                FirewallVerdict::Permitted
+            );
+        }
+        assert_eq!(
+            fw.incoming_permitted(0, client("192.168.1.10"), "/stats"),
+            FirewallVerdict::Denylisted
+        );
+        let c = client("2.2.2.2");
+        fw.add_traffic(0, c.ip(), 2000);
+        assert_eq!(
+            fw.incoming_permitted(0, c, "/stats"),
+            FirewallVerdict::TrafficLimited
+        );
+        assert_eq!(
+            fw.incoming_permitted(1, c, "/stats"),
+            FirewallVerdict::Permitted
+        );
+        fw.refresh(100);
+        assert!(fw.requests.is_empty() && fw.traffic.is_empty());
     }
 }
@@ -0,0 +1,139 @@
+use super::*;
+use crate::core::{
+    ChainSourcedTx, Money, Signature, Transaction, TransactionAndDelta, TransactionData,
+};
+use crate::mpn::{MpnExecutor, MpnUpdateCounts, MpnWork};
+use crate::node::api;
+
+pub async fn execute_mpn<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+) -> Result<(), NodeError> {
+    let mempool = match api::get_zero_mempool(context.clone(), GetZeroMempoolRequest {}).await {
+        Ok(resp) => resp,
+        Err(NodeError::StatesOutdated) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    // Only a snapshot of what the executor needs is taken under the lock of
+    // the node, syncing and planning are done outside of it
+    let (mpn, target, accounts, counts, contract_height, wallet, contract_id, nonce) = {
+        let ctx = context.read().await;
+        let mpn = if let Some(mpn) = ctx.mpn_executor.clone() {
+            mpn
+        } else {
+            return Ok(());
+        };
+        let config = ctx.blockchain.config().clone();
+        let contract_id = config.mpn_contract_id;
+        let address = ctx.wallet.get_address();
+
+        let mut nonce = ctx.blockchain.get_account(address.clone())?.nonce;
+        for tx in ctx.mempool.chain_sourced.keys() {
+            if let ChainSourcedTx::TransactionAndDelta(tx_delta) = tx {
+                if tx_delta.tx.src.as_ref() == Some(&address) {
+                    if let TransactionData::UpdateContract {
+                        contract_id: cid, ..
+                    } = &tx_delta.tx.data
+                    {
+                        if *cid == contract_id {
+                            // Wait for the previous update to get into a block
+                            return Ok(());
+                        }
+                    }
+                    nonce = nonce.max(tx_delta.tx.nonce);
+                }
+            }
+        }
+
+        let height = ctx.blockchain.get_height()?;
+        let contract = ctx.blockchain.get_contract(contract_id)?;
+        if !mpn.params.is_compatible(&contract, &config, height) {
+            return Err(crate::mpn::MpnError::IncompatibleParams.into());
+        }
+
+        let account = ctx.blockchain.get_contract_account(contract_id)?;
+        // Accounts are only paged through when the local state is behind
+        let accounts = if mpn
+            .executor
+            .lock()
+            .await
+            .is_synced(&account.compressed_state)
+        {
+            None
+        } else {
+            Some(MpnExecutor::fetch_accounts(&ctx.blockchain)?)
+        };
+
+        let counts = MpnUpdateCounts {
+            function_calls: config.mpn_num_function_calls_at(height),
+            deposits: config.mpn_num_contract_deposits,
+            withdraws: config.mpn_num_contract_withdraws,
+        };
+        (
+            mpn,
+            account.compressed_state,
+            accounts,
+            counts,
+            account.height,
+            ctx.wallet.clone(),
+            contract_id,
+            nonce,
+        )
+    };
+
+    let (works, delta) = {
+        let mut executor = mpn.executor.lock().await;
+        if let Some(accounts) = accounts {
+            executor.sync(target, accounts)?;
+        }
+        executor.plan(
+            contract_id,
+            contract_height,
+            counts,
+            &wallet.get_address(),
+            mempool.deposits,
+            mempool.withdraws,
+            mempool.updates,
+        )?
+    };
+    if works.is_empty() {
+        return Ok(());
+    }
+    let params = mpn.params.clone();
+
+    log::info!("Proving {} MPN contract updates...", works.len());
+    let updates = tokio::task::spawn_blocking(move || {
+        works
+            .into_iter()
+            .map(|work: MpnWork| work.prove(&params))
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await
+    .expect("proving task panicked")?;
+
+    let mut tx = Transaction {
+        memo: String::new(),
+        src: Some(wallet.get_address()),
+        data: TransactionData::UpdateContract {
+            contract_id,
+            updates,
+        },
+        nonce: nonce + 1,
+        fee: Money::ziesha(0),
+        valid_after_height: None,
+        valid_until_height: None,
+        sig: Signature::Unsigned,
+    };
+    wallet.sign_tx(&mut tx);
+    api::transact(
+        context.clone(),
+        TransactRequest {
+            tx_delta: TransactionAndDelta {
+                tx,
+                state_delta: Some(delta),
+            },
+        },
+    )
+    .await?;
+    Ok(())
+}
@@ -1,6 +1,9 @@
 mod log_info;
 
 mod discover_peers;
+#[cfg(feature = "mpn-executor")]
+mod execute_mpn;
+mod notify_subscribers;
 mod refresh;
 mod sync_blocks;
 mod sync_clock;
@@ -38,6 +41,23 @@ pub async fn make_loop<
 
 pub async fn heartbeater<B: Blockchain>(ctx: Arc<RwLock<NodeContext<B>>>) -> Result<(), NodeError> {
     let ints = ctx.read().await.opts.heartbeat_intervals.clone();
+
+    #[cfg(feature = "mpn-executor")]
+    let execute_mpn = async {
+        let interval = ctx
+            .read()
+            .await
+            .opts
+            .mpn_executor
+            .as_ref()
+            .map(|o| o.interval);
+        if let Some(interval) = interval {
+            make_loop(&ctx, |ctx| execute_mpn::execute_mpn(ctx.clone()), interval).await;
+        }
+    };
+    #[cfg(not(feature = "mpn-executor"))]
+    let execute_mpn = async {};
+
     tokio::join!(
         make_loop(&ctx, |ctx| log_info::log_info(ctx.clone()), ints.log_info),
         make_loop(&ctx, |ctx| refresh::refresh(ctx.clone()), ints.refresh),
@@ -71,6 +91,12 @@ pub async fn heartbeater<B: Blockchain>(ctx: Arc<RwLock<NodeContext<B>>>) -> Res
             |ctx| sync_state::sync_state(ctx.clone()),
             ints.sync_state
         ),
+        make_loop(
+            &ctx,
+            |ctx| notify_subscribers::notify_subscribers(ctx.clone()),
+            ints.notify_subscribers
+        ),
+        execute_mpn,
     );
 
     Ok(())
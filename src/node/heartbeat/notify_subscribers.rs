@@ -0,0 +1,12 @@
+use super::*;
+
+pub async fn notify_subscribers<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+) -> Result<(), NodeError> {
+    let ctx = context.read().await;
+    ctx.subscriptions
+        .lock()
+        .await
+        .on_chain_update(&ctx.blockchain)?;
+    Ok(())
+}
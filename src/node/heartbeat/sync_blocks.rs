@@ -1,5 +1,7 @@
 use super::*;
+use crate::blockchain::BlockchainError;
 use crate::common::*;
+use crate::utils::metrics::METRICS;
 
 pub async fn sync_blocks<B: Blockchain>(
     context: Arc<RwLock<NodeContext<B>>>,
@@ -33,6 +35,7 @@ pub async fn sync_blocks<B: Blockchain>(
             );
 
             let local_height = ctx.blockchain.get_height()?;
+            let finalized_height = ctx.blockchain.get_finalized_height()?;
             let start_height = std::cmp::min(local_height, peer.height);
             drop(ctx);
 
@@ -159,6 +162,14 @@ pub async fn sync_blocks<B: Blockchain>(
 
                 if let Some(local_header) = local_header {
                     if local_header.hash() != peer_header.hash() {
+                        if index < finalized_height {
+                            log::warn!(
+                                "Peer {} is trying to reorg beyond the finality depth!",
+                                peer.address
+                            );
+                            chain_fail = true;
+                            break;
+                        }
                         headers.insert(0, peer_header);
                     } else {
                         break;
@@ -190,6 +201,13 @@ pub async fn sync_blocks<B: Blockchain>(
                     }
                     result
                 }
+                Err(BlockchainError::BeyondFinality) => {
+                    log::warn!(
+                        "Peer {} is trying to reorg beyond the finality depth!",
+                        peer.address
+                    );
+                    false
+                }
                 Err(e) => {
                     log::warn!("Chain is invalid! Error: {}", e);
                     false
@@ -203,6 +221,7 @@ pub async fn sync_blocks<B: Blockchain>(
 
             drop(ctx);
 
+            let fetch_start = std::time::Instant::now();
             if let Ok(resp) = net
                 .bincode_get::<GetBlocksRequest, GetBlocksResponse>(
                     format!("http://{}/bincode/blocks", peer.address).to_string(),
@@ -216,6 +235,7 @@ pub async fn sync_blocks<B: Blockchain>(
                 )
                 .await
             {
+                METRICS.sync_blocks_fetch.observe(fetch_start.elapsed());
                 let mut ctx = context.write().await;
 
                 match ctx.blockchain.extend(headers[0].number, &resp.blocks) {
@@ -41,10 +41,12 @@ pub async fn sync_mempool<B: Blockchain>(
                 if ctx.mempool.mpn_sourced.len() >= ctx.opts.mpn_mempool_capacity {
                     break;
                 }
-                ctx.mempool
-                    .mpn_sourced
-                    .entry(tx)
-                    .or_insert(TransactionStats::new(now));
+                if !ctx.mempool.mpn_sourced.contains_key(&tx) {
+                    ctx.subscriptions.get_mut().on_mpn_mempool_addition(&tx);
+                    ctx.mempool
+                        .mpn_sourced
+                        .insert(tx, TransactionStats::new(now));
+                }
             }
         }
     }
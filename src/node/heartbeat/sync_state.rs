@@ -56,8 +56,11 @@ pub async fn sync_state<B: Blockchain>(
             {
                 Ok(resp) => {
                     log::info!("States downloaded! Applying...");
-                    match context.write().await.blockchain.update_states(&resp.patch) {
-                        Ok(_) => {}
+                    let mut ctx = context.write().await;
+                    match ctx.blockchain.update_states(&resp.patch) {
+                        Ok(_) => {
+                            ctx.notify_subscribers();
+                        }
                         Err(e) => {
                             log::warn!("Wrong state-patch given! Error: {}", e);
                         }
@@ -7,28 +7,40 @@ mod firewall;
 mod heartbeat;
 mod http;
 mod mempool;
+#[cfg(feature = "mpn-executor")]
+mod mpn_executor;
 mod peer_manager;
+mod pool;
+mod rpc;
 pub mod seeds;
-use crate::blockchain::Blockchain;
+mod subscription;
+mod templates;
+use crate::blockchain::{Blockchain, FeeRates};
 use crate::client::{
-    messages::{GetJsonMempoolResponse, SocialProfiles},
+    messages::{GetJsonMempoolResponse, GetJsonRpcMethodsResponse, SocialProfiles},
     Limit, NodeError, NodeRequest, OutgoingSender, Peer, PeerAddress, Timestamp,
     MINER_TOKEN_HEADER, NETWORK_HEADER, SIGNATURE_HEADER,
 };
+use crate::core::Amount;
 use crate::crypto::ed25519;
 use crate::crypto::SignatureScheme;
 use crate::utils::local_timestamp;
 use crate::wallet::TxBuilder;
 use context::NodeContext;
-pub use firewall::Firewall;
+pub use firewall::{Firewall, FirewallLimits, FirewallOptions, FirewallVerdict, IpRange};
 use hyper::body::HttpBody;
 use hyper::{Body, Method, Request, Response, StatusCode};
 use mempool::Mempool;
+#[cfg(feature = "mpn-executor")]
+pub use mpn_executor::{MpnExecutorOptions, NodeMpnExecutor};
 use peer_manager::PeerManager;
+pub use pool::{Pool, PoolOptions};
 use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
+use subscription::Subscriptions;
+pub use templates::{MinerTemplate, MinerTemplates};
 
 use tokio::sync::RwLock;
 use tokio::try_join;
@@ -43,6 +55,7 @@ pub struct HeartbeatIntervals {
     pub sync_blocks: Duration,
     pub sync_mempool: Duration,
     pub sync_state: Duration,
+    pub notify_subscribers: Duration,
 }
 
 #[derive(Debug, Clone)]
@@ -60,6 +73,12 @@ pub struct NodeOptions {
     pub state_unavailable_ban_time: u32,
     pub candidate_remove_threshold: u32,
     pub mpn_mempool_capacity: usize,
+    pub pool: Option<PoolOptions>,
+    pub max_miner_templates: usize,
+    pub miner_template_fee_threshold: Amount,
+    pub fee_rates: FeeRates,
+    #[cfg(feature = "mpn-executor")]
+    pub mpn_executor: Option<MpnExecutorOptions>,
 }
 
 fn fetch_miner_token(req: &Request<Body>) -> Result<Option<String>, NodeError> {
@@ -103,26 +122,44 @@ async fn node_service<B: Blockchain>(
 ) -> Result<Response<Body>, NodeError> {
     let is_local = client.map(|c| c.ip().is_loopback()).unwrap_or(true);
     match async {
-        let is_miner = fetch_miner_token(&req)? == context.read().await.miner_token;
+        // A node without a miner token has no authenticated miners
+        let is_miner = {
+            let miner_token = fetch_miner_token(&req)?;
+            miner_token.is_some() && miner_token == context.read().await.miner_token
+        };
 
         let mut response = Response::builder()
             .header("Access-Control-Allow-Origin", "*")
             .body(Body::default())?;
 
+        let path = req.uri().path().to_string();
+
         if let Some(client) = client {
-            if !is_miner {
-                let mut ctx = context.write().await;
-                let now = ctx.local_timestamp();
+            let mut ctx = context.write().await;
+            let now = ctx.local_timestamp();
+            if is_miner {
+                if let Some(firewall) = &mut ctx.firewall {
+                    firewall.bypass();
+                }
+            } else {
                 if ctx.peer_manager.is_ip_punished(now, client.ip()) {
                     log::warn!("{} -> PeerManager dropped request!", client);
                     *response.status_mut() = StatusCode::FORBIDDEN;
                     return Ok(response);
                 }
                 if let Some(firewall) = &mut ctx.firewall {
-                    if !firewall.incoming_permitted(client) {
-                        log::warn!("{} -> Firewall dropped request!", client);
-                        *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
-                        return Ok(response);
+                    match firewall.incoming_permitted(now, client, &path) {
+                        FirewallVerdict::Permitted => {}
+                        FirewallVerdict::Denylisted => {
+                            log::warn!("{} -> Firewall dropped denylisted request!", client);
+                            *response.status_mut() = StatusCode::FORBIDDEN;
+                            return Ok(response);
+                        }
+                        FirewallVerdict::RateLimited | FirewallVerdict::TrafficLimited => {
+                            log::warn!("{} -> Firewall dropped request!", client);
+                            *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                            return Ok(response);
+                        }
                     }
                 }
             }
@@ -139,7 +176,6 @@ async fn node_service<B: Blockchain>(
                 .body(Body::default())?);
         }
 
-        let path = req.uri().path().to_string();
         let qs = req.uri().query().unwrap_or("").to_string();
 
         log::info!(
@@ -168,8 +204,9 @@ async fn node_service<B: Blockchain>(
         if let Some(req_sz) = body.size_hint().upper() {
             if let Some(client) = client {
                 let mut ctx = context.write().await;
+                let now = ctx.local_timestamp();
                 if let Some(firewall) = &mut ctx.firewall {
-                    firewall.add_traffic(client.ip(), req_sz);
+                    firewall.add_traffic(now, client.ip(), req_sz);
                 }
             }
         } else {
@@ -210,11 +247,90 @@ async fn node_service<B: Blockchain>(
                 )?);
             }
 
+            // Pool miners will call this to fetch new share work.
+            (Method::GET, "/pool/puzzle") => {
+                *response.body_mut() = Body::from(serde_json::to_vec(
+                    &api::get_pool_puzzle(Arc::clone(&context), serde_qs::from_str(&qs)?).await?,
+                )?);
+            }
+
+            // Pool miners will call this when they have found a share.
+            (Method::POST, "/pool/share") => {
+                *response.body_mut() = Body::from(serde_json::to_vec(
+                    &api::post_pool_share(
+                        Arc::clone(&context),
+                        serde_json::from_slice(&body_bytes)?,
+                    )
+                    .await?,
+                )?);
+            }
+
+            (Method::GET, "/pool/shares") => {
+                *response.body_mut() = Body::from(serde_json::to_vec(
+                    &api::get_pool_shares(Arc::clone(&context), serde_qs::from_str(&qs)?).await?,
+                )?);
+            }
+
             (Method::GET, "/stats") => {
                 *response.body_mut() = Body::from(serde_json::to_vec(
                     &api::get_stats(Arc::clone(&context), serde_qs::from_str(&qs)?).await?,
                 )?);
             }
+            (Method::GET, "/metrics") => {
+                response.headers_mut().insert(
+                    hyper::header::CONTENT_TYPE,
+                    hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+                );
+                *response.body_mut() = Body::from(
+                    api::get_metrics(Arc::clone(&context), serde_qs::from_str(&qs)?).await?,
+                );
+            }
+            (Method::GET, "/subscribe") => {
+                let events =
+                    api::subscribe(client, Arc::clone(&context), serde_qs::from_str(&qs)?).await?;
+                response.headers_mut().insert(
+                    hyper::header::CONTENT_TYPE,
+                    hyper::header::HeaderValue::from_static("application/x-ndjson"),
+                );
+                // Events are streamed as newline-delimited json objects
+                *response.body_mut() =
+                    Body::wrap_stream(futures::stream::unfold(events, |mut events| async {
+                        let event = events.recv().await?;
+                        let mut line = serde_json::to_vec(&event).map_err(std::io::Error::other);
+                        if let Ok(line) = &mut line {
+                            line.push(b'\n');
+                        }
+                        Some((line, events))
+                    }));
+            }
+            (Method::POST, "/jsonrpc") => {
+                response.headers_mut().insert(
+                    hyper::header::CONTENT_TYPE,
+                    hyper::header::HeaderValue::from_static("application/json"),
+                );
+                match rpc::json_rpc(
+                    Arc::clone(&context),
+                    client,
+                    is_local,
+                    is_miner,
+                    &body_bytes,
+                )
+                .await
+                {
+                    Some(resp) => {
+                        *response.body_mut() = Body::from(serde_json::to_vec(&resp)?);
+                    }
+                    None => {
+                        *response.status_mut() = StatusCode::NO_CONTENT;
+                    }
+                }
+            }
+            (Method::GET, "/jsonrpc/methods") => {
+                *response.body_mut() =
+                    Body::from(serde_json::to_vec(&GetJsonRpcMethodsResponse {
+                        methods: rpc::methods(),
+                    })?);
+            }
             (Method::GET, "/debug") => {
                 *response.body_mut() = Body::from(serde_json::to_vec(
                     &api::get_debug_data(Arc::clone(&context), serde_qs::from_str(&qs)?).await?,
@@ -235,6 +351,11 @@ async fn node_service<B: Blockchain>(
                     &api::get_mpn_account(Arc::clone(&context), serde_qs::from_str(&qs)?).await?,
                 )?);
             }
+            (Method::GET, "/mpn/address") => {
+                *response.body_mut() = Body::from(serde_json::to_vec(
+                    &api::get_mpn_address(Arc::clone(&context), serde_qs::from_str(&qs)?).await?,
+                )?);
+            }
             (Method::GET, "/peers") => {
                 *response.body_mut() = Body::from(serde_json::to_vec(
                     &api::get_peers(client, Arc::clone(&context), serde_qs::from_str(&qs)?).await?,
@@ -245,6 +366,12 @@ async fn node_service<B: Blockchain>(
                     &api::get_token(Arc::clone(&context), serde_qs::from_str(&qs)?).await?,
                 )?);
             }
+            (Method::GET, "/contract/events") => {
+                *response.body_mut() = Body::from(serde_json::to_vec(
+                    &api::get_contract_events(Arc::clone(&context), serde_qs::from_str(&qs)?)
+                        .await?,
+                )?);
+            }
             (Method::POST, "/peers") => {
                 *response.body_mut() = Body::from(serde_json::to_vec(
                     &api::post_peer(
@@ -316,12 +443,36 @@ async fn node_service<B: Blockchain>(
                         .await?,
                 )?);
             }
+            (Method::GET, "/explorer/contract/events") => {
+                *response.body_mut() = Body::from(serde_json::to_vec(
+                    &api::get_explorer_contract_events(
+                        Arc::clone(&context),
+                        serde_qs::from_str(&qs)?,
+                    )
+                    .await?,
+                )?);
+            }
             (Method::GET, "/explorer/mpn/accounts") => {
                 *response.body_mut() = Body::from(serde_json::to_vec(
                     &api::get_explorer_mpn_accounts(Arc::clone(&context), serde_qs::from_str(&qs)?)
                         .await?,
                 )?);
             }
+            (Method::GET, "/explorer/mpn/address") => {
+                *response.body_mut() = Body::from(serde_json::to_vec(
+                    &api::get_explorer_mpn_address(Arc::clone(&context), serde_qs::from_str(&qs)?)
+                        .await?,
+                )?);
+            }
+            (Method::GET, "/explorer/mpn/address/history") => {
+                *response.body_mut() = Body::from(serde_json::to_vec(
+                    &api::get_explorer_mpn_address_history(
+                        Arc::clone(&context),
+                        serde_qs::from_str(&qs)?,
+                    )
+                    .await?,
+                )?);
+            }
             (Method::GET, "/bincode/headers") => {
                 *response.body_mut() = Body::from(bincode::serialize(
                     &api::get_headers(Arc::clone(&context), bincode::deserialize(&body_bytes)?)
@@ -388,8 +539,9 @@ async fn node_service<B: Blockchain>(
         if let Some(resp_sz) = response.body().size_hint().upper() {
             if let Some(client) = client {
                 let mut ctx = context.write().await;
+                let now = ctx.local_timestamp();
                 if let Some(firewall) = &mut ctx.firewall {
-                    firewall.add_traffic(client.ip(), resp_sz);
+                    firewall.add_traffic(now, client.ip(), resp_sz);
                 }
             }
         }
@@ -416,7 +568,7 @@ async fn node_service<B: Blockchain>(
                     .unwrap_or_else(|| "UNKNOWN".into()),
                 e
             );
-            Err(e)
+            Ok(e.to_http_response())
         }
     }
 }
@@ -437,6 +589,13 @@ pub async fn node_create<B: Blockchain>(
     firewall: Option<Firewall>,
     miner_token: Option<String>,
 ) -> Result<(), NodeError> {
+    #[cfg(feature = "mpn-executor")]
+    let mpn_executor = opts
+        .mpn_executor
+        .as_ref()
+        .map(NodeMpnExecutor::load)
+        .transpose()?;
+
     let context = Arc::new(RwLock::new(NodeContext {
         miner_token,
         firewall,
@@ -454,6 +613,7 @@ pub async fn node_create<B: Blockchain>(
         blockchain,
         wallet,
         mempool: Mempool::default(),
+        subscriptions: Default::default(),
         peer_manager: PeerManager::new(
             address,
             bootstrap,
@@ -464,7 +624,13 @@ pub async fn node_create<B: Blockchain>(
         banned_headers: HashMap::new(),
         outdated_since: None,
 
-        miner_puzzle: None,
+        miner_templates: MinerTemplates::new(opts.max_miner_templates),
+        pool: opts
+            .pool
+            .clone()
+            .map(|p| Pool::new(p, opts.max_miner_templates)),
+        #[cfg(feature = "mpn-executor")]
+        mpn_executor,
     }));
 
     let server_future = async {
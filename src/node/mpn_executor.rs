@@ -0,0 +1,30 @@
+use crate::mpn::{MpnError, MpnExecutor, MpnParams};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone)]
+pub struct MpnExecutorOptions {
+    pub params_dir: PathBuf,
+    pub interval: Duration,
+}
+
+/// Proving parameters and the local MPN state of the built-in executor. The
+/// state is locked separately, so that it's synced and planned on outside of
+/// the lock of the node.
+#[derive(Clone)]
+pub struct NodeMpnExecutor {
+    pub params: Arc<MpnParams>,
+    pub executor: Arc<Mutex<MpnExecutor>>,
+}
+
+impl NodeMpnExecutor {
+    pub fn load(opts: &MpnExecutorOptions) -> Result<Self, MpnError> {
+        let params = MpnParams::load(&opts.params_dir)?;
+        Ok(Self {
+            executor: Arc::new(Mutex::new(MpnExecutor::new(params.shape))),
+            params: Arc::new(params),
+        })
+    }
+}
@@ -1,4 +1,5 @@
 use crate::client::{Peer, PeerAddress};
+use crate::utils::metrics::METRICS;
 use rand::prelude::IteratorRandom;
 use std::collections::HashMap;
 use std::net::IpAddr;
@@ -79,6 +80,7 @@ impl PeerManager {
 
     // Punish peer for a certain time
     pub fn punish_ip_for(&mut self, now: u32, ip: IpAddr, secs: u32) {
+        METRICS.punishments.inc();
         self.candidates.remove(&ip);
         self.nodes.remove(&ip);
         self.punishments.insert(
@@ -106,6 +108,17 @@ impl PeerManager {
         self.nodes.len()
     }
 
+    pub fn candidate_count(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn punished_count(&self, now: u32) -> usize {
+        self.punishments
+            .values()
+            .filter(|det| now < det.punished_till)
+            .count()
+    }
+
     pub fn get_nodes(&self) -> impl Iterator<Item = &Peer> {
         self.nodes.values().map(|n| &n.peer)
     }
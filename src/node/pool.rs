@@ -0,0 +1,123 @@
+use crate::client::messages::PoolMinerShares;
+use crate::consensus::pow::Difficulty;
+use crate::core::hash::Hash;
+use crate::core::{Address, Hasher};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+#[derive(Debug, Clone)]
+pub struct PoolOptions {
+    pub share_target: Difficulty,
+}
+
+/// Keeps track of the shares submitted by the miners of the pool, per round.
+/// A round ends when the pool finds a block.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    opts: PoolOptions,
+    miners: HashMap<Address, PoolMinerShares>,
+    last_round: HashMap<Address, PoolMinerShares>,
+    /// Submitted nonces of the most recent templates
+    seen_nonces: VecDeque<(u64, HashSet<u64>)>,
+    max_templates: usize,
+}
+
+impl Pool {
+    /// Nonces are remembered for the last `max_templates` templates, just like
+    /// the templates of the node itself
+    pub fn new(opts: PoolOptions, max_templates: usize) -> Self {
+        Self {
+            opts,
+            miners: HashMap::new(),
+            last_round: HashMap::new(),
+            seen_nonces: VecDeque::new(),
+            max_templates,
+        }
+    }
+
+    /// Shares are never harder than the block itself
+    pub fn share_target(&self, block_target: Difficulty) -> Difficulty {
+        std::cmp::min(self.opts.share_target, block_target)
+    }
+
+    /// Start of the nonces the miner searches, so that the miners of the pool
+    /// don't redo each other's work
+    pub fn nonce_start(miner: &Address) -> u64 {
+        let hash = Hasher::hash(&bincode::serialize(miner).unwrap());
+        u64::from_le_bytes(hash[..8].try_into().unwrap())
+    }
+
+    /// Returns false if the nonce has already been submitted for the template
+    pub fn register_nonce(&mut self, template_id: u64, nonce: u64) -> bool {
+        if let Some((_, nonces)) = self
+            .seen_nonces
+            .iter_mut()
+            .find(|(id, _)| *id == template_id)
+        {
+            return nonces.insert(nonce);
+        }
+        self.seen_nonces
+            .push_back((template_id, [nonce].into_iter().collect()));
+        while self.seen_nonces.len() > self.max_templates {
+            self.seen_nonces.pop_front();
+        }
+        true
+    }
+
+    pub fn credit(&mut self, miner: Address, target: Difficulty) {
+        let stats = self.miners.entry(miner).or_default();
+        stats.shares += 1;
+        stats.work += target.power();
+    }
+
+    /// Shares of the current round
+    pub fn miners(&self) -> &HashMap<Address, PoolMinerShares> {
+        &self.miners
+    }
+
+    /// Shares of the round which ended with the last block found by the pool
+    pub fn last_round(&self) -> &HashMap<Address, PoolMinerShares> {
+        &self.last_round
+    }
+
+    pub fn finish_round(&mut self) {
+        self.last_round = std::mem::take(&mut self.miners);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_shares() {
+        let easy = Difficulty(0x007fffff);
+        let hard = Difficulty(0x0000ffff);
+        let mut pool = Pool::new(PoolOptions { share_target: easy }, 2);
+        assert_eq!(pool.share_target(hard), easy);
+        assert_eq!(
+            Pool::new(PoolOptions { share_target: hard }, 2).share_target(easy),
+            easy
+        );
+
+        assert!(pool.register_nonce(1, 1));
+        assert!(!pool.register_nonce(1, 1));
+        assert!(pool.register_nonce(2, 1));
+        // Shares of older templates are still checked while they are kept
+        assert!(!pool.register_nonce(1, 1));
+        assert!(pool.register_nonce(3, 1));
+        assert!(pool.register_nonce(1, 1));
+
+        let miner = Address::default();
+        let other: Address = crate::wallet::TxBuilder::new(b"other").get_address();
+        assert_ne!(Pool::nonce_start(&miner), Pool::nonce_start(&other));
+
+        pool.credit(miner.clone(), easy);
+        pool.credit(miner.clone(), easy);
+        assert_eq!(pool.miners()[&miner].shares, 2);
+        assert_eq!(pool.miners()[&miner].work, 2 * easy.power());
+
+        pool.finish_round();
+        assert!(pool.miners().is_empty());
+        assert_eq!(pool.last_round()[&miner].shares, 2);
+    }
+}
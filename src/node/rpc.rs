@@ -0,0 +1,427 @@
+use super::{api, FirewallVerdict, NodeContext, NodeError};
+use crate::blockchain::Blockchain;
+use crate::client::messages::*;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+pub const SERVER_ERROR: i64 = -32000;
+pub const BLOCKCHAIN_ERROR: i64 = -32001;
+pub const FORBIDDEN: i64 = -32002;
+pub const RATE_LIMITED: i64 = -32003;
+
+/// Maximum number of calls in a single batch request
+pub const MAX_BATCH_SIZE: usize = 32;
+
+enum RpcCallError {
+    MethodNotFound,
+    InvalidParams(serde_json::Error),
+    Forbidden,
+    RateLimited,
+    Node(NodeError),
+}
+
+impl From<NodeError> for RpcCallError {
+    fn from(e: NodeError) -> Self {
+        Self::Node(e)
+    }
+}
+
+impl From<RpcCallError> for JsonRpcError {
+    fn from(e: RpcCallError) -> Self {
+        match e {
+            RpcCallError::MethodNotFound => JsonRpcError {
+                code: METHOD_NOT_FOUND,
+                message: "method not found".into(),
+                data: None,
+            },
+            RpcCallError::InvalidParams(e) => JsonRpcError {
+                code: INVALID_PARAMS,
+                message: format!("invalid params: {}", e),
+                data: None,
+            },
+            RpcCallError::Forbidden => JsonRpcError {
+                code: FORBIDDEN,
+                message: "method is only available locally".into(),
+                data: None,
+            },
+            RpcCallError::RateLimited => JsonRpcError {
+                code: RATE_LIMITED,
+                message: "rate limited by the firewall".into(),
+                data: None,
+            },
+            RpcCallError::Node(e) => JsonRpcError {
+                code: error_code(&e),
+                message: e.to_string(),
+                data: serde_json::to_value(e.to_response()).ok(),
+            },
+        }
+    }
+}
+
+fn error_code(e: &NodeError) -> i64 {
+    match e {
+        NodeError::BlockchainError(_) => BLOCKCHAIN_ERROR,
+        NodeError::JsonError(_)
+        | NodeError::QueryStringError(_)
+        | NodeError::BincodeError(_)
+        | NodeError::Utf8Error(_)
+        | NodeError::AddrParseError(_)
+        | NodeError::AccountParseAddressError(_)
+        | NodeError::TokenIdParseError(_)
+        | NodeError::ContractIdParseError(_)
+        | NodeError::MpnAddressParseError(_)
+        | NodeError::InputError(_) => INVALID_PARAMS,
+        NodeError::ServerError(_) | NodeError::ClientError(_) => INTERNAL_ERROR,
+        _ => SERVER_ERROR,
+    }
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(
+    params: serde_json::Value,
+) -> Result<T, RpcCallError> {
+    // Omitted params are treated as an empty object
+    let params = if params.is_null() {
+        serde_json::Value::Object(Default::default())
+    } else {
+        params
+    };
+    serde_json::from_value(params).map_err(RpcCallError::InvalidParams)
+}
+
+/// Records the fields of the struct being deserialized, and aborts right after
+struct StructIntrospector<'a>(&'a mut Option<&'static [&'static str]>);
+
+impl<'de, 'a> serde::Deserializer<'de> for StructIntrospector<'a> {
+    type Error = serde::de::value::Error;
+
+    fn deserialize_any<V: serde::de::Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(serde::de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: serde::de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = Some(fields);
+        Err(serde::de::Error::custom("introspected"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+        byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct map
+        enum identifier ignored_any
+    }
+}
+
+/// Names of the fields of the params object of a method, empty if the params
+/// are not a struct
+fn params_fields<T: serde::de::DeserializeOwned>() -> Vec<String> {
+    let mut introspected = None;
+    let _ = T::deserialize(StructIntrospector(&mut introspected));
+    introspected
+        .unwrap_or_default()
+        .iter()
+        .map(|f| f.to_string())
+        .collect()
+}
+
+macro_rules! rpc_methods {
+    ( $( $name:literal => $handler:path [$access:ident] $route:literal : $req:ty => $resp:ty; )* ) => {
+        pub fn methods() -> Vec<JsonRpcMethod> {
+            vec![
+                $(
+                    JsonRpcMethod {
+                        name: $name.into(),
+                        params: stringify!($req).into(),
+                        params_fields: params_fields::<$req>(),
+                        result: stringify!($resp).into(),
+                        local_only: rpc_methods!(@local_only $access),
+                    },
+                )*
+            ]
+        }
+
+        /// The HTTP route of the method, its firewall cost is charged per call
+        fn route(method: &str) -> Option<&'static str> {
+            match method {
+                $( $name => Some($route), )*
+                _ => None,
+            }
+        }
+
+        async fn dispatch<B: Blockchain>(
+            context: &Arc<RwLock<NodeContext<B>>>,
+            client: Option<SocketAddr>,
+            is_local: bool,
+            method: &str,
+            params: serde_json::Value,
+        ) -> Result<serde_json::Value, RpcCallError> {
+            match method {
+                $(
+                    $name => {
+                        let req: $req = parse_params(params)?;
+                        let resp: $resp =
+                            rpc_methods!(@call $access, $handler, context, client, is_local, req);
+                        Ok(serde_json::to_value(resp).map_err(NodeError::from)?)
+                    }
+                )*
+                _ => Err(RpcCallError::MethodNotFound),
+            }
+        }
+    };
+    (@local_only public) => { false };
+    (@local_only with_client) => { false };
+    (@local_only local) => { true };
+    (@call public, $handler:path, $ctx:ident, $client:ident, $is_local:ident, $req:ident) => {
+        $handler(Arc::clone($ctx), $req).await?
+    };
+    (@call with_client, $handler:path, $ctx:ident, $client:ident, $is_local:ident, $req:ident) => {
+        $handler($client, Arc::clone($ctx), $req).await?
+    };
+    (@call local, $handler:path, $ctx:ident, $client:ident, $is_local:ident, $req:ident) => {{
+        if !$is_local {
+            return Err(RpcCallError::Forbidden);
+        }
+        $handler(Arc::clone($ctx), $req).await?
+    }};
+}
+
+rpc_methods! {
+    "stats" => api::get_stats [public] "/stats" : GetStatsRequest => GetStatsResponse;
+    "debug" => api::get_debug_data [public] "/debug" : GetDebugDataRequest => GetDebugDataResponse;
+    "metrics" => api::get_metrics [public] "/metrics" : GetMetricsRequest => String;
+    "account" => api::get_account [public] "/account" : GetAccountRequest => GetAccountResponse;
+    "balance" => api::get_balance [public] "/balance" : GetBalanceRequest => GetBalanceResponse;
+    "token" => api::get_token [public] "/token" : GetTokenInfoRequest => GetTokenInfoResponse;
+    "contract_events" => api::get_contract_events [public] "/contract/events" : GetContractEventsRequest => GetContractEventsResponse;
+    "mpn_account" => api::get_mpn_account [public] "/mpn/account" : GetMpnAccountRequest => GetMpnAccountResponse;
+    "mpn_address" => api::get_mpn_address [public] "/mpn/address" : GetMpnAddressRequest => GetMpnAddressResponse;
+    "peers" => api::get_peers [with_client] "/peers" : GetPeersRequest => GetPeersResponse;
+    "post_peer" => api::post_peer [with_client] "/peers" : HandshakeRequest => HandshakeResponse;
+    "headers" => api::get_headers [public] "/bincode/headers" : GetHeadersRequest => GetHeadersResponse;
+    "blocks" => api::get_blocks [public] "/bincode/blocks" : GetBlocksRequest => GetBlocksResponse;
+    "post_block" => api::post_block [public] "/bincode/blocks" : PostBlockRequest => PostBlockResponse;
+    "states" => api::get_states [public] "/bincode/states" : GetStatesRequest => GetStatesResponse;
+    "outdated_heights" => api::get_outdated_heights [public] "/bincode/states/outdated" : GetOutdatedHeightsRequest => GetOutdatedHeightsResponse;
+    "mempool" => api::get_mempool [public] "/mempool" : GetMempoolRequest => GetMempoolResponse;
+    "zero_mempool" => api::get_zero_mempool [local] "/bincode/mempool/zero" : GetZeroMempoolRequest => GetZeroMempoolResponse;
+    "transact" => api::transact [public] "/bincode/transact" : TransactRequest => TransactResponse;
+    "transact_zero" => api::post_mpn_transaction [public] "/transact/zero" : PostMpnTransactionRequest => PostMpnTransactionResponse;
+    "transact_deposit" => api::post_mpn_deposit [public] "/bincode/transact/deposit" : PostMpnDepositRequest => PostMpnDepositResponse;
+    "transact_withdraw" => api::post_mpn_withdraw [public] "/bincode/transact/withdraw" : PostMpnWithdrawRequest => PostMpnWithdrawResponse;
+    "explorer_blocks" => api::get_explorer_blocks [public] "/explorer/blocks" : GetExplorerBlocksRequest => GetExplorerBlocksResponse;
+    "explorer_contract_events" => api::get_explorer_contract_events [public] "/explorer/contract/events" : GetExplorerContractEventsRequest => GetExplorerContractEventsResponse;
+    "explorer_mpn_accounts" => api::get_explorer_mpn_accounts [public] "/explorer/mpn/accounts" : GetExplorerMpnAccountsRequest => GetExplorerMpnAccountsResponse;
+    "explorer_mpn_address" => api::get_explorer_mpn_address [public] "/explorer/mpn/address" : GetExplorerMpnAddressRequest => GetExplorerMpnAddressResponse;
+    "explorer_mpn_address_history" => api::get_explorer_mpn_address_history [public] "/explorer/mpn/address/history" : GetExplorerMpnAddressHistoryRequest => GetExplorerMpnAddressHistoryResponse;
+    "miner_puzzle" => api::get_miner_puzzle [public] "/miner/puzzle" : GetMinerPuzzleRequest => GetMinerPuzzleResponse;
+    "miner_solution" => api::post_miner_solution [public] "/miner/solution" : PostMinerSolutionRequest => PostMinerSolutionResponse;
+    "pool_puzzle" => api::get_pool_puzzle [public] "/pool/puzzle" : GetPoolPuzzleRequest => GetPoolPuzzleResponse;
+    "pool_share" => api::post_pool_share [public] "/pool/share" : PostPoolShareRequest => PostPoolShareResponse;
+    "pool_shares" => api::get_pool_shares [public] "/pool/shares" : GetPoolSharesRequest => GetPoolSharesResponse;
+    "shutdown" => api::shutdown [local] "/shutdown" : ShutdownRequest => ShutdownResponse;
+}
+
+fn error_response(id: serde_json::Value, error: JsonRpcError) -> JsonRpcResponse {
+    JsonRpcResponse {
+        jsonrpc: JSONRPC_VERSION.into(),
+        result: None,
+        error: Some(error),
+        id,
+    }
+}
+
+/// Charges the client as if the route of the method was requested directly
+async fn charge<B: Blockchain>(
+    context: &Arc<RwLock<NodeContext<B>>>,
+    client: SocketAddr,
+    method: &str,
+) -> Result<(), RpcCallError> {
+    let mut ctx = context.write().await;
+    let now = ctx.local_timestamp();
+    if let Some(firewall) = &mut ctx.firewall {
+        let route = route(method).unwrap_or("/jsonrpc/methods");
+        if firewall.incoming_permitted(now, client, route) != FirewallVerdict::Permitted {
+            return Err(RpcCallError::RateLimited);
+        }
+    }
+    Ok(())
+}
+
+async fn handle_single<B: Blockchain>(
+    context: &Arc<RwLock<NodeContext<B>>>,
+    client: Option<SocketAddr>,
+    is_local: bool,
+    is_miner: bool,
+    req: serde_json::Value,
+) -> Option<JsonRpcResponse> {
+    let req: JsonRpcRequest = match serde_json::from_value(req) {
+        Ok(req) => req,
+        Err(e) => {
+            return Some(error_response(
+                serde_json::Value::Null,
+                JsonRpcError {
+                    code: INVALID_REQUEST,
+                    message: format!("invalid request: {}", e),
+                    data: None,
+                },
+            ));
+        }
+    };
+    if req.jsonrpc != JSONRPC_VERSION {
+        return Some(error_response(
+            req.id.unwrap_or_default(),
+            JsonRpcError {
+                code: INVALID_REQUEST,
+                message: "only jsonrpc 2.0 is supported".into(),
+                data: None,
+            },
+        ));
+    }
+    let charged = match client {
+        Some(client) if !is_miner => charge(context, client, &req.method).await,
+        _ => Ok(()),
+    };
+    let result = if let Err(e) = charged {
+        Err(e)
+    } else if req.method == "rpc_methods" {
+        Ok(
+            serde_json::to_value(GetJsonRpcMethodsResponse { methods: methods() })
+                .unwrap_or_default(),
+        )
+    } else {
+        dispatch(context, client, is_local, &req.method, req.params).await
+    };
+    if let Err(RpcCallError::Node(e)) = &result {
+        log::warn!("JSON-RPC method {} failed: {}", req.method, e);
+    }
+    let id = req.id?;
+    Some(match result {
+        Ok(result) => JsonRpcResponse {
+            jsonrpc: JSONRPC_VERSION.into(),
+            result: Some(result),
+            error: None,
+            id,
+        },
+        Err(e) => error_response(id, e.into()),
+    })
+}
+
+/// Handles a single or a batch JSON-RPC 2.0 request. `None` is returned when
+/// there is nothing to respond (E.g. the request only had notifications)
+pub async fn json_rpc<B: Blockchain>(
+    context: Arc<RwLock<NodeContext<B>>>,
+    client: Option<SocketAddr>,
+    is_local: bool,
+    is_miner: bool,
+    body: &[u8],
+) -> Option<serde_json::Value> {
+    let req: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(req) => req,
+        Err(e) => {
+            return serde_json::to_value(error_response(
+                serde_json::Value::Null,
+                JsonRpcError {
+                    code: PARSE_ERROR,
+                    message: format!("parse error: {}", e),
+                    data: None,
+                },
+            ))
+            .ok();
+        }
+    };
+    match req {
+        serde_json::Value::Array(reqs) => {
+            if reqs.is_empty() {
+                return serde_json::to_value(error_response(
+                    serde_json::Value::Null,
+                    JsonRpcError {
+                        code: INVALID_REQUEST,
+                        message: "empty batch".into(),
+                        data: None,
+                    },
+                ))
+                .ok();
+            }
+            if reqs.len() > MAX_BATCH_SIZE {
+                return serde_json::to_value(error_response(
+                    serde_json::Value::Null,
+                    JsonRpcError {
+                        code: INVALID_REQUEST,
+                        message: format!("batch is larger than {} calls", MAX_BATCH_SIZE),
+                        data: None,
+                    },
+                ))
+                .ok();
+            }
+            let mut resps = Vec::new();
+            for req in reqs {
+                if let Some(resp) = handle_single(&context, client, is_local, is_miner, req).await {
+                    resps.push(resp);
+                }
+            }
+            if resps.is_empty() {
+                None
+            } else {
+                serde_json::to_value(resps).ok()
+            }
+        }
+        req => handle_single(&context, client, is_local, is_miner, req)
+            .await
+            .and_then(|resp| serde_json::to_value(resp).ok()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::BlockchainError;
+
+    #[test]
+    fn test_error_codes() {
+        let err: JsonRpcError = RpcCallError::MethodNotFound.into();
+        assert_eq!(err.code, METHOD_NOT_FOUND);
+        let err: JsonRpcError = RpcCallError::Node(NodeError::BlockchainError(
+            BlockchainError::BalanceInsufficient,
+        ))
+        .into();
+        assert_eq!(err.code, BLOCKCHAIN_ERROR);
+        let err: JsonRpcError =
+            RpcCallError::Node(NodeError::InputError(InputError::Invalid)).into();
+        assert_eq!(err.code, INVALID_PARAMS);
+        let err: JsonRpcError = RpcCallError::Node(NodeError::WrongNetwork).into();
+        assert_eq!(err.code, SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_methods_are_unique() {
+        let methods = methods();
+        let names = methods
+            .iter()
+            .map(|m| m.name.clone())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(names.len(), methods.len());
+        assert!(methods.iter().any(|m| m.name == "shutdown" && m.local_only));
+        assert!(parse_params::<GetStatsRequest>(serde_json::Value::Null).is_ok());
+    }
+
+    #[test]
+    fn test_methods_params() {
+        let methods = methods();
+        let balance = methods.iter().find(|m| m.name == "balance").unwrap();
+        assert_eq!(balance.params, "GetBalanceRequest");
+        assert_eq!(balance.params_fields, vec!["address", "token_id"]);
+        assert!(methods.iter().all(|m| route(&m.name).is_some()));
+        assert_eq!(route("post_block"), Some("/bincode/blocks"));
+    }
+}
@@ -0,0 +1,246 @@
+use crate::blockchain::{Blockchain, BlockchainError};
+use crate::client::messages::{NodeEvent, SubscribeRequest};
+use crate::client::NodeError;
+use crate::core::{Address, Amount, Header, MpnAddress, MpnSourcedTx, TokenId};
+use crate::zk::MpnAccount;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use tokio::sync::mpsc;
+
+/// Number of recent headers kept for finding the fork point of a reorg
+const RECENT_HEADERS: usize = 64;
+
+/// Number of undelivered events after which a subscriber is considered too slow
+/// and is dropped
+const EVENT_BUFFER: usize = 256;
+
+/// Maximum number of open subscriptions of a single client
+const MAX_SUBSCRIBERS_PER_IP: usize = 4;
+
+type AccountSnapshot = (u32, Vec<(TokenId, Amount)>);
+
+struct Subscriber {
+    ip: Option<IpAddr>,
+    headers: bool,
+    rollbacks: bool,
+    mpn_mempool: bool,
+    tokens: Vec<TokenId>,
+    accounts: Vec<(Address, Option<AccountSnapshot>)>,
+    mpn_accounts: Vec<(MpnAddress, Option<MpnAccount>)>,
+    chan: mpsc::Sender<NodeEvent>,
+}
+
+#[derive(Default)]
+pub struct Subscriptions {
+    recent: VecDeque<Header>,
+    subscribers: Vec<Subscriber>,
+    outdated: bool,
+}
+
+impl Subscriptions {
+    /// Fails when the subscriber is gone or is not keeping up with the events
+    fn send(chan: &mpsc::Sender<NodeEvent>, event: NodeEvent) -> bool {
+        chan.try_send(event).is_ok()
+    }
+
+    /// Chain changes are delivered on the next `on_chain_update`
+    pub fn mark_outdated(&mut self) {
+        self.outdated = true;
+    }
+
+    pub fn subscribe<B: Blockchain>(
+        &mut self,
+        blockchain: &B,
+        ip: Option<IpAddr>,
+        req: SubscribeRequest,
+    ) -> Result<mpsc::Receiver<NodeEvent>, NodeError> {
+        self.subscribers.retain(|s| !s.chan.is_closed());
+        if ip.is_some()
+            && self.subscribers.iter().filter(|s| s.ip == ip).count() >= MAX_SUBSCRIBERS_PER_IP
+        {
+            return Err(NodeError::TooManySubscriptions);
+        }
+        let (chan, recv) = mpsc::channel(EVENT_BUFFER);
+        let mut tokens = req
+            .tokens
+            .iter()
+            .map(|t| t.parse())
+            .collect::<Result<Vec<TokenId>, _>>()?;
+        if tokens.is_empty() {
+            tokens.push(TokenId::Ziesha);
+        }
+        let mut subscriber = Subscriber {
+            ip,
+            headers: req.headers,
+            rollbacks: req.rollbacks,
+            mpn_mempool: req.mpn_mempool,
+            tokens,
+            accounts: req
+                .addresses
+                .iter()
+                .map(|a| a.parse().map(|a| (a, None)))
+                .collect::<Result<Vec<_>, _>>()?,
+            mpn_accounts: req
+                .mpn_addresses
+                .iter()
+                .map(|a| {
+                    a.parse::<MpnAddress>()
+                        .map(|a| (a, None))
+                        .map_err(|_| crate::client::messages::InputError::Invalid)
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            chan,
+        };
+        if self.subscribers.is_empty() {
+            self.recent.clear();
+            let height = blockchain.get_height()?;
+            let since = height.saturating_sub(RECENT_HEADERS as u64);
+            self.recent
+                .extend(blockchain.get_headers(since, height - since)?);
+        }
+        // Initial snapshots are sent right away
+        Self::refresh_accounts(blockchain, &mut subscriber)?;
+        self.subscribers.push(subscriber);
+        Ok(recv)
+    }
+
+    fn refresh_accounts<B: Blockchain>(
+        blockchain: &B,
+        subscriber: &mut Subscriber,
+    ) -> Result<bool, BlockchainError> {
+        let mut alive = true;
+        for (addr, snapshot) in subscriber.accounts.iter_mut() {
+            let nonce = blockchain.get_account(addr.clone())?.nonce;
+            let balances = subscriber
+                .tokens
+                .iter()
+                .map(|t| Ok((*t, blockchain.get_balance(addr.clone(), *t)?)))
+                .collect::<Result<Vec<_>, BlockchainError>>()?;
+            let curr = (nonce, balances);
+            if snapshot.as_ref() != Some(&curr) {
+                alive &= Self::send(
+                    &subscriber.chan,
+                    NodeEvent::AccountChanged {
+                        address: addr.to_string(),
+                        nonce: curr.0,
+                        balances: curr
+                            .1
+                            .iter()
+                            .map(|(t, a)| (t.to_string(), *a))
+                            .collect::<HashMap<_, _>>(),
+                    },
+                );
+                *snapshot = Some(curr);
+            }
+        }
+        for (addr, snapshot) in subscriber.mpn_accounts.iter_mut() {
+            let acc = match blockchain.get_mpn_account_index(addr) {
+                Ok(index) => blockchain.get_mpn_account(index)?,
+                Err(BlockchainError::MpnAccountSlotsFull) => MpnAccount::default(),
+                Err(e) => return Err(e),
+            };
+            // The slot might still be empty
+            let acc = if acc.address.is_on_curve()
+                && crate::crypto::jubjub::PublicKey(acc.address.compress()) == addr.pub_key
+            {
+                acc
+            } else {
+                MpnAccount::default()
+            };
+            if snapshot.as_ref() != Some(&acc) {
+                alive &= Self::send(
+                    &subscriber.chan,
+                    NodeEvent::MpnAccountChanged {
+                        address: addr.to_string(),
+                        account: acc.clone(),
+                    },
+                );
+                *snapshot = Some(acc);
+            }
+        }
+        Ok(alive)
+    }
+
+    /// Delivers the chain changes since the subscriptions were marked as outdated
+    pub fn on_chain_update<B: Blockchain>(
+        &mut self,
+        blockchain: &B,
+    ) -> Result<(), BlockchainError> {
+        if !std::mem::take(&mut self.outdated) || self.subscribers.is_empty() {
+            return Ok(());
+        }
+        let height = blockchain.get_height()?;
+
+        // Remove the headers which are not on the chain anymore
+        let mut rolled_back_to = None;
+        while let Some(last) = self.recent.back() {
+            if last.number < height && blockchain.get_header(last.number)? == *last {
+                break;
+            }
+            rolled_back_to = Some(last.number);
+            self.recent.pop_back();
+        }
+        let fork_height = self
+            .recent
+            .back()
+            .map(|h| h.number + 1)
+            .or(rolled_back_to)
+            .unwrap_or(0);
+        let new_headers = blockchain.get_headers(fork_height, height - fork_height)?;
+
+        let mut events = Vec::new();
+        if rolled_back_to.is_some() {
+            events.push((
+                true,
+                NodeEvent::Rollback {
+                    height: fork_height,
+                },
+            ));
+        }
+        for header in new_headers.iter() {
+            events.push((
+                false,
+                NodeEvent::NewHeader {
+                    header: header.clone(),
+                },
+            ));
+        }
+
+        self.recent.extend(new_headers);
+        while self.recent.len() > RECENT_HEADERS {
+            self.recent.pop_front();
+        }
+
+        let mut i = 0;
+        while i < self.subscribers.len() {
+            let subscriber = &mut self.subscribers[i];
+            let mut alive = true;
+            for (is_rollback, event) in events.iter() {
+                if (*is_rollback && subscriber.rollbacks) || (!*is_rollback && subscriber.headers) {
+                    alive &= Self::send(&subscriber.chan, event.clone());
+                }
+            }
+            alive &= Self::refresh_accounts(blockchain, subscriber)?;
+            if alive {
+                i += 1;
+            } else {
+                self.subscribers.swap_remove(i);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn on_mpn_mempool_addition(&mut self, tx: &MpnSourcedTx) {
+        if let MpnSourcedTx::MpnTransaction(tx) = tx {
+            self.subscribers.retain(|s| {
+                !s.mpn_mempool
+                    || Self::send(
+                        &s.chan,
+                        NodeEvent::MpnMempoolTransaction {
+                            tx: tx.clone().into(),
+                        },
+                    )
+            });
+        }
+    }
+}
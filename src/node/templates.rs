@@ -0,0 +1,58 @@
+use crate::blockchain::BlockAndPatch;
+use crate::client::messages::Puzzle;
+use crate::core::Amount;
+use std::collections::VecDeque;
+
+#[derive(Clone)]
+pub struct MinerTemplate {
+    pub draft: BlockAndPatch,
+    pub puzzle: Puzzle,
+    pub fees: Amount, // Fees of the mempool when the template was built
+}
+
+/// Recent block templates handed to the miners. All of them are built on the
+/// current tip, so a solution to any of them is still valid.
+#[derive(Clone)]
+pub struct MinerTemplates {
+    capacity: usize,
+    next_id: u64,
+    templates: VecDeque<MinerTemplate>,
+}
+
+impl MinerTemplates {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            next_id: 0,
+            templates: VecDeque::new(),
+        }
+    }
+
+    pub fn latest(&self) -> Option<&MinerTemplate> {
+        self.templates.back()
+    }
+
+    /// Finds a template by its id, `None` means the latest one
+    pub fn get(&self, id: Option<u64>) -> Option<&MinerTemplate> {
+        match id {
+            Some(id) => self.templates.iter().find(|t| t.puzzle.template_id == id),
+            None => self.latest(),
+        }
+    }
+
+    /// Assigns an id to the template and keeps it
+    pub fn push(&mut self, mut template: MinerTemplate) -> &MinerTemplate {
+        template.puzzle.template_id = self.next_id;
+        self.next_id += 1;
+        self.templates.push_back(template);
+        while self.templates.len() > self.capacity {
+            self.templates.pop_front();
+        }
+        self.templates.back().unwrap()
+    }
+
+    /// Templates become invalid as soon as the tip of the chain changes
+    pub fn clear(&mut self) {
+        self.templates.clear();
+    }
+}
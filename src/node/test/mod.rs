@@ -245,6 +245,7 @@ fn sample_contract_call() -> TransactionAndDelta {
         state_delta.clone(),
         state_model.compress::<ZkHasher>(&full_state.data).unwrap(),
         zk::ZkProof::Dummy(true),
+        vec![],
         Money::ziesha(0),
         Money::ziesha(0),
         1,
@@ -409,3 +410,62 @@ async fn test_chain_rolls_back() -> Result<(), NodeError> {
     tokio::try_join!(node_futs, route_futs, test_logic)?;
     Ok(())
 }
+
+#[tokio::test]
+async fn test_subscription_events() -> Result<(), NodeError> {
+    use crate::client::messages::{NodeEvent, SubscribeRequest};
+    use futures::StreamExt;
+
+    init();
+
+    let rules = Arc::new(RwLock::new(vec![]));
+    let conf = blockchain::get_test_blockchain_config();
+    let miner = TxBuilder::new(&Vec::from("ABC"));
+
+    let (node_futs, route_futs, chans) = simulation::test_network(
+        Arc::clone(&rules),
+        vec![NodeOpts {
+            config: conf.clone(),
+            wallet: miner.clone(),
+            addr: 120,
+            bootstrap: vec![],
+            timestamp_offset: 5,
+        }],
+    );
+    let test_logic = async {
+        let mut events = Box::pin(
+            chans[0]
+                .subscribe(SubscribeRequest {
+                    headers: true,
+                    addresses: vec![miner.get_address().to_string()],
+                    ..Default::default()
+                })
+                .await?,
+        );
+
+        // Current state of the account is sent on subscription
+        assert!(matches!(
+            events.next().await.unwrap()?,
+            NodeEvent::AccountChanged { .. }
+        ));
+
+        chans[0].mine().await?;
+        assert!(
+            matches!(events.next().await.unwrap()?, NodeEvent::NewHeader { header } if header.number == 1)
+        );
+        // Miner has received the block reward
+        assert!(matches!(
+            events.next().await.unwrap()?,
+            NodeEvent::AccountChanged { .. }
+        ));
+
+        drop(events);
+        for chan in chans.iter() {
+            chan.shutdown().await?;
+        }
+
+        Ok::<(), NodeError>(())
+    };
+    tokio::try_join!(node_futs, route_futs, test_logic)?;
+    Ok(())
+}
@@ -0,0 +1,241 @@
+//! Helpers for writing `ZkContract`s. The state of a contract is described by
+//! a `StateSchema` with named fields, which compiles to a `ZkStateModel`, and
+//! its cells are addressed by paths like `balances[3].amount` instead of raw
+//! `ZkDataLocator`s.
+
+use crate::core::ZkHasher as CoreZkHasher;
+use crate::zk::{
+    StateManagerError, ZkCompressedState, ZkDataLocator, ZkDataPairs, ZkDeltaPairs, ZkHasher,
+    ZkScalar, ZkStateModel,
+};
+use ff::Field;
+use thiserror::Error;
+
+/// Lists are indexed with 64-bit numbers
+const MAX_LIST_LOG4_SIZE: u8 = 31;
+
+#[derive(Error, Debug)]
+pub enum SdkError {
+    #[error("field {0} not found")]
+    FieldNotFound(String),
+    #[error("field {0} is defined more than once")]
+    DuplicateField(String),
+    #[error("struct has {0} fields, more than the hasher supports")]
+    TooManyFields(usize),
+    #[error("list of 4^{0} items is too large")]
+    ListTooLarge(u8),
+    #[error("index {0} is out of the list")]
+    IndexOutOfRange(u64),
+    #[error("path {0} is invalid")]
+    InvalidPath(String),
+    #[error("path {0} does not point to a scalar")]
+    NotAScalar(String),
+    #[error("state manager error: {0}")]
+    StateManagerError(#[from] StateManagerError),
+}
+
+/// Declarative description of the state of a contract
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateSchema {
+    Scalar,
+    Struct(Vec<(String, StateSchema)>),
+    List {
+        log4_size: u8,
+        item: Box<StateSchema>,
+    },
+}
+
+impl StateSchema {
+    pub fn scalar() -> Self {
+        Self::Scalar
+    }
+
+    pub fn structure<S: Into<String>, I: IntoIterator<Item = (S, StateSchema)>>(fields: I) -> Self {
+        Self::Struct(fields.into_iter().map(|(n, s)| (n.into(), s)).collect())
+    }
+
+    /// A list of `4^log4_size` items
+    pub fn list(log4_size: u8, item: StateSchema) -> Self {
+        Self::List {
+            log4_size,
+            item: Box::new(item),
+        }
+    }
+
+    /// Checks the schema and builds the state model of the contract
+    pub fn compile(&self) -> Result<ZkStateModel, SdkError> {
+        Ok(match self {
+            Self::Scalar => ZkStateModel::Scalar,
+            Self::Struct(fields) => {
+                if fields.len() > CoreZkHasher::MAX_ARITY {
+                    return Err(SdkError::TooManyFields(fields.len()));
+                }
+                for (i, (name, _)) in fields.iter().enumerate() {
+                    if fields[..i].iter().any(|(n, _)| n == name) {
+                        return Err(SdkError::DuplicateField(name.clone()));
+                    }
+                }
+                ZkStateModel::Struct {
+                    field_types: fields
+                        .iter()
+                        .map(|(_, s)| s.compile())
+                        .collect::<Result<Vec<_>, _>>()?,
+                }
+            }
+            Self::List { log4_size, item } => {
+                if *log4_size > MAX_LIST_LOG4_SIZE {
+                    return Err(SdkError::ListTooLarge(*log4_size));
+                }
+                ZkStateModel::List {
+                    log4_size: *log4_size,
+                    item_type: Box::new(item.compile()?),
+                }
+            }
+        })
+    }
+
+    /// Resolves a path like `accounts[2].balance` into the locator of a cell.
+    /// Items of a list at the root are addressed as `[2]`.
+    pub fn locate(&self, path: &str) -> Result<ZkDataLocator, SdkError> {
+        let invalid = || SdkError::InvalidPath(path.into());
+        let mut locator = ZkDataLocator(vec![]);
+        let mut curr = self;
+        if path.is_empty() {
+            return Ok(locator);
+        }
+        for (i, part) in path.split('.').enumerate() {
+            let (name, indices) = part.split_at(part.find('[').unwrap_or(part.len()));
+            // Only a list at the root may be indexed without a field name
+            if name.is_empty() && (i > 0 || indices.is_empty()) {
+                return Err(invalid());
+            }
+            if !name.is_empty() {
+                let fields = match curr {
+                    Self::Struct(fields) => fields,
+                    _ => return Err(SdkError::FieldNotFound(name.into())),
+                };
+                let (ind, (_, field)) = fields
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (n, _))| n == name)
+                    .ok_or_else(|| SdkError::FieldNotFound(name.into()))?;
+                locator.0.push(ind as u64);
+                curr = field;
+            }
+            let mut rest = indices;
+            while !rest.is_empty() {
+                let end = rest.find(']').ok_or_else(invalid)?;
+                if !rest.starts_with('[') {
+                    return Err(invalid());
+                }
+                let ind: u64 = rest[1..end].parse().map_err(|_| invalid())?;
+                match curr {
+                    Self::List { log4_size, item } => {
+                        if *log4_size > MAX_LIST_LOG4_SIZE {
+                            return Err(SdkError::ListTooLarge(*log4_size));
+                        }
+                        if ind >= 1 << (2 * log4_size) {
+                            return Err(SdkError::IndexOutOfRange(ind));
+                        }
+                        locator.0.push(ind);
+                        curr = item;
+                    }
+                    _ => return Err(invalid()),
+                }
+                rest = &rest[end + 1..];
+            }
+        }
+        Ok(locator)
+    }
+
+    fn locate_scalar(&self, path: &str) -> Result<ZkDataLocator, SdkError> {
+        let locator = self.locate(path)?;
+        let mut curr = self;
+        for ind in locator.0.iter() {
+            curr = match curr {
+                Self::Struct(fields) => &fields[*ind as usize].1,
+                Self::List { item, .. } => item,
+                Self::Scalar => unreachable!(),
+            };
+        }
+        if *curr != Self::Scalar {
+            return Err(SdkError::NotAScalar(path.into()));
+        }
+        Ok(locator)
+    }
+}
+
+/// Full state of a contract, keeping the changes not yet submitted to the
+/// chain as a delta
+#[derive(Debug, Clone)]
+pub struct ContractState {
+    schema: StateSchema,
+    model: ZkStateModel,
+    data: ZkDataPairs,
+    delta: ZkDeltaPairs,
+}
+
+impl ContractState {
+    /// Empty state, every cell is zero
+    pub fn new(schema: StateSchema) -> Result<Self, SdkError> {
+        Self::from_data(schema, Default::default())
+    }
+
+    pub fn from_data(schema: StateSchema, data: ZkDataPairs) -> Result<Self, SdkError> {
+        Ok(Self {
+            model: schema.compile()?,
+            schema,
+            data,
+            delta: Default::default(),
+        })
+    }
+
+    pub fn schema(&self) -> &StateSchema {
+        &self.schema
+    }
+
+    pub fn model(&self) -> &ZkStateModel {
+        &self.model
+    }
+
+    pub fn data(&self) -> &ZkDataPairs {
+        &self.data
+    }
+
+    pub fn get(&self, path: &str) -> Result<ZkScalar, SdkError> {
+        let locator = self.schema.locate_scalar(path)?;
+        Ok(self.data.0.get(&locator).cloned().unwrap_or_default())
+    }
+
+    pub fn set<V: Into<ZkScalar>>(&mut self, path: &str, value: V) -> Result<(), SdkError> {
+        let locator = self.schema.locate_scalar(path)?;
+        let value = value.into();
+        // Zero cells are not stored, just like on the chain
+        if value.is_zero_vartime() {
+            self.data.0.remove(&locator);
+            self.delta.0.insert(locator, None);
+        } else {
+            self.data.0.insert(locator.clone(), value);
+            self.delta.0.insert(locator, Some(value));
+        }
+        Ok(())
+    }
+
+    /// Changes made since the state was created or last committed
+    pub fn delta(&self) -> &ZkDeltaPairs {
+        &self.delta
+    }
+
+    /// Takes the pending changes, e.g. for sending them along with a contract
+    /// update
+    pub fn commit(&mut self) -> ZkDeltaPairs {
+        std::mem::take(&mut self.delta)
+    }
+
+    pub fn compress(&self) -> Result<ZkCompressedState, SdkError> {
+        Ok(self.model.compress::<CoreZkHasher>(&self.data)?)
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,146 @@
+use super::*;
+use crate::zk::ZkStateBuilder;
+
+fn token_schema() -> StateSchema {
+    StateSchema::structure([
+        ("total_supply", StateSchema::scalar()),
+        (
+            "balances",
+            StateSchema::list(
+                2,
+                StateSchema::structure([
+                    ("owner", StateSchema::scalar()),
+                    ("amount", StateSchema::scalar()),
+                ]),
+            ),
+        ),
+    ])
+}
+
+#[test]
+fn test_schema_compile() {
+    assert_eq!(
+        token_schema().compile().unwrap(),
+        ZkStateModel::Struct {
+            field_types: vec![
+                ZkStateModel::Scalar,
+                ZkStateModel::List {
+                    log4_size: 2,
+                    item_type: Box::new(ZkStateModel::Struct {
+                        field_types: vec![ZkStateModel::Scalar, ZkStateModel::Scalar],
+                    }),
+                },
+            ],
+        }
+    );
+    assert!(matches!(
+        StateSchema::structure([("a", StateSchema::scalar()), ("a", StateSchema::scalar())])
+            .compile(),
+        Err(SdkError::DuplicateField(f)) if f == "a"
+    ));
+    assert!(matches!(
+        StateSchema::structure((0..20).map(|i| (i.to_string(), StateSchema::scalar()))).compile(),
+        Err(SdkError::TooManyFields(20))
+    ));
+    assert!(StateSchema::list(31, StateSchema::scalar())
+        .compile()
+        .is_ok());
+    assert!(matches!(
+        StateSchema::list(32, StateSchema::scalar()).compile(),
+        Err(SdkError::ListTooLarge(32))
+    ));
+}
+
+#[test]
+fn test_schema_locate() {
+    let schema = token_schema();
+    assert_eq!(
+        schema.locate("total_supply").unwrap(),
+        ZkDataLocator(vec![0])
+    );
+    assert_eq!(
+        schema.locate("balances[3].amount").unwrap(),
+        ZkDataLocator(vec![1, 3, 1])
+    );
+    assert_eq!(
+        schema.locate("balances[15]").unwrap(),
+        ZkDataLocator(vec![1, 15])
+    );
+    assert!(matches!(
+        schema.locate("balances[16].amount"),
+        Err(SdkError::IndexOutOfRange(16))
+    ));
+    assert!(matches!(
+        schema.locate("balances[1].name"),
+        Err(SdkError::FieldNotFound(f)) if f == "name"
+    ));
+    for invalid in [
+        "total_supply[1]",
+        "balances[x].amount",
+        "balances[1.amount",
+        "balances.[1]",
+        "[1]",
+    ] {
+        assert!(
+            matches!(schema.locate(invalid), Err(SdkError::InvalidPath(_))),
+            "{}",
+            invalid
+        );
+    }
+
+    let list = StateSchema::list(1, StateSchema::list(1, StateSchema::scalar()));
+    assert_eq!(list.locate("[2][3]").unwrap(), ZkDataLocator(vec![2, 3]));
+    assert!(matches!(
+        StateSchema::list(200, StateSchema::scalar()).locate("[0]"),
+        Err(SdkError::ListTooLarge(200))
+    ));
+}
+
+#[test]
+fn test_contract_state() {
+    let mut state = ContractState::new(token_schema()).unwrap();
+    let empty = state.compress().unwrap();
+    assert_eq!(
+        empty,
+        ZkCompressedState::empty::<CoreZkHasher>(state.model().clone())
+    );
+
+    state.set("total_supply", 100).unwrap();
+    state.set("balances[2].owner", 5).unwrap();
+    state.set("balances[2].amount", 100).unwrap();
+    assert_eq!(
+        state.get("balances[2].amount").unwrap(),
+        ZkScalar::from(100)
+    );
+    assert_eq!(state.get("balances[1].amount").unwrap(), ZkScalar::ZERO);
+    assert!(matches!(
+        state.get("balances[2]"),
+        Err(SdkError::NotAScalar(_))
+    ));
+    assert!(matches!(
+        state.set("balances", 1),
+        Err(SdkError::NotAScalar(_))
+    ));
+
+    // Same as applying the delta through the state manager
+    let delta = state.commit();
+    assert_eq!(delta.0.len(), 3);
+    assert!(state.delta().0.is_empty());
+    let mut builder = ZkStateBuilder::<CoreZkHasher>::new(state.model().clone());
+    builder.batch_set(&delta).unwrap();
+    let compressed = state.compress().unwrap();
+    assert_eq!(builder.compress().unwrap(), compressed);
+    assert_eq!(compressed.size(), 3);
+
+    // Zeroed cells are removed
+    state.set("balances[2].owner", 0).unwrap();
+    assert_eq!(
+        state.delta().0.get(&ZkDataLocator(vec![1, 2, 0])),
+        Some(&None)
+    );
+    assert_eq!(state.data().0.len(), 2);
+    assert_eq!(state.compress().unwrap().size(), 2);
+
+    let restored = ContractState::from_data(token_schema(), state.data().clone()).unwrap();
+    assert_eq!(restored.compress().unwrap(), state.compress().unwrap());
+}
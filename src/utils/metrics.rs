@@ -0,0 +1,154 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+    pub fn add(&self, v: u64) {
+        self.0.fetch_add(v, Ordering::Relaxed);
+    }
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Keeps the count and the sum of the observed durations (A Prometheus summary
+/// without quantiles)
+#[derive(Debug, Default)]
+pub struct Timer {
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Timer {
+    pub fn observe(&self, duration: Duration) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+    pub fn time<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        let start = Instant::now();
+        let res = f();
+        self.observe(start.elapsed());
+        res
+    }
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+    }
+}
+
+/// Process-wide metrics of the events happening inside the blockchain and the node
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub apply_block: Timer,
+    pub zk_verify: Timer,
+    pub sync_blocks_fetch: Timer,
+    pub reorgs: Counter,
+    pub rolled_back_blocks: Counter,
+    pub punishments: Counter,
+}
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+/// Renders metrics in the Prometheus text exposition format
+#[derive(Debug, Default)]
+pub struct PrometheusWriter {
+    out: String,
+}
+
+impl PrometheusWriter {
+    fn header(&mut self, name: &str, help: &str, typ: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, typ);
+    }
+    fn sample<V: std::fmt::Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        if labels.is_empty() {
+            let _ = writeln!(self.out, "{} {}", name, value);
+        } else {
+            let labels = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = writeln!(self.out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+    pub fn gauge<V: std::fmt::Display>(&mut self, name: &str, help: &str, value: V) {
+        self.header(name, help, "gauge");
+        self.sample(name, &[], value);
+    }
+    pub fn labeled_gauge<V: std::fmt::Display>(
+        &mut self,
+        name: &str,
+        help: &str,
+        label: &str,
+        values: &[(&str, V)],
+    ) {
+        self.header(name, help, "gauge");
+        for (label_value, value) in values.iter() {
+            self.sample(name, &[(label, label_value)], value);
+        }
+    }
+    pub fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.header(name, help, "counter");
+        self.sample(name, &[], value);
+    }
+    pub fn labeled_counter(&mut self, name: &str, help: &str, label: &str, values: &[(&str, u64)]) {
+        self.header(name, help, "counter");
+        for (label_value, value) in values.iter() {
+            self.sample(name, &[(label, label_value)], value);
+        }
+    }
+    pub fn timer(&mut self, name: &str, help: &str, timer: &Timer) {
+        self.header(name, help, "summary");
+        self.sample(&format!("{}_sum", name), &[], timer.sum().as_secs_f64());
+        self.sample(&format!("{}_count", name), &[], timer.count());
+    }
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prometheus_format() {
+        let timer = Timer::default();
+        timer.observe(Duration::from_millis(1500));
+        timer.observe(Duration::from_millis(500));
+        let mut w = PrometheusWriter::default();
+        w.gauge("bazuka_height", "Height of the chain", 12);
+        w.labeled_counter(
+            "bazuka_drops_total",
+            "Dropped requests",
+            "reason",
+            &[("rate\"limit", 3)],
+        );
+        w.timer("bazuka_apply_block_seconds", "Time spent", &timer);
+        assert_eq!(
+            w.finish(),
+            "# HELP bazuka_height Height of the chain\n\
+             # TYPE bazuka_height gauge\n\
+             bazuka_height 12\n\
+             # HELP bazuka_drops_total Dropped requests\n\
+             # TYPE bazuka_drops_total counter\n\
+             bazuka_drops_total{reason=\"rate\\\"limit\"} 3\n\
+             # HELP bazuka_apply_block_seconds Time spent\n\
+             # TYPE bazuka_apply_block_seconds summary\n\
+             bazuka_apply_block_seconds_sum 2\n\
+             bazuka_apply_block_seconds_count 2\n"
+        );
+    }
+}
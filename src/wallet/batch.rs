@@ -0,0 +1,172 @@
+use super::TxBuilder;
+use crate::core::{
+    Address, Amount, Money, MpnAddress, RegularSendEntry, TokenId, TransactionAndDelta,
+    TransactionData, ZieshaAddress,
+};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum BatchError {
+    #[error("invalid payout at line {0}, expected <address>,<token>,<amount>")]
+    InvalidRow(usize),
+    #[error("a single payout doesn't fit in a transaction")]
+    PayoutTooBig,
+}
+
+/// A single row of a payout list
+#[derive(Debug, Clone)]
+pub struct Payout {
+    pub address: ZieshaAddress,
+    pub money: Money,
+}
+
+/// Parses a CSV payout list with `<address>,<token>,<amount>` rows. Empty
+/// lines, `#` comments and a header row starting with `address` are ignored.
+pub fn parse_payouts(csv: &str) -> Result<Vec<Payout>, BatchError> {
+    let mut payouts = Vec::new();
+    for (i, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (i == 0 && line.starts_with("address")) {
+            continue;
+        }
+        let cols = line.split(',').map(|c| c.trim()).collect::<Vec<_>>();
+        if cols.len() != 3 {
+            return Err(BatchError::InvalidRow(i + 1));
+        }
+        let invalid = || BatchError::InvalidRow(i + 1);
+        let token_id = if cols[1].eq_ignore_ascii_case("ziesha") {
+            TokenId::Ziesha
+        } else {
+            TokenId::from_str(cols[1]).map_err(|_| invalid())?
+        };
+        payouts.push(Payout {
+            address: cols[0].parse().map_err(|_| invalid())?,
+            money: Money {
+                token_id,
+                amount: Amount::from_str(cols[2]).map_err(|_| invalid())?,
+            },
+        });
+    }
+    Ok(payouts)
+}
+
+/// Splits the payouts into main-chain and MPN payouts
+pub fn split_payouts(payouts: Vec<Payout>) -> (Vec<RegularSendEntry>, Vec<(MpnAddress, Money)>) {
+    let mut chain = Vec::new();
+    let mut mpn = Vec::new();
+    for payout in payouts {
+        match payout.address {
+            ZieshaAddress::ChainAddress(dst) => chain.push(RegularSendEntry {
+                dst,
+                amount: payout.money,
+            }),
+            ZieshaAddress::MpnAddress(dst) => mpn.push((dst, payout.money)),
+        }
+    }
+    (chain, mpn)
+}
+
+impl TxBuilder {
+    /// Packs the entries into as few signed transactions as possible, each
+    /// not bigger than `max_size` bytes. Nonces start from `first_nonce`.
+    pub fn create_batch_transactions(
+        &self,
+        memo: String,
+        entries: Vec<RegularSendEntry>,
+        fee: Money,
+        first_nonce: u32,
+        max_size: usize,
+    ) -> Result<Vec<TransactionAndDelta>, BatchError> {
+        // Entries are encoded one after another, so the size of a transaction
+        // is the size of an empty one plus the sizes of its entries
+        let empty_size = self
+            .create_multi_transaction(memo.clone(), Vec::new(), fee, first_nonce)
+            .tx
+            .size();
+        let mut txs: Vec<TransactionAndDelta> = Vec::new();
+        let mut curr: Vec<RegularSendEntry> = Vec::new();
+        let mut curr_size = empty_size;
+        for entry in entries {
+            let entry_size = bincode::serialized_size(&entry).unwrap() as usize;
+            if empty_size + entry_size > max_size {
+                return Err(BatchError::PayoutTooBig);
+            }
+            if curr_size + entry_size > max_size {
+                let nonce = first_nonce + txs.len() as u32;
+                txs.push(self.create_multi_transaction(
+                    memo.clone(),
+                    std::mem::take(&mut curr),
+                    fee,
+                    nonce,
+                ));
+                curr_size = empty_size;
+            }
+            curr.push(entry);
+            curr_size += entry_size;
+        }
+        if !curr.is_empty() {
+            let nonce = first_nonce + txs.len() as u32;
+            txs.push(self.create_multi_transaction(memo, curr, fee, nonce));
+        }
+        Ok(txs)
+    }
+}
+
+/// Receivers of a main-chain transaction, used in payout reports
+pub fn receivers(tx: &TransactionAndDelta) -> Vec<Address> {
+    match &tx.tx.data {
+        TransactionData::RegularSend { entries } => entries.iter().map(|e| e.dst.clone()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_payouts() {
+        let alice = TxBuilder::new(&Vec::from("ABC"));
+        let bob = TxBuilder::new(&Vec::from("CBA"));
+        let mpn_bob = MpnAddress {
+            pub_key: bob.get_zk_address(),
+        };
+        let csv = format!(
+            "address,token,amount\n{},Ziesha,1.5\n\n# comment\n{},ziesha,2\n",
+            bob.get_address(),
+            mpn_bob
+        );
+        let payouts = parse_payouts(&csv).unwrap();
+        assert_eq!(payouts.len(), 2);
+        assert_eq!(payouts[0].money, Money::ziesha(1_500_000_000));
+        assert!(matches!(
+            parse_payouts("abc,Ziesha,1"),
+            Err(BatchError::InvalidRow(1))
+        ));
+
+        let (chain, mpn) = split_payouts(payouts);
+        assert_eq!(mpn, vec![(mpn_bob, Money::ziesha(2_000_000_000))]);
+
+        let entries = vec![chain[0].clone(); 10];
+        let single = alice
+            .create_batch_transactions("".into(), entries.clone(), Money::ziesha(0), 1, 100000)
+            .unwrap();
+        assert_eq!(single.len(), 1);
+        let max_size = single[0].tx.size() / 3;
+        let txs = alice
+            .create_batch_transactions("".into(), entries, Money::ziesha(0), 1, max_size)
+            .unwrap();
+        assert!(txs.len() > 3);
+        assert_eq!(txs.iter().map(|tx| receivers(tx).len()).sum::<usize>(), 10);
+        for (i, tx) in txs.iter().enumerate() {
+            assert_eq!(tx.tx.nonce, 1 + i as u32);
+            assert!(tx.tx.size() <= max_size);
+            assert!(tx.tx.verify_signature());
+        }
+        assert!(matches!(
+            alice.create_batch_transactions("".into(), chain, Money::ziesha(0), 1, 10),
+            Err(BatchError::PayoutTooBig)
+        ));
+    }
+}
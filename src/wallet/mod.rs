@@ -1,10 +1,12 @@
+pub mod batch;
 mod tx_builder;
 pub use tx_builder::TxBuilder;
 
 use crate::core::{
-    ChainSourcedTx, MpnAddress, MpnDeposit, MpnSourcedTx, MpnWithdraw, TokenId, TransactionAndDelta,
+    ChainSourcedTx, Money, MpnAddress, MpnDeposit, MpnSourcedTx, MpnWithdraw, TokenId,
+    TransactionAndDelta,
 };
-use crate::zk::MpnTransaction;
+use crate::zk::{MpnAccount, MpnTransaction};
 use bip39::Mnemonic;
 use rand_core_mnemonic::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
@@ -97,6 +99,31 @@ impl Wallet {
             None
         }
     }
+    /// Credits `acc` (The account of `to`) with the pending transactions of `from`
+    /// (Those with nonces not below `nonce`), so that the token slots they take are
+    /// not picked again for other tokens
+    pub fn reserve_mpn_token_slots(
+        &self,
+        from: &MpnAddress,
+        nonce: u64,
+        to: &MpnAddress,
+        acc: &mut MpnAccount,
+    ) {
+        for tx in self.mpn_sourced_txs.get(from).into_iter().flatten() {
+            if let MpnSourcedTx::MpnTransaction(tx) = tx {
+                if tx.nonce >= nonce && tx.dst_pub_key == to.pub_key {
+                    let money = acc
+                        .tokens
+                        .entry(tx.dst_token_index)
+                        .or_insert_with(|| Money::new(tx.amount.token_id, 0));
+                    if money.token_id != tx.amount.token_id {
+                        *money = Money::new(tx.amount.token_id, 0);
+                    }
+                    money.amount += tx.amount.amount;
+                }
+            }
+        }
+    }
     pub fn seed(&self) -> [u8; 64] {
         self.mnemonic.to_seed("")
     }
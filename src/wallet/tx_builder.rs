@@ -1,7 +1,8 @@
 use crate::core::{
-    Address, Amount, ContractDeposit, ContractId, ContractUpdate, ContractWithdraw, Money,
-    MpnAddress, MpnDeposit, MpnWithdraw, RegularSendEntry, Signature, Signer, Token, TokenId,
-    Transaction, TransactionAndDelta, TransactionData, ZkSigner,
+    AccountPolicy, Address, Amount, ContractAdminPolicy, ContractDeposit, ContractId,
+    ContractUpdate, ContractUpgrade, ContractWithdraw, Money, MpnAddress, MpnDeposit, MpnWithdraw,
+    RegularSendEntry, Signature, Signer, Token, TokenId, Transaction, TransactionAndDelta,
+    TransactionData, ZkSigner,
 };
 use crate::crypto::SignatureScheme;
 use crate::crypto::ZkSignatureScheme;
@@ -80,6 +81,8 @@ impl TxBuilder {
             },
             nonce,
             fee,
+            valid_after_height: None,
+            valid_until_height: None,
             sig: Signature::Unsigned,
         };
         self.sign_tx(&mut tx);
@@ -93,6 +96,76 @@ impl TxBuilder {
             token_id,
         )
     }
+    pub fn set_account_policy(
+        &self,
+        memo: String,
+        policy: Option<AccountPolicy>,
+        fee: Money,
+        nonce: u32,
+    ) -> TransactionAndDelta {
+        let mut tx = Transaction {
+            memo,
+            src: Some(self.get_address()),
+            data: TransactionData::SetAccountPolicy { policy },
+            nonce,
+            fee,
+            valid_after_height: None,
+            valid_until_height: None,
+            sig: Signature::Unsigned,
+        };
+        self.sign_tx(&mut tx);
+        TransactionAndDelta {
+            tx,
+            state_delta: None,
+        }
+    }
+    pub fn recover_account(
+        &self,
+        memo: String,
+        account: Address,
+        key: Address,
+        fee: Money,
+        nonce: u32,
+    ) -> TransactionAndDelta {
+        let mut tx = Transaction {
+            memo,
+            src: Some(self.get_address()),
+            data: TransactionData::RecoverAccount { account, key },
+            nonce,
+            fee,
+            valid_after_height: None,
+            valid_until_height: None,
+            sig: Signature::Unsigned,
+        };
+        self.sign_tx(&mut tx);
+        TransactionAndDelta {
+            tx,
+            state_delta: None,
+        }
+    }
+    pub fn cancel_recovery(
+        &self,
+        memo: String,
+        account: Address,
+        fee: Money,
+        nonce: u32,
+    ) -> TransactionAndDelta {
+        let mut tx = Transaction {
+            memo,
+            src: Some(self.get_address()),
+            data: TransactionData::CancelRecovery { account },
+            nonce,
+            fee,
+            valid_after_height: None,
+            valid_until_height: None,
+            sig: Signature::Unsigned,
+        };
+        self.sign_tx(&mut tx);
+        TransactionAndDelta {
+            tx,
+            state_delta: None,
+        }
+    }
     pub fn create_multi_transaction(
         &self,
         memo: String,
@@ -106,6 +179,8 @@ impl TxBuilder {
             data: TransactionData::RegularSend { entries },
             nonce,
             fee,
+            valid_after_height: None,
+            valid_until_height: None,
             sig: Signature::Unsigned,
         };
         self.sign_tx(&mut tx);
@@ -141,20 +216,51 @@ impl TxBuilder {
         tx.sign(&self.zk_private_key);
         tx
     }
+    /// Like `create_mpn_transaction`, but picks the token slots of the accounts
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_mpn_transfer(
+        &self,
+        log4_token_capacity: u8,
+        src: &zk::MpnAccount,
+        to: MpnAddress,
+        dst: &zk::MpnAccount,
+        amount: Money,
+        fee: Money,
+        nonce: u64,
+    ) -> Result<zk::MpnTransaction, zk::MpnTokenError> {
+        let from_token_index = src.find_token_index(log4_token_capacity, amount.token_id, false)?;
+        let fee_token_index = src.find_token_index(log4_token_capacity, fee.token_id, false)?;
+        let to_token_index = dst.find_token_index(log4_token_capacity, amount.token_id, true)?;
+        Ok(self.create_mpn_transaction(
+            from_token_index,
+            to,
+            to_token_index,
+            amount,
+            fee_token_index,
+            fee,
+            nonce,
+        ))
+    }
     pub fn create_contract(
         &self,
         memo: String,
         contract: zk::ZkContract,
         initial_state: zk::ZkDataPairs,
+        admin: Option<ContractAdminPolicy>,
         fee: Money,
         nonce: u32,
     ) -> TransactionAndDelta {
         let mut tx = Transaction {
             memo,
             src: Some(self.get_address()),
-            data: TransactionData::CreateContract { contract },
+            data: match admin {
+                Some(admin) => TransactionData::CreateUpgradableContract { contract, admin },
+                None => TransactionData::CreateContract { contract },
+            },
             nonce,
             fee,
+            valid_after_height: None,
+            valid_until_height: None,
             sig: Signature::Unsigned,
         };
         self.sign_tx(&mut tx);
@@ -164,6 +270,37 @@ impl TxBuilder {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
+    pub fn upgrade_contract(
+        &self,
+        memo: String,
+        contract_id: ContractId,
+        upgrade: ContractUpgrade,
+        proof: Option<zk::ZkProof>,
+        fee: Money,
+        nonce: u32,
+    ) -> TransactionAndDelta {
+        let mut tx = Transaction {
+            memo,
+            src: Some(self.get_address()),
+            data: TransactionData::UpgradeContract {
+                contract_id,
+                upgrade,
+                proof,
+            },
+            nonce,
+            fee,
+            valid_after_height: None,
+            valid_until_height: None,
+            sig: Signature::Unsigned,
+        };
+        self.sign_tx(&mut tx);
+        TransactionAndDelta {
+            tx,
+            state_delta: None,
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn call_function(
         &self,
@@ -173,6 +310,7 @@ impl TxBuilder {
         state_delta: zk::ZkDeltaPairs,
         next_state: zk::ZkCompressedState,
         proof: zk::ZkProof,
+        events: Vec<zk::ZkScalar>,
         exec_fee: Money,
         miner_fee: Money,
         nonce: u32,
@@ -188,10 +326,13 @@ impl TxBuilder {
                     next_state,
                     proof,
                     fee: exec_fee,
+                    events,
                 }],
             },
             nonce,
             fee: miner_fee,
+            valid_after_height: None,
+            valid_until_height: None,
             sig: Signature::Unsigned,
         };
         let bytes = bincode::serialize(&tx).unwrap();
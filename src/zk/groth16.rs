@@ -35,6 +35,97 @@ pub struct Groth16Proof {
     c: (Fp, Fp, bool),
 }
 
+impl From<bellman::groth16::VerifyingKey<Bls12>> for Groth16VerifyingKey {
+    fn from(vk: bellman::groth16::VerifyingKey<Bls12>) -> Self {
+        unsafe {
+            Self {
+                alpha_g1: std::mem::transmute::<BellmanG1, (Fp, Fp, bool)>(vk.alpha_g1),
+                beta_g1: std::mem::transmute::<BellmanG1, (Fp, Fp, bool)>(vk.beta_g1),
+                beta_g2: std::mem::transmute::<BellmanG2, ((Fp, Fp), (Fp, Fp), bool)>(vk.beta_g2),
+                gamma_g2: std::mem::transmute::<BellmanG2, ((Fp, Fp), (Fp, Fp), bool)>(vk.gamma_g2),
+                delta_g1: std::mem::transmute::<BellmanG1, (Fp, Fp, bool)>(vk.delta_g1),
+                delta_g2: std::mem::transmute::<BellmanG2, ((Fp, Fp), (Fp, Fp), bool)>(vk.delta_g2),
+                ic: vk
+                    .ic
+                    .into_iter()
+                    .map(|p| std::mem::transmute::<BellmanG1, (Fp, Fp, bool)>(p))
+                    .collect(),
+            }
+        }
+    }
+}
+
+impl From<bellman::groth16::Proof<Bls12>> for Groth16Proof {
+    fn from(proof: bellman::groth16::Proof<Bls12>) -> Self {
+        unsafe {
+            Self {
+                a: std::mem::transmute::<BellmanG1, (Fp, Fp, bool)>(proof.a),
+                b: std::mem::transmute::<BellmanG2, ((Fp, Fp), (Fp, Fp), bool)>(proof.b),
+                c: std::mem::transmute::<BellmanG1, (Fp, Fp, bool)>(proof.c),
+            }
+        }
+    }
+}
+
+impl From<&Groth16VerifyingKey> for bellman::groth16::VerifyingKey<Bls12> {
+    fn from(vk: &Groth16VerifyingKey) -> Self {
+        unsafe {
+            Self {
+                alpha_g1: std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(vk.alpha_g1.clone()),
+                beta_g1: std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(vk.beta_g1.clone()),
+                beta_g2: std::mem::transmute::<((Fp, Fp), (Fp, Fp), bool), BellmanG2>(
+                    vk.beta_g2.clone(),
+                ),
+                gamma_g2: std::mem::transmute::<((Fp, Fp), (Fp, Fp), bool), BellmanG2>(
+                    vk.gamma_g2.clone(),
+                ),
+                delta_g1: std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(vk.delta_g1.clone()),
+                delta_g2: std::mem::transmute::<((Fp, Fp), (Fp, Fp), bool), BellmanG2>(
+                    vk.delta_g2.clone(),
+                ),
+                ic: vk
+                    .ic
+                    .iter()
+                    .cloned()
+                    .map(|p| std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(p))
+                    .collect(),
+            }
+        }
+    }
+}
+
+impl From<&Groth16Proof> for bellman::groth16::Proof<Bls12> {
+    fn from(proof: &Groth16Proof) -> Self {
+        unsafe {
+            Self {
+                a: std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(proof.a.clone()),
+                b: std::mem::transmute::<((Fp, Fp), (Fp, Fp), bool), BellmanG2>(proof.b.clone()),
+                c: std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(proof.c.clone()),
+            }
+        }
+    }
+}
+
+/// Public inputs of a contract circuit, in the order the circuits expect them
+#[derive(Debug, Clone, Copy)]
+pub struct Groth16Inputs {
+    pub prev_height: u64,
+    pub prev_state: ZkScalar,
+    pub aux_data: ZkScalar,
+    pub next_state: ZkScalar,
+}
+
+impl Groth16Inputs {
+    fn to_bellman(self) -> Vec<BellmanFr> {
+        vec![
+            self.prev_height.into(),
+            self.prev_state.into(),
+            self.aux_data.into(),
+            self.next_state.into(),
+        ]
+    }
+}
+
 pub fn groth16_verify(
     vk: &Groth16VerifyingKey,
     prev_height: u64,
@@ -43,48 +134,33 @@ pub fn groth16_verify(
     next_state: ZkScalar,
     proof: &Groth16Proof,
 ) -> bool {
-    let (vk, proof) = unsafe {
-        let alpha_g1 = std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(vk.alpha_g1.clone());
-        let beta_g1 = std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(vk.beta_g1.clone());
-        let beta_g2 =
-            std::mem::transmute::<((Fp, Fp), (Fp, Fp), bool), BellmanG2>(vk.beta_g2.clone());
-        let gamma_g2 =
-            std::mem::transmute::<((Fp, Fp), (Fp, Fp), bool), BellmanG2>(vk.gamma_g2.clone());
-        let delta_g1 = std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(vk.delta_g1.clone());
-        let delta_g2 =
-            std::mem::transmute::<((Fp, Fp), (Fp, Fp), bool), BellmanG2>(vk.delta_g2.clone());
-        let ic = vk
-            .ic
-            .iter()
-            .cloned()
-            .map(|p| std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(p))
-            .collect();
-        let proof = bellman::groth16::Proof::<Bls12> {
-            a: std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(proof.a.clone()),
-            b: std::mem::transmute::<((Fp, Fp), (Fp, Fp), bool), BellmanG2>(proof.b.clone()),
-            c: std::mem::transmute::<(Fp, Fp, bool), BellmanG1>(proof.c.clone()),
-        };
-        let vk =
-            bellman::groth16::prepare_verifying_key(&bellman::groth16::VerifyingKey::<Bls12> {
-                alpha_g1,
-                beta_g1,
-                beta_g2,
-                gamma_g2,
-                delta_g1,
-                delta_g2,
-                ic,
-            });
-        (vk, proof)
+    let inputs = Groth16Inputs {
+        prev_height,
+        prev_state,
+        aux_data,
+        next_state,
     };
     bellman::groth16::verify_proof(
-        &vk,
-        &proof,
-        &[
-            prev_height.into(),
-            prev_state.into(),
-            aux_data.into(),
-            next_state.into(),
-        ],
+        &bellman::groth16::prepare_verifying_key(&vk.into()),
+        &proof.into(),
+        &inputs.to_bellman(),
     )
     .is_ok()
 }
+
+/// Verifies proofs of the same circuit all at once, by checking a random linear
+/// combination of their pairing equations. It is as costly as a single
+/// verification plus a multi-exponentiation with a term per proof.
+pub fn groth16_batch_verify(
+    vk: &Groth16VerifyingKey,
+    items: &[(Groth16Inputs, &Groth16Proof)],
+) -> bool {
+    let mut verifier = bellman::groth16::batch::Verifier::<Bls12>::new();
+    for (inputs, proof) in items {
+        verifier.queue((
+            bellman::groth16::Proof::<Bls12>::from(*proof),
+            inputs.to_bellman(),
+        ));
+    }
+    verifier.verify(rand::thread_rng(), &vk.into()).is_ok()
+}
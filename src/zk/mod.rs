@@ -1,5 +1,9 @@
-use crate::core::{hash::Hash, Amount, Hasher, Money, TokenId, ZkHasher as ZkMainHasher};
+use crate::core::{
+    hash::Hash, Amount, ContractDeposit, ContractWithdraw, Hasher, Money, TokenId,
+    ZkHasher as ZkMainHasher,
+};
 use crate::crypto::{jubjub, DeriveMpnAccountIndex, ZkSignatureScheme};
+use crate::utils::metrics::METRICS;
 
 use ff::{Field, PrimeField};
 use num_bigint::BigUint;
@@ -13,7 +17,10 @@ use thiserror::Error;
 mod state;
 pub use state::*;
 pub mod groth16;
+pub mod plonk;
 pub mod poseidon;
+#[cfg(test)]
+pub mod testing;
 
 #[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct MpnAccount {
@@ -50,26 +57,70 @@ impl MpnAccount {
         }
         state_builder.compress().unwrap().state_hash
     }
+    /// Whether the token slot is able to receive `token_id`: it is either unused or
+    /// holds the same token. With `reuse_empty`, slots with no balance left are
+    /// treated as unused too.
+    pub fn token_slot_accepts(&self, index: u64, token_id: TokenId, reuse_empty: bool) -> bool {
+        match self.tokens.get(&index) {
+            Some(money) => money.token_id == token_id || (reuse_empty && money.amount == Amount(0)),
+            None => true,
+        }
+    }
+    /// Finds the slot holding `token_id` (The one with the highest balance, if several).
+    /// When `empty_allowed`, falls back to an unused slot, and then to a slot with no
+    /// balance left.
     pub fn find_token_index(
         &self,
         log4_token_capacity: u8,
         token_id: TokenId,
         empty_allowed: bool,
-    ) -> Option<u64> {
-        for (ind, money) in self.tokens.iter() {
-            if money.token_id == token_id {
-                return Some(*ind);
-            }
+    ) -> Result<u64, MpnTokenError> {
+        if let Some((ind, _)) = self
+            .tokens
+            .iter()
+            .filter(|(_, money)| money.token_id == token_id)
+            .max_by_key(|(ind, money)| (money.amount, std::cmp::Reverse(**ind)))
+        {
+            return Ok(*ind);
         }
-        if empty_allowed {
-            for ind in 0..1 << (2 * log4_token_capacity) {
-                if !self.tokens.contains_key(&ind) {
-                    return Some(ind);
-                }
-            }
+        if !empty_allowed {
+            return Err(MpnTokenError::TokenNotFound(token_id));
+        }
+        let capacity = 1 << (2 * log4_token_capacity);
+        (0..capacity)
+            .find(|ind| !self.tokens.contains_key(ind))
+            .or_else(|| (0..capacity).find(|ind| self.token_slot_accepts(*ind, token_id, true)))
+            .ok_or(MpnTokenError::AccountFull)
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MpnTokenError {
+    #[error("token {0} not found in the mpn account")]
+    TokenNotFound(TokenId),
+    #[error("mpn account has no free token slots left")]
+    AccountFull,
+}
+
+/// Finds the MPN account of a key: the first of its candidate slots which is either
+/// owned by it or still empty. Accounts are never emptied, so a key keeps the slot
+/// it has taken. Returns the choice along with the slot, or `None` when all of the
+/// candidate slots are taken by other keys.
+pub fn locate_mpn_account<E, F: FnMut(u64) -> Result<MpnAccount, E>>(
+    pub_key: &jubjub::PublicKey,
+    log4_account_capacity: u8,
+    slot_choices: u8,
+    mut get_account: F,
+) -> Result<Option<(u8, u64)>, E> {
+    let address = pub_key.decompress();
+    for choice in 0..slot_choices {
+        let index = pub_key.mpn_account_slot(log4_account_capacity, choice);
+        let acc = get_account(index)?;
+        if !acc.address.is_on_curve() || acc.address == address {
+            return Ok(Some((choice, index)));
         }
-        None
     }
+    Ok(None)
 }
 
 // Amount is passed by default
@@ -112,7 +163,9 @@ pub trait ZkHasher: Clone {
     fn hash(vals: &[ZkScalar]) -> ZkScalar;
 }
 
+/// PLONK proofs are rejected without the universal setup of the network
 pub fn check_proof(
+    plonk_srs: Option<&plonk::PlonkVerifierSrs>,
     vk: &ZkVerifierKey,
     prev_height: u64,
     prev_state: &ZkCompressedState,
@@ -121,18 +174,35 @@ pub fn check_proof(
     proof: &ZkProof,
 ) -> bool {
     match vk {
-        ZkVerifierKey::Groth16(vk) =>
-        {
-            #[allow(irrefutable_let_patterns)]
+        ZkVerifierKey::Groth16(vk) => {
             if let ZkProof::Groth16(proof) = proof {
-                groth16::groth16_verify(
-                    vk,
-                    prev_height,
-                    prev_state.state_hash,
-                    calldata.state_hash,
-                    next_state.state_hash,
-                    proof,
-                )
+                METRICS.zk_verify.time(|| {
+                    groth16::groth16_verify(
+                        vk,
+                        prev_height,
+                        prev_state.state_hash,
+                        calldata.state_hash,
+                        next_state.state_hash,
+                        proof,
+                    )
+                })
+            } else {
+                false
+            }
+        }
+        ZkVerifierKey::Plonk(vk) => {
+            if let (ZkProof::Plonk(proof), Some(srs)) = (proof, plonk_srs) {
+                METRICS.zk_verify.time(|| {
+                    plonk::plonk_verify(
+                        srs,
+                        vk,
+                        prev_height,
+                        prev_state.state_hash,
+                        calldata.state_hash,
+                        next_state.state_hash,
+                        proof,
+                    )
+                })
             } else {
                 false
             }
@@ -148,6 +218,85 @@ pub fn check_proof(
     }
 }
 
+/// Contract update proofs whose verification is deferred, so that the proofs
+/// sharing a verifying key can be checked together
+#[derive(Default)]
+pub struct ZkProofBatch {
+    items: Vec<(ZkVerifierKey, u64, ZkScalar, ZkScalar, ZkScalar, ZkProof)>,
+}
+
+impl ZkProofBatch {
+    pub fn queue(
+        &mut self,
+        vk: &ZkVerifierKey,
+        prev_height: u64,
+        prev_state: &ZkCompressedState,
+        calldata: &ZkCompressedState,
+        next_state: &ZkCompressedState,
+        proof: &ZkProof,
+    ) {
+        self.items.push((
+            vk.clone(),
+            prev_height,
+            prev_state.state_hash,
+            calldata.state_hash,
+            next_state.state_hash,
+            proof.clone(),
+        ));
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns true only if all of the queued proofs are correct
+    pub fn verify(&self, plonk_srs: Option<&plonk::PlonkVerifierSrs>) -> bool {
+        let mut groups: Vec<(&groth16::Groth16VerifyingKey, Vec<_>)> = Vec::new();
+        for (vk, prev_height, prev_state, aux_data, next_state, proof) in self.items.iter() {
+            match (vk, proof) {
+                (ZkVerifierKey::Groth16(vk), ZkProof::Groth16(proof)) => {
+                    let item = (
+                        groth16::Groth16Inputs {
+                            prev_height: *prev_height,
+                            prev_state: *prev_state,
+                            aux_data: *aux_data,
+                            next_state: *next_state,
+                        },
+                        proof.as_ref(),
+                    );
+                    if let Some((_, items)) = groups.iter_mut().find(|(k, _)| *k == vk.as_ref()) {
+                        items.push(item);
+                    } else {
+                        groups.push((vk, vec![item]));
+                    }
+                }
+                _ => {
+                    if !check_proof(
+                        plonk_srs,
+                        vk,
+                        *prev_height,
+                        &ZkCompressedState::new(*prev_state, 0),
+                        &ZkCompressedState::new(*aux_data, 0),
+                        &ZkCompressedState::new(*next_state, 0),
+                        proof,
+                    ) {
+                        return false;
+                    }
+                }
+            }
+        }
+        METRICS.zk_verify.time(|| {
+            groups
+                .iter()
+                .all(|(vk, items)| groth16::groth16_batch_verify(vk, items))
+        })
+    }
+}
+
 lazy_static! {
     static ref ZKSCALAR_MODULUS: BigUint = BigUint::from_str(
         "52435875175126190479447740508185965837690552500527637822603658699938581184513"
@@ -490,6 +639,95 @@ impl ZkCompressedState {
     pub fn size(&self) -> u64 {
         self.state_size
     }
+    /// Aux data of a contract update emitting `events`. The events are chained
+    /// into the hash, so the aux data of updates without events is unchanged.
+    pub fn with_events<H: ZkHasher>(mut self, events: &[ZkScalar]) -> Self {
+        for event in events {
+            self.state_hash = H::hash(&[self.state_hash, *event]);
+        }
+        self
+    }
+}
+
+fn payments_aux_data(
+    num_fields: usize,
+    log4_payment_capacity: u8,
+    payments: impl Iterator<Item = Vec<ZkScalar>>,
+) -> Result<ZkCompressedState, StateManagerError> {
+    let mut builder = ZkStateBuilder::<ZkMainHasher>::new(ZkStateModel::List {
+        item_type: Box::new(ZkStateModel::Struct {
+            field_types: vec![ZkStateModel::Scalar; num_fields],
+        }),
+        log4_size: log4_payment_capacity,
+    });
+    for (i, fields) in payments.enumerate() {
+        builder.batch_set(&ZkDeltaPairs(
+            fields
+                .into_iter()
+                .enumerate()
+                .map(|(j, val)| (ZkDataLocator(vec![i as u64, j as u64]), Some(val)))
+                .collect(),
+        ))?;
+    }
+    builder.compress()
+}
+
+/// Aux data of a deposit update (Without its events), a list of
+/// (Enabled, Token-id, Amount, Calldata) structs
+pub fn deposits_aux_data(
+    log4_payment_capacity: u8,
+    deposits: &[ContractDeposit],
+) -> Result<ZkCompressedState, StateManagerError> {
+    payments_aux_data(
+        4,
+        log4_payment_capacity,
+        deposits.iter().map(|d| {
+            vec![
+                ZkScalar::from(1),
+                d.amount.token_id.into(),
+                ZkScalar::from(d.amount.amount),
+                d.calldata,
+            ]
+        }),
+    )
+}
+
+/// Aux data of a withdraw update (Without its events), a list of
+/// (Enabled, Token-id, Amount, Fee token-id, Fee, Fingerprint, Calldata) structs
+pub fn withdraws_aux_data(
+    log4_payment_capacity: u8,
+    withdraws: &[ContractWithdraw],
+) -> Result<ZkCompressedState, StateManagerError> {
+    payments_aux_data(
+        7,
+        log4_payment_capacity,
+        withdraws.iter().map(|w| {
+            vec![
+                ZkScalar::from(1),
+                w.amount.token_id.into(),
+                ZkScalar::from(w.amount.amount),
+                w.fee.token_id.into(),
+                ZkScalar::from(w.fee.amount),
+                w.fingerprint(),
+                w.calldata,
+            ]
+        }),
+    )
+}
+
+/// Aux data of a function call (Without its events), a (Token-id, Total fee) struct
+pub fn function_call_aux_data(fee: Money) -> Result<ZkCompressedState, StateManagerError> {
+    let mut builder = ZkStateBuilder::<ZkMainHasher>::new(ZkStateModel::Struct {
+        field_types: vec![ZkStateModel::Scalar, ZkStateModel::Scalar],
+    });
+    builder.batch_set(&ZkDeltaPairs(
+        [
+            (ZkDataLocator(vec![0]), Some(fee.token_id.into())),
+            (ZkDataLocator(vec![1]), Some(ZkScalar::from(fee.amount))),
+        ]
+        .into(),
+    ))?;
+    builder.compress()
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -497,6 +735,7 @@ pub enum ZkVerifierKey {
     Groth16(Box<groth16::Groth16VerifyingKey>),
     #[cfg(test)]
     Dummy,
+    Plonk(Box<plonk::PlonkVerifyingKey>),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -540,12 +779,6 @@ impl std::hash::Hash for MpnTransaction {
 }
 
 impl MpnTransaction {
-    pub fn src_index(&self, log4_account_capacity: u8) -> u64 {
-        self.src_pub_key.mpn_account_index(log4_account_capacity)
-    }
-    pub fn dst_index(&self, log4_account_capacity: u8) -> u64 {
-        self.dst_pub_key.mpn_account_index(log4_account_capacity)
-    }
     pub fn verify(&self) -> bool {
         jubjub::JubJub::<ZkMainHasher>::verify(&self.src_pub_key, self.hash(), &self.sig)
     }
@@ -575,11 +808,23 @@ pub struct ZkContract {
     pub functions: Vec<ZkSingleInputVerifierKey>,         // Vec<VK> f(prev_state) -> next_state
 }
 
+impl ZkContract {
+    /// Verifier keys of the deposit, withdraw and regular functions
+    pub fn verifier_keys(&self) -> impl Iterator<Item = &ZkVerifierKey> {
+        self.deposit_functions
+            .iter()
+            .chain(self.withdraw_functions.iter())
+            .map(|f| &f.verifier_key)
+            .chain(self.functions.iter().map(|f| &f.verifier_key))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ZkProof {
     Groth16(Box<groth16::Groth16Proof>),
     #[cfg(test)]
     Dummy(bool),
+    Plonk(Box<plonk::PlonkProof>),
 }
 
 #[cfg(test)]
@@ -0,0 +1,348 @@
+//! PLONK proofs with KZG commitments over BLS12-381. Unlike Groth16, the
+//! trusted setup is universal: a single `PlonkSrs` can be used for preparing
+//! the keys of any circuit that fits in it.
+//!
+//! The verifier is only tested against the unbatched reference verifier of
+//! the tests, it should be audited externally before PLONK proofs are
+//! scheduled on a public network.
+
+use super::groth16::Fp;
+use super::ZkScalar;
+use bls12_381::{G1Affine, G1Projective, G2Affine, Scalar as BellmanFr};
+use ff::{Field, PrimeField};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_512};
+
+mod poly;
+mod prover;
+pub use prover::*;
+
+/// Number of public inputs of contract circuits, see `PlonkInputs`
+pub const NUM_PLONK_INPUTS: usize = 4;
+
+type G1Raw = (Fp, Fp, bool);
+type G2Raw = ((Fp, Fp), (Fp, Fp), bool);
+
+fn g1_to_raw(p: G1Affine) -> G1Raw {
+    unsafe { std::mem::transmute::<G1Affine, G1Raw>(p) }
+}
+
+fn g1_from_raw(p: &G1Raw) -> Option<G1Affine> {
+    let p = unsafe { std::mem::transmute::<G1Raw, G1Affine>(p.clone()) };
+    bool::from(p.is_on_curve() & p.is_torsion_free()).then_some(p)
+}
+
+fn g2_to_raw(p: G2Affine) -> G2Raw {
+    unsafe { std::mem::transmute::<G2Affine, G2Raw>(p) }
+}
+
+fn g2_from_raw(p: &G2Raw) -> Option<G2Affine> {
+    let p = unsafe { std::mem::transmute::<G2Raw, G2Affine>(p.clone()) };
+    bool::from(p.is_on_curve() & p.is_torsion_free()).then_some(p)
+}
+
+fn mul(p: G1Affine, s: ZkScalar) -> G1Projective {
+    p * BellmanFr::from(s)
+}
+
+/// Coset shifts of the second and third wire columns in the permutation
+/// argument
+fn coset_shifts() -> [ZkScalar; 3] {
+    let k1 = ZkScalar::MULTIPLICATIVE_GENERATOR;
+    [ZkScalar::ONE, k1, k1.square()]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlonkVerifyingKey {
+    /// Number of rows of the circuit, a power of two
+    n: u64,
+    /// Commitments to `q_l`, `q_r`, `q_o`, `q_m` and `q_c`
+    selectors: Vec<G1Raw>,
+    /// Commitments to the permutation polynomials of the three wire columns
+    sigmas: Vec<G1Raw>,
+}
+
+impl PlonkVerifyingKey {
+    /// Could the key have been prepared with the given setup?
+    pub fn fits(&self, srs: &PlonkVerifierSrs) -> bool {
+        self.n.is_power_of_two() && self.n <= srs.max_rows
+    }
+}
+
+/// Part of a universal setup needed by the verifiers. A network pins a single
+/// canonical setup, keys of all of the circuits should be prepared with it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlonkVerifierSrs {
+    /// `[x]_2` of the setup
+    x_g2: G2Raw,
+    /// Maximum number of rows of the circuits supported by the setup
+    max_rows: u64,
+}
+
+impl PlonkVerifierSrs {
+    /// Verifier part of `PlonkSrs::insecure_from_seed`, without computing the
+    /// powers of the secret in G1
+    pub fn insecure_from_seed(seed: &[u8], max_rows: usize) -> Self {
+        Self {
+            x_g2: g2_to_raw((G2Affine::generator() * BellmanFr::from(seed_secret(seed))).into()),
+            max_rows: max_rows as u64,
+        }
+    }
+}
+
+/// Secret of the setups derived from a public seed
+fn seed_secret(seed: &[u8]) -> ZkScalar {
+    let mut hasher = Sha3_512::new();
+    hasher.update(b"bazuka-plonk-insecure-setup");
+    hasher.update(seed);
+    let out: [u8; 64] = hasher.finalize().into();
+    BellmanFr::from_bytes_wide(&out).into()
+}
+
+/// Evaluations of the committed polynomials on the challenge point `zeta`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct PlonkEvaluations {
+    pub wires: [ZkScalar; 3],
+    pub selectors: [ZkScalar; 5],
+    pub sigmas: [ZkScalar; 3],
+    pub z: ZkScalar,
+    pub t: [ZkScalar; 3],
+    /// Evaluation of `z` on `zeta * omega`
+    pub z_omega: ZkScalar,
+}
+
+impl PlonkEvaluations {
+    /// Evaluations opened on `zeta`, in the order they are batched
+    fn opened_on_zeta(&self) -> Vec<ZkScalar> {
+        let mut res = Vec::new();
+        res.extend(self.wires);
+        res.extend(self.selectors);
+        res.extend(self.sigmas);
+        res.push(self.z);
+        res.extend(self.t);
+        res
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlonkProof {
+    wires: [G1Raw; 3],
+    z: G1Raw,
+    t: [G1Raw; 3],
+    evaluations: PlonkEvaluations,
+    w_zeta: G1Raw,
+    w_zeta_omega: G1Raw,
+}
+
+/// Public inputs of a contract circuit, in the order the circuits expect them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlonkInputs {
+    pub prev_height: u64,
+    pub prev_state: ZkScalar,
+    pub aux_data: ZkScalar,
+    pub next_state: ZkScalar,
+}
+
+impl PlonkInputs {
+    fn to_vec(self) -> [ZkScalar; NUM_PLONK_INPUTS] {
+        [
+            self.prev_height.into(),
+            self.prev_state,
+            self.aux_data,
+            self.next_state,
+        ]
+    }
+}
+
+/// Fiat-Shamir transcript, challenges depend on everything absorbed so far
+struct Transcript(Sha3_512);
+
+impl Transcript {
+    fn new(vk: &PlonkVerifyingKey, inputs: &[ZkScalar]) -> Self {
+        let mut transcript = Self(Sha3_512::new());
+        transcript.0.update(b"bazuka-plonk");
+        transcript.0.update(vk.n.to_le_bytes());
+        for p in vk.selectors.iter().chain(vk.sigmas.iter()) {
+            transcript
+                .0
+                .update(g1_from_raw(p).unwrap_or_default().to_compressed());
+        }
+        for inp in inputs {
+            transcript.append_scalar(inp);
+        }
+        transcript
+    }
+    fn append_point(&mut self, p: &G1Affine) {
+        self.0.update(p.to_compressed());
+    }
+    fn append_scalar(&mut self, s: &ZkScalar) {
+        self.0.update(s.to_repr().as_ref());
+    }
+    fn challenge(&mut self) -> ZkScalar {
+        let out: [u8; 64] = self.0.clone().finalize().into();
+        self.0.update(out);
+        BellmanFr::from_bytes_wide(&out).into()
+    }
+}
+
+/// `PI(x) = -sum(input_i * L_i(x))`, the public inputs sit on the first rows
+fn public_inputs_eval(domain: &poly::Domain, inputs: &[ZkScalar], x: ZkScalar) -> Option<ZkScalar> {
+    let mut res = ZkScalar::ZERO;
+    for (i, inp) in inputs.iter().enumerate() {
+        res -= domain.lagrange(i, x)? * inp;
+    }
+    Some(res)
+}
+
+struct ParsedProof {
+    wires: [G1Affine; 3],
+    z: G1Affine,
+    t: [G1Affine; 3],
+    w_zeta: G1Affine,
+    w_zeta_omega: G1Affine,
+}
+
+impl PlonkProof {
+    fn parse(&self) -> Option<ParsedProof> {
+        Some(ParsedProof {
+            wires: [
+                g1_from_raw(&self.wires[0])?,
+                g1_from_raw(&self.wires[1])?,
+                g1_from_raw(&self.wires[2])?,
+            ],
+            z: g1_from_raw(&self.z)?,
+            t: [
+                g1_from_raw(&self.t[0])?,
+                g1_from_raw(&self.t[1])?,
+                g1_from_raw(&self.t[2])?,
+            ],
+            w_zeta: g1_from_raw(&self.w_zeta)?,
+            w_zeta_omega: g1_from_raw(&self.w_zeta_omega)?,
+        })
+    }
+}
+
+pub fn plonk_verify(
+    srs: &PlonkVerifierSrs,
+    vk: &PlonkVerifyingKey,
+    prev_height: u64,
+    prev_state: ZkScalar,
+    aux_data: ZkScalar,
+    next_state: ZkScalar,
+    proof: &PlonkProof,
+) -> bool {
+    let inputs = PlonkInputs {
+        prev_height,
+        prev_state,
+        aux_data,
+        next_state,
+    };
+    verify(srs, vk, &inputs.to_vec(), proof).is_some()
+}
+
+fn verify(
+    srs: &PlonkVerifierSrs,
+    vk: &PlonkVerifyingKey,
+    inputs: &[ZkScalar],
+    proof: &PlonkProof,
+) -> Option<()> {
+    if !vk.fits(srs) {
+        return None;
+    }
+    let domain = poly::Domain::new(usize::try_from(vk.n).ok()?)?;
+    if domain.size < inputs.len() || vk.selectors.len() != 5 || vk.sigmas.len() != 3 {
+        return None;
+    }
+    let selectors = vk
+        .selectors
+        .iter()
+        .map(g1_from_raw)
+        .collect::<Option<Vec<_>>>()?;
+    let sigmas = vk
+        .sigmas
+        .iter()
+        .map(g1_from_raw)
+        .collect::<Option<Vec<_>>>()?;
+    let x_g2 = g2_from_raw(&srs.x_g2)?;
+    let comms = proof.parse()?;
+    let evals = &proof.evaluations;
+
+    let mut transcript = Transcript::new(vk, inputs);
+    for p in comms.wires.iter() {
+        transcript.append_point(p);
+    }
+    let beta = transcript.challenge();
+    let gamma = transcript.challenge();
+    transcript.append_point(&comms.z);
+    let alpha = transcript.challenge();
+    for p in comms.t.iter() {
+        transcript.append_point(p);
+    }
+    let zeta = transcript.challenge();
+    for e in evals.opened_on_zeta() {
+        transcript.append_scalar(&e);
+    }
+    transcript.append_scalar(&evals.z_omega);
+    let v = transcript.challenge();
+    transcript.append_point(&comms.w_zeta);
+    transcript.append_point(&comms.w_zeta_omega);
+    let u = transcript.challenge();
+
+    // Gate and permutation constraints, checked on `zeta`
+    let zh_zeta = domain.vanishing(zeta);
+    if zh_zeta.is_zero_vartime() {
+        return None;
+    }
+    let l1_zeta = domain.lagrange(0, zeta)?;
+    let pi_zeta = public_inputs_eval(&domain, inputs, zeta)?;
+    let [a, b, c] = evals.wires;
+    let [q_l, q_r, q_o, q_m, q_c] = evals.selectors;
+    let gate = q_m * a * b + q_l * a + q_r * b + q_o * c + q_c + pi_zeta;
+    let mut perm_num = evals.z;
+    let mut perm_den = evals.z_omega;
+    for ((w, k), s) in evals
+        .wires
+        .iter()
+        .zip(coset_shifts().iter())
+        .zip(evals.sigmas.iter())
+    {
+        perm_num *= *w + beta * k * zeta + gamma;
+        perm_den *= *w + beta * s + gamma;
+    }
+    let zeta_n = zh_zeta + ZkScalar::ONE;
+    let t_zeta = evals.t[0] + zeta_n * (evals.t[1] + zeta_n * evals.t[2]);
+    let lhs =
+        gate + alpha * (perm_num - perm_den) + alpha.square() * (evals.z - ZkScalar::ONE) * l1_zeta;
+    if lhs != t_zeta * zh_zeta {
+        return None;
+    }
+
+    // Openings of the commitments, all checked in a single pairing equation
+    let commitments = comms
+        .wires
+        .iter()
+        .chain(selectors.iter())
+        .chain(sigmas.iter())
+        .chain(std::iter::once(&comms.z))
+        .chain(comms.t.iter());
+    let mut f = G1Projective::identity();
+    let mut e = ZkScalar::ZERO;
+    let mut v_i = ZkScalar::ONE;
+    for (comm, eval) in commitments.zip(evals.opened_on_zeta()) {
+        f += mul(*comm, v_i);
+        e += v_i * eval;
+        v_i *= v;
+    }
+    let zeta_omega = zeta * domain.omega;
+    let g1 = G1Affine::generator();
+    let rhs = f - mul(g1, e) + mul(comms.w_zeta, zeta) + mul(comms.z, u)
+        - mul(g1, u * evals.z_omega)
+        + mul(comms.w_zeta_omega, u * zeta_omega);
+    let lhs = G1Projective::from(comms.w_zeta) + mul(comms.w_zeta_omega, u);
+    (bls12_381::pairing(&G1Affine::from(lhs), &x_g2)
+        == bls12_381::pairing(&G1Affine::from(rhs), &G2Affine::generator()))
+    .then_some(())
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,152 @@
+use super::super::ZkScalar;
+use ff::{Field, PrimeField};
+
+/// Multiplicative subgroup of size `2^k`, the rows of a circuit
+#[derive(Debug, Clone, Copy)]
+pub struct Domain {
+    pub size: usize,
+    pub omega: ZkScalar,
+    omega_inv: ZkScalar,
+    size_inv: ZkScalar,
+}
+
+impl Domain {
+    pub fn new(size: usize) -> Option<Self> {
+        if !size.is_power_of_two() || size.trailing_zeros() > ZkScalar::S {
+            return None;
+        }
+        let mut omega = ZkScalar::ROOT_OF_UNITY;
+        for _ in size.trailing_zeros()..ZkScalar::S {
+            omega = omega.square();
+        }
+        Some(Self {
+            size,
+            omega,
+            omega_inv: omega.invert().unwrap(),
+            size_inv: ZkScalar::from(size as u64).invert().unwrap(),
+        })
+    }
+
+    pub fn element(&self, i: usize) -> ZkScalar {
+        self.omega.pow_vartime([i as u64])
+    }
+
+    pub fn elements(&self) -> Vec<ZkScalar> {
+        powers(self.omega, self.size)
+    }
+
+    /// `x^n - 1`, zero on the whole domain
+    pub fn vanishing(&self, x: ZkScalar) -> ZkScalar {
+        let mut res = x;
+        for _ in 0..self.size.trailing_zeros() {
+            res = res.square();
+        }
+        res - ZkScalar::ONE
+    }
+
+    /// Value of the `i`th Lagrange basis polynomial on a point outside of the
+    /// domain
+    pub fn lagrange(&self, i: usize, x: ZkScalar) -> Option<ZkScalar> {
+        let w = self.element(i);
+        let den = (x - w) * ZkScalar::from(self.size as u64);
+        Option::from(den.invert()).map(|inv: ZkScalar| w * self.vanishing(x) * inv)
+    }
+
+    /// Coefficients to evaluations
+    pub fn fft(&self, coeffs: &mut [ZkScalar]) {
+        fft(coeffs, self.omega);
+    }
+
+    /// Evaluations to coefficients
+    pub fn ifft(&self, evals: &mut [ZkScalar]) {
+        fft(evals, self.omega_inv);
+        for v in evals.iter_mut() {
+            *v *= self.size_inv;
+        }
+    }
+
+    /// Evaluations on the coset `g * H`, where `g` is the multiplicative
+    /// generator of the field
+    pub fn coset_fft(&self, coeffs: &mut [ZkScalar]) {
+        distribute_powers(coeffs, ZkScalar::MULTIPLICATIVE_GENERATOR);
+        self.fft(coeffs);
+    }
+
+    pub fn coset_ifft(&self, evals: &mut [ZkScalar]) {
+        self.ifft(evals);
+        distribute_powers(evals, ZkScalar::MULTIPLICATIVE_GENERATOR.invert().unwrap());
+    }
+}
+
+fn fft(a: &mut [ZkScalar], omega: ZkScalar) {
+    let n = a.len();
+    let log_n = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - log_n);
+        if i < j {
+            a.swap(i, j);
+        }
+    }
+    let mut m = 1;
+    while m < n {
+        let w_m = omega.pow_vartime([(n / (2 * m)) as u64]);
+        for k in (0..n).step_by(2 * m) {
+            let mut w = ZkScalar::ONE;
+            for j in 0..m {
+                let t = a[k + j + m] * w;
+                a[k + j + m] = a[k + j] - t;
+                a[k + j] += t;
+                w *= w_m;
+            }
+        }
+        m *= 2;
+    }
+}
+
+pub fn powers(base: ZkScalar, count: usize) -> Vec<ZkScalar> {
+    let mut res = Vec::with_capacity(count);
+    let mut curr = ZkScalar::ONE;
+    for _ in 0..count {
+        res.push(curr);
+        curr *= base;
+    }
+    res
+}
+
+/// Multiplies the `i`th coefficient by `g^i`, i.e `p(X) -> p(gX)`
+pub fn distribute_powers(coeffs: &mut [ZkScalar], g: ZkScalar) {
+    let mut curr = ZkScalar::ONE;
+    for c in coeffs.iter_mut() {
+        *c *= curr;
+        curr *= g;
+    }
+}
+
+pub fn evaluate(coeffs: &[ZkScalar], x: ZkScalar) -> ZkScalar {
+    coeffs
+        .iter()
+        .rev()
+        .fold(ZkScalar::ZERO, |acc, c| acc * x + c)
+}
+
+/// Quotient of `(p(X) - p(z)) / (X - z)`
+pub fn divide_by_linear(coeffs: &[ZkScalar], z: ZkScalar) -> Vec<ZkScalar> {
+    let mut quotient = vec![ZkScalar::ZERO; coeffs.len().saturating_sub(1)];
+    let mut carry = ZkScalar::ZERO;
+    for i in (1..coeffs.len()).rev() {
+        carry = coeffs[i] + carry * z;
+        quotient[i - 1] = carry;
+    }
+    quotient
+}
+
+/// Adds `b(X) * (X^n - 1)` to `p(X)`, which keeps its values on the domain
+pub fn add_vanishing_multiple(coeffs: &mut Vec<ZkScalar>, n: usize, b: &[ZkScalar]) {
+    if coeffs.len() < n + b.len() {
+        coeffs.resize(n + b.len(), ZkScalar::ZERO);
+    }
+    for (i, b_i) in b.iter().enumerate() {
+        coeffs[i] -= b_i;
+        coeffs[n + i] += b_i;
+    }
+}
@@ -0,0 +1,488 @@
+use super::poly::{self, Domain};
+use super::*;
+use rand::RngCore;
+use rayon::prelude::*;
+use thiserror::Error;
+
+/// Extra coefficients needed on top of the number of rows, because of the
+/// blinding factors added to the wire and permutation polynomials
+const BLINDING_DEGREE: usize = 6;
+
+#[derive(Error, Debug)]
+pub enum PlonkError {
+    #[error("the setup supports circuits of at most {0} rows")]
+    SetupTooSmall(usize),
+    #[error("circuit does not match the proving key")]
+    CircuitMismatch,
+}
+
+/// Powers of a secret `x` in G1, and `x` in G2. Anyone knowing `x` can forge
+/// proofs of any circuit prepared with this setup.
+#[derive(Debug, Clone)]
+pub struct PlonkSrs {
+    g1_powers: Vec<G1Affine>,
+    x_g2: G2Affine,
+}
+
+impl PlonkSrs {
+    /// Runs the setup alone. The randomness of `rng` should be destroyed
+    /// afterwards, public networks should rather use the output of a
+    /// multi-party ceremony.
+    pub fn generate<R: RngCore>(max_rows: usize, rng: &mut R) -> Self {
+        Self::with_secret(ZkScalar::random(rng), max_rows)
+    }
+
+    /// Setup whose secret is derived from a public seed, so anyone is able to
+    /// forge proofs with it. Only for the local networks (See
+    /// `PlonkVerifierSrs::insecure_from_seed`).
+    pub fn insecure_from_seed(seed: &[u8], max_rows: usize) -> Self {
+        Self::with_secret(seed_secret(seed), max_rows)
+    }
+
+    fn with_secret(x: ZkScalar, max_rows: usize) -> Self {
+        let g1 = G1Projective::generator();
+        let projective = poly::powers(x, max_rows + BLINDING_DEGREE)
+            .into_par_iter()
+            .map(|x_i| g1 * BellmanFr::from(x_i))
+            .collect::<Vec<_>>();
+        let mut g1_powers = vec![G1Affine::identity(); projective.len()];
+        G1Projective::batch_normalize(&projective, &mut g1_powers);
+        Self {
+            g1_powers,
+            x_g2: (G2Affine::generator() * BellmanFr::from(x)).into(),
+        }
+    }
+
+    pub fn max_rows(&self) -> usize {
+        self.g1_powers.len() - BLINDING_DEGREE
+    }
+
+    pub fn verifier_srs(&self) -> PlonkVerifierSrs {
+        PlonkVerifierSrs {
+            x_g2: g2_to_raw(self.x_g2),
+            max_rows: self.max_rows() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlonkVariable(usize);
+
+/// Enforces `q_l * a + q_r * b + q_o * c + q_m * a * b + q_c = 0` on the
+/// wires of a row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PlonkGate {
+    pub q_l: ZkScalar,
+    pub q_r: ZkScalar,
+    pub q_o: ZkScalar,
+    pub q_m: ZkScalar,
+    pub q_c: ZkScalar,
+}
+
+impl PlonkGate {
+    fn selectors(&self) -> [ZkScalar; 5] {
+        [self.q_l, self.q_r, self.q_o, self.q_m, self.q_c]
+    }
+}
+
+/// Rows of gates along with the values of their wires. Wires holding the same
+/// variable are constrained to be equal.
+#[derive(Debug, Clone)]
+pub struct PlonkCircuit {
+    values: Vec<ZkScalar>,
+    rows: Vec<([usize; 3], PlonkGate)>,
+}
+
+impl PlonkCircuit {
+    /// Starts a circuit whose first rows hold the public inputs
+    pub fn new(inputs: PlonkInputs) -> Self {
+        // Variable 0 is a zero filling the unused wires
+        let mut circuit = Self {
+            values: vec![ZkScalar::ZERO],
+            rows: Vec::new(),
+        };
+        for value in inputs.to_vec() {
+            let var = circuit.alloc(value);
+            circuit.rows.push((
+                [var.0, 0, 0],
+                PlonkGate {
+                    q_l: ZkScalar::ONE,
+                    ..Default::default()
+                },
+            ));
+        }
+        circuit
+    }
+
+    /// Variables of the inputs in the order of `PlonkInputs`
+    pub fn inputs(&self) -> [PlonkVariable; NUM_PLONK_INPUTS] {
+        [
+            PlonkVariable(1),
+            PlonkVariable(2),
+            PlonkVariable(3),
+            PlonkVariable(4),
+        ]
+    }
+
+    pub fn value(&self, var: PlonkVariable) -> ZkScalar {
+        self.values[var.0]
+    }
+
+    pub fn alloc(&mut self, value: ZkScalar) -> PlonkVariable {
+        self.values.push(value);
+        PlonkVariable(self.values.len() - 1)
+    }
+
+    pub fn gate(&mut self, a: PlonkVariable, b: PlonkVariable, c: PlonkVariable, gate: PlonkGate) {
+        self.rows.push(([a.0, b.0, c.0], gate));
+    }
+
+    pub fn add(&mut self, a: PlonkVariable, b: PlonkVariable) -> PlonkVariable {
+        let c = self.alloc(self.value(a) + self.value(b));
+        self.gate(
+            a,
+            b,
+            c,
+            PlonkGate {
+                q_l: ZkScalar::ONE,
+                q_r: ZkScalar::ONE,
+                q_o: -ZkScalar::ONE,
+                ..Default::default()
+            },
+        );
+        c
+    }
+
+    pub fn mul(&mut self, a: PlonkVariable, b: PlonkVariable) -> PlonkVariable {
+        let c = self.alloc(self.value(a) * self.value(b));
+        self.gate(
+            a,
+            b,
+            c,
+            PlonkGate {
+                q_m: ZkScalar::ONE,
+                q_o: -ZkScalar::ONE,
+                ..Default::default()
+            },
+        );
+        c
+    }
+
+    pub fn constant(&mut self, value: ZkScalar) -> PlonkVariable {
+        let a = self.alloc(value);
+        let zero = PlonkVariable(0);
+        self.gate(
+            a,
+            zero,
+            zero,
+            PlonkGate {
+                q_l: ZkScalar::ONE,
+                q_c: -value,
+                ..Default::default()
+            },
+        );
+        a
+    }
+
+    pub fn assert_equal(&mut self, a: PlonkVariable, b: PlonkVariable) {
+        self.gate(
+            a,
+            b,
+            PlonkVariable(0),
+            PlonkGate {
+                q_l: ZkScalar::ONE,
+                q_r: -ZkScalar::ONE,
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.rows.len().next_power_of_two()
+    }
+
+    fn public_inputs(&self) -> Vec<ZkScalar> {
+        self.inputs().iter().map(|v| self.value(*v)).collect()
+    }
+
+    /// Values of the wire columns, padded to the number of rows
+    fn wire_evals(&self) -> [Vec<ZkScalar>; 3] {
+        let mut cols = [vec![], vec![], vec![]];
+        for (j, col) in cols.iter_mut().enumerate() {
+            *col = self.rows.iter().map(|(w, _)| self.values[w[j]]).collect();
+            col.resize(self.num_rows(), ZkScalar::ZERO);
+        }
+        cols
+    }
+}
+
+/// Circuit specific data derived from the universal setup, no secrets involved
+#[derive(Debug, Clone)]
+pub struct PlonkProvingKey {
+    domain: Domain,
+    rows: Vec<([usize; 3], PlonkGate)>,
+    g1_powers: Vec<G1Affine>,
+    selectors: Vec<Vec<ZkScalar>>,
+    sigmas: Vec<Vec<ZkScalar>>,
+    sigma_evals: Vec<Vec<ZkScalar>>,
+    vk: PlonkVerifyingKey,
+}
+
+impl PlonkProvingKey {
+    pub fn new(srs: &PlonkSrs, circuit: &PlonkCircuit) -> Result<Self, PlonkError> {
+        let n = circuit.num_rows();
+        if n > srs.max_rows() {
+            return Err(PlonkError::SetupTooSmall(srs.max_rows()));
+        }
+        let domain = Domain::new(n).ok_or(PlonkError::SetupTooSmall(srs.max_rows()))?;
+        let g1_powers = srs.g1_powers[..n + BLINDING_DEGREE].to_vec();
+
+        let selectors = (0..5)
+            .map(|k| {
+                let mut evals = circuit
+                    .rows
+                    .iter()
+                    .map(|(_, g)| g.selectors()[k])
+                    .collect::<Vec<_>>();
+                evals.resize(n, ZkScalar::ZERO);
+                domain.ifft(&mut evals);
+                evals
+            })
+            .collect::<Vec<_>>();
+
+        // Wires holding the same variable form a cycle, each wire is mapped to
+        // the next one. Wire `j` of row `i` is labeled with `k_j * omega^i`.
+        let elements = domain.elements();
+        let shifts = coset_shifts();
+        let label = |(j, i): (usize, usize)| shifts[j] * elements[i];
+        let mut cycles = vec![Vec::new(); circuit.values.len()];
+        for j in 0..3 {
+            for i in 0..n {
+                let var = circuit.rows.get(i).map(|(w, _)| w[j]).unwrap_or(0);
+                cycles[var].push((j, i));
+            }
+        }
+        let mut sigma_evals = vec![vec![ZkScalar::ZERO; n]; 3];
+        for cycle in cycles {
+            for (k, &(j, i)) in cycle.iter().enumerate() {
+                sigma_evals[j][i] = label(cycle[(k + 1) % cycle.len()]);
+            }
+        }
+        let sigmas = sigma_evals
+            .iter()
+            .map(|evals| {
+                let mut coeffs = evals.clone();
+                domain.ifft(&mut coeffs);
+                coeffs
+            })
+            .collect::<Vec<_>>();
+
+        let commit = |coeffs: &Vec<ZkScalar>| g1_to_raw(commit(&g1_powers, coeffs));
+        let vk = PlonkVerifyingKey {
+            n: n as u64,
+            selectors: selectors.iter().map(commit).collect(),
+            sigmas: sigmas.iter().map(commit).collect(),
+        };
+        Ok(Self {
+            domain,
+            rows: circuit.rows.clone(),
+            g1_powers,
+            selectors,
+            sigmas,
+            sigma_evals,
+            vk,
+        })
+    }
+
+    pub fn verifier_key(&self) -> PlonkVerifyingKey {
+        self.vk.clone()
+    }
+
+    pub fn prove<R: RngCore>(
+        &self,
+        circuit: &PlonkCircuit,
+        rng: &mut R,
+    ) -> Result<PlonkProof, PlonkError> {
+        if circuit.rows != self.rows {
+            return Err(PlonkError::CircuitMismatch);
+        }
+        let domain = &self.domain;
+        let n = domain.size;
+        let commit = |coeffs: &Vec<ZkScalar>| commit(&self.g1_powers, coeffs);
+        let mut blinding = |count: usize| {
+            (0..count)
+                .map(|_| ZkScalar::random(&mut *rng))
+                .collect::<Vec<_>>()
+        };
+        let inputs = circuit.public_inputs();
+        let mut transcript = Transcript::new(&self.vk, &inputs);
+
+        // Round 1: wires
+        let wire_evals = circuit.wire_evals();
+        let mut wires = Vec::new();
+        let mut wire_comms = Vec::new();
+        for evals in wire_evals.iter() {
+            let mut coeffs = evals.clone();
+            domain.ifft(&mut coeffs);
+            poly::add_vanishing_multiple(&mut coeffs, n, &blinding(2));
+            let comm = commit(&coeffs);
+            transcript.append_point(&comm);
+            wires.push(coeffs);
+            wire_comms.push(comm);
+        }
+        let beta = transcript.challenge();
+        let gamma = transcript.challenge();
+
+        // Round 2: permutation accumulator
+        let elements = domain.elements();
+        let shifts = coset_shifts();
+        let mut z_evals = vec![ZkScalar::ONE; n];
+        for i in 0..n - 1 {
+            let mut num = ZkScalar::ONE;
+            let mut den = ZkScalar::ONE;
+            for j in 0..3 {
+                num *= wire_evals[j][i] + beta * shifts[j] * elements[i] + gamma;
+                den *= wire_evals[j][i] + beta * self.sigma_evals[j][i] + gamma;
+            }
+            z_evals[i + 1] = z_evals[i] * num * den.invert().unwrap();
+        }
+        let mut z = z_evals;
+        domain.ifft(&mut z);
+        poly::add_vanishing_multiple(&mut z, n, &blinding(3));
+        let z_comm = commit(&z);
+        transcript.append_point(&z_comm);
+        let alpha = transcript.challenge();
+
+        // Round 3: quotient, evaluated on a coset big enough for its degree
+        let big = Domain::new(8 * n).ok_or(PlonkError::SetupTooSmall(n))?;
+        let coset_evals = |coeffs: &[ZkScalar]| {
+            let mut evals = coeffs.to_vec();
+            evals.resize(big.size, ZkScalar::ZERO);
+            big.coset_fft(&mut evals);
+            evals
+        };
+        let lagrange_coeffs = |evals: Vec<ZkScalar>| {
+            let mut coeffs = evals;
+            coeffs.resize(n, ZkScalar::ZERO);
+            domain.ifft(&mut coeffs);
+            coeffs
+        };
+        let mut z_shifted = z.clone();
+        poly::distribute_powers(&mut z_shifted, domain.omega);
+        let wires_big = wires.iter().map(|p| coset_evals(p)).collect::<Vec<_>>();
+        let selectors_big = self
+            .selectors
+            .iter()
+            .map(|p| coset_evals(p))
+            .collect::<Vec<_>>();
+        let sigmas_big = self
+            .sigmas
+            .iter()
+            .map(|p| coset_evals(p))
+            .collect::<Vec<_>>();
+        let z_big = coset_evals(&z);
+        let z_shifted_big = coset_evals(&z_shifted);
+        let pi_big = coset_evals(&lagrange_coeffs(inputs.iter().map(|v| -*v).collect()));
+        let l1_big = coset_evals(&lagrange_coeffs(vec![ZkScalar::ONE]));
+        let mut xs = poly::powers(big.omega, big.size);
+        for x in xs.iter_mut() {
+            *x *= ZkScalar::MULTIPLICATIVE_GENERATOR;
+        }
+        // The vanishing polynomial only has 8 distinct values on the coset
+        let zh_inv = xs[..8]
+            .iter()
+            .map(|x| domain.vanishing(*x).invert().unwrap())
+            .collect::<Vec<_>>();
+        let mut t = (0..big.size)
+            .into_par_iter()
+            .map(|i| {
+                let (a, b, c) = (wires_big[0][i], wires_big[1][i], wires_big[2][i]);
+                let s = |k: usize| selectors_big[k][i];
+                let gate = s(0) * a + s(1) * b + s(2) * c + s(3) * a * b + s(4) + pi_big[i];
+                let mut perm_num = z_big[i];
+                let mut perm_den = z_shifted_big[i];
+                for j in 0..3 {
+                    let w = wires_big[j][i];
+                    perm_num *= w + beta * shifts[j] * xs[i] + gamma;
+                    perm_den *= w + beta * sigmas_big[j][i] + gamma;
+                }
+                let init = (z_big[i] - ZkScalar::ONE) * l1_big[i];
+                (gate + alpha * (perm_num - perm_den) + alpha.square() * init) * zh_inv[i % 8]
+            })
+            .collect::<Vec<_>>();
+        big.coset_ifft(&mut t);
+        t.truncate(3 * n + BLINDING_DEGREE);
+        let t_parts = [t[..n].to_vec(), t[n..2 * n].to_vec(), t[2 * n..].to_vec()];
+        let t_comms = t_parts.iter().map(commit).collect::<Vec<_>>();
+        for comm in t_comms.iter() {
+            transcript.append_point(comm);
+        }
+        let zeta = transcript.challenge();
+
+        // Round 4: evaluations
+        let zeta_omega = zeta * domain.omega;
+        let opened = wires
+            .iter()
+            .chain(self.selectors.iter())
+            .chain(self.sigmas.iter())
+            .chain(std::iter::once(&z))
+            .chain(t_parts.iter())
+            .collect::<Vec<_>>();
+        let at_zeta = opened
+            .iter()
+            .map(|p| poly::evaluate(p, zeta))
+            .collect::<Vec<_>>();
+        let evaluations = PlonkEvaluations {
+            wires: [at_zeta[0], at_zeta[1], at_zeta[2]],
+            selectors: [at_zeta[3], at_zeta[4], at_zeta[5], at_zeta[6], at_zeta[7]],
+            sigmas: [at_zeta[8], at_zeta[9], at_zeta[10]],
+            z: at_zeta[11],
+            t: [at_zeta[12], at_zeta[13], at_zeta[14]],
+            z_omega: poly::evaluate(&z, zeta_omega),
+        };
+        for e in evaluations.opened_on_zeta() {
+            transcript.append_scalar(&e);
+        }
+        transcript.append_scalar(&evaluations.z_omega);
+        let v = transcript.challenge();
+
+        // Round 5: opening proofs
+        let mut combined = vec![ZkScalar::ZERO; n + BLINDING_DEGREE];
+        let mut v_i = ZkScalar::ONE;
+        for p in opened {
+            for (acc, c) in combined.iter_mut().zip(p.iter()) {
+                *acc += v_i * c;
+            }
+            v_i *= v;
+        }
+        let w_zeta = commit(&poly::divide_by_linear(&combined, zeta));
+        let w_zeta_omega = commit(&poly::divide_by_linear(&z, zeta_omega));
+
+        Ok(PlonkProof {
+            wires: [
+                g1_to_raw(wire_comms[0]),
+                g1_to_raw(wire_comms[1]),
+                g1_to_raw(wire_comms[2]),
+            ],
+            z: g1_to_raw(z_comm),
+            t: [
+                g1_to_raw(t_comms[0]),
+                g1_to_raw(t_comms[1]),
+                g1_to_raw(t_comms[2]),
+            ],
+            evaluations,
+            w_zeta: g1_to_raw(w_zeta),
+            w_zeta_omega: g1_to_raw(w_zeta_omega),
+        })
+    }
+}
+
+fn commit(g1_powers: &[G1Affine], coeffs: &[ZkScalar]) -> G1Affine {
+    assert!(coeffs.len() <= g1_powers.len());
+    coeffs
+        .par_iter()
+        .zip(g1_powers.par_iter())
+        .map(|(c, p)| mul(*p, *c))
+        .reduce(G1Projective::identity, |a, b| a + b)
+        .into()
+}
@@ -0,0 +1,383 @@
+use super::*;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+fn inputs(prev_height: u64, prev_state: u64, aux_data: u64, next_state: u64) -> PlonkInputs {
+    PlonkInputs {
+        prev_height,
+        prev_state: prev_state.into(),
+        aux_data: aux_data.into(),
+        next_state: next_state.into(),
+    }
+}
+
+fn verify_inputs(
+    srs: &PlonkSrs,
+    vk: &PlonkVerifyingKey,
+    inputs: PlonkInputs,
+    proof: &PlonkProof,
+) -> bool {
+    plonk_verify(
+        &srs.verifier_srs(),
+        vk,
+        inputs.prev_height,
+        inputs.prev_state,
+        inputs.aux_data,
+        inputs.next_state,
+        proof,
+    )
+}
+
+// next_state = prev_state * aux_data + prev_height + 5
+fn mul_add_circuit(inputs: PlonkInputs, next_state: ZkScalar) -> PlonkCircuit {
+    let mut circuit = PlonkCircuit::new(inputs);
+    let [height, prev, aux, next] = circuit.inputs();
+    let prod = circuit.mul(prev, aux);
+    let sum = circuit.add(prod, height);
+    let five = circuit.constant(5.into());
+    let res = circuit.add(sum, five);
+    let expected = circuit.alloc(next_state);
+    circuit.assert_equal(res, expected);
+    circuit.assert_equal(expected, next);
+    circuit
+}
+
+#[test]
+fn test_domain() {
+    let domain = poly::Domain::new(8).unwrap();
+    assert_eq!(domain.element(8), ZkScalar::ONE);
+    assert_ne!(domain.element(4), ZkScalar::ONE);
+
+    let coeffs = (0..8u64).map(ZkScalar::from).collect::<Vec<_>>();
+    let mut evals = coeffs.clone();
+    domain.fft(&mut evals);
+    for (i, e) in evals.iter().enumerate() {
+        assert_eq!(*e, poly::evaluate(&coeffs, domain.element(i)));
+    }
+    domain.ifft(&mut evals);
+    assert_eq!(evals, coeffs);
+
+    let mut evals = coeffs.clone();
+    domain.coset_fft(&mut evals);
+    assert_eq!(
+        evals[3],
+        poly::evaluate(
+            &coeffs,
+            ZkScalar::MULTIPLICATIVE_GENERATOR * domain.element(3)
+        )
+    );
+    domain.coset_ifft(&mut evals);
+    assert_eq!(evals, coeffs);
+
+    let x = ZkScalar::from(12345);
+    let lagrange_sum = (0..8).fold(ZkScalar::ZERO, |acc, i| {
+        acc + domain.lagrange(i, x).unwrap()
+    });
+    assert_eq!(lagrange_sum, ZkScalar::ONE);
+    assert_eq!(domain.lagrange(0, domain.element(5)), Some(ZkScalar::ZERO));
+    assert!(domain.lagrange(5, domain.element(5)).is_none());
+
+    let quotient = poly::divide_by_linear(&coeffs, x);
+    let y = ZkScalar::from(777);
+    assert_eq!(
+        poly::evaluate(&quotient, y) * (y - x),
+        poly::evaluate(&coeffs, y) - poly::evaluate(&coeffs, x)
+    );
+}
+
+#[test]
+fn test_plonk_proofs() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let srs = PlonkSrs::generate(32, &mut rng);
+
+    let valid = inputs(3, 10, 20, 208);
+    let pk = PlonkProvingKey::new(&srs, &mul_add_circuit(valid, 208.into())).unwrap();
+    let vk = pk.verifier_key();
+    let proof = pk
+        .prove(&mul_add_circuit(valid, 208.into()), &mut rng)
+        .unwrap();
+    assert!(verify_inputs(&srs, &vk, valid, &proof));
+
+    // Survives serialization
+    let ser = bincode::serialize(&(&vk, &proof)).unwrap();
+    let (vk_de, proof_de): (PlonkVerifyingKey, PlonkProof) = bincode::deserialize(&ser).unwrap();
+    assert!(verify_inputs(&srs, &vk_de, valid, &proof_de));
+
+    // Proof is bound to its inputs
+    for wrong in [
+        inputs(4, 10, 20, 208),
+        inputs(3, 11, 20, 208),
+        inputs(3, 10, 21, 208),
+        inputs(3, 10, 20, 209),
+    ] {
+        assert!(!verify_inputs(&srs, &vk, wrong, &proof));
+    }
+
+    // Witnesses not satisfying the circuit give invalid proofs
+    let unsatisfied = inputs(3, 10, 20, 209);
+    let proof = pk
+        .prove(&mul_add_circuit(unsatisfied, 209.into()), &mut rng)
+        .unwrap();
+    assert!(!verify_inputs(&srs, &vk, unsatisfied, &proof));
+    let proof = pk
+        .prove(&mul_add_circuit(valid, 209.into()), &mut rng)
+        .unwrap();
+    assert!(!verify_inputs(&srs, &vk, valid, &proof));
+
+    // Proofs of other circuits are rejected
+    let other = PlonkProvingKey::new(&srs, &PlonkCircuit::new(valid)).unwrap();
+    let proof = other.prove(&PlonkCircuit::new(valid), &mut rng).unwrap();
+    assert!(verify_inputs(&srs, &other.verifier_key(), valid, &proof));
+    assert!(!verify_inputs(&srs, &vk, valid, &proof));
+    assert!(matches!(
+        pk.prove(&PlonkCircuit::new(valid), &mut rng),
+        Err(PlonkError::CircuitMismatch)
+    ));
+
+    // Tampered evaluations are caught by the opening check
+    let mut proof = pk
+        .prove(&mul_add_circuit(valid, 208.into()), &mut rng)
+        .unwrap();
+    proof.evaluations.sigmas[0] += ZkScalar::ONE;
+    assert!(!verify_inputs(&srs, &vk, valid, &proof));
+
+    // Proofs are only checked against the setup pinned by the network
+    let proof = pk
+        .prove(&mul_add_circuit(valid, 208.into()), &mut rng)
+        .unwrap();
+    let other_srs = PlonkSrs::generate(32, &mut rng);
+    assert!(!verify_inputs(&other_srs, &vk, valid, &proof));
+    let small_srs = PlonkSrs::generate(4, &mut rng);
+    assert!(vk.fits(&srs.verifier_srs()));
+    assert!(!vk.fits(&small_srs.verifier_srs()));
+}
+
+#[test]
+fn test_plonk_setup_too_small() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let srs = PlonkSrs::generate(4, &mut rng);
+    assert_eq!(srs.max_rows(), 4);
+    let circuit = mul_add_circuit(inputs(3, 10, 20, 208), 208.into());
+    assert!(matches!(
+        PlonkProvingKey::new(&srs, &circuit),
+        Err(PlonkError::SetupTooSmall(4))
+    ));
+}
+
+/// Verifier written from the PLONK equations, with the openings checked one
+/// by one instead of batched in a single pairing. It knows the secret of the
+/// setup, so the KZG openings are checked in G1 directly.
+fn reference_verify(
+    secret: ZkScalar,
+    max_rows: u64,
+    vk: &PlonkVerifyingKey,
+    inputs: &[ZkScalar],
+    proof: &PlonkProof,
+) -> bool {
+    let n = vk.n;
+    if !n.is_power_of_two()
+        || n > max_rows
+        || (n as usize) < inputs.len()
+        || vk.selectors.len() != 5
+        || vk.sigmas.len() != 3
+    {
+        return false;
+    }
+    let points = vk
+        .selectors
+        .iter()
+        .chain(vk.sigmas.iter())
+        .chain(proof.wires.iter())
+        .chain(std::iter::once(&proof.z))
+        .chain(proof.t.iter())
+        .chain([&proof.w_zeta, &proof.w_zeta_omega])
+        .map(g1_from_raw)
+        .collect::<Option<Vec<_>>>();
+    let points = match points {
+        Some(points) => points,
+        None => return false,
+    };
+    let (selectors, rest) = points.split_at(5);
+    let (sigmas, rest) = rest.split_at(3);
+    let (wires, rest) = rest.split_at(3);
+    let (z, rest) = rest.split_at(1);
+    let (t, rest) = rest.split_at(3);
+    let (w_zeta, w_zeta_omega) = (rest[0], rest[1]);
+    let evals = &proof.evaluations;
+
+    let mut transcript = Transcript::new(vk, inputs);
+    for p in wires {
+        transcript.append_point(p);
+    }
+    let beta = transcript.challenge();
+    let gamma = transcript.challenge();
+    transcript.append_point(&z[0]);
+    let alpha = transcript.challenge();
+    for p in t {
+        transcript.append_point(p);
+    }
+    let zeta = transcript.challenge();
+    for e in evals.opened_on_zeta() {
+        transcript.append_scalar(&e);
+    }
+    transcript.append_scalar(&evals.z_omega);
+    let v = transcript.challenge();
+
+    // omega is the n-th root of unity, L_i(x) = omega^i (x^n - 1) / (n (x - omega^i))
+    let omega = ZkScalar::ROOT_OF_UNITY.pow_vartime([1u64 << (ZkScalar::S - n.trailing_zeros())]);
+    let zeta_n = zeta.pow_vartime([n]);
+    if zeta_n == ZkScalar::ONE {
+        return false;
+    }
+    let lagrange = |i: usize| {
+        let omega_i = omega.pow_vartime([i as u64]);
+        omega_i
+            * (zeta_n - ZkScalar::ONE)
+            * (ZkScalar::from(n) * (zeta - omega_i)).invert().unwrap()
+    };
+    let pi = inputs
+        .iter()
+        .enumerate()
+        .fold(ZkScalar::ZERO, |acc, (i, inp)| acc - lagrange(i) * inp);
+    let [a, b, c] = evals.wires;
+    let [q_l, q_r, q_o, q_m, q_c] = evals.selectors;
+    let [s_a, s_b, s_c] = evals.sigmas;
+    let k1 = ZkScalar::MULTIPLICATIVE_GENERATOR;
+    let k2 = k1 * k1;
+    let gate = q_l * a + q_r * b + q_o * c + q_m * a * b + q_c + pi;
+    let perm = evals.z
+        * (a + beta * zeta + gamma)
+        * (b + beta * k1 * zeta + gamma)
+        * (c + beta * k2 * zeta + gamma)
+        - evals.z_omega
+            * (a + beta * s_a + gamma)
+            * (b + beta * s_b + gamma)
+            * (c + beta * s_c + gamma);
+    let first = (evals.z - ZkScalar::ONE) * lagrange(0);
+    let t_zeta = evals.t[0] + zeta_n * evals.t[1] + zeta_n * zeta_n * evals.t[2];
+    if gate + alpha * perm + alpha * alpha * first != t_zeta * (zeta_n - ZkScalar::ONE) {
+        return false;
+    }
+
+    // sum(v^i * (C_i - e_i)) = (x - zeta) * W_zeta
+    let g1 = G1Affine::generator();
+    let mut opened = G1Projective::identity();
+    let mut v_i = ZkScalar::ONE;
+    let commitments = wires
+        .iter()
+        .chain(selectors.iter())
+        .chain(sigmas.iter())
+        .chain(z.iter())
+        .chain(t.iter());
+    for (comm, eval) in commitments.zip(evals.opened_on_zeta()) {
+        opened += (G1Projective::from(*comm) - mul(g1, eval)) * BellmanFr::from(v_i);
+        v_i *= v;
+    }
+    // z - z(zeta * omega) = (x - zeta * omega) * W_zeta_omega
+    opened == mul(w_zeta, secret - zeta)
+        && G1Projective::from(z[0]) - mul(g1, evals.z_omega)
+            == mul(w_zeta_omega, secret - zeta * omega)
+}
+
+/// Random circuit of additions, multiplications and constants on top of the
+/// inputs, whose last value is asserted to be `next_state`
+fn random_circuit(seed: u64, inputs: PlonkInputs) -> (PlonkCircuit, ZkScalar) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut circuit = PlonkCircuit::new(inputs);
+    let mut vars = circuit.inputs()[..3].to_vec();
+    for _ in 0..1 + rng.next_u32() % 12 {
+        let a = vars[rng.next_u32() as usize % vars.len()];
+        let b = vars[rng.next_u32() as usize % vars.len()];
+        let var = match rng.next_u32() % 3 {
+            0 => circuit.add(a, b),
+            1 => circuit.mul(a, b),
+            _ => circuit.constant(rng.next_u64().into()),
+        };
+        vars.push(var);
+    }
+    let res = *vars.last().unwrap();
+    let value = circuit.value(res);
+    circuit.assert_equal(res, circuit.inputs()[3]);
+    (circuit, value)
+}
+
+#[test]
+fn test_plonk_verifier_matches_reference() {
+    let seed = b"TEST SETUP";
+    let srs = PlonkSrs::insecure_from_seed(seed, 64);
+    assert_eq!(
+        srs.verifier_srs(),
+        PlonkVerifierSrs::insecure_from_seed(seed, 64)
+    );
+    let secret = seed_secret(seed);
+    let mut rng = StdRng::seed_from_u64(4);
+
+    let check = |vk: &PlonkVerifyingKey, inputs: PlonkInputs, proof: &PlonkProof| {
+        let accepted = verify_inputs(&srs, vk, inputs, proof);
+        assert_eq!(
+            accepted,
+            reference_verify(secret, 64, vk, &inputs.to_vec(), proof)
+        );
+        accepted
+    };
+
+    for circuit_seed in 0..8 {
+        let mut valid = inputs(
+            rng.next_u64(),
+            rng.next_u64(),
+            rng.next_u64(),
+            rng.next_u64(),
+        );
+        valid.next_state = random_circuit(circuit_seed, valid).1;
+        let (circuit, _) = random_circuit(circuit_seed, valid);
+        let pk = PlonkProvingKey::new(&srs, &circuit).unwrap();
+        let vk = pk.verifier_key();
+        let proof = pk.prove(&circuit, &mut rng).unwrap();
+        assert!(check(&vk, valid, &proof));
+
+        // Unsatisfied witness
+        let mut wrong = valid;
+        wrong.next_state += ZkScalar::ONE;
+        let unsatisfied = pk.prove(&random_circuit(circuit_seed, wrong).0, &mut rng);
+        assert!(!check(&vk, wrong, &unsatisfied.unwrap()));
+
+        // Changed inputs
+        let mut wrong = valid;
+        match rng.next_u32() % 3 {
+            0 => wrong.prev_height += 1,
+            1 => wrong.prev_state += ZkScalar::ONE,
+            _ => wrong.aux_data += ZkScalar::ONE,
+        }
+        assert!(!check(&vk, wrong, &proof));
+
+        // Tampered evaluations
+        for _ in 0..2 {
+            let mut tampered = proof.clone();
+            let evals = &mut tampered.evaluations;
+            let delta = ZkScalar::from(1 + rng.next_u64());
+            match rng.next_u32() % 6 {
+                0 => evals.wires[rng.next_u32() as usize % 3] += delta,
+                1 => evals.selectors[rng.next_u32() as usize % 5] += delta,
+                2 => evals.sigmas[rng.next_u32() as usize % 3] += delta,
+                3 => evals.z += delta,
+                4 => evals.t[rng.next_u32() as usize % 3] += delta,
+                _ => evals.z_omega += delta,
+            }
+            assert!(!check(&vk, valid, &tampered));
+        }
+
+        // Tampered commitments
+        for _ in 0..2 {
+            let mut tampered = proof.clone();
+            let point = g1_to_raw(mul(G1Affine::generator(), ZkScalar::random(&mut rng)).into());
+            match rng.next_u32() % 5 {
+                0 => tampered.wires[rng.next_u32() as usize % 3] = point,
+                1 => tampered.z = point,
+                2 => tampered.t[rng.next_u32() as usize % 3] = point,
+                3 => tampered.w_zeta = point,
+                _ => tampered.w_zeta_omega = point,
+            }
+            assert!(!check(&vk, valid, &tampered));
+        }
+    }
+}
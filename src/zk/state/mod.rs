@@ -78,6 +78,9 @@ impl<H: ZkHasher> ZkStateBuilder<H> {
     pub fn compress(self) -> Result<ZkCompressedState, StateManagerError> {
         KvStoreStateManager::<H>::root(&self.db, self.contract_id)
     }
+    pub fn root(&self) -> Result<ZkCompressedState, StateManagerError> {
+        KvStoreStateManager::<H>::root(&self.db, self.contract_id)
+    }
 
     pub fn prove(
         &self,
@@ -132,6 +135,47 @@ impl<H: ZkHasher> KvStoreStateManager<H> {
         })
     }
 
+    /// States of an MPN account along with the contract heights they belong to, newest
+    /// first, starting with the current state and going back `count` updates at most
+    /// (As far as the stored rollback patches allow).
+    pub fn get_mpn_account_history<K: KvStore>(
+        db: &K,
+        mpn_contract_id: ContractId,
+        index: u64,
+        count: u64,
+    ) -> Result<Vec<(u64, MpnAccount)>, StateManagerError> {
+        let height = Self::height_of(db, mpn_contract_id)?;
+        let mut fork = db.mirror();
+        let mut history = vec![(
+            height,
+            Self::get_mpn_account(&fork, mpn_contract_id, index)?,
+        )];
+        let mut state_size = Self::root(db, mpn_contract_id)?.state_size;
+        for away in 1..=std::cmp::min(count, height) {
+            let patch = if let Some(patch) = Self::rollback_of(db, mpn_contract_id, away)? {
+                patch
+            } else {
+                break;
+            };
+            for (loc, val) in patch.0 {
+                if loc.0.first() == Some(&index) {
+                    Self::set_data(
+                        &mut fork,
+                        mpn_contract_id,
+                        loc,
+                        val.unwrap_or_default(),
+                        &mut state_size,
+                    )?;
+                }
+            }
+            history.push((
+                height - away,
+                Self::get_mpn_account(&fork, mpn_contract_id, index)?,
+            ));
+        }
+        Ok(history)
+    }
+
     pub fn get_mpn_accounts<K: KvStore>(
         db: &K,
         mpn_contract_id: ContractId,
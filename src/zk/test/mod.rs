@@ -69,6 +69,164 @@ fn test_zk_list_membership_proof() {
     }
 }
 
+#[test]
+fn test_locate_mpn_account() {
+    use crate::crypto::DeriveMpnAccountIndex;
+    let keys = (0..64)
+        .map(|i| crate::wallet::TxBuilder::new(format!("KEY{}", i).as_bytes()).get_zk_address())
+        .collect::<Vec<_>>();
+    let (owner, other) = (&keys[0], &keys[1]);
+    let taken = |index: u64| {
+        if index == owner.mpn_account_slot(1, 0) {
+            MpnAccount {
+                address: owner.decompress(),
+                ..Default::default()
+            }
+        } else {
+            Default::default()
+        }
+    };
+    let locate = |key: &jubjub::PublicKey, choices| {
+        locate_mpn_account(key, 1, choices, |i| Ok::<_, ()>(taken(i))).unwrap()
+    };
+
+    // Owners keep their slot, others skip it
+    assert_eq!(locate(owner, 2), Some((0, owner.mpn_account_slot(1, 0))));
+    assert_eq!(
+        locate(other, 1).is_some(),
+        other.mpn_account_slot(1, 0) != owner.mpn_account_slot(1, 0)
+    );
+    let colliding = keys
+        .iter()
+        .find(|k| *k != owner && k.mpn_account_slot(1, 0) == owner.mpn_account_slot(1, 0))
+        .unwrap();
+    assert_eq!(locate(colliding, 1), None);
+    assert_eq!(
+        locate(colliding, 2),
+        (colliding.mpn_account_slot(1, 1) != owner.mpn_account_slot(1, 0))
+            .then(|| (1, colliding.mpn_account_slot(1, 1)))
+    );
+}
+
+#[test]
+fn test_find_token_index() {
+    let custom = TokenId::Custom(ZkScalar::from(123));
+    let mut acc = MpnAccount::default();
+    assert_eq!(acc.find_token_index(1, TokenId::Ziesha, true), Ok(0));
+    assert_eq!(
+        acc.find_token_index(1, TokenId::Ziesha, false),
+        Err(MpnTokenError::TokenNotFound(TokenId::Ziesha))
+    );
+
+    // The slot with the highest balance is picked
+    acc.tokens.insert(0, Money::ziesha(10));
+    acc.tokens.insert(2, Money::ziesha(20));
+    assert_eq!(acc.find_token_index(1, TokenId::Ziesha, false), Ok(2));
+    assert_eq!(acc.find_token_index(1, custom, true), Ok(1));
+
+    // Unused slots are preferred over the ones with no balance left
+    acc.tokens.insert(1, Money::new(custom, 0));
+    acc.tokens.insert(3, Money::new(custom, 5));
+    acc.tokens.remove(&2);
+    assert_eq!(acc.find_token_index(1, TokenId::Ziesha, false), Ok(0));
+    assert_eq!(acc.find_token_index(1, custom, false), Ok(3));
+    let other = TokenId::Custom(ZkScalar::from(321));
+    assert_eq!(acc.find_token_index(1, other, true), Ok(2));
+    acc.tokens.insert(2, Money::ziesha(1));
+    assert_eq!(acc.find_token_index(1, other, true), Ok(1));
+    assert!(acc.token_slot_accepts(1, other, true));
+    assert!(!acc.token_slot_accepts(1, other, false));
+
+    acc.tokens.insert(1, Money::new(custom, 1));
+    assert_eq!(
+        acc.find_token_index(1, other, true),
+        Err(MpnTokenError::AccountFull)
+    );
+}
+
+#[test]
+fn test_mpn_account_history() -> Result<(), StateManagerError> {
+    let mut db = RamKvStore::new();
+    let c0 =
+        ContractId::from_str("0000000000000000000000000000000000000000000000000000000000000000")
+            .unwrap();
+    let token_model = ZkStateModel::List {
+        log4_size: 1,
+        item_type: Box::new(ZkStateModel::Struct {
+            field_types: vec![ZkStateModel::Scalar, ZkStateModel::Scalar],
+        }),
+    };
+    db.update(&[WriteOp::Put(
+        format!("CON-{}", c0).into(),
+        empty_contract::<PoseidonHasher>(ZkStateModel::List {
+            log4_size: 2,
+            item_type: Box::new(ZkStateModel::Struct {
+                field_types: vec![
+                    ZkStateModel::Scalar,
+                    ZkStateModel::Scalar,
+                    ZkStateModel::Scalar,
+                    token_model,
+                ],
+            }),
+        })
+        .into(),
+    )])?;
+
+    let updates = [
+        vec![
+            (vec![5, 1], ZkScalar::from(12)),
+            (vec![5, 2], ZkScalar::from(34)),
+            (vec![5, 3, 0, 0], TokenId::Ziesha.into()),
+            (vec![5, 3, 0, 1], ZkScalar::from(100)),
+        ],
+        vec![
+            (vec![5, 0], ZkScalar::from(1)),
+            (vec![5, 3, 0, 1], ZkScalar::from(70)),
+        ],
+        // Other accounts don't affect the history
+        vec![(vec![6, 0], ZkScalar::from(1))],
+    ];
+    for (i, update) in updates.into_iter().enumerate() {
+        KvStoreStateManager::<PoseidonHasher>::update_contract(
+            &mut db,
+            c0,
+            &ZkDeltaPairs(
+                update
+                    .into_iter()
+                    .map(|(loc, val)| (ZkDataLocator(loc), Some(val)))
+                    .collect(),
+            ),
+            i as u64 + 1,
+        )?;
+    }
+
+    let history = KvStoreStateManager::<PoseidonHasher>::get_mpn_account_history(&db, c0, 5, 10)?;
+    assert_eq!(
+        history
+            .iter()
+            .map(|(h, acc)| (*h, acc.nonce, acc.tokens.get(&0).map(|m| m.amount)))
+            .collect::<Vec<_>>(),
+        vec![
+            (3, 1, Some(Amount(70))),
+            (2, 1, Some(Amount(70))),
+            (1, 0, Some(Amount(100))),
+            (0, 0, None),
+        ]
+    );
+    assert_eq!(history[2].1.address.0, ZkScalar::from(12));
+    assert_eq!(history[3].1, MpnAccount::default());
+    // The state itself is not touched
+    assert_eq!(
+        KvStoreStateManager::<PoseidonHasher>::get_mpn_account(&db, c0, 5)?,
+        history[0].1
+    );
+    assert_eq!(
+        KvStoreStateManager::<PoseidonHasher>::get_mpn_account_history(&db, c0, 5, 1)?.len(),
+        2
+    );
+    Ok(())
+}
+
 #[test]
 fn test_state_manager_scalar() -> Result<(), StateManagerError> {
     let mut db = RamKvStore::new();
@@ -295,3 +453,111 @@ fn test_state_manager_list() -> Result<(), StateManagerError> {
 
     Ok(())
 }
+
+#[test]
+fn test_proof_batch() {
+    use super::testing::TestCircuit;
+    let circuit_a = TestCircuit::setup(1);
+    let circuit_b = TestCircuit::setup(2);
+    let state = |v: u64| ZkCompressedState::new(ZkScalar::from(v), 0);
+    let prove = |circuit: &TestCircuit, height: u64| {
+        circuit.prove(
+            height,
+            &state(height),
+            &state(height + 1),
+            &state(height + 2),
+        )
+    };
+
+    let mut batch = ZkProofBatch::default();
+    assert!(batch.is_empty() && batch.verify(None));
+    for (circuit, height) in [(&circuit_a, 1), (&circuit_b, 2), (&circuit_a, 3)] {
+        batch.queue(
+            &circuit.verifier_key(),
+            height,
+            &state(height),
+            &state(height + 1),
+            &state(height + 2),
+            &prove(circuit, height),
+        );
+    }
+    assert_eq!(batch.len(), 3);
+    assert!(batch.verify(None));
+
+    // Proof of the other circuit
+    let mut wrong_circuit = ZkProofBatch::default();
+    wrong_circuit.queue(
+        &circuit_a.verifier_key(),
+        4,
+        &state(4),
+        &state(5),
+        &state(6),
+        &prove(&circuit_b, 4),
+    );
+    // Proof of other inputs
+    let mut wrong_inputs = ZkProofBatch::default();
+    wrong_inputs.queue(
+        &circuit_b.verifier_key(),
+        4,
+        &state(4),
+        &state(5),
+        &state(7),
+        &prove(&circuit_b, 4),
+    );
+    for wrong in [wrong_circuit, wrong_inputs] {
+        assert!(!wrong.verify(None));
+        let mut batch_with_wrong = ZkProofBatch {
+            items: batch.items.clone(),
+        };
+        batch_with_wrong.items.extend(wrong.items);
+        assert!(!batch_with_wrong.verify(None));
+    }
+}
+
+// Compares batch verification with one by one verification, run with:
+// cargo test --release test_proof_batch_benchmark -- --ignored --nocapture
+#[test]
+#[ignore]
+fn test_proof_batch_benchmark() {
+    use super::testing::TestCircuit;
+    let circuit = TestCircuit::setup(1);
+    let vk = circuit.verifier_key();
+    let state = |v: u64| ZkCompressedState::new(ZkScalar::from(v), 0);
+    for num_proofs in [1, 4, 16, 64] {
+        let mut batch = ZkProofBatch::default();
+        for height in 0..num_proofs {
+            let proof = circuit.prove(height, &state(height), &state(0), &state(height + 1));
+            batch.queue(
+                &vk,
+                height,
+                &state(height),
+                &state(0),
+                &state(height + 1),
+                &proof,
+            );
+        }
+
+        let start = std::time::Instant::now();
+        for (vk, prev_height, prev_state, aux_data, next_state, proof) in batch.items.iter() {
+            assert!(check_proof(
+                None,
+                vk,
+                *prev_height,
+                &ZkCompressedState::new(*prev_state, 0),
+                &ZkCompressedState::new(*aux_data, 0),
+                &ZkCompressedState::new(*next_state, 0),
+                proof
+            ));
+        }
+        let single = start.elapsed();
+
+        let start = std::time::Instant::now();
+        assert!(batch.verify(None));
+        let batched = start.elapsed();
+
+        println!(
+            "{} proofs: one by one {:?}, batched {:?}",
+            num_proofs, single, batched
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Small Groth16 circuits for running the contract flows of the tests against
+//! real proof verification.
+
+use super::{groth16::Groth16VerifyingKey, ZkCompressedState, ZkProof, ZkScalar, ZkVerifierKey};
+use bellman::groth16::{create_random_proof, generate_random_parameters, Parameters};
+use bellman::{Circuit, ConstraintSystem, SynthesisError};
+use bls12_381::{Bls12, Scalar as BellmanFr};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+/// Public inputs in the order `groth16_verify` passes them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransitionInputs {
+    pub height: u64,
+    pub prev_state: ZkScalar,
+    pub aux_data: ZkScalar,
+    pub next_state: ZkScalar,
+}
+
+/// Accepts any transition, the proofs are only bound to their public inputs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransitionCircuit {
+    pub inputs: TransitionInputs,
+}
+
+impl Circuit<BellmanFr> for TransitionCircuit {
+    fn synthesize<CS: ConstraintSystem<BellmanFr>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
+        let values = [
+            ZkScalar::from(self.inputs.height),
+            self.inputs.prev_state,
+            self.inputs.aux_data,
+            self.inputs.next_state,
+        ];
+        for (i, value) in values.into_iter().enumerate() {
+            let input = cs.alloc_input(|| format!("input {}", i), || Ok(value.into()))?;
+            cs.enforce(
+                || format!("input {} check", i),
+                |lc| lc + input,
+                |lc| lc + CS::one(),
+                |lc| lc + input,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Proving parameters of a `TransitionCircuit`. Each setup gives a distinct
+/// verifying key, so proofs made for one function are rejected by another.
+pub struct TestCircuit {
+    params: Parameters<Bls12>,
+}
+
+impl TestCircuit {
+    pub fn setup(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Self {
+            params: generate_random_parameters(TransitionCircuit::default(), &mut rng).unwrap(),
+        }
+    }
+
+    pub fn verifier_key(&self) -> ZkVerifierKey {
+        ZkVerifierKey::Groth16(Box::new(Groth16VerifyingKey::from(self.params.vk.clone())))
+    }
+
+    pub fn prove(
+        &self,
+        height: u64,
+        prev_state: &ZkCompressedState,
+        aux_data: &ZkCompressedState,
+        next_state: &ZkCompressedState,
+    ) -> ZkProof {
+        let circuit = TransitionCircuit {
+            inputs: TransitionInputs {
+                height,
+                prev_state: prev_state.state_hash,
+                aux_data: aux_data.state_hash,
+                next_state: next_state.state_hash,
+            },
+        };
+        let proof = create_random_proof(circuit, &self.params, &mut rand::thread_rng()).unwrap();
+        ZkProof::Groth16(Box::new(proof.into()))
+    }
+}